@@ -0,0 +1,123 @@
+//! 比较 `io::forward` 与旧的转发方式 (1500字节缓冲区 + 加锁拆分) 的吞吐量
+//!
+//! cargo bench --bench forward
+
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use fuso::{
+    ext::{AsyncReadExt, AsyncWriteExt},
+    io, AsyncRead, AsyncWrite,
+};
+use tokio::net::TcpStream;
+
+#[path = "../src/testing/tcp.rs"]
+mod tcp;
+
+const SIZES: [usize; 2] = [1024 * 1024, 16 * 1024 * 1024];
+
+async fn pair() -> (TcpStream, TcpStream) {
+    let (s1, s2) = tcp::pair().await;
+
+    // 避免大量TIME_WAIT的连接拖慢后面的测试
+    s1.set_linger(Some(Duration::ZERO)).unwrap();
+    s2.set_linger(Some(Duration::ZERO)).unwrap();
+
+    (s1, s2)
+}
+
+/// 旧的实现, 每个方向一个1500字节的缓冲区, 通过加锁的 `ReadHalf`/`WriteHalf` 读写
+async fn legacy_forward<S1, S2>(s1: S1, s2: S2) -> fuso::Result<()>
+where
+    S1: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S2: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    async fn copy<R, W>(mut reader: R, mut writer: W) -> fuso::Result<()>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut buf = vec![0u8; 1500];
+        loop {
+            let n = reader.read(&mut buf).await?;
+            if n == 0 {
+                let _ = writer.flush().await;
+                return writer.close().await;
+            }
+            writer.write_all(&buf[..n]).await?;
+        }
+    }
+
+    let (r1, w1) = io::split(s1);
+    let (r2, w2) = io::split(s2);
+
+    tokio::select! {
+        r = copy(r1, w2) => r,
+        r = copy(r2, w1) => r,
+    }
+}
+
+async fn transfer(size: usize, legacy: bool) {
+    let (mut client, s1) = pair().await;
+    let (s2, mut server) = pair().await;
+
+    let forward = tokio::spawn(async move {
+        if legacy {
+            legacy_forward(s1, s2).await
+        } else {
+            io::forward(s1, s2).await
+        }
+    });
+
+    let writer = tokio::spawn(async move {
+        let chunk = vec![0x66u8; 64 * 1024];
+        let mut remaining = size;
+        while remaining > 0 {
+            let n = remaining.min(chunk.len());
+            tokio::io::AsyncWriteExt::write_all(&mut client, &chunk[..n])
+                .await
+                .unwrap();
+            remaining -= n;
+        }
+        client
+    });
+
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut received = 0;
+    while received < size {
+        let n = tokio::io::AsyncReadExt::read(&mut server, &mut buf)
+            .await
+            .unwrap();
+        assert!(n > 0);
+        received += n;
+    }
+
+    drop(writer.await.unwrap());
+    drop(server);
+    let _ = forward.await;
+}
+
+fn bench_forward(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let mut group = c.benchmark_group("forward");
+
+    for size in SIZES {
+        group.throughput(Throughput::Bytes(size as u64));
+
+        group.bench_with_input(BenchmarkId::new("legacy", size), &size, |b, &size| {
+            b.to_async(&runtime).iter(|| transfer(size, true))
+        });
+
+        group.bench_with_input(BenchmarkId::new("forward", size), &size, |b, &size| {
+            b.to_async(&runtime).iter(|| transfer(size, false))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_forward);
+criterion_main!(benches);
@@ -1,11 +1,34 @@
-use std::{future::Future, ops::Deref, pin::Pin, sync::Arc, task::Poll};
-
-use crate::{
-    ext::{AsyncReadExt, AsyncWriteExt},
-    AsyncRead, AsyncWrite,
+use std::{
+    future::Future,
+    ops::Deref,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
 };
 
-type BoxedFuture = Pin<Box<dyn Future<Output = crate::Result<()>> + Send + 'static>>;
+use crate::{current_runtime, ready, AsyncRead, AsyncWrite, ReadBuf};
+
+#[cfg(all(target_os = "linux", feature = "fuso-splice"))]
+use super::splice::{self, Splice};
+
+/// 初始的转发缓冲区, 读满后逐步扩大
+const MIN_BUFFER_SIZE: usize = 8 * 1024;
+const MAX_BUFFER_SIZE: usize = 64 * 1024;
+
+/// 一个方向结束后等待另一个方向结束的最长时间
+pub const DEFAULT_LINGER: Duration = Duration::from_secs(30);
+
+macro_rules! poll_direction {
+    ($transfer: expr, $poll: expr) => {
+        if !$transfer.done {
+            if let Poll::Ready(Err(e)) = $poll {
+                log::warn!("forward error {}", e);
+                return Poll::Ready(Err(e));
+            }
+        }
+    };
+}
 
 macro_rules! unwrap {
     ($r: expr) => {
@@ -16,8 +39,33 @@ macro_rules! unwrap {
     };
 }
 
-pub struct Forward {
-    futures: Vec<BoxedFuture>,
+/// 双向转发, 两端直接由同一个任务轮询, 不需要拆分与加锁
+///
+/// 一个方向读到EOF后, 如果对端支持只关闭写入方向 (见 `AsyncWrite::is_half_closable`) 则关闭它,
+/// 否则只刷出数据, 另一个方向继续转发; 两个方向都结束或者等待超过 `linger` 后完成
+///
+/// 开启 `fuso-splice` 时, 如果两端都是原始的tcp连接, 在linux上通过 `splice(2)` 转发
+pub struct Forward<S1, S2> {
+    s1: S1,
+    s2: S2,
+    upload: Transfer,
+    download: Transfer,
+    linger: Duration,
+    linger_timer: Option<Pin<Box<dyn Future<Output = ()> + Send + 'static>>>,
+    #[cfg(all(target_os = "linux", feature = "fuso-splice"))]
+    splice: Option<(Splice, Splice)>,
+}
+
+/// 单个方向的转发状态
+struct Transfer {
+    buf: Vec<u8>,
+    pos: usize,
+    cap: usize,
+    grow: bool,
+    eof: bool,
+    need_flush: bool,
+    done: bool,
+    amount: u64,
 }
 
 pub struct Inner<S>(std::sync::Mutex<S>);
@@ -26,84 +74,182 @@ pub struct ReadHalf<R>(Arc<Inner<R>>);
 
 pub struct WriteHalf<W>(Arc<Inner<W>>);
 
-impl Future for Forward {
-    type Output = crate::Result<()>;
+impl Transfer {
+    fn new() -> Self {
+        Self {
+            buf: vec![0; MIN_BUFFER_SIZE],
+            pos: 0,
+            cap: 0,
+            grow: false,
+            eof: false,
+            need_flush: false,
+            done: false,
+            amount: 0,
+        }
+    }
 
-    fn poll(
-        mut self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> std::task::Poll<Self::Output> {
-        let mut futures = Vec::new();
-        while let Some(mut future) = self.futures.pop() {
-            match Pin::new(&mut future).poll(cx) {
-                Poll::Pending => futures.push(future),
-                Poll::Ready(r) => {
-                    return Poll::Ready(r);
+    fn poll_transfer<R, W>(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut reader: Pin<&mut R>,
+        mut writer: Pin<&mut W>,
+    ) -> Poll<crate::Result<()>>
+    where
+        R: AsyncRead,
+        W: AsyncWrite,
+    {
+        loop {
+            if self.pos == self.cap && !self.eof {
+                let mut buf = ReadBuf::new(&mut self.buf);
+                match reader.as_mut().poll_read(cx, &mut buf) {
+                    Poll::Pending => {
+                        // 没有更多数据时把已写入的数据刷出去
+                        if self.need_flush {
+                            ready!(writer.as_mut().poll_flush(cx))?;
+                            self.need_flush = false;
+                        }
+
+                        return Poll::Pending;
+                    }
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    Poll::Ready(Ok(0)) => {
+                        log::trace!("forward eof after {}bytes", self.amount);
+                        self.eof = true;
+                    }
+                    Poll::Ready(Ok(n)) => {
+                        log::trace!("forward {}bytes data", n);
+                        self.grow = n == self.buf.len() && self.buf.len() < MAX_BUFFER_SIZE;
+                        self.pos = 0;
+                        self.cap = n;
+                    }
+                }
+            }
+
+            while self.pos < self.cap {
+                let n = ready!(writer
+                    .as_mut()
+                    .poll_write(cx, &self.buf[self.pos..self.cap]))?;
+
+                if n == 0 {
+                    return Poll::Ready(Err(Into::<std::io::Error>::into(
+                        std::io::ErrorKind::WriteZero,
+                    )
+                    .into()));
+                }
+
+                self.pos += n;
+                self.amount += n as u64;
+                self.need_flush = true;
+            }
+
+            if self.grow {
+                self.grow = false;
+                self.buf.resize(self.buf.len() * 2, 0);
+            }
+
+            if self.eof {
+                ready!(writer.as_mut().poll_flush(cx))?;
+                // 只关闭写入方向, 对端仍然可以继续发送数据; 不支持时由两个方向都结束后的drop关闭
+                if writer.is_half_closable() {
+                    ready!(writer.as_mut().poll_close(cx))?;
                 }
+                self.done = true;
+                return Poll::Ready(Ok(()));
             }
         }
+    }
+}
 
-        drop(std::mem::replace(&mut self.futures, futures));
+impl<S1, S2> Forward<S1, S2> {
+    /// 已转发的字节数, (s1 -> s2, s2 -> s1)
+    pub fn transferred(&self) -> (u64, u64) {
+        #[cfg(all(target_os = "linux", feature = "fuso-splice"))]
+        if let Some((upload, download)) = &self.splice {
+            return (upload.amount, download.amount);
+        }
 
-        if self.futures.is_empty() {
-            Poll::Ready(Ok(()))
-        } else {
-            Poll::Pending
+        (self.upload.amount, self.download.amount)
+    }
+
+    /// 一个方向结束后等待另一个方向结束的最长时间
+    pub fn with_linger(mut self, linger: Duration) -> Self {
+        self.linger = linger;
+        self
+    }
+
+    /// 只剩一个方向时开始计时, 超时后不再等待
+    fn poll_linger(&mut self, cx: &mut Context<'_>, done: (bool, bool)) -> Poll<crate::Result<()>> {
+        match done {
+            (true, true) => Poll::Ready(Ok(())),
+            (false, false) => Poll::Pending,
+            _ => {
+                let linger = self.linger;
+                let timer = self
+                    .linger_timer
+                    .get_or_insert_with(|| current_runtime().sleep(linger));
+
+                ready!(timer.as_mut().poll(cx));
+                log::debug!("the other direction is still open after {:?}", linger);
+
+                Poll::Ready(Ok(()))
+            }
         }
     }
 }
 
-pub fn forward<S1, S2>(s1: S1, s2: S2) -> Forward
+impl<S1, S2> Future for Forward<S1, S2>
 where
-    S1: AsyncRead + AsyncWrite + Unpin + Send + 'static,
-    S2: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S1: AsyncRead + AsyncWrite + Unpin + 'static,
+    S2: AsyncRead + AsyncWrite + Unpin + 'static,
 {
-    let (s1_reader, s1_writer) = split(s1);
-    let (s2_reader, s2_writer) = split(s2);
+    type Output = crate::Result<()>;
 
-    fn copy<R, W>(mut reader: R, mut writer: W) -> BoxedFuture
-    where
-        R: AsyncRead + Unpin + Send + 'static,
-        W: AsyncWrite + Unpin + Send + 'static,
-    {
-        Box::pin(async move {
-            let mut buf = unsafe {
-                let mut buf = Vec::with_capacity(1500);
-                buf.set_len(1500);
-                buf
-            };
-
-            loop {
-                let r = reader.read(&mut buf).await;
-
-                if r.is_err() {
-                    return Err(unsafe { r.unwrap_err_unchecked() });
-                }
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
 
-                let n = unsafe { r.unwrap_unchecked() };
+        #[cfg(all(target_os = "linux", feature = "fuso-splice"))]
+        if let Some((upload, download)) = &mut this.splice {
+            poll_direction!(upload, upload.poll_transfer(cx, &mut this.s1, &mut this.s2));
+            poll_direction!(
+                download,
+                download.poll_transfer(cx, &mut this.s2, &mut this.s1)
+            );
 
-                if n == 0 {
-                    let _ = writer.flush().await;
-                    return writer.close().await;
-                }
+            let done = (upload.done, download.done);
+            return this.poll_linger(cx, done);
+        }
 
-                log::trace!("forward {}bytes data", n);
+        poll_direction!(
+            this.upload,
+            this.upload
+                .poll_transfer(cx, Pin::new(&mut this.s1), Pin::new(&mut this.s2))
+        );
 
-                let r = writer.write_all(&buf[..n]).await;
+        poll_direction!(
+            this.download,
+            this.download
+                .poll_transfer(cx, Pin::new(&mut this.s2), Pin::new(&mut this.s1))
+        );
 
-                if r.is_err() {
-                    return Err(unsafe {
-                        let err = r.unwrap_err_unchecked();
-                        log::warn!("forward error {}", err);
-                        err
-                    });
-                }
-            }
-        })
+        let done = (this.upload.done, this.download.done);
+        this.poll_linger(cx, done)
     }
+}
 
+pub fn forward<S1, S2>(s1: S1, s2: S2) -> Forward<S1, S2>
+where
+    S1: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S2: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
     Forward {
-        futures: vec![copy(s1_reader, s2_writer), copy(s2_reader, s1_writer)],
+        #[cfg(all(target_os = "linux", feature = "fuso-splice"))]
+        splice: splice::new_pair(&s1, &s2),
+        s1,
+        s2,
+        upload: Transfer::new(),
+        download: Transfer::new(),
+        linger: DEFAULT_LINGER,
+        linger_timer: None,
     }
 }
 
@@ -150,6 +296,10 @@ where
         Pin::new(&mut *inner).poll_close(cx)
     }
 
+    fn is_half_closable(&self) -> bool {
+        self.0.lock().is_ok_and(|inner| inner.is_half_closable())
+    }
+
     fn poll_flush(
         self: Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
@@ -179,3 +329,151 @@ impl<T> Clone for WriteHalf<T> {
         Self(self.0.clone())
     }
 }
+
+#[cfg(test)]
+#[cfg(feature = "fuso-rt-tokio")]
+mod tests {
+    use std::{
+        pin::Pin,
+        task::{Context, Poll},
+        time::Duration,
+    };
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use crate::{testing::tcp::pair, AsyncRead, AsyncWrite, ReadBuf};
+
+    /// 关闭时关闭整个连接, 如同kcp或加密后的连接
+    struct Whole(TcpStream);
+
+    impl AsyncRead for Whole {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<crate::Result<usize>> {
+            AsyncRead::poll_read(Pin::new(&mut self.0), cx, buf)
+        }
+    }
+
+    impl AsyncWrite for Whole {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<crate::Result<usize>> {
+            AsyncWrite::poll_write(Pin::new(&mut self.0), cx, buf)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<crate::Result<()>> {
+            AsyncWrite::poll_flush(Pin::new(&mut self.0), cx)
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<crate::Result<()>> {
+            panic!("should not close the whole stream")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_half_close() {
+        let (mut a, s1) = pair().await;
+        let (s2, mut b) = pair().await;
+
+        let forward = tokio::spawn(super::forward(s1, s2));
+
+        a.write_all(b"hello").await.unwrap();
+        a.shutdown().await.unwrap();
+
+        let mut buf = Vec::new();
+        b.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"hello");
+
+        // a 关闭写入后仍然能收到 b 的数据
+        b.write_all(b"world").await.unwrap();
+        b.shutdown().await.unwrap();
+
+        let mut buf = Vec::new();
+        a.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"world");
+
+        forward.await.unwrap().unwrap();
+    }
+
+    /// 不支持只关闭写入方向时不关闭, 另一个方向继续转发
+    #[tokio::test]
+    async fn test_whole_close() {
+        let (mut a, s1) = pair().await;
+        let (s2, mut b) = pair().await;
+
+        let forward = tokio::spawn(super::forward(s1, Whole(s2)));
+
+        a.write_all(b"hello").await.unwrap();
+        a.shutdown().await.unwrap();
+
+        let mut buf = [0u8; 5];
+        b.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        b.write_all(b"world").await.unwrap();
+        b.shutdown().await.unwrap();
+
+        let mut buf = Vec::new();
+        a.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"world");
+
+        forward.await.unwrap().unwrap();
+    }
+
+    /// 一个方向结束后另一个方向迟迟不结束时不再等待
+    #[tokio::test]
+    async fn test_linger() {
+        let (mut a, s1) = pair().await;
+        let (s2, mut b) = pair().await;
+
+        let forward = super::forward(s1, s2).with_linger(Duration::from_millis(100));
+        let forward = tokio::spawn(forward);
+
+        a.shutdown().await.unwrap();
+
+        let mut buf = Vec::new();
+        b.read_to_end(&mut buf).await.unwrap();
+
+        tokio::time::timeout(Duration::from_secs(5), forward)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+
+        let mut buf = Vec::new();
+        a.read_to_end(&mut buf).await.unwrap();
+        assert!(buf.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_large_transfer() {
+        let (mut a, s1) = pair().await;
+        let (s2, mut b) = pair().await;
+
+        let forward = tokio::spawn(super::forward(s1, s2));
+
+        let data = (0..4 * 1024 * 1024).map(|i| i as u8).collect::<Vec<_>>();
+        let expect = data.clone();
+
+        let writer = tokio::spawn(async move {
+            a.write_all(&data).await.unwrap();
+            a.shutdown().await.unwrap();
+            a
+        });
+
+        let mut buf = Vec::new();
+        b.read_to_end(&mut buf).await.unwrap();
+        assert!(buf == expect);
+
+        drop(b);
+        drop(writer.await.unwrap());
+        forward.await.unwrap().unwrap();
+    }
+}
@@ -3,6 +3,8 @@ pub mod io;
 pub mod join;
 pub mod r#macro;
 pub mod select;
+#[cfg(all(target_os = "linux", feature = "fuso-splice"))]
+pub mod splice;
 pub mod sync;
 pub mod time;
 
@@ -17,13 +19,8 @@ use crate::NetSocket;
 
 pub type BoxedFuture<'lifetime, T> = Pin<Box<dyn Future<Output = T> + 'lifetime>>;
 
-#[cfg(feature = "fuso-rt-smol")]
-mod smol_io {
-    pub use smol::io::{AsyncRead, AsyncWrite};
-}
-
-#[cfg(feature = "fuso-rt-custom")]
-mod io {
+#[cfg(any(feature = "fuso-rt-smol", feature = "fuso-rt-custom"))]
+mod futures_io {
     pub use futures::io::{AsyncRead, AsyncWrite};
 }
 
@@ -57,6 +54,11 @@ pub trait AsyncWrite {
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<crate::Result<()>>;
 
     fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<crate::Result<()>>;
+
+    /// `poll_close` 只关闭写入方向时返回true, 关闭后仍然可以读取对端的数据
+    fn is_half_closable(&self) -> bool {
+        false
+    }
 }
 
 pub trait Stream: NetSocket + AsyncRead + AsyncWrite + Unpin {}
@@ -87,6 +89,10 @@ impl AsyncWrite for Box<dyn Stream + Send> {
     fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<crate::Result<()>> {
         Pin::new(&mut **self).poll_close(cx)
     }
+
+    fn is_half_closable(&self) -> bool {
+        (**self).is_half_closable()
+    }
 }
 
 impl<S> Stream for S where S: NetSocket + AsyncWrite + AsyncRead + Unpin {}
@@ -114,6 +120,12 @@ where
     fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<crate::Result<()>> {
         tokio::io::AsyncWrite::poll_shutdown(self, cx).map_err(Into::into)
     }
+
+    /// tokio的 `poll_shutdown` 只关闭写入方向
+    #[inline]
+    fn is_half_closable(&self) -> bool {
+        true
+    }
 }
 
 #[cfg(feature = "fuso-rt-tokio")]
@@ -138,7 +150,7 @@ where
 #[cfg(any(feature = "fuso-rt-smol", feature = "fuso-rt-custom"))]
 impl<T> AsyncWrite for T
 where
-    T: smol_io::AsyncWrite,
+    T: futures_io::AsyncWrite,
 {
     #[inline]
     fn poll_write(
@@ -158,12 +170,18 @@ where
     fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<crate::Result<()>> {
         futures::AsyncWrite::poll_close(self, cx).map_err(Into::into)
     }
+
+    /// smol的 `TcpStream` 关闭时同样只关闭写入方向
+    #[inline]
+    fn is_half_closable(&self) -> bool {
+        true
+    }
 }
 
 #[cfg(any(feature = "fuso-rt-smol", feature = "fuso-rt-custom"))]
 impl<T> AsyncRead for T
 where
-    T: smol_io::AsyncRead,
+    T: futures_io::AsyncRead,
 {
     #[inline]
     fn poll_read(
@@ -194,10 +212,16 @@ impl<'a> ReadBuf<'a> {
         Self { buf, offset: 0 }
     }
 
+    #[cfg(feature = "fuso-rt-tokio")]
     pub fn len(&self) -> usize {
         self.buf.capacity()
     }
 
+    #[cfg(any(feature = "fuso-rt-smol", feature = "fuso-rt-custom"))]
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
     #[cfg(any(feature = "fuso-rt-smol", feature = "fuso-rt-custom"))]
     pub fn remaining(&self) -> usize {
         self.len() - self.offset
@@ -227,6 +251,11 @@ impl<'a> ReadBuf<'a> {
         &mut self.buf[..self.offset]
     }
 
+    #[cfg(any(feature = "fuso-rt-smol", feature = "fuso-rt-custom"))]
+    pub fn filled(&self) -> &[u8] {
+        &self.buf[..self.offset]
+    }
+
     #[cfg(any(feature = "fuso-rt-smol", feature = "fuso-rt-custom"))]
     pub fn advance(&mut self, n: usize) {
         debug_assert!(self.offset + n <= self.buf.len());
@@ -0,0 +1,225 @@
+use std::{
+    any::Any,
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::{ready, AsyncWrite, FusoStream, NetSocket};
+
+/// 管道的默认容量, 每次最多搬运这么多数据
+const PIPE_SIZE: usize = 64 * 1024;
+
+/// 可以直接通过 `splice(2)` 读写的原始socket
+pub trait RawSocket {
+    fn as_raw_fd(&self) -> RawFd;
+
+    fn poll_read_ready(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+
+    fn poll_write_ready(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+
+    /// 执行读取操作, 返回 `WouldBlock` 时清除就绪状态
+    fn try_read_io(&self, f: &mut dyn FnMut() -> io::Result<usize>) -> io::Result<usize>;
+
+    /// 执行写入操作, 返回 `WouldBlock` 时清除就绪状态
+    fn try_write_io(&self, f: &mut dyn FnMut() -> io::Result<usize>) -> io::Result<usize>;
+}
+
+/// 只有未经过加密、压缩等包装的 `FusoStream` 才能使用splice
+pub fn as_raw_socket<S: 'static>(stream: &S) -> Option<&dyn RawSocket> {
+    (stream as &dyn Any)
+        .downcast_ref::<FusoStream>()?
+        .as_raw_socket()
+}
+
+/// 两端都是原始socket时为每个方向创建一个管道
+pub(crate) fn new_pair<S1: 'static, S2: 'static>(s1: &S1, s2: &S2) -> Option<(Splice, Splice)> {
+    as_raw_socket(s1)?;
+    as_raw_socket(s2)?;
+
+    match Splice::new().and_then(|upload| Ok((upload, Splice::new()?))) {
+        Ok(pair) => {
+            log::trace!("forward with splice");
+            Some(pair)
+        }
+        Err(e) => {
+            log::debug!("failed to create pipe, fallback to copy, {}", e);
+            None
+        }
+    }
+}
+
+/// 单个方向的零拷贝转发, socket -> pipe -> socket
+pub(crate) struct Splice {
+    pipe_rd: OwnedFd,
+    pipe_wr: OwnedFd,
+    buffered: usize,
+    eof: bool,
+    pub(crate) done: bool,
+    pub(crate) amount: u64,
+}
+
+impl Splice {
+    pub(crate) fn new() -> io::Result<Self> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(unsafe {
+            Self {
+                pipe_rd: OwnedFd::from_raw_fd(fds[0]),
+                pipe_wr: OwnedFd::from_raw_fd(fds[1]),
+                buffered: 0,
+                eof: false,
+                done: false,
+                amount: 0,
+            }
+        })
+    }
+
+    pub(crate) fn poll_transfer<R, W>(
+        &mut self,
+        cx: &mut Context<'_>,
+        reader: &mut R,
+        writer: &mut W,
+    ) -> Poll<crate::Result<()>>
+    where
+        R: 'static,
+        W: AsyncWrite + Unpin + 'static,
+    {
+        let (rd, wr) = match (as_raw_socket(reader), as_raw_socket(writer)) {
+            (Some(rd), Some(wr)) => (rd, wr),
+            _ => return Poll::Ready(Err(io::Error::from(io::ErrorKind::Unsupported).into())),
+        };
+
+        loop {
+            // 管道清空之后才继续读取, 这样 EAGAIN 只可能来自socket
+            while self.buffered > 0 {
+                ready!(wr.poll_write_ready(cx))?;
+
+                let fd = wr.as_raw_fd();
+                let n = match wr
+                    .try_write_io(&mut || splice(self.pipe_rd.as_raw_fd(), fd, self.buffered))
+                {
+                    Ok(0) => {
+                        return Poll::Ready(Err(io::Error::from(io::ErrorKind::WriteZero).into()))
+                    }
+                    Ok(n) => n,
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                    Err(e) => return Poll::Ready(Err(e.into())),
+                };
+
+                self.buffered -= n;
+                self.amount += n as u64;
+            }
+
+            if self.eof {
+                break;
+            }
+
+            ready!(rd.poll_read_ready(cx))?;
+
+            let fd = rd.as_raw_fd();
+            match rd.try_read_io(&mut || splice(fd, self.pipe_wr.as_raw_fd(), PIPE_SIZE)) {
+                Ok(0) => {
+                    log::trace!("splice eof after {}bytes", self.amount);
+                    self.eof = true;
+                }
+                Ok(n) => self.buffered = n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Poll::Ready(Err(e.into())),
+            }
+        }
+
+        // 只关闭写入方向, 对端仍然可以继续发送数据
+        ready!(Pin::new(writer).poll_close(cx))?;
+        self.done = true;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn splice(fd_in: RawFd, fd_out: RawFd, len: usize) -> io::Result<usize> {
+    let n = unsafe {
+        libc::splice(
+            fd_in,
+            std::ptr::null_mut(),
+            fd_out,
+            std::ptr::null_mut(),
+            len,
+            libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK,
+        )
+    };
+
+    if n < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(n as usize)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "fuso-rt-tokio")]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use crate::{compress::Lz4Compress, io, testing::tcp::pair, FusoStream};
+
+    #[tokio::test]
+    async fn test_splice_forward() {
+        let (mut a, s1) = pair().await;
+        let (s2, mut b) = pair().await;
+
+        let (s1, s2) = (FusoStream::new(s1), FusoStream::new(s2));
+        assert!(super::as_raw_socket(&s1).is_some());
+        assert!(super::new_pair(&s1, &s2).is_some());
+
+        let data = (0..4 * 1024 * 1024)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<u8>>();
+
+        let forward = tokio::spawn(async move {
+            let mut forward = io::forward(s1, s2);
+            (&mut forward).await.map(|_| forward.transferred())
+        });
+
+        let writer = {
+            let data = data.clone();
+            tokio::spawn(async move {
+                a.write_all(&data).await.unwrap();
+                a.shutdown().await.unwrap();
+                a
+            })
+        };
+
+        let mut buf = Vec::new();
+        b.read_to_end(&mut buf).await.unwrap();
+        assert!(buf == data);
+
+        // 一个方向结束后另一个方向仍然可以转发
+        b.write_all(b"world").await.unwrap();
+        b.shutdown().await.unwrap();
+
+        let mut a = writer.await.unwrap();
+        let mut buf = Vec::new();
+        a.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"world");
+
+        let transferred = forward.await.unwrap().unwrap();
+        assert_eq!(transferred, (data.len() as u64, 5));
+    }
+
+    #[tokio::test]
+    async fn test_wrapped_stream() {
+        let (s1, _s2) = pair().await;
+        let s1 = FusoStream::new(Lz4Compress::new(s1));
+        assert!(super::as_raw_socket(&s1).is_none());
+
+        // 只识别 FusoStream
+        let (s1, s2) = pair().await;
+        assert!(super::as_raw_socket(&s1).is_none());
+        assert!(super::new_pair(&s1, &FusoStream::new(s2)).is_none());
+    }
+}
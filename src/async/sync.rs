@@ -3,4 +3,7 @@
 pub use smol::lock::Mutex;
 
 #[cfg(feature = "fuso-rt-tokio")]
-pub use tokio::sync::Mutex;
\ No newline at end of file
+pub use tokio::sync::Mutex;
+
+#[cfg(feature = "fuso-rt-custom")]
+pub use async_mutex::Mutex;
\ No newline at end of file
@@ -1,34 +1,60 @@
-use std::{future::Future, time::Duration};
+use std::{fmt::Display, future::Future, task::Poll, time::Duration};
 
-#[cfg(feature = "fuso-rt-tokio")]
-pub async fn wait_for<F, O>(timeout: Duration, fut: F) -> crate::Result<O>
-where
-    F: Future<Output = O> + Send + 'static,
-    O: Send + 'static,
-{
-    Ok(tokio::time::timeout(timeout, fut).await?)
-}
+use crate::{current_runtime, Kind};
 
-#[cfg(feature = "fuso-rt-tokio")]
-pub async fn sleep(timeout: Duration) {
-    tokio::time::sleep(timeout).await
+/// 等待超时, 与具体的运行时无关
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+impl Display for Elapsed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "deadline has elapsed")
+    }
 }
 
-#[cfg(feature = "fuso-rt-smol")]
 pub async fn wait_for<F, O>(timeout: Duration, fut: F) -> crate::Result<O>
 where
-    F: Future<Output = O> + Send + 'static,
-    O: Send + 'static,
+    F: Future<Output = O> + Send,
+    O: Send,
 {
-    use crate::error;
+    let mut fut = std::pin::pin!(fut);
+    let mut timer = current_runtime().sleep(timeout);
 
-    smol::future::race(async move { Ok(fut.await) }, async move {
-        Err(error::Kind::Timeout(smol::Timer::after(timeout).await).into())
+    std::future::poll_fn(|cx| {
+        if let Poll::Ready(output) = fut.as_mut().poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+
+        match timer.as_mut().poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(Kind::Timeout(Elapsed).into())),
+            Poll::Pending => Poll::Pending,
+        }
     })
     .await
 }
 
-#[cfg(feature = "fuso-rt-smol")]
 pub async fn sleep(timeout: Duration) {
-    let _ = smol::Timer::after(timeout).await;
+    current_runtime().sleep(timeout).await
+}
+
+#[cfg(test)]
+#[cfg(feature = "fuso-rt-tokio")]
+mod tests {
+    use std::time::Duration;
+
+    use crate::Kind;
+
+    #[tokio::test]
+    async fn test_wait_for() {
+        let r = super::wait_for(Duration::from_millis(100), async { 1 }).await;
+        assert_eq!(r.unwrap(), 1);
+
+        let r = super::wait_for(
+            Duration::from_millis(10),
+            super::sleep(Duration::from_secs(10)),
+        )
+        .await;
+
+        assert!(matches!(r.unwrap_err().kind(), Kind::Timeout(_)));
+    }
 }
@@ -37,15 +37,113 @@ pub struct FusoArgs {
     /// 发送心跳延时
     #[clap(long, default_value = "30")]
     heartbeat_delay: u64,
-    /// 最大等待读取时间
-    #[clap(long, default_value = "5")]
-    maximum_rtime: u64,
-    /// 最大等待写入时间
-    #[clap(long, default_value = "5")]
-    maximum_wtime: u64,
+    /// 转发连接的最大空闲时间, 未指定时不限制
+    #[clap(long)]
+    maximum_rtime: Option<u64>,
+    /// 转发连接写入阻塞的最大时间, 未指定时不限制
+    #[clap(long)]
+    maximum_wtime: Option<u64>,
     /// 最大等待建立连接时间
     #[clap(long, default_value = "10")]
     maximum_wctime: u64,
+    /// 关闭时等待转发结束的时间
+    #[clap(long, default_value = "10")]
+    grace_period: u64,
+    /// 允许接收的最大帧(字节), 超过时断开连接
+    #[clap(long, default_value = "1048576")]
+    max_frame_size: usize,
+    /// kcp模式: normal, fast, turbo
+    #[clap(long, default_value = "fast")]
+    kcp_mode: fuso::kcp::KcpConfig,
+    /// kcp mtu
+    #[clap(long)]
+    kcp_mtu: Option<usize>,
+    /// kcp收发窗口大小
+    #[clap(long)]
+    kcp_wnd: Option<u16>,
+    /// kcp使用流模式
+    #[clap(long)]
+    kcp_stream: bool,
+    /// 使用quic代替kcp接受客户端连接, 两者占用同一端口的udp, 需要指定 quic-cert 与 quic-key
+    #[cfg(feature = "fuso-quic")]
+    #[clap(long)]
+    quic: bool,
+    /// quic服务端证书链, PEM或DER编码, 客户端可以使用 --quic-cert 固定该证书
+    #[cfg(feature = "fuso-quic")]
+    #[clap(long)]
+    quic_cert: Option<String>,
+    /// quic服务端私钥, PEM或DER编码的 PKCS#8
+    #[cfg(feature = "fuso-quic")]
+    #[clap(long)]
+    quic_key: Option<String>,
+    /// udp打洞服务端口, 同名的p2p客户端经由该端口交换地址, 未指定时不启用p2p
+    #[clap(long)]
+    p2p_port: Option<u16>,
+    /// 信任的负载均衡网段, 来自这些地址的tcp连接需要以PROXY协议头(v1/v2)开始, 可多次指定
+    #[clap(long)]
+    proxy_protocol_trusted: Vec<fuso::Cidr>,
+    /// 只允许这些网段访问映射的端口, 优先于客户端的规则, 可多次指定
+    #[clap(long)]
+    visitor_allow: Vec<fuso::Cidr>,
+    /// 拒绝这些网段访问映射的端口, 优先于客户端的规则, 可多次指定
+    #[clap(long)]
+    visitor_deny: Vec<fuso::Cidr>,
+    /// 允许多个客户端绑定同一个端口并分配访问者: round-robin, least-conn, sticky
+    #[clap(long)]
+    balance: Option<fuso::penetrate::Balance>,
+    /// 映射名称已被其他客户端使用时: reject 拒绝, rename 追加序号
+    #[clap(long, default_value = "reject")]
+    name_conflict: fuso::penetrate::NameConflict,
+    /// 直连模式允许访问的目标, 如 10.0.0.0/8:22, *.example.com, 可多次指定, 未指定时不启用直连模式
+    #[cfg(feature = "fuso-proxy")]
+    #[clap(long)]
+    proxy_allow: Vec<fuso::proxy::Rule>,
+    /// tun设备名称, 需要自行配置地址与路由
+    #[cfg(all(target_os = "linux", feature = "fuso-tun", feature = "fuso-rt-tokio"))]
+    #[clap(long, default_value = "fuso0")]
+    tun_name: String,
+    /// tun客户端需要提供的令牌, 未指定时不启用tun, 同一时间只服务一个客户端
+    #[cfg(all(target_os = "linux", feature = "fuso-tun", feature = "fuso-rt-tokio"))]
+    #[clap(long)]
+    tun_token: Option<String>,
+}
+
+impl FusoArgs {
+    fn kcp_config(&self) -> fuso::kcp::KcpConfig {
+        let mut config = self.kcp_mode.clone();
+
+        if let Some(mtu) = self.kcp_mtu {
+            config.mtu = mtu;
+        }
+
+        if let Some(wnd) = self.kcp_wnd {
+            config.snd_wnd = wnd;
+            config.rcv_wnd = wnd;
+        }
+
+        config.stream = self.kcp_stream;
+
+        config
+    }
+
+    fn visitor_acl(&self) -> fuso::Acl {
+        fuso::Acl {
+            allow: self.visitor_allow.clone(),
+            deny: self.visitor_deny.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "fuso-quic")]
+impl FusoArgs {
+    fn quic_accepter(&self) -> fuso::Result<fuso::quic::QuicAccepterProvider> {
+        match (&self.quic_cert, &self.quic_key) {
+            (Some(cert), Some(key)) => fuso::quic::QuicAccepterProvider::load(cert, key),
+            _ => {
+                Err(fuso::Kind::Message("--quic requires --quic-cert and --quic-key".into()).into())
+            }
+        }
+    }
 }
 
 fn init_logger(log_level: log::LevelFilter) {
@@ -67,24 +165,113 @@ fn init_logger(log_level: log::LevelFilter) {
 #[cfg(feature = "fuso-rt-tokio")]
 #[tokio::main]
 async fn main() -> fuso::Result<()> {
-    use fuso::{Socket, TokioExecutor, TokioUdpServerProvider, UdpForwardProvider};
-    use std::time::Duration;
+    use fuso::{TokioExecutor, TokioUdpServerProvider};
 
     let args = FusoArgs::parse();
 
     init_logger(args.log_level);   
 
-    fuso::builder_server_with_tokio()
-        .with_kcp_accepter(TokioUdpServerProvider, TokioExecutor)
+    fuso::protocol::set_max_frame_size(args.max_frame_size);
+
+    #[cfg(feature = "fuso-quic")]
+    if args.quic {
+        let builder = fuso::builder_server_with_tokio().with_quic_accepter(args.quic_accepter()?);
+        return serve(args, builder).await;
+    }
+
+    let builder = fuso::builder_server_with_tokio().with_kcp_accepter(
+        TokioUdpServerProvider,
+        args.kcp_config(),
+        TokioExecutor,
+    );
+
+    serve(args, builder).await
+}
+
+/// kcp与quic的监听类型不同, 其余的配置相同
+#[cfg(feature = "fuso-rt-tokio")]
+async fn serve<SF, A>(
+    args: FusoArgs,
+    builder: fuso::server::ServerBuilder<
+        fuso::TokioExecutor,
+        SF,
+        fuso::TokioConnector,
+        fuso::FusoStream,
+    >,
+) -> fuso::Result<()>
+where
+    SF: fuso::Provider<
+            fuso::Socket,
+            Output = std::pin::Pin<
+                Box<dyn std::future::Future<Output = fuso::Result<A>> + Send + 'static>,
+            >,
+        > + Send
+        + Sync
+        + 'static,
+    A: fuso::Accepter<Stream = fuso::FusoStream> + Unpin + Send + 'static,
+{
+    use fuso::{Socket, UdpForwardProvider};
+    use std::time::Duration;
+
+    let shutdown = fuso::Shutdown::default();
+
+    shutdown.listen_signal();
+
+    let p2p = match args.p2p_port {
+        None => None,
+        Some(port) => {
+            let tickets = fuso::p2p::Tickets::default();
+            let udp = tokio::net::UdpSocket::bind((args.listen, port)).await?;
+            tokio::spawn(fuso::p2p::RendezvousServer::new(udp, tickets.clone()).run());
+            Some(fuso::p2p::P2pService::new(port, tickets))
+        }
+    };
+
+    let penetrate = builder
+        .with_proxy_protocol(args.proxy_protocol_trusted.clone())
         .with_penetrate()
+        .visitor_acl(args.visitor_acl())
+        .name_conflict(args.name_conflict);
+
+    let penetrate = match args.balance {
+        None => penetrate,
+        Some(balance) => penetrate.with_balance(balance),
+    };
+
+    let penetrate = match p2p {
+        None => penetrate,
+        Some(p2p) => penetrate.with_p2p(p2p),
+    };
+
+    #[cfg(feature = "fuso-proxy")]
+    let penetrate = match args.proxy_allow.is_empty() {
+        true => penetrate,
+        false => penetrate.with_forward_proxy(args.proxy_allow.into_iter().collect()),
+    };
+
+    #[cfg(all(target_os = "linux", feature = "fuso-tun", feature = "fuso-rt-tokio"))]
+    let penetrate = match args.tun_token.as_deref() {
+        None => penetrate,
+        Some(token) => {
+            let device = fuso::tun::TunDevice::open(&args.tun_name)?;
+            log::info!("tun {} enabled", device.name());
+            penetrate.with_tun(fuso::tun::TunService::new(device, token))
+        }
+    };
+
+    penetrate
         .max_wait_time(Duration::from_secs(args.maximum_wctime))
         .heartbeat_timeout(Duration::from_secs(args.heartbeat_delay))
+        .read_timeout(args.maximum_rtime.map(Duration::from_secs))
+        .write_timeout(args.maximum_wtime.map(Duration::from_secs))
         .with_adapter_mode()
         .with_normal_unpacker()
         .with_socks_unpacker()
         .with_udp_forward(UdpForwardProvider)
         .build()
         .bind(Socket::tcp((args.listen, args.port)))
+        .with_shutdown(shutdown)
+        .with_grace_period(Duration::from_secs(args.grace_period))
         .run()
         .await
         .expect("server start failed");
@@ -100,27 +287,79 @@ async fn main() {}
 #[tokio::main]
 async fn main() {}
 
+/// 自定义运行时需要由使用者通过 `fuso::install_runtime` 提供, 命令行程序无法使用
+#[cfg(feature = "fuso-rt-custom")]
+fn main() {
+    eprintln!("fuso-rt-custom has no built-in runtime, use fuso as a library instead");
+}
+
 #[cfg(feature = "fuso-rt-smol")]
 fn main() -> fuso::Result<()> {
-    use fuso::{Handshake, Socket};
+    use fuso::{SmolExecutor, SmolUdpForwardProvider, SmolUdpServerProvider, SmolUdpSocket, Socket};
+    use std::time::Duration;
 
-    env_logger::builder()
-        .filter_module("fuso", log::LevelFilter::Trace)
-        .default_format()
-        .format_module_path(false)
-        .init();
+    let args = FusoArgs::parse();
+
+    init_logger(args.log_level);
+
+    fuso::protocol::set_max_frame_size(args.max_frame_size);
 
     smol::block_on(async move {
-        fuso::builder_server_with_smol()
-            .with_handshake(Handshake)
+        let shutdown = fuso::Shutdown::default();
+
+        shutdown.listen_signal();
+
+        let p2p = match args.p2p_port {
+            None => None,
+            Some(port) => {
+                let tickets = fuso::p2p::Tickets::default();
+                let udp = SmolUdpSocket::bind((args.listen, port))?;
+                smol::spawn(fuso::p2p::RendezvousServer::new(udp, tickets.clone()).run()).detach();
+                Some(fuso::p2p::P2pService::new(port, tickets))
+            }
+        };
+
+        let penetrate = fuso::builder_server_with_smol()
+            .with_kcp_accepter(SmolUdpServerProvider, args.kcp_config(), SmolExecutor)
+            .with_proxy_protocol(args.proxy_protocol_trusted.clone())
             .with_penetrate()
+            .visitor_acl(args.visitor_acl())
+            .name_conflict(args.name_conflict);
+
+        let penetrate = match args.balance {
+            None => penetrate,
+            Some(balance) => penetrate.with_balance(balance),
+        };
+
+        let penetrate = match p2p {
+            None => penetrate,
+            Some(p2p) => penetrate.with_p2p(p2p),
+        };
+
+        #[cfg(feature = "fuso-proxy")]
+        let penetrate = match args.proxy_allow.is_empty() {
+            true => penetrate,
+            false => penetrate.with_forward_proxy(args.proxy_allow.into_iter().collect()),
+        };
+
+        penetrate
+            .max_wait_time(Duration::from_secs(args.maximum_wctime))
+            .heartbeat_timeout(Duration::from_secs(args.heartbeat_delay))
+            .read_timeout(args.maximum_rtime.map(Duration::from_secs))
+            .write_timeout(args.maximum_wtime.map(Duration::from_secs))
             .with_adapter_mode()
-            .use_normal()
-            .use_socks()
+            .with_normal_unpacker()
+            .with_socks_unpacker()
+            .with_udp_forward(SmolUdpForwardProvider)
             .build()
-            .bind(Socket::Tcp(([0, 0, 0, 0], 8888).into()))
+            .bind(Socket::tcp((args.listen, args.port)))
+            .with_shutdown(shutdown)
+            .with_grace_period(Duration::from_secs(args.grace_period))
             .run()
             .await
+            .expect("server start failed");
+
+        Ok(())
     })
 }
 
@@ -0,0 +1,282 @@
+use std::sync::Arc;
+
+use crate::{
+    io,
+    protocol::{
+        AsyncRecvPacket, AsyncSendPacket, Bind, Hello, Poto, ToPacket, TryToPoto, PROTOCOL_VERSION,
+    },
+    Accepter, AccepterExt, Address, ClientProvider, Executor, Provider, Socket, Stream,
+};
+
+use super::BoxedFuture;
+
+/// 桥接, 接收下游客户端的连接并转发到服务端.
+/// 服务端返回的监听地址会被替换为桥接端新开的端口, 下游客户端的映射连接经由该端口转发到服务端,
+/// 因此桥接端可以继续作为其他桥接端的上游
+pub struct Bridge<E, A, CF> {
+    executor: Arc<E>,
+    accepter_provider: Arc<A>,
+    client_provider: ClientProvider<CF>,
+}
+
+impl<E, A, CF> Bridge<E, A, CF> {
+    pub fn new(executor: E, accepter_provider: A, client_provider: ClientProvider<CF>) -> Self {
+        Self {
+            executor: Arc::new(executor),
+            accepter_provider: Arc::new(accepter_provider),
+            client_provider,
+        }
+    }
+}
+
+impl<E, A, CF, L, S> Bridge<E, A, CF>
+where
+    E: Executor + Send + Sync + 'static,
+    A: Provider<Socket, Output = BoxedFuture<L>> + Send + Sync + 'static,
+    L: Accepter<Stream = S> + Unpin + Send + 'static,
+    CF: Provider<Socket, Output = BoxedFuture<S>> + Send + Sync + 'static,
+    S: Stream + Send + 'static,
+{
+    pub async fn run<T: Into<Socket>>(self, bind: T) -> crate::Result<()> {
+        let bind = bind.into();
+        let mut accepter = self.accepter_provider.call(bind.clone()).await?;
+
+        log::info!(
+            "the bridge listens on {}, server is {}",
+            accepter.local_addr()?,
+            self.client_provider.default_socket()
+        );
+
+        loop {
+            let stream = accepter.accept().await?;
+
+            if let Ok(addr) = stream.peer_addr() {
+                log::debug!("bridge connection from {}", addr);
+            }
+
+            let this = self.clone();
+            let bind = bind.clone();
+
+            self.executor.spawn(async move {
+                if let Err(e) = this.relay(bind, stream).await {
+                    log::warn!("bridge relay error {}", e);
+                }
+            });
+        }
+    }
+
+    async fn relay(self, bind: Socket, mut downstream: S) -> crate::Result<()> {
+        let mut packet = downstream.recv_packet().await?;
+
+        let mut upstream = self
+            .client_provider
+            .connect(self.client_provider.default_socket().clone())
+            .await?;
+
+        if let Ok(Poto::Hello(Hello::Hello(mut version))) = packet.clone().try_message() {
+            // 之后的 Bind 需要由桥接端改写, 不能协商出桥接端不认识的协议
+            version.protocol = version.protocol.min(PROTOCOL_VERSION);
+
+            upstream
+                .send_packet(&Poto::Hello(Hello::Hello(version)).to_packet_vec())
+                .await?;
+
+            let reply = upstream.recv_packet().await?;
+            let accepted = matches!(
+                reply.clone().try_message(),
+                Ok(Poto::Hello(Hello::Accept(_)))
+            );
+
+            downstream.send_packet(&reply.encode()).await?;
+
+            if !accepted {
+                return io::forward(downstream, upstream).await;
+            }
+
+            packet = downstream.recv_packet().await?;
+        }
+
+        let is_bind = matches!(
+            packet.clone().try_message(),
+            Ok(Poto::Bind(
+                Bind::Bind(_) | Bind::Filter(..) | Bind::Named(..)
+            ))
+        );
+
+        upstream.send_packet(&packet.encode()).await?;
+
+        // 映射连接与其他连接直接转发
+        if !is_bind {
+            return io::forward(downstream, upstream).await;
+        }
+
+        let message = upstream.recv_packet().await?.try_message()?;
+
+        let (mut remote_bind, name) = match message {
+            Poto::Bind(Bind::Bind(remote_bind)) => (remote_bind, None),
+            Poto::Bind(Bind::Named(name, remote_bind, _)) => (remote_bind, Some(name)),
+            message => {
+                downstream.send_packet(&message.to_packet_vec()).await?;
+                return io::forward(downstream, upstream).await;
+            }
+        };
+
+        if remote_bind.is_ip_unspecified() {
+            remote_bind.from_set_host(self.client_provider.default_socket());
+        }
+
+        let mut local_bind = bind;
+        local_bind.set_port(0);
+
+        let mut accepter = self.accepter_provider.call(local_bind).await?;
+
+        let port = match accepter.local_addr()? {
+            Address::Single(socket) => socket.port(),
+            Address::Many(_) => return Err(crate::Kind::Unexpected("many address".into()).into()),
+        };
+
+        log::info!("bridge {} to {}", port, remote_bind);
+
+        let mut bridge_bind = remote_bind.clone();
+        bridge_bind.set_ip([0, 0, 0, 0]);
+        bridge_bind.set_port(port);
+
+        let message = match name {
+            Some(name) => Bind::Named(name, bridge_bind, Default::default()),
+            None => Bind::Bind(bridge_bind),
+        };
+
+        downstream
+            .send_packet(&Poto::Bind(message).to_packet_vec())
+            .await?;
+
+        let client_provider = self.client_provider.clone();
+        let executor = self.executor.clone();
+
+        let mut task = self.executor.spawn(async move {
+            loop {
+                let stream = match accepter.accept().await {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        log::warn!("bridge accept error {}", e);
+                        break;
+                    }
+                };
+
+                let client_provider = client_provider.clone();
+                let remote_bind = remote_bind.clone();
+
+                executor.spawn(async move {
+                    match client_provider.connect(remote_bind).await {
+                        Ok(upstream) => {
+                            let _ = io::forward(stream, upstream).await;
+                        }
+                        Err(e) => {
+                            log::warn!("bridge failed to connect to server {}", e);
+                        }
+                    }
+                });
+            }
+        });
+
+        let r = io::forward(downstream, upstream).await;
+
+        task.abort();
+
+        r
+    }
+}
+
+impl<E, A, CF> Clone for Bridge<E, A, CF> {
+    fn clone(&self) -> Self {
+        Self {
+            executor: self.executor.clone(),
+            accepter_provider: self.accepter_provider.clone(),
+            client_provider: self.client_provider.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "fuso-rt-tokio")]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use crate::{
+        testing, Provider, Socket, TokioAccepter, TokioPenetrateConnector, TokioTcpListener,
+    };
+
+    use super::BoxedFuture;
+
+    /// 记录桥接端创建的监听地址
+    #[derive(Default, Clone)]
+    struct Recorder(Arc<Mutex<Vec<Socket>>>);
+
+    impl Provider<Socket> for Recorder {
+        type Output = BoxedFuture<TokioTcpListener>;
+
+        fn call(&self, socket: Socket) -> Self::Output {
+            self.0.lock().unwrap().push(socket.clone());
+            TokioAccepter.call(socket)
+        }
+    }
+
+    /// 客户端经由桥接端握手并以名称注册, 映射连接应通过桥接端新开的端口到达服务端
+    #[tokio::test]
+    async fn test_bridge_rewrite_bind() {
+        let (builder, listened) = crate::builder_server_with_tokio().listening();
+        let penetrate = builder.with_penetrate();
+        let registry = penetrate.registry();
+
+        let server = testing::serve(listened, move |bind| {
+            penetrate
+                .with_adapter_mode()
+                .with_normal_unpacker()
+                .build()
+                .bind(bind)
+                .run()
+        })
+        .await;
+
+        let recorder = Recorder::default();
+        let (accepter, listened) = testing::listening(recorder.clone());
+
+        let bridge = crate::builder_client_with_tokio().build_bridge(server, accepter);
+
+        tokio::spawn(bridge.run(Socket::tcp(([127, 0, 0, 1], 0))));
+
+        let bridge = listened.addr().await;
+        let service = testing::echo().await;
+
+        testing::spawn(move || async move {
+            crate::builder_client_with_tokio()
+                .using_penetrate(Socket::tcp(([127, 0, 0, 1], 0)), Socket::tcp(service))
+                .name(Some(String::from("web")))
+                .build(bridge, TokioPenetrateConnector::new().await.unwrap())
+                .run()
+                .await
+        });
+
+        let mapping = testing::until(|| registry.get("web")).await;
+
+        // 桥接端自身的监听与为该客户端改写的端口
+        let sockets = recorder.0.lock().unwrap().clone();
+        assert_eq!(sockets.len(), 2);
+        assert_eq!(sockets[1].port(), 0);
+
+        let mut visitor = TcpStream::connect(("127.0.0.1", mapping.port))
+            .await
+            .unwrap();
+
+        visitor.write_all(b"hello world!").await.unwrap();
+
+        let mut buf = [0u8; 12];
+        visitor.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello world!");
+    }
+}
@@ -2,9 +2,10 @@ use std::sync::Arc;
 
 use crate::{
     generator::Generator, ClientProvider, Executor, Provider, ProviderTransfer, Fuso, Socket, Stream,
+    DEFAULT_GRACE_PERIOD,
 };
 
-use super::{BoxedFuture, Client};
+use super::{BoxedFuture, Bridge, Client};
 
 pub struct ClientBuilder<E, CF, S> {
     pub(crate) executor: E,
@@ -31,6 +32,75 @@ where
             executor: Arc::new(self.executor),
             handshake: self.handshake,
             client_provider: self.client_provider.set_server_socket(socket),
+            shutdown: Default::default(),
+            grace_period: DEFAULT_GRACE_PERIOD,
+            reconnect: Default::default(),
         })
     }
+
+    /// 以桥接模式运行, 将下游客户端的连接转发到 `socket`
+    pub fn build_bridge<A: Into<Socket>, AP>(
+        self,
+        socket: A,
+        accepter_provider: AP,
+    ) -> Bridge<E, AP, CF> {
+        Bridge::new(
+            self.executor,
+            accepter_provider,
+            self.client_provider.set_server_socket(socket.into()),
+        )
+    }
+
+    /// 以直连模式运行, 本地连接均由服务端 `socket` 代为连接到 `target`
+    #[cfg(feature = "fuso-proxy")]
+    pub fn build_forward_proxy<A: Into<Socket>, AP>(
+        self,
+        socket: A,
+        target: crate::Addr,
+        accepter_provider: AP,
+    ) -> crate::proxy::ForwardProxy<E, AP, CF, S> {
+        crate::proxy::ForwardProxy::new(
+            target,
+            self.executor,
+            accepter_provider,
+            self.handshake,
+            self.client_provider.set_server_socket(socket.into()),
+        )
+    }
+
+    /// 经由服务端 `socket` 与同名的p2p客户端打洞, `udp_provider` 用于创建打洞的udp
+    #[cfg(feature = "fuso-kcp")]
+    pub fn build_p2p<A: Into<Socket>, U>(
+        self,
+        socket: A,
+        name: String,
+        udp_provider: U,
+    ) -> crate::p2p::P2pClient<E, CF, S, U> {
+        crate::p2p::P2pClient::new(
+            name,
+            self.executor,
+            udp_provider,
+            self.handshake,
+            self.client_provider.set_server_socket(socket.into()),
+        )
+    }
+
+    /// 经由服务端 `socket` 的tun设备访问服务端所在的网络, 到达的连接由 `connector` 连接原始的目标地址
+    #[cfg(feature = "fuso-tun")]
+    pub fn build_tun<A: Into<Socket>, C>(
+        self,
+        socket: A,
+        token: String,
+        addr: std::net::Ipv4Addr,
+        connector: C,
+    ) -> crate::tun::TunClient<E, CF, S, C> {
+        crate::tun::TunClient::new(
+            token,
+            addr,
+            self.executor,
+            connector,
+            self.handshake,
+            self.client_provider.set_server_socket(socket.into()),
+        )
+    }
 }
@@ -1,13 +1,26 @@
+mod bridge;
 mod builder;
+mod reconnect;
+mod transport;
+mod upstream;
 
-use std::{future::Future, pin::Pin, sync::Arc, time::Duration};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
+pub use bridge::*;
 pub use builder::*;
+pub use reconnect::*;
+pub use transport::*;
+pub use upstream::*;
 
 use crate::{
     generator::{Generator, GeneratorEx},
-    time, ClientProvider, Executor, Provider, ProviderTransfer, ProviderWrapper, Fuso, Serve, Socket,
-    Stream,
+    time, ClientProvider, Executor, Fuso, Kind, Provider, ProviderTransfer, ProviderWrapper,
+    Serve, Shutdown, Socket, Stream, TaskTracker,
 };
 
 pub type BoxedFuture<T> = Pin<Box<dyn Future<Output = crate::Result<T>> + Send + 'static>>;
@@ -23,6 +36,9 @@ pub struct Client<E, H, CF, S> {
     pub(crate) handler: Arc<H>,
     pub(crate) handshake: Option<ProviderTransfer<S>>,
     pub(crate) client_provider: ClientProvider<CF>,
+    pub(crate) shutdown: Shutdown,
+    pub(crate) grace_period: Duration,
+    pub(crate) reconnect: Reconnect,
 }
 
 impl<E, H, CF, S, G> Client<E, H, CF, S>
@@ -37,56 +53,113 @@ where
         let executor = self.executor;
         let provider = self.client_provider.clone();
         let handshake = self.handshake;
+        let shutdown = self.shutdown;
+        let tasks = TaskTracker::default();
+        let reconnect = self.reconnect;
+        let mut retries = 0;
 
-        loop {
+        let r = loop {
             let socket = self.socket.clone();
 
-            let stream = match self.client_provider.connect(socket).await {
-                Ok(stream) => {
-                    log::info!("connection established");
-                    stream
-                }
-                Err(e) => {
-                    log::warn!("connect to {} failed err: {}", self.socket, e);
-                    time::sleep(Duration::from_secs(2)).await;
-                    continue;
-                }
-            };
+            let session = async {
+                let stream = time::wait_for(
+                    reconnect.connect_timeout,
+                    self.client_provider.connect(socket),
+                )
+                .await??;
+
+                log::info!("connection established");
 
-            let stream = match handshake.as_ref() {
-                Some(handshake) => handshake.call(stream).await,
-                None => Ok(stream),
+                let stream = match handshake.as_ref() {
+                    Some(handshake) => time::wait_for(
+                        reconnect.connect_timeout,
+                        handshake.call(stream),
+                    )
+                    .await
+                    .and_then(|r| r)
+                    .map_err(|e| {
+                        log::error!("handshake failed {}", e);
+                        e
+                    })?,
+                    None => stream,
+                };
+
+                self.handler.call((provider.clone(), stream)).await
             };
 
-            let stream = match stream {
-                Ok(stream) => stream,
-                Err(e) => {
-                    log::error!("handshake failed {}", e);
-                    break Ok(());
+            let mut generate = match shutdown.run_until(session).await {
+                None => break Ok(()),
+                Some(Ok(generate)) => generate,
+                Some(Err(e)) if matches!(e.kind(), Kind::Incompatible(_)) => {
+                    log::error!("{}", e);
+                    break Err(e);
                 }
-            };
+                Some(Err(e)) => {
+                    log::warn!("connect to {} failed err: {}", self.socket, e);
 
-            let mut generate = match self.handler.call((provider.clone(), stream)).await {
-                Ok(generate) => generate,
-                Err(e) => {
-                    log::warn!("{}", e);
-                    continue;
+                    match reconnect.next_delay(&mut retries) {
+                        None => {
+                            log::error!("give up after {} retries", retries);
+                            break Err(e);
+                        }
+                        Some(delay) => {
+                            log::info!("reconnect in {:?}", delay);
+                            shutdown.run_until(time::sleep(delay)).await;
+                            continue;
+                        }
+                    }
                 }
             };
 
-            loop {
-                match generate.next().await {
-                    Ok(None) => break,
-                    Ok(Some(fut)) => {
-                        executor.spawn(fut);
+            let established = Instant::now();
+
+            let closed = loop {
+                match shutdown.run_until(generate.next()).await {
+                    None => {
+                        log::debug!("notify the server to close");
+                        let _ = generate.close().await;
+                        break None;
+                    }
+                    Some(Ok(None)) => break Some(None),
+                    Some(Ok(Some(fut))) => {
+                        tasks.spawn(&*executor, fut);
                     }
-                    Err(e) => {
+                    Some(Err(e)) => {
                         log::error!("{}", e);
-                        break;
+                        break Some(Some(e));
                     }
                 }
+            };
+
+            let closed = match closed {
+                None => break Ok(()),
+                Some(closed) => closed,
+            };
+
+            // 会话维持足够久才认为连接已恢复, 否则服务端反复断开时会不停地立即重连
+            if established.elapsed() >= reconnect.min_uptime {
+                retries = 0;
             }
-        }
+
+            match reconnect.next_delay(&mut retries) {
+                None => {
+                    log::error!("give up after {} retries", retries);
+                    break Err(closed.unwrap_or_else(|| {
+                        Kind::Message(String::from("the server keeps closing the session")).into()
+                    }));
+                }
+                Some(delay) => {
+                    log::info!("session closed, reconnect in {:?}", delay);
+                    shutdown.run_until(time::sleep(delay)).await;
+                }
+            }
+        };
+
+        log::info!("the client is shutting down, {} tasks are running", tasks.len());
+
+        tasks.drain(self.grace_period).await;
+
+        r
     }
 }
 
@@ -103,4 +176,26 @@ where
             fut: Box::pin(self.0.run()),
         })
     }
+
+    /// 使用外部的关闭信号, 多个客户端可以共享同一个信号
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.0.shutdown = shutdown;
+        self
+    }
+
+    /// 关闭时等待转发结束的时间, 超时后取消剩余的转发
+    pub fn with_grace_period(mut self, grace_period: Duration) -> Self {
+        self.0.grace_period = grace_period;
+        self
+    }
+
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.0.shutdown.clone()
+    }
+
+    /// 设置断开后的重连策略
+    pub fn with_reconnect(mut self, reconnect: Reconnect) -> Self {
+        self.0.reconnect = reconnect;
+        self
+    }
 }
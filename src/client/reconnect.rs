@@ -0,0 +1,193 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+/// 客户端与服务端断开后的重连策略, 等待时间按指数增长并加入随机抖动
+#[derive(Debug, Clone)]
+pub struct Reconnect {
+    /// 第一次重连前的等待时间
+    pub delay: Duration,
+    /// 等待时间的上限
+    pub max_delay: Duration,
+    /// 连续失败的最大重试次数, 为None时不限制
+    pub max_retries: Option<usize>,
+    /// 建立连接与握手的超时时间
+    pub connect_timeout: Duration,
+    /// 会话维持超过该时间后才重置重试次数
+    pub min_uptime: Duration,
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        Self {
+            delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            max_retries: None,
+            connect_timeout: Duration::from_secs(10),
+            min_uptime: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Reconnect {
+    /// 第 `retries` 次重试前需要等待的时间, 在 `[backoff / 2, backoff]` 之间随机,
+    /// 避免大量客户端在服务端重启后同时重连
+    pub fn backoff(&self, retries: usize) -> Duration {
+        let backoff = self
+            .delay
+            .saturating_mul(1 << retries.min(16))
+            .min(self.max_delay);
+
+        let half = backoff / 2;
+
+        half + half.mul_f64(random())
+    }
+
+    /// 返回下次重连前的等待时间并增加重试次数, 超过重试次数时返回None
+    pub fn next_delay(&self, retries: &mut usize) -> Option<Duration> {
+        if matches!(self.max_retries, Some(max_retries) if *retries >= max_retries) {
+            return None;
+        }
+
+        let delay = self.backoff(*retries);
+
+        *retries += 1;
+
+        Some(delay)
+    }
+}
+
+/// `[0, 1)` 之间的随机数, 每个 `RandomState` 的密钥都不相同
+fn random() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Reconnect;
+
+    #[test]
+    fn test_backoff() {
+        let reconnect = Reconnect {
+            delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(8),
+            max_retries: Some(5),
+            connect_timeout: Duration::from_secs(1),
+            min_uptime: Duration::from_secs(1),
+        };
+
+        for (retries, expect) in [1, 2, 4, 8, 8, 8].into_iter().enumerate() {
+            let backoff = reconnect.backoff(retries);
+            let expect = Duration::from_secs(expect);
+            assert!(backoff >= expect / 2 && backoff <= expect, "{:?}", backoff);
+        }
+
+        let mut retries = 0;
+
+        while reconnect.next_delay(&mut retries).is_some() {}
+
+        assert_eq!(retries, 5);
+    }
+
+    /// 服务端不可用时, 超过重试次数后客户端返回错误
+    #[cfg(feature = "fuso-rt-tokio")]
+    #[tokio::test]
+    async fn test_give_up() {
+        use crate::{Socket, TokioPenetrateConnector};
+
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let r = tokio::time::timeout(
+            Duration::from_secs(5),
+            crate::builder_client_with_tokio()
+                .using_penetrate(
+                    Socket::tcp(([127, 0, 0, 1], 0)),
+                    Socket::tcp(([127, 0, 0, 1], 0)),
+                )
+                .reconnect_delay(Duration::from_millis(10))
+                .maximum_retries(Some(2))
+                .build(
+                    Socket::tcp(([127, 0, 0, 1], port)),
+                    TokioPenetrateConnector::new().await.unwrap(),
+                )
+                .run(),
+        )
+        .await;
+
+        assert!(r.unwrap().is_err());
+    }
+
+    /// 服务端完成绑定后立即断开, 会话过短时不重置重试次数
+    #[cfg(feature = "fuso-rt-tokio")]
+    #[tokio::test]
+    async fn test_short_session() {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        use crate::{
+            protocol::{self, AsyncRecvPacket, AsyncSendPacket, Hello, Poto, ToPacket, TryToPoto},
+            Socket, TokioPenetrateConnector,
+        };
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let sessions = Arc::new(AtomicUsize::new(0));
+
+        tokio::spawn({
+            let sessions = sessions.clone();
+            async move {
+                loop {
+                    let (mut tcp, _) = listener.accept().await.unwrap();
+
+                    let remote = match tcp.recv_packet().await.unwrap().try_message().unwrap() {
+                        Poto::Hello(Hello::Hello(version)) => version,
+                        message => panic!("{}", message),
+                    };
+
+                    protocol::accept_hello(&mut tcp, remote).await.unwrap();
+
+                    let bind = match tcp.recv_packet().await.unwrap().try_message().unwrap() {
+                        Poto::Bind(bind) => bind,
+                        message => panic!("{}", message),
+                    };
+
+                    let message = Poto::Bind(bind).to_packet_vec();
+                    tcp.send_packet(&message).await.unwrap();
+
+                    sessions.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        });
+
+        let r = tokio::time::timeout(
+            Duration::from_secs(5),
+            crate::builder_client_with_tokio()
+                .using_penetrate(
+                    Socket::tcp(([127, 0, 0, 1], 0)),
+                    Socket::tcp(([127, 0, 0, 1], 0)),
+                )
+                .reconnect_delay(Duration::from_millis(10))
+                .maximum_retries(Some(2))
+                .build(
+                    Socket::tcp(([127, 0, 0, 1], port)),
+                    TokioPenetrateConnector::new().await.unwrap(),
+                )
+                .run(),
+        )
+        .await;
+
+        assert!(r.unwrap().is_err());
+        assert_eq!(sessions.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,38 @@
+use std::{fmt::Display, str::FromStr};
+
+/// 客户端连接服务端时使用的传输方式, 同时作用于控制连接与映射连接
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    #[default]
+    Tcp,
+    Kcp,
+    /// 优先使用tcp, 连接失败后使用kcp
+    PreferTcp,
+    /// 优先使用kcp, 若kcp始终收不到服务端的响应, 下次连接时改用tcp
+    PreferKcp,
+}
+
+impl FromStr for Transport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "tcp" => Self::Tcp,
+            "kcp" => Self::Kcp,
+            "auto" | "prefer-tcp" => Self::PreferTcp,
+            "prefer-kcp" => Self::PreferKcp,
+            _ => return Err(format!("unknown transport {}", s)),
+        })
+    }
+}
+
+impl Display for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Tcp => "tcp",
+            Self::Kcp => "kcp",
+            Self::PreferTcp => "prefer-tcp",
+            Self::PreferKcp => "prefer-kcp",
+        })
+    }
+}
@@ -9,6 +9,12 @@ pub trait NetSocket {
     fn peer_addr(&self) -> crate::Result<Address>;
 
     fn local_addr(&self) -> crate::Result<Address>;
+
+    /// 原始的tcp连接, 经过加密、压缩等包装的连接不能使用splice
+    #[cfg(all(target_os = "linux", feature = "fuso-splice"))]
+    fn as_raw_socket(&self) -> Option<&dyn crate::splice::RawSocket> {
+        None
+    }
 }
 
 pub trait Accepter: NetSocket {
@@ -18,6 +24,10 @@ pub trait Accepter: NetSocket {
 
 pub struct AccepterWrapper<S>(Box<dyn Accepter<Stream = S> + Send + Unpin + 'static>);
 
+/// 可以在任务间共享的udp socket
+#[derive(Clone)]
+pub struct UdpSocketWrapper(Arc<dyn UdpSocket + Send + Sync + Unpin + 'static>);
+
 pub trait UdpSocket: NetSocket {
     fn poll_recv_from(
         self: Pin<&Self>,
@@ -260,3 +270,53 @@ where
         Pin::new(&mut *self.0).poll_accept(cx)
     }
 }
+
+impl UdpSocketWrapper {
+    pub fn wrap<U>(udp: U) -> Self
+    where
+        U: UdpSocket + Send + Sync + Unpin + 'static,
+    {
+        UdpSocketWrapper(Arc::new(udp))
+    }
+}
+
+impl NetSocket for UdpSocketWrapper {
+    fn local_addr(&self) -> crate::Result<Address> {
+        self.0.local_addr()
+    }
+
+    fn peer_addr(&self) -> crate::Result<Address> {
+        self.0.peer_addr()
+    }
+}
+
+impl UdpSocket for UdpSocketWrapper {
+    fn poll_recv_from(
+        self: Pin<&Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<SocketAddr>> {
+        Pin::new(&*self.0).poll_recv_from(cx, buf)
+    }
+
+    fn poll_send(self: Pin<&Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        Pin::new(&*self.0).poll_send(cx, buf)
+    }
+
+    fn poll_recv(
+        self: Pin<&Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        Pin::new(&*self.0).poll_recv(cx, buf)
+    }
+
+    fn poll_send_to(
+        self: Pin<&Self>,
+        cx: &mut Context<'_>,
+        addr: &SocketAddr,
+        buf: &[u8],
+    ) -> Poll<Result<usize>> {
+        Pin::new(&*self.0).poll_send_to(cx, addr, buf)
+    }
+}
@@ -27,6 +27,11 @@ impl NetSocket for FusoStream {
     fn local_addr(&self) -> crate::Result<Address> {
         self.0.local_addr()
     }
+
+    #[cfg(all(target_os = "linux", feature = "fuso-splice"))]
+    fn as_raw_socket(&self) -> Option<&dyn crate::splice::RawSocket> {
+        self.0.as_raw_socket()
+    }
 }
 
 impl AsyncWrite for FusoStream {
@@ -51,6 +56,10 @@ impl AsyncWrite for FusoStream {
     ) -> std::task::Poll<crate::Result<()>> {
         Pin::new(&mut *self.0).poll_close(cx)
     }
+
+    fn is_half_closable(&self) -> bool {
+        self.0.is_half_closable()
+    }
 }
 
 impl AsyncRead for FusoStream {
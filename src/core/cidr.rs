@@ -0,0 +1,215 @@
+use std::{fmt::Display, net::IpAddr, str::FromStr};
+
+#[cfg(feature = "fuso-serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::InvalidAddr;
+
+/// ip网段, 如 `10.0.0.0/8`, `fd00::/8`, 单个ip视为完整的前缀
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "fuso-serde",
+    derive(Deserialize, Serialize),
+    serde(try_from = "(IpAddr, u8)", into = "(IpAddr, u8)")
+)]
+pub struct Cidr {
+    ip: IpAddr,
+    prefix: u8,
+}
+
+/// 访问者的ip黑白名单, 先匹配 `deny` 再匹配 `allow`
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "fuso-serde", derive(Deserialize, Serialize))]
+pub struct Acl {
+    pub allow: Vec<Cidr>,
+    pub deny: Vec<Cidr>,
+}
+
+impl Cidr {
+    pub fn new(ip: IpAddr, prefix: u8) -> crate::Result<Self> {
+        if prefix > Self::max_prefix(&ip) {
+            return Err(InvalidAddr::Domain(format!("{}/{}", ip, prefix)).into());
+        }
+
+        Ok(Self { ip, prefix })
+    }
+
+    pub fn host(ip: IpAddr) -> Self {
+        Self {
+            ip,
+            prefix: Self::max_prefix(&ip),
+        }
+    }
+
+    pub fn is_ipv4(&self) -> bool {
+        self.ip.is_ipv4()
+    }
+
+    pub fn ip(&self) -> IpAddr {
+        self.ip
+    }
+
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    fn max_prefix(ip: &IpAddr) -> u8 {
+        if ip.is_ipv4() {
+            32
+        } else {
+            128
+        }
+    }
+
+    /// ipv4网段同样匹配ipv4映射的ipv6地址
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        fn mask(bits: u32, prefix: u8) -> u128 {
+            match prefix {
+                0 => 0,
+                prefix => u128::MAX << (bits - prefix as u32),
+            }
+        }
+
+        match (self.ip, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = mask(32, self.prefix) as u32;
+                u32::from(net) & mask == u32::from(*ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = mask(128, self.prefix);
+                u128::from(net) & mask == u128::from(*ip) & mask
+            }
+            (IpAddr::V4(_), IpAddr::V6(ip)) => match ip.to_ipv4_mapped() {
+                Some(ip) => self.contains(&IpAddr::V4(ip)),
+                None => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidAddr::Domain(s.to_string());
+
+        match s.split_once('/') {
+            None => Ok(Self::host(s.parse().map_err(|_| invalid())?)),
+            Some((ip, prefix)) => Self::new(
+                ip.parse().map_err(|_| invalid())?,
+                prefix.parse().map_err(|_| invalid())?,
+            ),
+        }
+    }
+}
+
+impl TryFrom<(IpAddr, u8)> for Cidr {
+    type Error = crate::Error;
+
+    fn try_from((ip, prefix): (IpAddr, u8)) -> Result<Self, Self::Error> {
+        Self::new(ip, prefix)
+    }
+}
+
+impl From<Cidr> for (IpAddr, u8) {
+    fn from(cidr: Cidr) -> Self {
+        (cidr.ip, cidr.prefix)
+    }
+}
+
+impl Acl {
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// 命中 `deny` 返回 `Some(false)`, 命中 `allow` 返回 `Some(true)`, 都未命中返回 `None`
+    pub fn matches(&self, ip: &IpAddr) -> Option<bool> {
+        if self.deny.iter().any(|cidr| cidr.contains(ip)) {
+            Some(false)
+        } else if self.allow.iter().any(|cidr| cidr.contains(ip)) {
+            Some(true)
+        } else {
+            None
+        }
+    }
+
+    /// 未命中任何规则时, 只有 `allow` 为空才放行
+    pub fn is_allowed(&self, ip: &IpAddr) -> bool {
+        self.matches(ip).unwrap_or(self.allow.is_empty())
+    }
+}
+
+impl Display for Acl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let join = |list: &[Cidr]| {
+            list.iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+
+        write!(f, "allow=[{}], deny=[{}]", join(&self.allow), join(&self.deny))
+    }
+}
+
+impl Display for Cidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.ip, self.prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::{Acl, Cidr};
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn test_cidr() {
+        let cidr: Cidr = "10.0.0.0/8".parse().unwrap();
+        assert!(cidr.contains(&ip("10.255.0.1")));
+        assert!(cidr.contains(&ip("::ffff:10.0.0.1")));
+        assert!(!cidr.contains(&ip("11.0.0.1")));
+
+        let cidr: Cidr = "fd00::/8".parse().unwrap();
+        assert!(cidr.contains(&ip("fd12::1")));
+        assert!(!cidr.contains(&ip("fe80::1")));
+        assert!(!cidr.contains(&ip("10.0.0.1")));
+
+        let cidr: Cidr = "192.168.1.10".parse().unwrap();
+        assert!(cidr.contains(&ip("192.168.1.10")));
+        assert!(!cidr.contains(&ip("192.168.1.11")));
+        assert_eq!(cidr.to_string(), "192.168.1.10/32");
+
+        assert!("0.0.0.0/0".parse::<Cidr>().unwrap().contains(&ip("1.2.3.4")));
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("10.0.0/8".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn test_acl() {
+        let acl = Acl {
+            allow: vec!["10.0.0.0/8".parse().unwrap()],
+            deny: vec!["10.0.1.0/24".parse().unwrap()],
+        };
+
+        assert!(acl.is_allowed(&ip("10.0.0.1")));
+        assert!(!acl.is_allowed(&ip("10.0.1.1")));
+        assert!(!acl.is_allowed(&ip("192.168.1.1")));
+        assert_eq!(acl.matches(&ip("192.168.1.1")), None);
+
+        let acl = Acl {
+            allow: vec![],
+            deny: vec!["192.168.0.0/16".parse().unwrap()],
+        };
+
+        assert!(acl.is_allowed(&ip("10.0.0.1")));
+        assert!(!acl.is_allowed(&ip("192.168.1.1")));
+        assert!(Acl::default().is_allowed(&ip("::1")));
+    }
+}
@@ -429,6 +429,7 @@ where
 }
 
 #[cfg(test)]
+#[cfg(feature = "fuso-rt-tokio")]
 #[allow(unused)]
 mod tests {
 
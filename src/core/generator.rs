@@ -5,10 +5,17 @@ use std::task::{Context, Poll};
 
 pub struct Generate<'a, G>(&'a mut G);
 
+pub struct Close<'a, G>(&'a mut G);
+
 pub trait Generator {
     type Output;
 
     fn poll_generate(self: Pin<&mut Self>, cx: &mut Context) -> Poll<crate::Result<Self::Output>>;
+
+    /// 关闭前通知对端, 默认不做任何处理
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context) -> Poll<crate::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
 }
 
 pub trait GeneratorEx: Generator {
@@ -18,6 +25,13 @@ pub trait GeneratorEx: Generator {
     {
         Generate(self)
     }
+
+    fn close<'a>(&'a mut self) -> Close<'a, Self>
+    where
+        Self: Sized,
+    {
+        Close(self)
+    }
 }
 
 impl<'a, G> Future for Generate<'a, G>
@@ -31,4 +45,15 @@ where
     }
 }
 
+impl<'a, G> Future for Close<'a, G>
+where
+    G: Generator + Unpin,
+{
+    type Output = crate::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> std::task::Poll<Self::Output> {
+        Pin::new(&mut *self.0).poll_close(cx)
+    }
+}
+
 impl<T> GeneratorEx for T where T: Generator + Unpin{}
\ No newline at end of file
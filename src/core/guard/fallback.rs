@@ -164,6 +164,10 @@ where
     ) -> std::task::Poll<crate::Result<()>> {
         Pin::new(&mut self.target).poll_close(cx)
     }
+
+    fn is_half_closable(&self) -> bool {
+        self.target.is_half_closable()
+    }
 }
 
 impl<'a, IO> Future for Mark<'a, IO>
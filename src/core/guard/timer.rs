@@ -1,76 +1,60 @@
-use std::cell::RefCell;
-use std::rc::Rc;
-use std::{future::Future, pin::Pin, task::Poll, time::Duration};
-
-use crate::{AsyncRead, AsyncWrite, BoxedFuture};
-
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use crate::{current_runtime, error::IdleErr, ready, AsyncRead, AsyncWrite};
+
+type Sleep = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+/// 读写超时
+///
+/// `read_timeout`: 连接在该时间内没有任何读写活动时视为空闲, 读取返回 [`IdleErr::Read`]
+///
+/// `write_timeout`: 单次写入阻塞超过该时间视为对端停止接收, 写入返回 [`IdleErr::Write`]
 pub struct Timer<T> {
-    target: Rc<RefCell<T>>,
+    target: T,
     read_timeout: Option<Duration>,
     write_timeout: Option<Duration>,
-    read_fut: Option<BoxedFuture<'static, crate::Result<usize>>>,
-    write_fut: Option<BoxedFuture<'static, crate::Result<usize>>>,
+    last_active: Instant,
+    read_deadline: Option<Sleep>,
+    write_deadline: Option<Sleep>,
 }
 
-unsafe impl<T> Send for Timer<T> {}
-unsafe impl<T> Sync for Timer<T> {}
-
-impl<T> Timer<T>
-where
-    T: 'static,
-{
+impl<T> Timer<T> {
     pub fn into_inner(self) -> T {
-        unsafe { self.target.as_ptr().read() }
+        self.target
     }
 
     pub fn new(target: T, read_timeout: Option<Duration>, write_timeout: Option<Duration>) -> Self {
+        log::debug!(
+            "[timer] overtime time read={:?}, write={:?}",
+            read_timeout,
+            write_timeout
+        );
+
         Self {
-            target: Rc::new(RefCell::new(target)),
+            target,
             read_timeout,
             write_timeout,
-            read_fut: None,
-            write_fut: None,
+            last_active: Instant::now(),
+            read_deadline: None,
+            write_deadline: None,
         }
     }
 
     pub fn with_read_write(target: T, timeout: Duration) -> Self {
-        log::debug!(
-            "[timer] overtime time read={}ms, write={}ms",
-            timeout.as_millis(),
-            timeout.as_millis()
-        );
-
-        Self {
-            target: Rc::new(RefCell::new(target)),
-            read_fut: None,
-            write_fut: None,
-            read_timeout: Some(timeout),
-            write_timeout: Some(timeout),
-        }
+        Self::new(target, Some(timeout), Some(timeout))
     }
 
     pub fn with_read(target: T, timeout: Duration) -> Self {
-        log::debug!("[timer] overtime time read={}ms", timeout.as_millis());
-
-        Self {
-            target: Rc::new(RefCell::new(target)),
-            read_fut: None,
-            write_fut: None,
-            read_timeout: Some(timeout),
-            write_timeout: None,
-        }
+        Self::new(target, Some(timeout), None)
     }
 
     pub fn with_write(target: T, timeout: Duration) -> Self {
-        log::debug!("[timer] overtime time write={}ms", timeout.as_millis());
-
-        Self {
-            target: Rc::new(RefCell::new(target)),
-            read_fut: None,
-            write_fut: None,
-            read_timeout: None,
-            write_timeout: Some(timeout),
-        }
+        Self::new(target, None, Some(timeout))
     }
 
     pub fn read_timeout(mut self, timeout: Duration) -> Self {
@@ -85,140 +69,172 @@ where
 
     pub fn reset_read_timeout(mut self) -> Self {
         self.read_timeout = None;
+        self.read_deadline = None;
         self
     }
 
     pub fn reset_write_timeout(mut self) -> Self {
         self.write_timeout = None;
+        self.write_deadline = None;
         self
     }
-}
 
+    /// 读取挂起时检查空闲时间, 期间有过写入活动会顺延
+    fn poll_read_deadline(&mut self, cx: &mut Context<'_>) -> Poll<crate::Result<()>> {
+        let timeout = match self.read_timeout {
+            None => return Poll::Pending,
+            Some(timeout) => timeout,
+        };
+
+        loop {
+            let last_active = self.last_active;
+            let deadline = self.read_deadline.get_or_insert_with(|| {
+                current_runtime().sleep(timeout.saturating_sub(last_active.elapsed()))
+            });
+
+            ready!(deadline.as_mut().poll(cx));
+
+            self.read_deadline = None;
+
+            if self.last_active.elapsed() >= timeout {
+                log::debug!("[timer] connection idle for {:?}", timeout);
+                return Poll::Ready(Err(IdleErr::Read(timeout).into()));
+            }
+        }
+    }
+
+    fn poll_write_deadline(&mut self, cx: &mut Context<'_>) -> Poll<crate::Result<()>> {
+        let timeout = match self.write_timeout {
+            None => return Poll::Pending,
+            Some(timeout) => timeout,
+        };
+
+        let deadline = self
+            .write_deadline
+            .get_or_insert_with(|| current_runtime().sleep(timeout));
+
+        ready!(deadline.as_mut().poll(cx));
+
+        self.write_deadline = None;
+
+        log::debug!("[timer] write stalled for {:?}", timeout);
+
+        Poll::Ready(Err(IdleErr::Write(timeout).into()))
+    }
+}
 
-#[allow(unused)]
 impl<T> AsyncWrite for Timer<T>
 where
-    T: AsyncWrite + Unpin + 'static,
+    T: AsyncWrite + Unpin,
 {
     fn poll_write(
-        mut self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
         buf: &[u8],
-    ) -> std::task::Poll<crate::Result<usize>> {
-        log::debug!("poll timer write");
-        let timeout = match self.read_timeout.as_ref() {
-            None => {
-                let mut target = self.target.try_borrow_mut()?;
-                return Pin::new(&mut *target).poll_write(cx, buf);
+    ) -> Poll<crate::Result<usize>> {
+        let this = &mut *self;
+        match Pin::new(&mut this.target).poll_write(cx, buf) {
+            Poll::Ready(r) => {
+                this.write_deadline = None;
+                this.last_active = Instant::now();
+                Poll::Ready(r)
             }
-            Some(timeout) => timeout.clone(),
-        };
-
-        let target = self.target.clone();
-        let len = buf.len();
-        let buf = buf.as_ptr();
-
-        let mut fut: BoxedFuture<'static, crate::Result<usize>> = match self.write_fut.take() {
-            Some(fut) => fut,
-            None => Box::pin(async move {
-                // let mut target = target.try_borrow_mut()?;
-                // let buf = unsafe {
-                //     match std::ptr::slice_from_raw_parts(buf, len).as_ref() {
-                //         Some(buf) => buf,
-                //         None => return Err(crate::error::Kind::Memory.into()),
-                //     }
-                // };
-
-                // match time::wait_for(timeout, target.write(buf)).await {
-                //     Ok(ready) => ready,
-                //     Err(e) => Err({
-                //         log::warn!("[timer] write timeout {}", e);
-                //         std::io::ErrorKind::TimedOut.into()
-                //     }),
-                // }
-                unimplemented!()
-            }),
-        };
+            Poll::Pending => {
+                ready!(this.poll_write_deadline(cx))?;
+                Poll::Pending
+            }
+        }
+    }
 
-        match Pin::new(&mut fut).poll(cx) {
-            Poll::Ready(ready) => Poll::Ready(ready),
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<crate::Result<()>> {
+        let this = &mut *self;
+        match Pin::new(&mut this.target).poll_flush(cx) {
+            Poll::Ready(r) => {
+                this.write_deadline = None;
+                Poll::Ready(r)
+            }
             Poll::Pending => {
-                drop(std::mem::replace(&mut self.read_fut, Some(fut)));
+                ready!(this.poll_write_deadline(cx))?;
                 Poll::Pending
             }
         }
     }
 
-    fn poll_flush(
-        self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> std::task::Poll<crate::Result<()>> {
-        let mut target = self.target.try_borrow_mut()?;
-        return Pin::new(&mut *target).poll_flush(cx);
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<crate::Result<()>> {
+        Pin::new(&mut self.target).poll_close(cx)
     }
 
-    fn poll_close(
-        self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> std::task::Poll<crate::Result<()>> {
-        let mut target = self.target.try_borrow_mut()?;
-        return Pin::new(&mut *target).poll_close(cx);
+    fn is_half_closable(&self) -> bool {
+        self.target.is_half_closable()
     }
 }
 
-
-#[allow(unused)]
 impl<T> AsyncRead for Timer<T>
 where
-    T: AsyncRead + Unpin + 'static,
+    T: AsyncRead + Unpin,
 {
     fn poll_read(
-        mut self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
         buf: &mut crate::ReadBuf<'_>,
-    ) -> std::task::Poll<crate::Result<usize>> {
-        log::debug!("poll timer read");
-        let timeout = match self.read_timeout.as_ref() {
-            None => {
-                let mut target = self.target.try_borrow_mut()?;
-                return Pin::new(&mut *target).poll_read(cx, buf);
+    ) -> Poll<crate::Result<usize>> {
+        let this = &mut *self;
+        match Pin::new(&mut this.target).poll_read(cx, buf) {
+            Poll::Ready(r) => {
+                this.read_deadline = None;
+                this.last_active = Instant::now();
+                Poll::Ready(r)
             }
-            Some(timeout) => timeout.clone(),
-        };
-
-        let target = self.target.clone();
-        let buf = buf.initialize_unfilled();
-        let len = buf.len();
-        let buf = buf.as_mut_ptr();
-
-        let mut fut: BoxedFuture<'static, crate::Result<usize>> = match self.read_fut.take() {
-            Some(fut) => fut,
-            None => Box::pin(async move {
-                let mut target = target.try_borrow_mut()?;
-                let buf = unsafe {
-                    match std::ptr::slice_from_raw_parts_mut(buf, len).as_mut() {
-                        Some(buf) => buf,
-                        None => return Err(crate::error::Kind::Memory.into()),
-                    }
-                };
-
-                // match time::wait_for(timeout, target.read(buf)).await {
-                //     Ok(ready) => ready,
-                //     Err(e) => Err({
-                //         log::warn!("[timer] read timeout {}", e);
-                //         std::io::ErrorKind::TimedOut.into()
-                //     }),
-                // }
-                unimplemented!()
-            }),
-        };
-
-        match Pin::new(&mut fut).poll(cx) {
-            Poll::Ready(ready) => Poll::Ready(ready),
             Poll::Pending => {
-                drop(std::mem::replace(&mut self.read_fut, Some(fut)));
+                ready!(this.poll_read_deadline(cx))?;
                 Poll::Pending
             }
         }
     }
 }
+
+#[cfg(test)]
+#[cfg(feature = "fuso-rt-tokio")]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        error::IdleErr,
+        ext::{AsyncReadExt, AsyncWriteExt},
+        testing::tcp::pair,
+        Kind,
+    };
+
+    use super::Timer;
+
+    #[tokio::test]
+    async fn test_idle_timeout() {
+        let (s1, mut s2) = pair().await;
+        let mut timer = Timer::with_read(s1, Duration::from_millis(100));
+
+        s2.write_all(b"hello").await.unwrap();
+
+        let mut buf = [0u8; 5];
+        timer.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        let err = timer.read(&mut buf).await.unwrap_err();
+        assert!(matches!(err.kind(), Kind::Idle(IdleErr::Read(_))));
+    }
+
+    #[tokio::test]
+    async fn test_write_stalled() {
+        let (s1, _s2) = pair().await;
+        let mut timer = Timer::with_write(s1, Duration::from_millis(100));
+
+        // 对端不读取, 直到写满缓冲区
+        let buf = vec![0u8; 64 * 1024];
+        let err = loop {
+            if let Err(e) = timer.write_all(&buf).await {
+                break e;
+            }
+        };
+
+        assert!(matches!(err.kind(), Kind::Idle(IdleErr::Write(_))));
+    }
+}
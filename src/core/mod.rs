@@ -12,6 +12,12 @@ pub use boxed::*;
 mod socket;
 pub use socket::*;
 
+mod shutdown;
+pub use shutdown::*;
+
+mod cidr;
+pub use cidr::*;
+
 pub mod encryption;
 pub mod generator;
 pub mod guard;
@@ -1,474 +1,729 @@
-use std::{
-    io::Read,
-    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
-};
-
-use bytes::{Buf, BufMut};
-
-use crate::{Addr, Error, Kind, Result, Socket};
-
-// macro_rules! invalid {
-//     () => {
-//         Err(Kind::Deserialize("invalid data".to_owned()).into())
-//     };
-// }
-
-// pub const MAGIC: u32 = 0xFC;
-
-// #[derive(Debug, Clone, PartialEq, Eq)]
-// pub struct Packet {
-//     pub magic: u32,
-//     pub data_len: u32,
-//     pub payload: Vec<u8>,
-// }
-
-// #[derive(Debug, Clone, PartialEq, Eq)]
-// pub enum Connect {
-//     TCP(Option<Addr>),
-//     UDP(Addr),
-// }
-
-// #[derive(Debug, Clone, PartialEq, Eq)]
-// pub enum Bind {
-//     Bind(Addr),
-//     Failed(Addr, Vec<u8>),
-// }
-
-// #[derive(Debug, Clone, PartialEq, Eq)]
-// pub enum Auth {
-//     Auth(Vec<u8>),
-//     NoAuth,
-// }
-
-// #[derive(Debug, Clone, PartialEq, Eq)]
-// pub enum Message {
-//     Bind(Bind),
-//     Map(u32, Socket),
-//     Connect(Connect, Auth),
-// }
-
-// trait BytesEx {
-//     /// self.len() > expect
-//     /// 自身剩余长度大于expect Ok 否则Err
-//     fn fat(&self, expect: usize) -> Result<()>;
-// }
-
-// impl BytesEx for &[u8] {
-//     fn fat(&self, expect: usize) -> Result<()> {
-//         if self.len() < expect {
-//             Err(Kind::Deserialize("invalid data".to_owned()).into())
-//         } else {
-//             Ok(())
-//         }
-//     }
-// }
-
-// trait GetMeta {
-//     fn is(magic: u8) -> Result<()>
-//     where
-//         Self: Sized,
-//     {
-//         if magic == Self::get_magic() {
-//             Ok(())
-//         } else {
-//             Err(Kind::Deserialize(format!("{:x} is not {}", magic, stringify!(Self))).into())
-//         }
-//     }
-
-//     fn get_magic() -> u8
-//     where
-//         Self: Sized;
-
-//     fn get_size(&self) -> usize;
-// }
-
-// impl GetMeta for Addr {
-//     fn get_magic() -> u8
-//     where
-//         Self: Sized,
-//     {
-//         0x1
-//     }
-
-//     fn get_size(&self) -> usize {
-//         2 + match self {
-//             Addr::Socket(addr) => match addr {
-//                 std::net::SocketAddr::V4(_) => 6,
-//                 std::net::SocketAddr::V6(_) => 18,
-//             },
-//             Addr::Domain(domain, _) => {
-//                 // u32 + data + port
-//                 domain.as_bytes().len() + 6
-//             }
-//         }
-//     }
-// }
-
-// impl GetMeta for Connect {
-//     fn get_magic() -> u8
-//     where
-//         Self: Sized,
-//     {
-//         0x2
-//     }
-
-//     fn get_size(&self) -> usize {
-//         2 + match self {
-//             Connect::TCP(tcp) => tcp.get_size(),
-//             Connect::UDP(udp) => udp.get_size(),
-//         }
-//     }
-// }
-
-// impl GetMeta for Bind {
-//     fn get_magic() -> u8
-//     where
-//         Self: Sized,
-//     {
-//         0x3
-//     }
-
-//     fn get_size(&self) -> usize {
-//         2 + match self {
-//             Bind::Bind(bind) => bind.get_size(),
-//             Bind::Failed(addr, err) => {
-//                 // addr + u32 + data
-//                 addr.get_size() + err.len() + 4
-//             }
-//         }
-//     }
-// }
-
-// impl GetMeta for Auth {
-//     fn get_magic() -> u8
-//     where
-//         Self: Sized,
-//     {
-//         0x4
-//     }
-
-//     fn get_size(&self) -> usize {
-//         2 + match self {
-//             Auth::Auth(auth) => auth.len(),
-//             Auth::NoAuth => 0,
-//         }
-//     }
-// }
-
-// impl GetMeta for Message {
-//     fn get_magic() -> u8
-//     where
-//         Self: Sized,
-//     {
-//         0x5
-//     }
-
-//     fn get_size(&self) -> usize {
-//         2 + match self {
-//             Message::Bind(bind) => bind.get_size(),
-//             Message::Connect(connect, auth) => connect.get_size() + auth.get_size(),
-//         }
-//     }
-// }
-
-// impl TryFrom<&[u8]> for Message {
-//     type Error = Error;
-
-//     fn try_from(data: &[u8]) -> Result<Self> {
-//         data.fat(2)?;
-
-//         let mut cur = std::io::Cursor::new(data);
-
-//         Self::is(cur.get_u8())?;
-
-//         let magic = cur.get_u8();
-//         let data = &data[2..];
-
-//         match magic {
-//             0x01 => Ok(Self::Bind(data.try_into()?)),
-//             0x02 => {
-//                 let addr = Connect::try_from(data)?;
-//                 let auth = &data[addr.get_size()..];
-//                 Ok(Self::Connect(addr, auth.try_into()?))
-//             }
-//             _ => invalid!(),
-//         }
-//     }
-// }
-
-// impl From<&Addr> for Vec<u8> {
-//     fn from(addr: &Addr) -> Self {
-//         let mut buf = Vec::new();
-
-//         buf.put_u8(Addr::get_magic());
-
-//         match addr {
-//             Addr::Socket(SocketAddr::V4(addr)) => {
-//                 buf.put_u8(0x1);
-//                 buf.put_slice(&addr.ip().octets());
-//                 buf.put_u16(addr.port())
-//             }
-//             Addr::Socket(SocketAddr::V6(addr)) => {
-//                 buf.put_u8(0x2);
-//                 buf.put_slice(&addr.ip().octets());
-//                 buf.put_u16(addr.port())
-//             }
-//             Addr::Domain(domain, port) => {
-//                 let domain = domain.as_bytes();
-//                 buf.put_u8(0x3);
-//                 buf.put_u32(domain.len() as u32);
-//                 buf.put_slice(domain);
-//                 buf.put_u16(*port);
-//             }
-//         }
-
-//         buf
-//     }
-// }
-
-// impl TryFrom<&[u8]> for Addr {
-//     type Error = Error;
-//     fn try_from(data: &[u8]) -> Result<Self> {
-//         data.fat(2)?;
-
-//         let mut cur = std::io::Cursor::new(data);
-
-//         Self::is(cur.get_u8())?;
-
-//         let magic = cur.get_u8();
-//         let data = &data[2..];
-
-//         match magic {
-//             0x1 => {
-//                 data.fat(6)?;
-
-//                 Ok(Self::Socket(SocketAddr::new(
-//                     Ipv4Addr::from(cur.get_u32()).into(),
-//                     cur.get_u16(),
-//                 )))
-//             }
-//             0x2 => {
-//                 data.fat(18)?;
-
-//                 Ok(Self::Socket(SocketAddr::new(
-//                     Ipv6Addr::from(cur.get_u128()).into(),
-//                     cur.get_u16(),
-//                 )))
-//             }
-//             0x03 => {
-//                 data.fat(4)?;
-
-//                 let domain_len = cur.get_u32() as usize;
-//                 let mut buf = Vec::with_capacity(domain_len);
-
-//                 unsafe {
-//                     buf.set_len(domain_len);
-//                 }
-
-//                 cur.read_exact(&mut buf)?;
-
-//                 let domain = String::from_utf8(buf)?;
-
-//                 data.fat(2)?;
-
-//                 Ok(Self::Domain(domain, cur.get_u16()))
-//             }
-//             _ => {
-//                 invalid!()
-//             }
-//         }
-//     }
-// }
-
-// impl From<&Connect> for Vec<u8> {
-//     fn from(addr: &Connect) -> Self {
-//         let mut buf = Vec::new();
-
-//         buf.put_u8(Connect::get_magic());
-
-//         match addr {
-//             Connect::TCP(addr) => {
-//                 buf.put_u8(0x01);
-//                 buf.put_slice(&Vec::from(addr));
-//             }
-//             Connect::UDP(addr) => {
-//                 buf.put_u8(0x02);
-//                 buf.put_slice(&Vec::from(addr));
-//             }
-//         }
-
-//         buf
-//     }
-// }
-
-// impl TryFrom<&[u8]> for Connect {
-//     type Error = Error;
-
-//     fn try_from(data: &[u8]) -> Result<Self> {
-//         data.fat(2)?;
-
-//         let mut cur = std::io::Cursor::new(data);
-
-//         Self::is(cur.get_u8())?;
-
-//         let magic = cur.get_u8();
-//         let data = &data[2..];
-
-//         match magic {
-//             0x01 => Ok(Self::TCP(data.try_into()?)),
-//             0x02 => Ok(Self::UDP(data.try_into()?)),
-//             _ => invalid!(),
-//         }
-//     }
-// }
-
-// impl From<&Bind> for Vec<u8> {
-//     fn from(bind: &Bind) -> Self {
-//         let mut buf = Vec::new();
-
-//         buf.put_u8(Bind::get_magic());
-
-//         match bind {
-//             Bind::Bind(addr) => {
-//                 buf.put_u8(0x01);
-//                 buf.put_slice(&Vec::from(addr))
-//             }
-//             Bind::Failed(addr, err) => {
-//                 buf.put_u8(0x02);
-//                 buf.put_slice(&Vec::from(addr));
-//                 buf.put_u32(err.len() as u32);
-//                 buf.put_slice(err)
-//             }
-//         }
-
-//         buf
-//     }
-// }
-
-// impl TryFrom<&[u8]> for Bind {
-//     type Error = Error;
-//     fn try_from(data: &[u8]) -> Result<Self> {
-//         data.fat(2)?;
-
-//         let mut cur = std::io::Cursor::new(data);
-
-//         Self::is(cur.get_u8())?;
-
-//         let magic = cur.get_u8();
-
-//         let data = &data[2..];
-
-//         match magic {
-//             0x01 => Ok(Self::Bind(data.try_into()?)),
-//             0x02 => {
-//                 let addr = Addr::try_from(data)?;
-
-//                 cur.advance(addr.get_size());
-
-//                 let data = &data[addr.get_size()..];
-
-//                 data.fat(4)?;
-
-//                 let len = cur.get_u32() as usize;
-
-//                 let data = &data[4..];
-
-//                 data.fat(len)?;
-
-//                 Ok(Self::Failed(addr, data[..len].to_vec()))
-//             }
-//             _ => invalid!(),
-//         }
-//     }
-// }
-
-// impl TryFrom<&[u8]> for Auth {
-//     type Error = Error;
-
-//     fn try_from(data: &[u8]) -> Result<Self> {
-//         data.fat(2)?;
-
-//         let mut cur = std::io::Cursor::new(data);
-
-//         Self::is(cur.get_u8())?;
-
-//         let magic = cur.get_u8();
-//         let data = &data[2..];
-
-//         match magic {
-//             0x1 => {
-//                 data.fat(4)?;
-//                 let len = cur.get_u32() as usize;
-//                 let data = &data[4..];
-//                 data.fat(len)?;
-
-//                 Ok(Self::Auth(data[..len].to_vec()))
-//             }
-//             0x2 => Ok(Self::NoAuth),
-//             _ => invalid!(),
-//         }
-//     }
-// }
-
-// impl From<&Auth> for Vec<u8> {
-//     fn from(auth: &Auth) -> Self {
-//         let mut buf = Vec::new();
-
-//         buf.put_u8(Auth::get_magic());
-
-//         match auth {
-//             Auth::Auth(auth) => {
-//                 buf.put_u8(0x01);
-//                 buf.put_u32(auth.len() as u32);
-//                 buf.put_slice(auth);
-//             }
-//             Auth::NoAuth => {
-//                 buf.put_u8(0x02);
-//             }
-//         }
-
-//         buf
-//     }
-// }
-
-// impl From<&Message> for Vec<u8> {
-//     fn from(behavior: &Message) -> Self {
-//         let mut buf = Vec::new();
-
-//         buf.put_u8(Message::get_magic());
-
-//         match behavior {
-//             Message::Bind(bind) => {
-//                 buf.put_u8(0x01);
-//                 buf.put_slice(&Vec::from(bind));
-//             }
-//             Message::Connect(conn, auth) => {
-//                 buf.put_u8(0x02);
-//                 buf.put_slice(&Vec::from(conn));
-//                 buf.put_slice(&Vec::from(auth));
-//             }
-//         }
-
-//         buf
-//     }
-// }
-
-// impl From<&Message> for Packet {
-//     fn from(packet: &Message) -> Self {
-//         let data = Vec::from(packet);
-//         Packet {
-//             magic: MAGIC,
-//             data_len: data.len() as u32,
-//             payload: data,
-//         }
-//     }
-// }
-
-// impl TryFrom<&Packet> for Message {
-//     type Error = Error;
-
-//     fn try_from(packet: &Packet) -> Result<Self> {
-//         Message::try_from(packet.payload.as_slice())
-//     }
-// }
-
+//! 不依赖serde的编解码
+//!
+//! 编码格式与 bincode 1.x 的默认配置一致, 启用与不启用 `fuso-serde` 的两端可以互通:
+//!
+//! - 整数: 小端定长, `bool` 为一个字节的 0 或 1
+//! - 字符串与字节数组: `u64` 长度 + 数据
+//! - `Option`: 一个字节的标记, 0 为 `None`, 1 为 `Some` 后跟数据
+//! - 枚举: `u32` 变体序号 (按声明顺序从0开始) + 各字段
+//! - 结构体: 依次编码各字段, 没有额外的头部
+//! - `SocketAddr`: 按枚举编码, V4 为 4字节地址 + `u16` 端口, V6 为 16字节地址 + `u16` 端口
+//! - `IpAddr`: 与 `SocketAddr` 相同, 但没有端口
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+
+use crate::{Acl, Addr, Cidr, InnerAddr, Kind, Result, Socket, SocketKind};
+
+use super::{Auth, Bind, Capabilities, Connect, Hello, Origin, Poto, Version};
+
+pub trait Encode {
+    fn encode(&self, buf: &mut Vec<u8>);
+}
+
+pub trait Decode: Sized {
+    /// 从 `buf` 的开头读取, 并跳过已读取的部分
+    fn decode(buf: &mut &[u8]) -> Result<Self>;
+}
+
+/// 与 `bincode::serialize` 相同, 允许尾部有多余的数据
+#[cfg(not(feature = "fuso-serde"))]
+pub fn encode<T: Encode>(value: &T) -> Vec<u8> {
+    let mut buf = Vec::new();
+    value.encode(&mut buf);
+    buf
+}
+
+#[cfg(not(feature = "fuso-serde"))]
+pub fn decode<T: Decode>(mut buf: &[u8]) -> Result<T> {
+    T::decode(&mut buf)
+}
+
+fn invalid<T>(what: &str) -> Result<T> {
+    Err(Kind::Deserialize(format!("invalid {}", what)).into())
+}
+
+fn take<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if buf.len() < len {
+        return Err(Kind::Deserialize(format!(
+            "unexpected end of data, expect {}bytes but only {}bytes",
+            len,
+            buf.len()
+        ))
+        .into());
+    }
+
+    let (head, tail) = buf.split_at(len);
+    *buf = tail;
+    Ok(head)
+}
+
+fn take_array<const N: usize>(buf: &mut &[u8]) -> Result<[u8; N]> {
+    let mut array = [0u8; N];
+    array.copy_from_slice(take(buf, N)?);
+    Ok(array)
+}
+
+fn encode_variant(variant: u32, buf: &mut Vec<u8>) {
+    variant.encode(buf)
+}
+
+macro_rules! impl_integer {
+    ($($ty: ty),*) => {
+        $(
+            impl Encode for $ty {
+                fn encode(&self, buf: &mut Vec<u8>) {
+                    buf.extend_from_slice(&self.to_le_bytes());
+                }
+            }
+
+            impl Decode for $ty {
+                fn decode(buf: &mut &[u8]) -> Result<Self> {
+                    Ok(<$ty>::from_le_bytes(take_array(buf)?))
+                }
+            }
+        )*
+    };
+}
+
+impl_integer!(u8, u16, u32, u64);
+
+impl Encode for bool {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        (*self as u8).encode(buf)
+    }
+}
+
+impl Decode for bool {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        match u8::decode(buf)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => invalid("bool"),
+        }
+    }
+}
+
+impl Encode for [u8] {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        (self.len() as u64).encode(buf);
+        buf.extend_from_slice(self);
+    }
+}
+
+impl Encode for Vec<u8> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.as_slice().encode(buf)
+    }
+}
+
+impl Decode for Vec<u8> {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        let len = u64::decode(buf)?;
+        if len > buf.len() as u64 {
+            return invalid("length");
+        }
+
+        Ok(take(buf, len as usize)?.to_vec())
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.as_bytes().encode(buf)
+    }
+}
+
+impl Decode for String {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        String::from_utf8(Vec::decode(buf)?).or_else(|_| invalid("utf8 string"))
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            None => 0u8.encode(buf),
+            Some(value) => {
+                1u8.encode(buf);
+                value.encode(buf);
+            }
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        match u8::decode(buf)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(buf)?)),
+            _ => invalid("option"),
+        }
+    }
+}
+
+impl Encode for SocketAddr {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            SocketAddr::V4(addr) => {
+                encode_variant(0, buf);
+                buf.extend_from_slice(&addr.ip().octets());
+                addr.port().encode(buf);
+            }
+            SocketAddr::V6(addr) => {
+                encode_variant(1, buf);
+                buf.extend_from_slice(&addr.ip().octets());
+                addr.port().encode(buf);
+            }
+        }
+    }
+}
+
+impl Decode for SocketAddr {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        match u32::decode(buf)? {
+            0 => {
+                let ip = Ipv4Addr::from(take_array::<4>(buf)?);
+                Ok(SocketAddrV4::new(ip, u16::decode(buf)?).into())
+            }
+            1 => {
+                let ip = Ipv6Addr::from(take_array::<16>(buf)?);
+                Ok(SocketAddrV6::new(ip, u16::decode(buf)?, 0, 0).into())
+            }
+            _ => invalid("socket addr"),
+        }
+    }
+}
+
+impl Encode for IpAddr {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            IpAddr::V4(ip) => {
+                encode_variant(0, buf);
+                buf.extend_from_slice(&ip.octets());
+            }
+            IpAddr::V6(ip) => {
+                encode_variant(1, buf);
+                buf.extend_from_slice(&ip.octets());
+            }
+        }
+    }
+}
+
+impl Decode for IpAddr {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        match u32::decode(buf)? {
+            0 => Ok(Ipv4Addr::from(take_array::<4>(buf)?).into()),
+            1 => Ok(Ipv6Addr::from(take_array::<16>(buf)?).into()),
+            _ => invalid("ip addr"),
+        }
+    }
+}
+
+impl Encode for Cidr {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.ip().encode(buf);
+        self.prefix().encode(buf);
+    }
+}
+
+impl Decode for Cidr {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        match Cidr::new(IpAddr::decode(buf)?, u8::decode(buf)?) {
+            Ok(cidr) => Ok(cidr),
+            Err(_) => invalid("cidr"),
+        }
+    }
+}
+
+impl Encode for Vec<Cidr> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        (self.len() as u64).encode(buf);
+        for cidr in self {
+            cidr.encode(buf);
+        }
+    }
+}
+
+impl Decode for Vec<Cidr> {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        let len = u64::decode(buf)?;
+        // 每一项至少5个字节, 避免按照伪造的长度分配内存
+        let mut list = Vec::with_capacity((len as usize).min(buf.len() / 5));
+        for _ in 0..len {
+            list.push(Cidr::decode(buf)?);
+        }
+        Ok(list)
+    }
+}
+
+impl Encode for Acl {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.allow.encode(buf);
+        self.deny.encode(buf);
+    }
+}
+
+impl Decode for Acl {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        Ok(Acl {
+            allow: Vec::decode(buf)?,
+            deny: Vec::decode(buf)?,
+        })
+    }
+}
+
+impl Encode for InnerAddr {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            InnerAddr::Socket(addr) => {
+                encode_variant(0, buf);
+                addr.encode(buf);
+            }
+            InnerAddr::Domain(domain, port) => {
+                encode_variant(1, buf);
+                domain.encode(buf);
+                port.encode(buf);
+            }
+        }
+    }
+}
+
+impl Decode for InnerAddr {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        match u32::decode(buf)? {
+            0 => Ok(InnerAddr::Socket(SocketAddr::decode(buf)?)),
+            1 => Ok(InnerAddr::Domain(String::decode(buf)?, u16::decode(buf)?)),
+            _ => invalid("addr"),
+        }
+    }
+}
+
+impl Encode for Addr {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.inner().encode(buf)
+    }
+}
+
+impl Decode for Addr {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        Ok(match InnerAddr::decode(buf)? {
+            InnerAddr::Socket(addr) => addr.into(),
+            InnerAddr::Domain(domain, port) => (domain, port).into(),
+        })
+    }
+}
+
+impl Encode for SocketKind {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        encode_variant(
+            match self {
+                SocketKind::Kcp => 0,
+                SocketKind::Udp => 1,
+                SocketKind::Tcp => 2,
+                SocketKind::Quic => 3,
+                SocketKind::Ufd => 4,
+            },
+            buf,
+        )
+    }
+}
+
+impl Decode for SocketKind {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        match u32::decode(buf)? {
+            0 => Ok(SocketKind::Kcp),
+            1 => Ok(SocketKind::Udp),
+            2 => Ok(SocketKind::Tcp),
+            3 => Ok(SocketKind::Quic),
+            4 => Ok(SocketKind::Ufd),
+            _ => invalid("socket kind"),
+        }
+    }
+}
+
+/// kind + target + is_mixed
+impl Encode for Socket {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.kind().encode(buf);
+        self.addr().encode(buf);
+        self.is_mixed().encode(buf);
+    }
+}
+
+impl Decode for Socket {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        let kind = SocketKind::decode(buf)?;
+        let target = Addr::decode(buf)?;
+        let is_mixed = bool::decode(buf)?;
+
+        Ok(Socket::tcp(target)
+            .with_kind(kind)
+            .if_stream_mixed(is_mixed))
+    }
+}
+
+impl Encode for Connect {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Connect::TCP(addr) => {
+                encode_variant(0, buf);
+                addr.encode(buf);
+            }
+            Connect::UDP(addr) => {
+                encode_variant(1, buf);
+                addr.encode(buf);
+            }
+            Connect::Tun => encode_variant(2, buf),
+            Connect::P2pProvide(name) => {
+                encode_variant(3, buf);
+                name.encode(buf);
+            }
+            Connect::P2pVisit(name) => {
+                encode_variant(4, buf);
+                name.encode(buf);
+            }
+            Connect::P2pTicket(ticket, secret, port) => {
+                encode_variant(5, buf);
+                ticket.encode(buf);
+                secret.encode(buf);
+                port.encode(buf);
+            }
+        }
+    }
+}
+
+impl Decode for Connect {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        match u32::decode(buf)? {
+            0 => Ok(Connect::TCP(Option::decode(buf)?)),
+            1 => Ok(Connect::UDP(Addr::decode(buf)?)),
+            2 => Ok(Connect::Tun),
+            3 => Ok(Connect::P2pProvide(String::decode(buf)?)),
+            4 => Ok(Connect::P2pVisit(String::decode(buf)?)),
+            5 => Ok(Connect::P2pTicket(
+                String::decode(buf)?,
+                String::decode(buf)?,
+                u16::decode(buf)?,
+            )),
+            _ => invalid("connect"),
+        }
+    }
+}
+
+impl Encode for Bind {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Bind::Bind(socket) => {
+                encode_variant(0, buf);
+                socket.encode(buf);
+            }
+            Bind::Failed(socket, err) => {
+                encode_variant(1, buf);
+                socket.encode(buf);
+                err.encode(buf);
+            }
+            Bind::Filter(socket, acl) => {
+                encode_variant(2, buf);
+                socket.encode(buf);
+                acl.encode(buf);
+            }
+            Bind::Named(name, socket, acl) => {
+                encode_variant(3, buf);
+                name.encode(buf);
+                socket.encode(buf);
+                acl.encode(buf);
+            }
+        }
+    }
+}
+
+impl Decode for Bind {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        match u32::decode(buf)? {
+            0 => Ok(Bind::Bind(Socket::decode(buf)?)),
+            1 => Ok(Bind::Failed(Socket::decode(buf)?, String::decode(buf)?)),
+            2 => Ok(Bind::Filter(Socket::decode(buf)?, Acl::decode(buf)?)),
+            3 => Ok(Bind::Named(
+                String::decode(buf)?,
+                Socket::decode(buf)?,
+                Acl::decode(buf)?,
+            )),
+            _ => invalid("bind"),
+        }
+    }
+}
+
+impl Encode for Auth {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Auth::Auth(data) => {
+                encode_variant(0, buf);
+                data.encode(buf);
+            }
+            Auth::NoAuth => encode_variant(1, buf),
+        }
+    }
+}
+
+impl Decode for Auth {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        match u32::decode(buf)? {
+            0 => Ok(Auth::Auth(Vec::decode(buf)?)),
+            1 => Ok(Auth::NoAuth),
+            _ => invalid("auth"),
+        }
+    }
+}
+
+impl Encode for Capabilities {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.bits().encode(buf)
+    }
+}
+
+impl Decode for Capabilities {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        Ok(Capabilities::from_bits(u32::decode(buf)?))
+    }
+}
+
+/// protocol + min_protocol + crate_version + capabilities
+impl Encode for Version {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.protocol.encode(buf);
+        self.min_protocol.encode(buf);
+        self.crate_version.encode(buf);
+        self.capabilities.encode(buf);
+    }
+}
+
+impl Decode for Version {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        Ok(Version {
+            protocol: u32::decode(buf)?,
+            min_protocol: u32::decode(buf)?,
+            crate_version: String::decode(buf)?,
+            capabilities: Capabilities::decode(buf)?,
+        })
+    }
+}
+
+impl Encode for Hello {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Hello::Hello(version) => {
+                encode_variant(0, buf);
+                version.encode(buf);
+            }
+            Hello::Accept(version) => {
+                encode_variant(1, buf);
+                version.encode(buf);
+            }
+            Hello::Reject(version, reason) => {
+                encode_variant(2, buf);
+                version.encode(buf);
+                reason.encode(buf);
+            }
+        }
+    }
+}
+
+impl Decode for Hello {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        match u32::decode(buf)? {
+            0 => Ok(Hello::Hello(Version::decode(buf)?)),
+            1 => Ok(Hello::Accept(Version::decode(buf)?)),
+            2 => Ok(Hello::Reject(Version::decode(buf)?, String::decode(buf)?)),
+            _ => invalid("hello"),
+        }
+    }
+}
+
+impl Encode for Origin {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.source.encode(buf);
+        self.destination.encode(buf);
+    }
+}
+
+impl Decode for Origin {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        Ok(Origin {
+            source: SocketAddr::decode(buf)?,
+            destination: SocketAddr::decode(buf)?,
+        })
+    }
+}
+
+impl Encode for Poto {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Poto::Ping => encode_variant(0, buf),
+            Poto::Close => encode_variant(1, buf),
+            Poto::MapError(id, err) => {
+                encode_variant(2, buf);
+                id.encode(buf);
+                err.encode(buf);
+            }
+            Poto::Bind(bind) => {
+                encode_variant(3, buf);
+                bind.encode(buf);
+            }
+            Poto::Map(id, socket) => {
+                encode_variant(4, buf);
+                id.encode(buf);
+                socket.encode(buf);
+            }
+            Poto::Connect(connect, auth) => {
+                encode_variant(5, buf);
+                connect.encode(buf);
+                auth.encode(buf);
+            }
+            Poto::Forward(addr) => {
+                encode_variant(6, buf);
+                addr.encode(buf);
+            }
+            Poto::Pong(timestamp) => {
+                encode_variant(7, buf);
+                timestamp.encode(buf);
+            }
+            Poto::Hello(hello) => {
+                encode_variant(8, buf);
+                hello.encode(buf);
+            }
+            Poto::MapFrom(id, socket, origin) => {
+                encode_variant(9, buf);
+                id.encode(buf);
+                socket.encode(buf);
+                origin.encode(buf);
+            }
+            Poto::PingAt(timestamp) => {
+                encode_variant(10, buf);
+                timestamp.encode(buf);
+            }
+        }
+    }
+}
+
+impl Decode for Poto {
+    fn decode(buf: &mut &[u8]) -> Result<Self> {
+        match u32::decode(buf)? {
+            0 => Ok(Poto::Ping),
+            1 => Ok(Poto::Close),
+            2 => Ok(Poto::MapError(u32::decode(buf)?, String::decode(buf)?)),
+            3 => Ok(Poto::Bind(Bind::decode(buf)?)),
+            4 => Ok(Poto::Map(u32::decode(buf)?, Socket::decode(buf)?)),
+            5 => Ok(Poto::Connect(Connect::decode(buf)?, Auth::decode(buf)?)),
+            6 => Ok(Poto::Forward(Addr::decode(buf)?)),
+            7 => Ok(Poto::Pong(u64::decode(buf)?)),
+            8 => Ok(Poto::Hello(Hello::decode(buf)?)),
+            9 => Ok(Poto::MapFrom(
+                u32::decode(buf)?,
+                Socket::decode(buf)?,
+                Origin::decode(buf)?,
+            )),
+            10 => Ok(Poto::PingAt(u64::decode(buf)?)),
+            _ => invalid("message"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        protocol::{Auth, Bind, Connect, Hello, Origin, Poto, Version},
+        Acl, Addr, Socket,
+    };
+
+    use super::{Decode, Encode};
+
+    fn samples() -> Vec<Poto> {
+        let domain = Addr::from((String::from("example.com"), 443));
+        let v4 = Addr::from(([127, 0, 0, 1], 8080));
+        let v6 = Addr::from(([0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1], 22));
+
+        vec![
+            Poto::Ping,
+            Poto::PingAt(u64::MAX),
+            Poto::Close,
+            Poto::MapError(7, String::from("connection refused")),
+            Poto::Bind(Bind::Bind(Socket::tcp(v4.clone()))),
+            Poto::Bind(Bind::Failed(Socket::kcp(10u16), String::from("in use"))),
+            Poto::Bind(Bind::Filter(
+                Socket::tcp(v4.clone()),
+                Acl {
+                    allow: vec!["10.0.0.0/8".parse().unwrap(), "fd00::/8".parse().unwrap()],
+                    deny: vec!["10.0.0.1".parse().unwrap()],
+                },
+            )),
+            Poto::Bind(Bind::Named(
+                String::from("web"),
+                Socket::tcp(8080u16),
+                Acl::default(),
+            )),
+            Poto::Map(1, Socket::udp(v6.clone()).if_stream_mixed(true)),
+            Poto::Map(2, Socket::ufd(domain.clone())),
+            Poto::Map(3, Socket::quic(v4.clone())),
+            Poto::Connect(Connect::TCP(None), Auth::NoAuth),
+            Poto::Connect(
+                Connect::TCP(Some(domain.clone())),
+                Auth::Auth(vec![1, 2, 3]),
+            ),
+            Poto::Connect(Connect::UDP(v6), Auth::Auth(vec![])),
+            Poto::Connect(Connect::Tun, Auth::Auth(b"token".to_vec())),
+            Poto::Connect(Connect::P2pProvide(String::from("web")), Auth::NoAuth),
+            Poto::Connect(Connect::P2pVisit(String::from("web")), Auth::NoAuth),
+            Poto::Connect(
+                Connect::P2pTicket(String::from("ticket"), String::from("secret"), 6723),
+                Auth::NoAuth,
+            ),
+            Poto::Forward(domain),
+            Poto::Pong(0),
+            Poto::Hello(Hello::Hello(Version::local())),
+            Poto::Hello(Hello::Accept(Version::legacy())),
+            Poto::Hello(Hello::Reject(Version::local(), String::from("too old"))),
+            Poto::MapFrom(
+                4,
+                Socket::tcp(v4.clone()),
+                Origin {
+                    source: "[2001:db8::1]:50000".parse().unwrap(),
+                    destination: "192.0.2.1:9999".parse().unwrap(),
+                },
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for poto in samples() {
+            let mut buf = Vec::new();
+            poto.encode(&mut buf);
+
+            let mut data = buf.as_slice();
+            assert_eq!(Poto::decode(&mut data).unwrap(), poto);
+            assert!(data.is_empty());
+
+            for len in 0..buf.len() {
+                assert!(Poto::decode(&mut &buf[..len]).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Poto::decode(&mut &[10, 0, 0, 0][..]).is_err());
+        assert!(bool::decode(&mut &[2][..]).is_err());
+        assert!(String::decode(&mut &[1, 0, 0, 0, 0, 0, 0, 0, 0xff][..]).is_err());
+        assert!(Vec::<u8>::decode(&mut &[0xff; 8][..]).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "fuso-serde")]
+    fn test_bincode_compatible() {
+        for poto in samples() {
+            let mut buf = Vec::new();
+            poto.encode(&mut buf);
+            assert_eq!(buf, bincode::serialize(&poto).unwrap(), "{:?}", poto);
+        }
+    }
+}
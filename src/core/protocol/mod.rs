@@ -3,16 +3,21 @@ mod serde;
 #[cfg(feature = "fuso-serde")]
 pub use self::serde::*;
 
-#[cfg(not(feature = "fuso-serde"))]
 mod local;
-
-#[cfg(not(feature = "fuso-serde"))]
 pub use local::*;
 
 pub mod proto;
 pub use proto::*;
 
-use std::{future::Future, pin::Pin, task::Poll};
+mod version;
+pub use version::*;
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::Poll,
+};
 
 use crate::{r#async::ReadBuf, AsyncRead, AsyncWrite, PacketErr, Result};
 
@@ -22,10 +27,40 @@ use crate::{r#async::ReadBuf, AsyncRead, AsyncWrite, PacketErr, Result};
 /// o => 0x6f
 pub const MAGIC: [u8; 4] = [0x66, 0x75, 0x73, 0x6f];
 
+/// 默认允许接收的最大帧, 控制消息与udp转发的数据都远小于该值
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+static MAX_FRAME_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_FRAME_SIZE);
+
 pub fn head_size() -> usize {
     8
 }
 
+/// `recv_packet` 允许接收的最大帧
+pub fn max_frame_size() -> usize {
+    MAX_FRAME_SIZE.load(Ordering::Relaxed)
+}
+
+/// 修改全局的最大帧, 超过该长度的包会返回 [`PacketErr::Oversize`]
+pub fn set_max_frame_size(size: usize) {
+    MAX_FRAME_SIZE.store(size, Ordering::Relaxed)
+}
+
+/// 解析包头, 返回数据长度
+pub fn parse_head(head: &[u8; 8]) -> Result<usize> {
+    let mut magic = [0u8; 4];
+    let mut data_len = [0u8; 4];
+
+    magic.copy_from_slice(&head[..4]);
+    data_len.copy_from_slice(&head[4..]);
+
+    if !self::good_packet(&magic) {
+        return Err(PacketErr::Head(magic).into());
+    }
+
+    Ok(u32::from_le_bytes(data_len) as usize)
+}
+
 pub fn good_packet(magic_buf: &[u8; 4]) -> bool {
     MAGIC.eq(magic_buf)
 }
@@ -61,6 +96,7 @@ where
     buf: Vec<u8>,
     state: State,
     offset: usize,
+    limit: usize,
     #[pin]
     reader: &'a mut R,
 }
@@ -78,6 +114,14 @@ where
 
 pub trait AsyncRecvPacket: AsyncRead {
     fn recv_packet<'a>(&'a mut self) -> RecvPacket<'a, Self>
+    where
+        Self: Unpin + Sized,
+    {
+        self.recv_packet_with_limit(max_frame_size())
+    }
+
+    /// 数据长度超过 `limit` 时返回 [`PacketErr::Oversize`]
+    fn recv_packet_with_limit<'a>(&'a mut self, limit: usize) -> RecvPacket<'a, Self>
     where
         Self: Unpin + Sized,
     {
@@ -85,6 +129,7 @@ pub trait AsyncRecvPacket: AsyncRead {
             buf: Vec::new(),
             state: State::None,
             offset: 0,
+            limit,
             reader: self,
         }
     }
@@ -148,6 +193,7 @@ where
         let mut this = self.project();
         let buf = this.buf;
         let offset = this.offset;
+        let limit = *this.limit;
 
         loop {
             match this.state {
@@ -162,38 +208,34 @@ where
                         buf.resize(head_size(), 0);
                     }
                 }
-                State::Head if *offset == buf.len() => unsafe {
-                    #[allow(unused)]
-                    #[repr(C)]
-                    struct Head {
-                        magic: [u8; 4],
-                        data_len: u32,
-                    }
-
-                    let head = (buf.as_ptr() as *const Head).as_ref().unwrap();
-
-                    if !self::good_packet(&head.magic) {
-                        log::debug!("received illegal package {:x?}", head.magic);
-                        break Poll::Ready(Err(PacketErr::Head(head.magic).into()));
+                State::Head if *offset == buf.len() => {
+                    let mut head = [0u8; 8];
+                    head.copy_from_slice(&buf[..]);
+
+                    let len = match self::parse_head(&head) {
+                        Ok(len) => len,
+                        Err(e) => {
+                            log::debug!("received illegal package {:x?}", &head[..4]);
+                            break Poll::Ready(Err(e));
+                        }
+                    };
+
+                    if len > limit {
+                        log::warn!("refuse to receive {}bytes packet, limit {}bytes", len, limit);
+                        break Poll::Ready(Err(PacketErr::Oversize { size: len, limit }.into()));
                     }
 
-                    let len = head.data_len;
-
                     log::trace!("received legal packet, data size {}bytes", len);
 
                     if len == 0 {
                         break Poll::Ready(Ok(self::empty_packet()));
                     }
 
-                    drop(std::mem::replace(buf, {
-                        let mut buf = Vec::with_capacity(len as usize);
-                        buf.set_len(len as usize);
-                        buf
-                    }));
+                    drop(std::mem::replace(buf, vec![0; len]));
 
                     *offset = 0;
                     drop(std::mem::replace(this.state, State::Body));
-                },
+                }
                 State::Body if *offset == buf.len() => {
                     log::trace!("packet reception completed {}bytes", buf.len());
                     break Poll::Ready(Ok(make_packet(std::mem::replace(buf, Default::default()))));
@@ -225,3 +267,69 @@ where
         }
     }
 }
+
+#[cfg(test)]
+#[cfg(feature = "fuso-rt-tokio")]
+mod tests {
+    use crate::{Kind, PacketErr};
+
+    use super::{make_packet, AsyncRecvPacket, Decode, Poto, MAGIC};
+
+    fn random(seed: &mut u64) -> u64 {
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 7;
+        *seed ^= *seed << 17;
+        *seed
+    }
+
+    fn is_oversize(e: &crate::Error) -> bool {
+        matches!(e.kind(), Kind::Packet(PacketErr::Oversize { .. }))
+    }
+
+    #[tokio::test]
+    async fn test_oversize() {
+        let mut head = MAGIC.to_vec();
+        head.extend(u32::MAX.to_le_bytes());
+
+        let err = (&head[..]).recv_packet().await.unwrap_err();
+        assert!(is_oversize(&err));
+
+        let packet = make_packet(vec![0; 5]).encode();
+        let err = (&packet[..]).recv_packet_with_limit(4).await.unwrap_err();
+        assert!(is_oversize(&err));
+
+        let packet = (&packet[..]).recv_packet_with_limit(5).await.unwrap();
+        assert_eq!(packet.payload, vec![0; 5]);
+    }
+
+    #[tokio::test]
+    async fn test_fuzz_decoder() {
+        let mut seed = 0xdec0de;
+
+        for _ in 0..10000 {
+            let len = random(&mut seed) as usize % 64;
+            let mut data = (0..len).map(|_| random(&mut seed) as u8).collect::<Vec<_>>();
+
+            // 大部分输入使用合法的包头, 以便覆盖包体与消息的解析
+            if random(&mut seed) % 4 != 0 && data.len() >= 8 {
+                data[..4].copy_from_slice(&MAGIC);
+                let body = (data.len() - 8) as u32;
+                let declared = match random(&mut seed) % 3 {
+                    0 => body,
+                    1 => random(&mut seed) as u32 % (body + 1),
+                    _ => random(&mut seed) as u32,
+                };
+                data[4..8].copy_from_slice(&declared.to_le_bytes());
+            }
+
+            let mut reader = &data[..];
+
+            if let Ok(packet) = reader.recv_packet_with_limit(32).await {
+                assert!(packet.payload.len() <= 32);
+                assert_eq!(packet.payload.len(), packet.data_len as usize);
+                let _ = Poto::decode(&mut &packet.payload[..]);
+                let _ = super::decode::<Poto>(&packet.payload);
+            }
+        }
+    }
+}
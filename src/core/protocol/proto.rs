@@ -1,9 +1,12 @@
-use std::fmt::Display;
+use std::{fmt::Display, net::SocketAddr};
 
 use bytes::{BufMut, BytesMut};
+#[cfg(feature = "fuso-serde")]
 use serde::{Deserialize, Serialize};
 
-use crate::{Addr, Socket};
+use crate::{Acl, Addr, Socket};
+
+use super::Version;
 
 pub const MAGIC: u32 = 0xFC;
 
@@ -14,25 +17,58 @@ pub struct Packet {
     pub payload: Vec<u8>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "fuso-serde", derive(Deserialize, Serialize))]
 pub enum Connect {
     TCP(Option<Addr>),
     UDP(Addr),
+    /// 在连接上收发tun的ip包, `Auth` 中携带令牌, 协议版本6开始使用
+    Tun,
+    /// 提供名称对应的p2p服务, 等待服务端分配打洞令牌, 协议版本7开始使用
+    P2pProvide(String),
+    /// 访问名称对应的p2p服务, 协议版本7开始使用
+    P2pVisit(String),
+    /// 服务端分配的打洞令牌、该端的注册密钥与打洞服务的端口, 协议版本7开始使用
+    P2pTicket(String, String, u16),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "fuso-serde", derive(Deserialize, Serialize))]
 pub enum Bind {
     Bind(Socket),
     Failed(Socket, String),
+    /// 携带访问者黑白名单的 `Bind`, 协议版本3开始使用
+    Filter(Socket, Acl),
+    /// 以名称注册映射, 协议版本4开始使用, 服务端回复实际注册的名称
+    Named(String, Socket, Acl),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "fuso-serde", derive(Deserialize, Serialize))]
 pub enum Auth {
     Auth(Vec<u8>),
     NoAuth,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+/// 建立连接后的第一个消息, 用于协商协议版本
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "fuso-serde", derive(Deserialize, Serialize))]
+pub enum Hello {
+    Hello(Version),
+    Accept(Version),
+    Reject(Version, String),
+}
+
+/// 访问者的真实来源, `destination` 为访问者连接的服务端地址
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fuso-serde", derive(Deserialize, Serialize))]
+pub struct Origin {
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "fuso-serde", derive(Deserialize, Serialize))]
 pub enum Poto {
     Ping,
     Close,
@@ -40,7 +76,13 @@ pub enum Poto {
     Bind(Bind),
     Map(u32, Socket),
     Connect(Connect, Auth),
-    Forward(Addr)
+    Forward(Addr),
+    Pong(u64),
+    Hello(Hello),
+    /// 携带访问者地址的 `Map`, 协议版本2开始使用
+    MapFrom(u32, Socket, Origin),
+    /// 携带发送时的时间戳, 对端收到后以 `Pong` 返回, 协议版本5开始使用
+    PingAt(u64),
 }
 
 impl Packet {
@@ -63,14 +105,14 @@ pub trait TryToPoto {
 
 impl ToPacket for Poto {
     fn to_packet_vec(self) -> Vec<u8> {
-        let data = unsafe { bincode::serialize(&self).unwrap_unchecked() };
+        let data = super::encode(&self);
         super::make_packet(data).encode()
     }
 }
 
 impl TryToPoto for Packet {
     fn try_message(self) -> crate::Result<Poto> {
-        bincode::deserialize(&self.payload).map_err(Into::into)
+        super::decode(&self.payload)
     }
 }
 
@@ -0,0 +1,9 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+pub fn encode<T: Serialize>(value: &T) -> Vec<u8> {
+    unsafe { bincode::serialize(value).unwrap_unchecked() }
+}
+
+pub fn decode<T: DeserializeOwned>(buf: &[u8]) -> crate::Result<T> {
+    bincode::deserialize(buf).map_err(Into::into)
+}
@@ -0,0 +1,241 @@
+use std::fmt::Display;
+
+#[cfg(feature = "fuso-serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{AsyncRead, AsyncWrite, Kind};
+
+use super::{AsyncRecvPacket, AsyncSendPacket, Hello, Poto, ToPacket, TryToPoto};
+
+/// 当前协议版本, `Poto` 的编码发生不兼容的变化时递增
+///
+/// 2: 新增 `Poto::MapFrom`
+/// 3: 新增 `Bind::Filter`
+/// 4: 新增 `Bind::Named`
+/// 5: 新增 `Poto::PingAt`, 之前的版本只认识不带时间戳的 `Ping`
+/// 6: 新增 `Connect::Tun`
+/// 7: 新增 `Connect::P2pProvide`, `Connect::P2pVisit`, `Connect::P2pTicket`
+pub const PROTOCOL_VERSION: u32 = 7;
+
+/// 能够兼容的最低协议版本, 不发送 `Hello` 的旧版本视为 0
+pub const MIN_PROTOCOL_VERSION: u32 = 0;
+
+/// 对端支持的能力, 目前只是保留的位, 之后的版本可以在不改变 `Version` 编码的情况下协商
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fuso-serde", derive(Deserialize, Serialize))]
+pub struct Capabilities(u32);
+
+/// 握手时交换的版本信息, 该结构的编码不能再改变
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "fuso-serde", derive(Deserialize, Serialize))]
+pub struct Version {
+    pub protocol: u32,
+    pub min_protocol: u32,
+    pub crate_version: String,
+    pub capabilities: Capabilities,
+}
+
+impl Capabilities {
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn bits(&self) -> u32 {
+        self.0
+    }
+
+    /// 保留未知的位, 以便转交给更新的版本
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    /// 当前没有需要协商的能力, 新增的功能通过协议版本判断
+    pub fn local() -> Self {
+        Self::empty()
+    }
+
+    pub fn intersection(&self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl Display for Capabilities {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#x}", self.0)
+    }
+}
+
+impl Version {
+    pub fn local() -> Self {
+        Self {
+            protocol: PROTOCOL_VERSION,
+            min_protocol: MIN_PROTOCOL_VERSION,
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            capabilities: Capabilities::local(),
+        }
+    }
+
+    /// 没有进行握手的旧版本
+    pub fn legacy() -> Self {
+        Self {
+            protocol: 0,
+            min_protocol: 0,
+            crate_version: String::from("unknown"),
+            capabilities: Capabilities::empty(),
+        }
+    }
+
+    /// 双方都支持的最高协议版本与共同的能力
+    pub fn negotiate(&self, remote: &Version) -> crate::Result<Version> {
+        let protocol = self.protocol.min(remote.protocol);
+        let min_protocol = self.min_protocol.max(remote.min_protocol);
+
+        if protocol < min_protocol {
+            return Err(Kind::Incompatible(format!(
+                "local {} supports protocol {}..={}, remote {} supports protocol {}..={}",
+                self.crate_version,
+                self.min_protocol,
+                self.protocol,
+                remote.crate_version,
+                remote.min_protocol,
+                remote.protocol
+            ))
+            .into());
+        }
+
+        Ok(Version {
+            protocol,
+            min_protocol,
+            crate_version: remote.crate_version.clone(),
+            capabilities: self.capabilities.intersection(remote.capabilities),
+        })
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "version={}, protocol={}, capabilities={}",
+            self.crate_version, self.protocol, self.capabilities
+        )
+    }
+}
+
+/// 客户端发起握手, 返回协商后的版本
+pub async fn hello<S>(stream: &mut S) -> crate::Result<Version>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let local = Version::local();
+    let message = Poto::Hello(Hello::Hello(local.clone())).to_packet_vec();
+
+    stream.send_packet(&message).await?;
+
+    let message = match stream.recv_packet().await {
+        Ok(packet) => packet.try_message()?,
+        Err(e) => {
+            log::warn!(
+                "no response to hello, the server may be older than {}",
+                local.crate_version
+            );
+            return Err(e);
+        }
+    };
+
+    match message {
+        Poto::Hello(Hello::Accept(version)) => Ok(version),
+        Poto::Hello(Hello::Reject(remote, reason)) => {
+            log::error!("the server rejected the handshake, {}", remote);
+            Err(Kind::Incompatible(reason).into())
+        }
+        message => Err(Kind::Unexpected(format!("{}", message)).into()),
+    }
+}
+
+/// 服务端响应握手, 版本不兼容时通知对端后返回错误
+pub async fn accept_hello<S>(stream: &mut S, remote: Version) -> crate::Result<Version>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let local = Version::local();
+
+    match local.negotiate(&remote) {
+        Ok(version) => {
+            let reply = Version {
+                crate_version: local.crate_version,
+                ..version.clone()
+            };
+
+            let message = Poto::Hello(Hello::Accept(reply)).to_packet_vec();
+            stream.send_packet(&message).await?;
+
+            Ok(version)
+        }
+        Err(e) => {
+            let message = Poto::Hello(Hello::Reject(local, e.to_string())).to_packet_vec();
+
+            if let Err(e) = stream.send_packet(&message).await {
+                log::warn!("failed to send reject message err={}", e);
+            }
+
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        protocol::{Hello, Poto, ToPacket, TryToPoto},
+        Kind,
+    };
+
+    use super::{Capabilities, Version};
+
+    #[test]
+    fn test_negotiate() {
+        let local = Version::local();
+
+        let version = local.negotiate(&Version::legacy()).unwrap();
+        assert_eq!(version.protocol, 0);
+        assert_eq!(version.capabilities, Capabilities::empty());
+
+        let newer = Version {
+            protocol: local.protocol + 1,
+            min_protocol: local.protocol + 1,
+            crate_version: String::from("99.0.0"),
+            capabilities: Capabilities::from_bits(0b101),
+        };
+
+        let err = local.negotiate(&newer).unwrap_err();
+        assert!(matches!(err.kind(), Kind::Incompatible(_)));
+
+        let compatible = Version {
+            min_protocol: local.protocol,
+            ..newer
+        };
+
+        let version = local.negotiate(&compatible).unwrap();
+        assert_eq!(version.protocol, local.protocol);
+        assert_eq!(version.capabilities, Capabilities::empty());
+
+        let common = Capabilities::from_bits(0b110).intersection(Capabilities::from_bits(0b011));
+        assert_eq!(common, Capabilities::from_bits(0b010));
+    }
+
+    /// 旧版本的 `Ping` 不带时间戳
+    #[test]
+    fn test_legacy_ping() {
+        let packet = crate::protocol::make_packet(vec![0, 0, 0, 0]);
+        assert_eq!(packet.try_message().unwrap(), Poto::Ping);
+        assert_eq!(Poto::Ping.to_packet_vec()[8..], [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_hello_packet() {
+        let hello = Poto::Hello(Hello::Hello(Version::local()));
+        let packet = crate::protocol::make_packet(hello.clone().to_packet_vec()[8..].to_vec());
+        assert_eq!(packet.try_message().unwrap(), hello);
+    }
+}
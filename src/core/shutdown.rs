@@ -0,0 +1,253 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    task::Poll,
+    time::Duration,
+};
+
+use crate::{time, Executor, Task};
+
+/// 默认等待转发结束的时间
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// 关闭信号, 克隆后共享同一个信号
+#[derive(Clone)]
+pub struct Shutdown {
+    sender: async_channel::Sender<()>,
+    receiver: async_channel::Receiver<()>,
+}
+
+/// 记录运行中的任务, 关闭时等待它们结束或者取消
+#[derive(Clone, Default)]
+pub struct TaskTracker {
+    inner: Arc<Tracked>,
+}
+
+struct Tracked {
+    next_id: AtomicU64,
+    /// 任务加入之前以 `None` 占位
+    tasks: Mutex<HashMap<u64, Option<Task<()>>>>,
+    idle: (async_channel::Sender<()>, async_channel::Receiver<()>),
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        let (sender, receiver) = async_channel::bounded(1);
+        Self { sender, receiver }
+    }
+}
+
+impl Shutdown {
+    /// 通知所有等待者关闭
+    pub fn shutdown(&self) {
+        self.sender.close();
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.sender.is_closed()
+    }
+
+    /// 等待关闭信号
+    pub async fn wait(&self) {
+        let _ = self.receiver.recv().await;
+    }
+
+    /// `fut` 在关闭前完成时返回 `Some`, 收到关闭信号时返回 `None`
+    pub async fn run_until<F>(&self, fut: F) -> Option<F::Output>
+    where
+        F: Future,
+    {
+        let mut fut = std::pin::pin!(fut);
+        let mut wait = std::pin::pin!(self.wait());
+
+        std::future::poll_fn(|cx| {
+            if wait.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(None);
+            }
+
+            fut.as_mut().poll(cx).map(Some)
+        })
+        .await
+    }
+}
+
+impl Default for Tracked {
+    fn default() -> Self {
+        Self {
+            next_id: Default::default(),
+            tasks: Default::default(),
+            idle: async_channel::bounded(1),
+        }
+    }
+}
+
+impl TaskTracker {
+    pub fn spawn<E, F, O>(&self, executor: &E, fut: F)
+    where
+        E: Executor,
+        F: Future<Output = O> + Send + 'static,
+        O: Send + 'static,
+    {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let tracker = self.clone();
+
+        // 先占位再释放锁, 执行器可能在spawn中直接运行任务, 任务结束时需要获取同一个锁
+        self.tasks().insert(id, None);
+
+        let mut task = executor.spawn(async move {
+            let _ = fut.await;
+            tracker.remove(id);
+        });
+
+        let mut tasks = self.tasks();
+
+        if let Some(slot) = tasks.get_mut(&id) {
+            *slot = Some(task);
+            return;
+        }
+
+        // 任务已经结束, 或者在加入之前已被取消
+        drop(tasks);
+        task.abort();
+    }
+
+    pub fn len(&self) -> usize {
+        self.tasks().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks().is_empty()
+    }
+
+    /// 等待所有任务结束
+    pub async fn wait_idle(&self) {
+        while !self.is_empty() {
+            let _ = self.inner.idle.1.recv().await;
+        }
+    }
+
+    /// 取消所有未结束的任务
+    pub fn abort_all(&self) {
+        let tasks = std::mem::take(&mut *self.tasks());
+
+        for mut task in tasks.into_values().flatten() {
+            task.abort();
+        }
+    }
+
+    /// 在 `grace` 内等待任务结束, 超时后取消剩余的任务
+    pub async fn drain(&self, grace: Duration) {
+        if time::wait_for(grace, self.wait_idle()).await.is_err() {
+            log::warn!("{} tasks are still running, abort them", self.len());
+            self.abort_all();
+        }
+    }
+
+    fn remove(&self, id: u64) {
+        let mut tasks = self.tasks();
+        let task = tasks.remove(&id);
+        let is_idle = tasks.is_empty();
+
+        drop(tasks);
+        drop(task);
+
+        if is_idle {
+            let _ = self.inner.idle.0.try_send(());
+        }
+    }
+
+    fn tasks(&self) -> MutexGuard<'_, HashMap<u64, Option<Task<()>>>> {
+        self.inner
+            .tasks
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "fuso-rt-tokio")]
+mod tests {
+    use std::{
+        future::Future,
+        marker::PhantomData,
+        task::{Context, Waker},
+        time::Duration,
+    };
+
+    use crate::{Executor, Shutdown, Task, TaskTracker, TokioExecutor};
+
+    /// 在spawn中直接运行任务的执行器
+    struct Inline;
+
+    impl Executor for Inline {
+        fn spawn<F, O>(&self, fut: F) -> Task<O>
+        where
+            F: Future<Output = O> + Send + 'static,
+            O: Send + 'static,
+        {
+            let mut fut = std::pin::pin!(fut);
+            let mut cx = Context::from_waker(Waker::noop());
+            assert!(fut.as_mut().poll(&mut cx).is_ready());
+
+            Task {
+                detach_task_fn: None,
+                abort_task_fn: None,
+                _marked: PhantomData,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_drain() {
+        let tracker = TaskTracker::default();
+
+        tracker.spawn(&TokioExecutor, async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        });
+
+        tracker.spawn(&TokioExecutor, async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+        });
+
+        assert_eq!(tracker.len(), 2);
+
+        tracker.drain(Duration::from_millis(100)).await;
+
+        assert!(tracker.is_empty());
+    }
+
+    /// 任务在spawn中结束时不能死锁, 也不能留下记录
+    #[test]
+    fn test_inline_executor() {
+        let tracker = TaskTracker::default();
+
+        tracker.spawn(&Inline, async {});
+
+        assert!(tracker.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_run_until() {
+        let shutdown = Shutdown::default();
+
+        assert_eq!(shutdown.run_until(async { 1 }).await, Some(1));
+
+        let handle = shutdown.clone();
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            handle.shutdown();
+        });
+
+        let r = shutdown
+            .run_until(tokio::time::sleep(Duration::from_secs(60)))
+            .await;
+
+        assert!(r.is_none());
+        assert!(shutdown.is_shutdown());
+    }
+}
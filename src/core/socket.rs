@@ -5,6 +5,7 @@ use std::{
     str::FromStr,
 };
 
+#[cfg(feature = "fuso-serde")]
 use serde::{Deserialize, Serialize};
 
 use crate::{Error, InvalidAddr};
@@ -33,16 +34,19 @@ pub enum Address {
     Many(Vec<Address>),
 }
 
-#[derive(Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "fuso-serde", derive(Deserialize, Serialize))]
 pub enum InnerAddr {
     Socket(SocketAddr),
     Domain(String, u16),
 }
 
-#[derive(Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "fuso-serde", derive(Deserialize, Serialize))]
 pub struct Addr(InnerAddr);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fuso-serde", derive(Deserialize, Serialize))]
 pub enum SocketKind {
     Kcp,
     Udp,
@@ -52,7 +56,8 @@ pub enum SocketKind {
     Ufd,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "fuso-serde", derive(Deserialize, Serialize))]
 pub struct Socket {
     kind: SocketKind,
     target: Addr,
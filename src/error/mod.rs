@@ -28,6 +28,8 @@ pub enum Encoding {
 #[derive(Debug)]
 pub enum PacketErr {
     Head([u8; 4]),
+    /// 数据长度超过了允许的最大帧
+    Oversize { size: usize, limit: usize },
 }
 
 #[derive(Debug)]
@@ -74,15 +76,49 @@ pub enum EncryptionErr {
     Rsa(rsa::errors::Error),
 }
 
+#[cfg(feature = "fuso-quic")]
+#[derive(Debug)]
+pub enum QuicErr {
+    Tls(rustls::Error),
+    Config(String),
+    Certificate(rcgen::Error),
+    Connect(quinn::ConnectError),
+    Connection(quinn::ConnectionError),
+    EndpointClosed,
+}
+
+#[derive(Debug)]
+pub enum UpstreamErr {
+    InvalidUrl(String),
+    /// http代理返回的状态行
+    Http(String),
+    /// socks5代理返回的错误码
+    Socks(u8),
+    Auth,
+}
+
+#[derive(Debug)]
+pub enum IdleErr {
+    /// 连接空闲超时
+    Read(std::time::Duration),
+    /// 写入阻塞超时
+    Write(std::time::Duration),
+}
+
+#[derive(Debug)]
+pub enum ProxyProtocolErr {
+    /// 可信来源的连接没有以PROXY协议头开始
+    Missing,
+    /// 协议头格式错误
+    Invalid(String),
+}
+
 #[derive(Debug)]
 pub enum Kind {
     Channel,
     AlreadyUsed,
     IO(std::io::Error),
-    #[cfg(feature = "fuso-rt-tokio")]
-    Timeout(tokio::time::error::Elapsed),
-    #[cfg(feature = "fuso-rt-smol")]
-    Timeout(std::time::Instant),
+    Timeout(crate::time::Elapsed),
     Memory,
     Mark,
     Sync(SyncErr),
@@ -100,6 +136,12 @@ pub enum Kind {
     Compress(CompressErr),
     Socket(SocketErr),
     Encryption(EncryptionErr),
+    #[cfg(feature = "fuso-quic")]
+    Quic(QuicErr),
+    Upstream(UpstreamErr),
+    Idle(IdleErr),
+    Incompatible(String),
+    ProxyProtocol(ProxyProtocolErr),
 }
 
 impl Display for SyncErr {
@@ -138,6 +180,9 @@ impl Display for PacketErr {
         write!(f, "{}", {
             match self {
                 PacketErr::Head(e) => format!("invalid packet head {:?}", e),
+                PacketErr::Oversize { size, limit } => {
+                    format!("packet of {}bytes exceeds the limit of {}bytes", size, limit)
+                }
             }
         })
     }
@@ -227,6 +272,49 @@ impl Display for EncryptionErr {
     }
 }
 
+#[cfg(feature = "fuso-quic")]
+impl Display for QuicErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QuicErr::Tls(e) => write!(f, "quic tls {}", e),
+            QuicErr::Config(e) => write!(f, "quic config {}", e),
+            QuicErr::Certificate(e) => write!(f, "quic certificate {}", e),
+            QuicErr::Connect(e) => write!(f, "quic connect {}", e),
+            QuicErr::Connection(e) => write!(f, "quic connection {}", e),
+            QuicErr::EndpointClosed => write!(f, "quic endpoint closed"),
+        }
+    }
+}
+
+impl Display for UpstreamErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UpstreamErr::InvalidUrl(url) => write!(f, "invalid upstream proxy {}", url),
+            UpstreamErr::Http(status) => write!(f, "upstream http proxy responded {}", status),
+            UpstreamErr::Socks(rep) => write!(f, "upstream socks5 proxy responded {}", rep),
+            UpstreamErr::Auth => write!(f, "upstream proxy authentication failed"),
+        }
+    }
+}
+
+impl Display for IdleErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IdleErr::Read(timeout) => write!(f, "connection idle for {:?}", timeout),
+            IdleErr::Write(timeout) => write!(f, "write stalled for {:?}", timeout),
+        }
+    }
+}
+
+impl Display for ProxyProtocolErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProxyProtocolErr::Missing => write!(f, "missing proxy protocol header"),
+            ProxyProtocolErr::Invalid(e) => write!(f, "invalid proxy protocol header, {}", e),
+        }
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let fmt = match self.kind() {
@@ -253,6 +341,12 @@ impl Display for Error {
             }
             Kind::Socket(socket) => format!("{}", socket),
             Kind::Encryption(e) => format!("{}", e),
+            #[cfg(feature = "fuso-quic")]
+            Kind::Quic(e) => format!("{}", e),
+            Kind::Upstream(e) => format!("{}", e),
+            Kind::Idle(e) => format!("{}", e),
+            Kind::Incompatible(e) => format!("incompatible version, {}", e),
+            Kind::ProxyProtocol(e) => format!("{}", e),
         };
         write!(f, "{}", fmt)
     }
@@ -344,6 +438,7 @@ impl From<std::cell::BorrowMutError> for Error {
     }
 }
 
+#[cfg(feature = "fuso-serde")]
 impl From<bincode::Error> for Error {
     fn from(e: bincode::Error) -> Self {
         Kind::Deserialize(e.to_string()).into()
@@ -352,8 +447,8 @@ impl From<bincode::Error> for Error {
 
 #[cfg(feature = "fuso-rt-tokio")]
 impl From<tokio::time::error::Elapsed> for Error {
-    fn from(e: tokio::time::error::Elapsed) -> Self {
-        Kind::Timeout(e).into()
+    fn from(_: tokio::time::error::Elapsed) -> Self {
+        Kind::Timeout(crate::time::Elapsed).into()
     }
 }
 
@@ -375,10 +470,28 @@ impl From<PacketErr> for Error {
     }
 }
 
-#[cfg(feature = "fuso-rt-smol")]
-impl From<std::time::Instant> for Error {
-    fn from(e: std::time::Instant) -> Self {
-        Kind::Timeout(e).into()
+#[cfg(feature = "fuso-quic")]
+impl From<QuicErr> for Error {
+    fn from(e: QuicErr) -> Self {
+        Kind::Quic(e).into()
+    }
+}
+
+impl From<UpstreamErr> for Error {
+    fn from(e: UpstreamErr) -> Self {
+        Kind::Upstream(e).into()
+    }
+}
+
+impl From<IdleErr> for Error {
+    fn from(e: IdleErr) -> Self {
+        Kind::Idle(e).into()
+    }
+}
+
+impl From<ProxyProtocolErr> for Error {
+    fn from(e: ProxyProtocolErr) -> Self {
+        Kind::ProxyProtocol(e).into()
     }
 }
 
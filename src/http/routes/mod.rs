@@ -0,0 +1,15 @@
+use axum::{extract::Extension, routing::get, Json, Router};
+
+use crate::penetrate::{Mapping, Registry};
+
+/// 服务端的接口, 需要调用方提供映射名称表
+pub fn router(registry: Registry) -> Router {
+    Router::new()
+        .route("/mappings", get(mappings))
+        .layer(Extension(registry))
+}
+
+/// 按名称排序的已注册映射
+async fn mappings(Extension(registry): Extension<Registry>) -> Json<Vec<Mapping>> {
+    Json(registry.mappings())
+}
@@ -4,6 +4,9 @@ mod error;
 mod net;
 mod runtime;
 
+#[cfg(test)]
+mod testing;
+
 pub mod client;
 pub mod server;
 
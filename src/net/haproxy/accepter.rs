@@ -0,0 +1,281 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use crate::{
+    protocol::Origin, server::ServerBuilder, time, Accepter, Address, AsyncRead, AsyncWrite, Cidr,
+    FusoStream, NetSocket, Provider, ServerProvider, Socket,
+};
+
+type BoxedFuture<T> = Pin<Box<dyn Future<Output = crate::Result<T>> + Send + 'static>>;
+
+/// 等待可信来源发送协议头的时间
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 为监听器增加PROXY协议握手, 只解析来自可信网段的tcp连接
+pub struct ProxyProtocolAccepter<P> {
+    provider: Arc<P>,
+    trusted: Arc<Vec<Cidr>>,
+}
+
+pub struct ProxyProtocolListener<A> {
+    accepter: A,
+    trusted: Arc<Vec<Cidr>>,
+    handshakes: Vec<BoxedFuture<FusoStream>>,
+}
+
+/// 经过PROXY协议握手的连接, `peer_addr` 返回协议头中的真实来源
+pub struct ProxiedStream<S> {
+    stream: S,
+    origin: Origin,
+}
+
+impl<P, A> Provider<Socket> for ProxyProtocolAccepter<P>
+where
+    P: Provider<Socket, Output = BoxedFuture<A>> + Send + Sync + 'static,
+    A: Accepter<Stream = FusoStream> + Unpin + Send + 'static,
+{
+    type Output = BoxedFuture<ProxyProtocolListener<A>>;
+
+    fn call(&self, socket: Socket) -> Self::Output {
+        let accepter = self.provider.call(socket);
+        let trusted = self.trusted.clone();
+
+        Box::pin(async move {
+            Ok(ProxyProtocolListener {
+                accepter: accepter.await?,
+                trusted,
+                handshakes: Vec::new(),
+            })
+        })
+    }
+}
+
+impl<A> ProxyProtocolListener<A> {
+    fn is_trusted(&self, stream: &FusoStream) -> bool {
+        if self.trusted.is_empty() {
+            return false;
+        }
+
+        match stream.peer_addr() {
+            Ok(Address::Single(socket)) if socket.is_tcp() => match socket.ip() {
+                Some(ip) => self.trusted.iter().any(|cidr| cidr.contains(&ip)),
+                None => false,
+            },
+            _ => false,
+        }
+    }
+
+    fn handshake(mut stream: FusoStream) -> BoxedFuture<FusoStream> {
+        Box::pin(async move {
+            let origin = time::wait_for(HANDSHAKE_TIMEOUT, super::read_header(&mut stream)).await?;
+
+            match origin? {
+                None => Ok(stream),
+                Some(origin) => {
+                    log::debug!(
+                        "proxy protocol from {}, the real address is {}",
+                        stream.peer_addr()?,
+                        origin.source
+                    );
+
+                    Ok(FusoStream::new(ProxiedStream { stream, origin }))
+                }
+            }
+        })
+    }
+}
+
+impl<A> NetSocket for ProxyProtocolListener<A>
+where
+    A: NetSocket,
+{
+    fn peer_addr(&self) -> crate::Result<Address> {
+        self.accepter.peer_addr()
+    }
+
+    fn local_addr(&self) -> crate::Result<Address> {
+        self.accepter.local_addr()
+    }
+}
+
+impl<A> Accepter for ProxyProtocolListener<A>
+where
+    A: Accepter<Stream = FusoStream> + Unpin,
+{
+    type Stream = FusoStream;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<crate::Result<Self::Stream>> {
+        while let Poll::Ready(stream) = Pin::new(&mut self.accepter).poll_accept(cx)? {
+            if !self.is_trusted(&stream) {
+                return Poll::Ready(Ok(stream));
+            }
+
+            self.handshakes.push(Self::handshake(stream));
+        }
+
+        let mut index = 0;
+
+        while index < self.handshakes.len() {
+            match Pin::new(&mut self.handshakes[index]).poll(cx) {
+                Poll::Pending => index += 1,
+                Poll::Ready(r) => {
+                    drop(self.handshakes.swap_remove(index));
+
+                    match r {
+                        Ok(stream) => return Poll::Ready(Ok(stream)),
+                        Err(e) => log::warn!("proxy protocol handshake failed {}", e),
+                    }
+                }
+            }
+        }
+
+        Poll::Pending
+    }
+}
+
+impl<S> NetSocket for ProxiedStream<S>
+where
+    S: NetSocket,
+{
+    fn peer_addr(&self) -> crate::Result<Address> {
+        Ok(Address::Single(Socket::tcp(self.origin.source)))
+    }
+
+    fn local_addr(&self) -> crate::Result<Address> {
+        self.stream.local_addr()
+    }
+
+    #[cfg(all(target_os = "linux", feature = "fuso-splice"))]
+    fn as_raw_socket(&self) -> Option<&dyn crate::splice::RawSocket> {
+        self.stream.as_raw_socket()
+    }
+}
+
+impl<S> AsyncRead for ProxiedStream<S>
+where
+    S: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut crate::ReadBuf<'_>,
+    ) -> Poll<crate::Result<usize>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl<S> AsyncWrite for ProxiedStream<S>
+where
+    S: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<crate::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<crate::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<crate::Result<()>> {
+        Pin::new(&mut self.stream).poll_close(cx)
+    }
+
+    fn is_half_closable(&self) -> bool {
+        self.stream.is_half_closable()
+    }
+}
+
+impl<E, SF, CF, A> ServerBuilder<E, SF, CF, FusoStream>
+where
+    SF: Provider<Socket, Output = BoxedFuture<A>> + Send + Sync + 'static,
+    A: Accepter<Stream = FusoStream> + Unpin + Send + 'static,
+{
+    /// 服务端位于tcp负载均衡之后时, 解析来自 `trusted` 的PROXY协议头 (v1/v2),
+    /// 之后访问者与客户端连接的 `peer_addr` 为真实地址, `trusted` 为空时不解析
+    pub fn with_proxy_protocol(
+        self,
+        trusted: Vec<Cidr>,
+    ) -> ServerBuilder<E, ProxyProtocolAccepter<SF>, CF, FusoStream> {
+        ServerBuilder {
+            executor: self.executor,
+            handshake: self.handshake,
+            is_mixed: self.is_mixed,
+            server_provider: ServerProvider {
+                connector_provider: self.server_provider.connector_provider,
+                accepter_provider: Arc::new(ProxyProtocolAccepter {
+                    provider: self.server_provider.accepter_provider,
+                    trusted: Arc::new(trusted),
+                }),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "fuso-rt-tokio")]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::{io::AsyncWriteExt, net::TcpStream};
+
+    use crate::{
+        ext::AsyncReadExt, haproxy::ProxyProtocol, protocol::Origin, AccepterExt, Address,
+        NetSocket, Provider, Socket, TokioAccepter,
+    };
+
+    use super::ProxyProtocolAccepter;
+
+    async fn accept(trusted: &str, data: &[u8]) -> (Address, Vec<u8>) {
+        let provider = ProxyProtocolAccepter {
+            provider: Arc::new(TokioAccepter),
+            trusted: Arc::new(vec![trusted.parse().unwrap()]),
+        };
+
+        let mut listener = provider.call(Socket::tcp(([127, 0, 0, 1], 0))).await.unwrap();
+        let addr = match listener.local_addr().unwrap() {
+            Address::Single(socket) => socket.as_string(),
+            Address::Many(_) => unreachable!(),
+        };
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(data).await.unwrap();
+
+        let mut stream = listener.accept().await.unwrap();
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+
+        (stream.peer_addr().unwrap(), buf.to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_proxy_protocol_accepter() {
+        let origin = Origin {
+            source: "203.0.113.7:40000".parse().unwrap(),
+            destination: "127.0.0.1:6722".parse().unwrap(),
+        };
+
+        let mut data = ProxyProtocol::V2.header(Some(&origin));
+        data.extend_from_slice(b"hello");
+
+        let (peer, buf) = accept("127.0.0.0/8", &data).await;
+        assert_eq!(peer.to_string(), Socket::tcp(origin.source).to_string());
+        assert_eq!(buf, b"hello");
+
+        // 不可信的来源不解析协议头
+        let (peer, buf) = accept("10.0.0.0/8", b"hello").await;
+        assert_ne!(peer.to_string(), Socket::tcp(origin.source).to_string());
+        assert_eq!(buf, b"hello");
+    }
+}
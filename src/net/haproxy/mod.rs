@@ -0,0 +1,357 @@
+//! HAProxy PROXY protocol
+//!
+//! <https://www.haproxy.org/download/2.8/doc/proxy-protocol.txt>
+
+mod accepter;
+pub use accepter::*;
+
+use std::{
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+};
+
+use crate::{ext::AsyncReadExt, protocol::Origin, AsyncRead, ProxyProtocolErr};
+
+/// v2 协议头的固定签名
+pub const SIGNATURE: [u8; 12] = [
+    0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+];
+
+/// v1 协议头的最大长度, 包括结尾的 `\r\n`
+const V1_MAX_LENGTH: usize = 107;
+
+/// 向本地服务发送的PROXY协议版本
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyProtocol {
+    V1,
+    V2,
+}
+
+impl ProxyProtocol {
+    /// 生成协议头, 来源未知时 v1 发送 `UNKNOWN`, v2 发送 `LOCAL` 命令
+    pub fn header(&self, origin: Option<&Origin>) -> Vec<u8> {
+        match self {
+            ProxyProtocol::V1 => encode_v1(origin),
+            ProxyProtocol::V2 => encode_v2(origin),
+        }
+    }
+}
+
+impl FromStr for ProxyProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "v1" | "1" => Self::V1,
+            "v2" | "2" => Self::V2,
+            _ => return Err(format!("unknown proxy protocol version {}", s)),
+        })
+    }
+}
+
+impl Display for ProxyProtocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProxyProtocol::V1 => write!(f, "v1"),
+            ProxyProtocol::V2 => write!(f, "v2"),
+        }
+    }
+}
+
+/// 两端地址族不同时, 把ipv4转换为ipv6映射地址
+fn unify(origin: &Origin) -> (SocketAddr, SocketAddr) {
+    let to_v6 = |addr: SocketAddr| match addr.ip() {
+        IpAddr::V4(ip) => SocketAddr::new(IpAddr::V6(ip.to_ipv6_mapped()), addr.port()),
+        IpAddr::V6(_) => addr,
+    };
+
+    match (origin.source, origin.destination) {
+        (src @ SocketAddr::V4(_), dst @ SocketAddr::V4(_)) => (src, dst),
+        (src, dst) => (to_v6(src), to_v6(dst)),
+    }
+}
+
+fn encode_v1(origin: Option<&Origin>) -> Vec<u8> {
+    let header = match origin.map(unify) {
+        None => String::from("PROXY UNKNOWN\r\n"),
+        Some((src, dst)) => format!(
+            "PROXY {} {} {} {} {}\r\n",
+            if src.is_ipv4() { "TCP4" } else { "TCP6" },
+            src.ip(),
+            dst.ip(),
+            src.port(),
+            dst.port()
+        ),
+    };
+
+    header.into_bytes()
+}
+
+fn encode_v2(origin: Option<&Origin>) -> Vec<u8> {
+    let mut header = SIGNATURE.to_vec();
+
+    match origin.map(unify) {
+        None => {
+            // LOCAL, AF_UNSPEC
+            header.extend_from_slice(&[0x20, 0x00, 0x00, 0x00]);
+        }
+        Some((src, dst)) => {
+            // PROXY
+            header.push(0x21);
+
+            match (src, dst) {
+                (SocketAddr::V4(src), SocketAddr::V4(dst)) => {
+                    // TCP over IPv4
+                    header.push(0x11);
+                    header.extend_from_slice(&12u16.to_be_bytes());
+                    header.extend_from_slice(&src.ip().octets());
+                    header.extend_from_slice(&dst.ip().octets());
+                }
+                (src, dst) => {
+                    // TCP over IPv6
+                    let ip = |addr: SocketAddr| match addr.ip() {
+                        IpAddr::V6(ip) => ip.octets(),
+                        IpAddr::V4(ip) => ip.to_ipv6_mapped().octets(),
+                    };
+
+                    header.push(0x21);
+                    header.extend_from_slice(&36u16.to_be_bytes());
+                    header.extend_from_slice(&ip(src));
+                    header.extend_from_slice(&ip(dst));
+                }
+            }
+
+            header.extend_from_slice(&src.port().to_be_bytes());
+            header.extend_from_slice(&dst.port().to_be_bytes());
+        }
+    }
+
+    header
+}
+
+/// 读取并解析协议头, 只读取协议头本身, 不会多读后面的数据
+///
+/// 返回 `None` 表示 v1 的 `UNKNOWN` 或 v2 的 `LOCAL`, 此时应使用连接自身的地址
+pub async fn read_header<S>(stream: &mut S) -> crate::Result<Option<Origin>>
+where
+    S: AsyncRead + Unpin,
+{
+    // v1 最短的协议头 "PROXY UNKNOWN\r\n" 也不少于12字节
+    let mut head = [0u8; 12];
+    stream.read_exact(&mut head).await?;
+
+    if head == SIGNATURE {
+        let mut head = [0u8; 4];
+        stream.read_exact(&mut head).await?;
+
+        let len = u16::from_be_bytes([head[2], head[3]]) as usize;
+        let mut data = vec![0u8; len];
+        stream.read_exact(&mut data).await?;
+
+        decode_v2(head[0], head[1], &data)
+    } else if head.starts_with(b"PROXY ") {
+        let mut line = head.to_vec();
+
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= V1_MAX_LENGTH {
+                return Err(ProxyProtocolErr::Invalid(String::from("line too long")).into());
+            }
+
+            let mut byte = [0u8; 1];
+            stream.read_exact(&mut byte).await?;
+            line.push(byte[0]);
+        }
+
+        decode_v1(&line[..line.len() - 2])
+    } else {
+        Err(ProxyProtocolErr::Missing.into())
+    }
+}
+
+fn decode_v1(line: &[u8]) -> crate::Result<Option<Origin>> {
+    let invalid = || ProxyProtocolErr::Invalid(String::from_utf8_lossy(line).into_owned());
+
+    let line = std::str::from_utf8(line).map_err(|_| invalid())?;
+    let fields = line.split(' ').collect::<Vec<_>>();
+
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", proto @ ("TCP4" | "TCP6"), src, dst, sport, dport] => {
+            let ip = |ip: &str| match *proto {
+                "TCP4" => ip.parse::<Ipv4Addr>().map(IpAddr::V4).ok(),
+                _ => ip.parse::<Ipv6Addr>().map(IpAddr::V6).ok(),
+            };
+
+            let port = |port: &str| port.parse::<u16>().ok();
+
+            Ok(Some(Origin {
+                source: SocketAddr::new(
+                    ip(src).ok_or_else(invalid)?,
+                    port(sport).ok_or_else(invalid)?,
+                ),
+                destination: SocketAddr::new(
+                    ip(dst).ok_or_else(invalid)?,
+                    port(dport).ok_or_else(invalid)?,
+                ),
+            }))
+        }
+        _ => Err(invalid().into()),
+    }
+}
+
+fn decode_v2(ver_cmd: u8, family: u8, data: &[u8]) -> crate::Result<Option<Origin>> {
+    let invalid = |e: &str| ProxyProtocolErr::Invalid(String::from(e));
+
+    if ver_cmd >> 4 != 2 {
+        return Err(invalid("unsupported version").into());
+    }
+
+    match ver_cmd & 0x0F {
+        // LOCAL, 负载均衡器自身的连接, 如健康检查
+        0x00 => return Ok(None),
+        0x01 => {}
+        _ => return Err(invalid("unsupported command").into()),
+    }
+
+    let origin = match family >> 4 {
+        // AF_INET
+        0x01 if data.len() >= 12 => {
+            let ip = |offset: usize| {
+                IpAddr::V4(Ipv4Addr::new(
+                    data[offset],
+                    data[offset + 1],
+                    data[offset + 2],
+                    data[offset + 3],
+                ))
+            };
+
+            let port = |offset: usize| u16::from_be_bytes([data[offset], data[offset + 1]]);
+
+            Origin {
+                source: SocketAddr::new(ip(0), port(8)),
+                destination: SocketAddr::new(ip(4), port(10)),
+            }
+        }
+        // AF_INET6
+        0x02 if data.len() >= 36 => {
+            let ip = |offset: usize| {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(&data[offset..offset + 16]);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            };
+
+            let port = |offset: usize| u16::from_be_bytes([data[offset], data[offset + 1]]);
+
+            Origin {
+                source: SocketAddr::new(ip(0), port(32)),
+                destination: SocketAddr::new(ip(16), port(34)),
+            }
+        }
+        // AF_UNSPEC, AF_UNIX
+        0x00 | 0x03 => return Ok(None),
+        _ => return Err(invalid("bad address").into()),
+    };
+
+    Ok(Some(origin))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::Origin;
+
+    use super::{decode_v1, decode_v2, ProxyProtocol, SIGNATURE};
+
+    fn origin(source: &str, destination: &str) -> Origin {
+        Origin {
+            source: source.parse().unwrap(),
+            destination: destination.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_v1_header() {
+        let v4 = origin("192.0.2.10:51234", "198.51.100.1:9999");
+        assert_eq!(
+            ProxyProtocol::V1.header(Some(&v4)),
+            b"PROXY TCP4 192.0.2.10 198.51.100.1 51234 9999\r\n"
+        );
+
+        let mixed = origin("192.0.2.10:51234", "[2001:db8::1]:443");
+        assert_eq!(
+            ProxyProtocol::V1.header(Some(&mixed)),
+            b"PROXY TCP6 ::ffff:192.0.2.10 2001:db8::1 51234 443\r\n"
+        );
+
+        assert_eq!(ProxyProtocol::V1.header(None), b"PROXY UNKNOWN\r\n");
+    }
+
+    #[test]
+    fn test_v2_header() {
+        let v4 = origin("192.0.2.10:51234", "198.51.100.1:9999");
+        let header = ProxyProtocol::V2.header(Some(&v4));
+
+        assert_eq!(&header[..12], &SIGNATURE);
+        assert_eq!(
+            &header[12..],
+            &[0x21, 0x11, 0x00, 0x0C, 192, 0, 2, 10, 198, 51, 100, 1, 0xC8, 0x22, 0x27, 0x0F]
+        );
+
+        let v6 = origin("[2001:db8::1]:80", "[2001:db8::2]:443");
+        let header = ProxyProtocol::V2.header(Some(&v6));
+        assert_eq!(header.len(), 16 + 36);
+        assert_eq!(&header[12..16], &[0x21, 0x21, 0x00, 0x24]);
+
+        let header = ProxyProtocol::V2.header(None);
+        assert_eq!(&header[12..], &[0x20, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn test_decode() {
+        for origin in [
+            origin("192.0.2.10:51234", "198.51.100.1:9999"),
+            origin("[2001:db8::1]:80", "[2001:db8::2]:443"),
+        ] {
+            let header = ProxyProtocol::V1.header(Some(&origin));
+            let line = &header[..header.len() - 2];
+            assert_eq!(decode_v1(line).unwrap(), Some(origin));
+
+            let header = ProxyProtocol::V2.header(Some(&origin));
+            assert_eq!(
+                decode_v2(header[12], header[13], &header[16..]).unwrap(),
+                Some(origin)
+            );
+        }
+
+        assert_eq!(decode_v1(b"PROXY UNKNOWN ffff::1 ffff::2 1 2").unwrap(), None);
+        assert_eq!(decode_v2(0x20, 0x00, &[]).unwrap(), None);
+
+        assert!(decode_v1(b"PROXY TCP4 192.0.2.10 198.51.100.1 51234").is_err());
+        assert!(decode_v1(b"PROXY TCP4 2001:db8::1 198.51.100.1 1 2").is_err());
+        assert!(decode_v1(b"PROXY TCP4 192.0.2.10 198.51.100.1 65536 2").is_err());
+        assert!(decode_v2(0x11, 0x11, &[0; 12]).is_err());
+        assert!(decode_v2(0x21, 0x11, &[0; 11]).is_err());
+    }
+
+    #[cfg(feature = "fuso-rt-tokio")]
+    #[tokio::test]
+    async fn test_read_header() {
+        let origin = origin("192.0.2.10:51234", "198.51.100.1:9999");
+
+        for proxy_protocol in [ProxyProtocol::V1, ProxyProtocol::V2] {
+            let mut data = proxy_protocol.header(Some(&origin));
+            data.extend_from_slice(b"hello");
+
+            let mut stream = data.as_slice();
+            assert_eq!(super::read_header(&mut stream).await.unwrap(), Some(origin));
+            assert_eq!(stream, b"hello");
+        }
+
+        let mut stream = &b"GET / HTTP/1.1\r\n\r\n"[..];
+        assert!(super::read_header(&mut stream).await.is_err());
+
+        let mut long = b"PROXY ".to_vec();
+        long.resize(200, b'a');
+        assert!(super::read_header(&mut long.as_slice()).await.is_err());
+    }
+}
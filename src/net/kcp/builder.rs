@@ -5,12 +5,13 @@ use crate::{
     FusoStream, NetSocket, Socket, ToBoxStream, UdpSocket, Address,
 };
 
-use super::KcpListener;
+use super::{KcpConfig, KcpListener};
 
 type BoxedFuture<T> = Pin<Box<dyn Future<Output = crate::Result<T>> + Send + 'static>>;
 
 pub struct KcpAccepterProvider<C, E> {
     provider: ProviderWrapper<Socket, C>,
+    config: KcpConfig,
     executor: E,
 }
 
@@ -65,6 +66,7 @@ where
     pub fn with_kcp_accepter<F, U>(
         self,
         provider: F,
+        config: KcpConfig,
         executor: E,
     ) -> ServerBuilder<E, MixListener<SF, KcpAccepterProvider<U, E>, FusoStream>, CF, FusoStream>
     where
@@ -72,6 +74,7 @@ where
         U: UdpSocket + Clone + Sync + Unpin + Send + 'static,
     {
         self.add_accepter(KcpAccepterProvider {
+            config,
             executor,
             provider: ProviderWrapper::wrap(provider),
         })
@@ -87,7 +90,14 @@ where
 
     fn call(&self, arg: Socket) -> Self::Output {
         let fut = self.provider.call(arg);
+        let config = self.config.clone();
         let executor = self.executor.clone();
-        Box::pin(async move { Ok(KcpAccepter(KcpListener::bind(fut.await?, executor)?)) })
+        Box::pin(async move {
+            Ok(KcpAccepter(KcpListener::bind_with_config(
+                fut.await?,
+                config,
+                executor,
+            )?))
+        })
     }
 }
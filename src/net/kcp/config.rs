@@ -0,0 +1,131 @@
+use std::str::FromStr;
+
+use crate::AsyncWrite;
+
+use super::{third_party::Kcp, FecConfig, FEC_OVERHEAD};
+
+/// kcp会话参数, 服务端与客户端应使用相同的配置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KcpConfig {
+    /// 发送窗口
+    pub snd_wnd: u16,
+    /// 接收窗口
+    pub rcv_wnd: u16,
+    /// 是否启用nodelay
+    pub nodelay: bool,
+    /// 内部刷新间隔(毫秒)
+    pub interval: i32,
+    /// 快速重传, 0表示关闭
+    pub resend: i32,
+    /// 是否关闭拥塞控制
+    pub nc: bool,
+    /// 最小重传超时(毫秒), 未指定时由nodelay决定
+    pub min_rto: Option<u32>,
+    pub mtu: usize,
+    /// 同一个包重传超过该次数后认为连接已断开
+    pub dead_link: u32,
+    /// 流模式, 发送的数据不保留消息边界
+    pub stream: bool,
+    /// 前向纠错, 仅对发起连接的一端有效, 监听端跟随对端的设置
+    pub fec: Option<FecConfig>,
+}
+
+impl KcpConfig {
+    pub fn normal() -> Self {
+        Self {
+            snd_wnd: 512,
+            rcv_wnd: 512,
+            nodelay: false,
+            interval: 40,
+            resend: 2,
+            nc: true,
+            min_rto: None,
+            mtu: 1400,
+            dead_link: 20,
+            stream: false,
+            fec: None,
+        }
+    }
+
+    pub fn fast() -> Self {
+        Self {
+            snd_wnd: 1024,
+            rcv_wnd: 1024,
+            nodelay: true,
+            interval: 20,
+            resend: 2,
+            nc: true,
+            min_rto: None,
+            mtu: 1400,
+            dead_link: 10,
+            stream: false,
+            fec: None,
+        }
+    }
+
+    pub fn turbo() -> Self {
+        Self {
+            snd_wnd: 2048,
+            rcv_wnd: 2048,
+            nodelay: true,
+            interval: 10,
+            resend: 2,
+            nc: true,
+            min_rto: Some(10),
+            mtu: 1400,
+            dead_link: 10,
+            stream: false,
+            fec: None,
+        }
+    }
+
+    /// 接收udp数据时使用的缓冲区大小
+    pub(crate) fn recv_buf_size(&self) -> usize {
+        self.mtu.max(1500)
+    }
+
+    pub(crate) fn make_kcp<O>(&self, conv: u32, output: O) -> crate::Result<Kcp<O>>
+    where
+        O: AsyncWrite + Unpin + 'static,
+    {
+        let mut kcp = if self.stream {
+            Kcp::new_stream(conv, output)
+        } else {
+            Kcp::new(conv, output)
+        };
+
+        // fec会在每个kcp包前加上额外的头部
+        kcp.set_mtu(match self.fec {
+            None => self.mtu,
+            Some(_) => self.mtu.saturating_sub(FEC_OVERHEAD),
+        })?;
+        kcp.set_wndsize(self.snd_wnd, self.rcv_wnd);
+        kcp.set_nodelay(self.nodelay, self.interval, self.resend, self.nc);
+        kcp.set_maximum_resend_times(self.dead_link);
+
+        if let Some(min_rto) = self.min_rto {
+            kcp.set_rx_minrto(min_rto);
+        }
+
+        Ok(kcp)
+    }
+}
+
+impl Default for KcpConfig {
+    fn default() -> Self {
+        Self::fast()
+    }
+}
+
+impl FromStr for KcpConfig {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        Ok(match mode {
+            "normal" => Self::normal(),
+            "fast" => Self::fast(),
+            "turbo" => Self::turbo(),
+            _ => return Err(format!("unknown kcp mode {}", mode)),
+        })
+    }
+}
@@ -0,0 +1,391 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    str::FromStr,
+};
+
+use reed_solomon_erasure::galois_8::ReedSolomon;
+
+use super::KcpErr;
+
+const FEC_CMD_DATA: u8 = 0xF1;
+const FEC_CMD_PARITY: u8 = 0xF2;
+
+/// conv(4) + cmd(1) + data_shards(1) + parity_shards(1) + index(1) + group(4)
+const FEC_HEADER: usize = 12;
+
+/// 数据分片额外携带了kcp包的长度
+pub const FEC_OVERHEAD: usize = FEC_HEADER + 2;
+
+/// galois_8 最多支持256个分片
+const MAX_SHARDS: usize = 256;
+
+/// 解码端最多保留的分组数量, 超出后最旧的分组将被丢弃
+const MAX_GROUPS: u32 = 128;
+
+/// fec分组参数, 每 `data_shards` 个kcp包生成 `parity_shards` 个校验包
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FecConfig {
+    pub data_shards: u8,
+    pub parity_shards: u8,
+}
+
+pub struct FecEncoder {
+    config: FecConfig,
+    codec: ReedSolomon,
+    group: u32,
+    shards: Vec<Vec<u8>>,
+    /// 等待发送的数据包与校验包
+    pub(crate) pending: VecDeque<Vec<u8>>,
+}
+
+#[derive(Default)]
+pub struct FecDecoder {
+    codec: Option<(FecConfig, ReedSolomon)>,
+    groups: HashMap<u32, FecGroup>,
+    newest: Option<u32>,
+}
+
+struct FecGroup {
+    shards: Vec<Option<Vec<u8>>>,
+    received: usize,
+    finished: bool,
+}
+
+struct FecHeader {
+    conv: u32,
+    cmd: u8,
+    config: FecConfig,
+    index: usize,
+    group: u32,
+}
+
+/// 是否为经过fec编码的数据包
+#[inline]
+pub fn is_fec(packet: &[u8]) -> bool {
+    packet.len() > FEC_HEADER && matches!(packet[4], FEC_CMD_DATA | FEC_CMD_PARITY)
+}
+
+impl FecConfig {
+    pub fn new(data_shards: u8, parity_shards: u8) -> crate::Result<Self> {
+        ReedSolomon::new(data_shards as usize, parity_shards as usize).map_err(KcpErr::Fec)?;
+
+        Ok(Self {
+            data_shards,
+            parity_shards,
+        })
+    }
+
+    /// 从对端发来的数据包中取出fec参数, 未使用fec时返回None
+    pub fn from_packet(packet: &[u8]) -> Option<Self> {
+        FecHeader::decode(packet).ok().map(|header| header.config)
+    }
+
+    #[inline]
+    fn total_shards(&self) -> usize {
+        self.data_shards as usize + self.parity_shards as usize
+    }
+}
+
+impl FromStr for FecConfig {
+    type Err = String;
+
+    /// 格式为 `data:parity`, 如 `10:3`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid fec config {}", s);
+
+        let (data_shards, parity_shards) = s.split_once(':').ok_or_else(invalid)?;
+
+        let data_shards = data_shards.trim().parse().map_err(|_| invalid())?;
+        let parity_shards = parity_shards.trim().parse().map_err(|_| invalid())?;
+
+        Self::new(data_shards, parity_shards).map_err(|e| e.to_string())
+    }
+}
+
+impl FecHeader {
+    fn decode(packet: &[u8]) -> crate::Result<Self> {
+        if !is_fec(packet) {
+            return Err(KcpErr::InvalidSegmentSize(packet.len()).into());
+        }
+
+        let config = FecConfig {
+            data_shards: packet[5],
+            parity_shards: packet[6],
+        };
+
+        let index = packet[7] as usize;
+
+        // 参数来自对端, 需要保证能创建出编解码器
+        if config.data_shards == 0
+            || config.parity_shards == 0
+            || config.total_shards() > MAX_SHARDS
+            || index >= config.total_shards()
+        {
+            return Err(KcpErr::UnsupportedCmd(packet[4]).into());
+        }
+
+        Ok(Self {
+            conv: u32::from_le_bytes([packet[0], packet[1], packet[2], packet[3]]),
+            cmd: packet[4],
+            config,
+            index,
+            group: u32::from_le_bytes([packet[8], packet[9], packet[10], packet[11]]),
+        })
+    }
+
+    fn encode(&self, payload: &[u8]) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(FEC_HEADER + payload.len());
+        packet.extend_from_slice(&self.conv.to_le_bytes());
+        packet.push(self.cmd);
+        packet.push(self.config.data_shards);
+        packet.push(self.config.parity_shards);
+        packet.push(self.index as u8);
+        packet.extend_from_slice(&self.group.to_le_bytes());
+        packet.extend_from_slice(payload);
+        packet
+    }
+}
+
+impl FecEncoder {
+    pub fn new(config: FecConfig) -> crate::Result<Self> {
+        Ok(Self {
+            config,
+            codec: ReedSolomon::new(config.data_shards as usize, config.parity_shards as usize)
+                .map_err(KcpErr::Fec)?,
+            group: 0,
+            shards: Vec::with_capacity(config.total_shards()),
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// 编码一个kcp包, 编码后的数据包以及分组凑齐后生成的校验包都会放入 `pending`
+    pub fn encode(&mut self, packet: &[u8]) -> crate::Result<()> {
+        if packet.len() < 4 || packet.len() > u16::MAX as usize {
+            return Err(KcpErr::InvalidSegmentSize(packet.len()).into());
+        }
+
+        let conv = u32::from_le_bytes([packet[0], packet[1], packet[2], packet[3]]);
+
+        let mut shard = Vec::with_capacity(packet.len() + 2);
+        shard.extend_from_slice(&(packet.len() as u16).to_le_bytes());
+        shard.extend_from_slice(packet);
+
+        self.pending.push_back(
+            FecHeader {
+                conv,
+                cmd: FEC_CMD_DATA,
+                config: self.config,
+                index: self.shards.len(),
+                group: self.group,
+            }
+            .encode(&shard),
+        );
+
+        self.shards.push(shard);
+
+        if self.shards.len() == self.config.data_shards as usize {
+            let shard_size = unsafe { self.shards.iter().map(Vec::len).max().unwrap_unchecked() };
+
+            let mut shards = std::mem::take(&mut self.shards);
+
+            for shard in shards.iter_mut() {
+                shard.resize(shard_size, 0);
+            }
+
+            shards.resize(self.config.total_shards(), vec![0; shard_size]);
+
+            self.codec.encode(&mut shards).map_err(KcpErr::Fec)?;
+
+            let data_shards = self.config.data_shards as usize;
+
+            for (index, parity) in shards.iter().enumerate().skip(data_shards) {
+                self.pending.push_back(
+                    FecHeader {
+                        conv,
+                        cmd: FEC_CMD_PARITY,
+                        config: self.config,
+                        index,
+                        group: self.group,
+                    }
+                    .encode(parity),
+                );
+            }
+
+            self.shards = Vec::with_capacity(self.config.total_shards());
+            self.group = self.group.wrapping_add(1);
+        }
+
+        Ok(())
+    }
+}
+
+impl FecDecoder {
+    /// 解码一个fec数据包, 返回可以直接交给kcp处理的数据包
+    pub fn decode(&mut self, packet: &[u8]) -> crate::Result<Vec<Vec<u8>>> {
+        let header = FecHeader::decode(packet)?;
+        let payload = &packet[FEC_HEADER..];
+        let config = header.config;
+
+        let mut packets = Vec::new();
+
+        if header.cmd == FEC_CMD_DATA {
+            packets.push(Self::unwrap_shard(payload)?.to_vec());
+        }
+
+        match self.newest {
+            Some(newest) if newest.wrapping_sub(header.group) < u32::MAX / 2 => {
+                if newest.wrapping_sub(header.group) >= MAX_GROUPS {
+                    return Ok(packets);
+                }
+            }
+            _ => {
+                self.newest = Some(header.group);
+                self.groups
+                    .retain(|group, _| header.group.wrapping_sub(*group) < MAX_GROUPS);
+            }
+        }
+
+        let group = self.groups.entry(header.group).or_insert_with(|| FecGroup {
+            shards: vec![None; config.total_shards()],
+            received: 0,
+            finished: false,
+        });
+
+        if group.finished
+            || group.shards.len() != config.total_shards()
+            || group.shards[header.index].is_some()
+        {
+            return Ok(packets);
+        }
+
+        group.shards[header.index] = Some(payload.to_vec());
+        group.received += 1;
+
+        let data_shards = config.data_shards as usize;
+
+        if group.received < data_shards {
+            return Ok(packets);
+        }
+
+        group.finished = true;
+
+        let missing = group.shards[..data_shards]
+            .iter()
+            .enumerate()
+            .filter_map(|(index, shard)| shard.is_none().then_some(index))
+            .collect::<Vec<_>>();
+
+        let mut shards = std::mem::take(&mut group.shards);
+
+        if missing.is_empty() {
+            return Ok(packets);
+        }
+
+        let shard_size = shards[data_shards..]
+            .iter()
+            .flatten()
+            .map(Vec::len)
+            .max()
+            .unwrap_or_default();
+
+        for shard in shards.iter_mut().flatten() {
+            if shard.len() > shard_size {
+                log::warn!("bad fec shard in group {}", header.group);
+                return Ok(packets);
+            }
+
+            shard.resize(shard_size, 0);
+        }
+
+        if !matches!(&self.codec, Some((current, _)) if *current == config) {
+            let codec = ReedSolomon::new(data_shards, config.parity_shards as usize)
+                .map_err(KcpErr::Fec)?;
+            self.codec = Some((config, codec));
+        }
+
+        let codec = unsafe { &self.codec.as_ref().unwrap_unchecked().1 };
+
+        if let Err(e) = codec.reconstruct_data(&mut shards) {
+            log::warn!("failed to recover fec group {} err={}", header.group, e);
+            return Ok(packets);
+        }
+
+        for index in missing {
+            if let Some(shard) = shards[index].as_ref() {
+                packets.push(Self::unwrap_shard(shard)?.to_vec());
+            }
+        }
+
+        log::trace!("recovered fec group {}", header.group);
+
+        Ok(packets)
+    }
+
+    fn unwrap_shard(shard: &[u8]) -> crate::Result<&[u8]> {
+        if shard.len() < 2 {
+            return Err(KcpErr::InvalidSegmentSize(shard.len()).into());
+        }
+
+        let len = u16::from_le_bytes([shard[0], shard[1]]) as usize;
+
+        shard
+            .get(2..2 + len)
+            .ok_or_else(|| KcpErr::InvalidSegmentDataSize(len, shard.len() - 2).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_fec, FecConfig, FecDecoder, FecEncoder};
+
+    #[test]
+    fn test_fec_recover() {
+        let config = FecConfig::new(4, 2).unwrap();
+        let mut encoder = FecEncoder::new(config).unwrap();
+
+        let packets = (0..4u8)
+            .map(|i| {
+                let mut packet = vec![1, 0, 0, 0];
+                packet.extend(std::iter::repeat(i).take(30 + i as usize * 7));
+                packet
+            })
+            .collect::<Vec<_>>();
+
+        for packet in packets.iter() {
+            encoder.encode(packet).unwrap();
+        }
+
+        let encoded = encoder.pending.drain(..).collect::<Vec<_>>();
+        assert_eq!(encoded.len(), 6);
+        assert!(encoded.iter().all(|packet| is_fec(packet)));
+        assert_eq!(FecConfig::from_packet(&encoded[0]), Some(config));
+
+        let mut decoder = FecDecoder::default();
+        let mut received = Vec::new();
+
+        // 丢弃两个数据包, 由校验包恢复
+        for (index, packet) in encoded.iter().enumerate() {
+            if index == 1 || index == 2 {
+                continue;
+            }
+
+            received.extend(decoder.decode(packet).unwrap());
+        }
+
+        received.sort();
+        assert_eq!(received, packets);
+    }
+
+    #[test]
+    fn test_fec_invalid_shards() {
+        let mut packet = vec![1, 0, 0, 0, 0xF1, 200, 100, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(FecConfig::from_packet(&packet), None);
+
+        packet[5] = 0;
+        assert_eq!(FecConfig::from_packet(&packet), None);
+
+        packet[5] = 200;
+        packet[6] = 56;
+        assert_eq!(FecConfig::from_packet(&packet), FecConfig::new(200, 56).ok());
+    }
+}
@@ -8,13 +8,22 @@ pub use stream::*;
 mod builder;
 pub use builder::*;
 
+mod config;
+pub use config::*;
+
+mod fec;
+pub use fec::*;
+
 use std::{
     collections::{hash_map::DefaultHasher, HashMap},
     future::Future,
     hash::{Hash, Hasher},
     net::SocketAddr,
     pin::Pin,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     task::{Poll, Waker},
     time::{Duration, Instant},
 };
@@ -72,14 +81,18 @@ pub struct KcpListener<C, E> {
     pub(crate) manager: Manager<C>,
     pub(crate) futures: Vec<BoxedFuture<crate::Result<State<C>>>>,
     pub(crate) executor: E,
+    pub(crate) config: KcpConfig,
 }
 
 pub struct KcpConnector<C, E> {
     core: C,
     task: Task<crate::Result<()>>,
     executor: E,
+    config: KcpConfig,
     sessions: Arc<Mutex<HashMap<u32, KLife<C>>>>,
     increment: Increment,
+    /// 是否收到过对端发来的数据
+    responded: Arc<AtomicBool>,
 }
 
 impl<C> Session<C>
@@ -88,11 +101,20 @@ where
 {
     pub async fn input(&mut self, data: Vec<u8>) -> crate::Result<()> {
         let mut kcore = self.kcore.lock()?;
-        let mut next_input = &data[..];
 
-        while next_input.len() >= third_party::KCP_OVERHEAD {
-            let n = kcore.input(next_input)?;
-            next_input = &data[n..];
+        let packets = if fec::is_fec(&data) {
+            kcore.fec.get_or_insert_with(Default::default).decode(&data)?
+        } else {
+            vec![data]
+        };
+
+        for data in packets {
+            let mut next_input = &data[..];
+
+            while next_input.len() >= third_party::KCP_OVERHEAD {
+                let n = kcore.input(next_input)?;
+                next_input = &next_input[n..];
+            }
         }
 
         if kcore.peeksize().is_ok() {
@@ -153,6 +175,7 @@ where
         conv: u32,
         target: Option<SocketAddr>,
         output: C,
+        config: &KcpConfig,
         executor: E,
         clean_callback: F,
     ) -> crate::Result<Self>
@@ -164,17 +187,18 @@ where
         let output = KOutput {
             output,
             target: target.clone(),
+            fec: match config.fec {
+                None => None,
+                Some(fec) => Some(FecEncoder::new(fec)?),
+            },
         };
 
-        let mut kcp = third_party::Kcp::new(conv, output);
-
-        kcp.set_wndsize(1024, 1024);
-        kcp.set_nodelay(true, 20, 2, true);
-        kcp.set_maximum_resend_times(10);
+        let kcp = config.make_kcp(conv, output)?;
 
         let kcore = Arc::new(std::sync::Mutex::new(KcpCore {
             kcp,
             kbuf: Buffer::new(),
+            fec: None,
             kupdate: None,
             write_waker: None,
             read_waker: None,
@@ -233,11 +257,16 @@ where
     E: Executor + Clone + Send + Sync + 'static,
 {
     pub fn bind(core: C, executor: E) -> crate::Result<Self> {
+        Self::bind_with_config(core, KcpConfig::default(), executor)
+    }
+
+    pub fn bind_with_config(core: C, config: KcpConfig, executor: E) -> crate::Result<Self> {
         let manager: Manager<C> = Default::default();
 
         let core_fut = Box::pin(Self::run_accept(
             core.clone(),
             manager.clone(),
+            config.clone(),
             executor.clone(),
         ));
 
@@ -245,17 +274,21 @@ where
             core,
             manager,
             executor,
+            config,
             futures: vec![core_fut],
         })
     }
 
-    async fn run_accept(core: C, manager: Manager<C>, executor: E) -> crate::Result<State<C>> {
-        loop {
-            let mut data = Vec::with_capacity(1500);
+    async fn run_accept(
+        core: C,
+        manager: Manager<C>,
+        config: KcpConfig,
+        executor: E,
+    ) -> crate::Result<State<C>> {
+        let buf_size = config.recv_buf_size();
 
-            unsafe {
-                data.set_len(1500);
-            }
+        loop {
+            let mut data = vec![0u8; buf_size];
 
             let (n, addr) = core.recv_from(&mut data).await?;
             data.truncate(n);
@@ -285,8 +318,14 @@ where
             let new_kcp = !sessions.contains_key(&conv);
 
             if new_kcp {
-                let new_session = KLife::Active({
-                    Session::new(conv, Some(addr), core.clone(), executor.clone(), {
+                // 是否启用fec由对端决定
+                let config = KcpConfig {
+                    fec: FecConfig::from_packet(&data),
+                    ..config.clone()
+                };
+
+                let new_session =
+                    Session::new(conv, Some(addr), core.clone(), &config, executor.clone(), {
                         {
                             let manager = manager.clone();
                             move |conv| async move {
@@ -310,10 +349,18 @@ where
                                 ()
                             }
                         }
-                    })?
-                });
+                    });
 
-                sessions.insert(conv, new_session);
+                // 不能因为某个对端的错误参数停止接收
+                match new_session {
+                    Ok(session) => {
+                        sessions.insert(conv, KLife::Active(session));
+                    }
+                    Err(e) => {
+                        log::warn!("failed to create kcp session conv={}, err={}", conv, e);
+                        continue;
+                    }
+                }
             }
 
             let klife = unsafe { sessions.get_mut(&conv).unwrap_unchecked() };
@@ -416,6 +463,7 @@ where
                     let accept_fut = Self::run_accept(
                         self.core.clone(),
                         self.manager.clone(),
+                        self.config.clone(),
                         self.executor.clone(),
                     );
 
@@ -460,30 +508,47 @@ where
     E: Executor + Clone + Sync + Send + 'static,
 {
     pub fn new(core: C, executor: E) -> Self {
-        let sessions: Arc<Mutex<HashMap<u32, KLife<C>>>> = Default::default();
+        Self::with_config(core, KcpConfig::default(), executor)
+    }
 
-        let task = { executor.spawn(Self::run_connect(core.clone(), sessions.clone())) };
+    pub fn with_config(core: C, config: KcpConfig, executor: E) -> Self {
+        let sessions: Arc<Mutex<HashMap<u32, KLife<C>>>> = Default::default();
+        let responded: Arc<AtomicBool> = Default::default();
+
+        let task = {
+            executor.spawn(Self::run_connect(
+                core.clone(),
+                sessions.clone(),
+                responded.clone(),
+                config.recv_buf_size(),
+            ))
+        };
 
         Self {
             core,
             task,
             executor,
+            config,
             sessions,
+            responded,
             increment: Default::default(),
         }
     }
 
+    /// 对端是否有过响应, kcp是无连接的, 可以据此判断对端是否可达
+    pub fn is_responded(&self) -> bool {
+        self.responded.load(Ordering::Relaxed)
+    }
+
     fn run_connect(
         core: C,
         sessions: Arc<Mutex<HashMap<u32, KLife<C>>>>,
+        responded: Arc<AtomicBool>,
+        buf_size: usize,
     ) -> BoxedFuture<crate::Result<()>> {
         let fut = async move {
             loop {
-                let mut buf = Vec::with_capacity(1500);
-
-                unsafe {
-                    buf.set_len(1500);
-                }
+                let mut buf = vec![0u8; buf_size];
 
                 let n = core.recv(&mut buf).await?;
                 buf.truncate(n);
@@ -495,6 +560,8 @@ where
 
                 let conv = third_party::get_conv(&buf);
 
+                responded.store(true, Ordering::Relaxed);
+
                 let mut sessions = sessions.lock().await;
 
                 sessions.retain(|_, klife| match klife {
@@ -549,7 +616,7 @@ where
         let sessions = self.sessions.clone();
         let executor = self.executor.clone();
 
-        let session = Session::new(conv, None, self.core.clone(), self.executor.clone(), {
+        let session = Session::new(conv, None, self.core.clone(), &self.config, self.executor.clone(), {
             let sessions = self.sessions.clone();
             move |conv| async move {
                 let mut sessions = sessions.lock().await;
@@ -720,6 +787,7 @@ where
 }
 
 #[cfg(test)]
+#[cfg(feature = "fuso-rt-tokio")]
 mod tests {
     use std::sync::Arc;
 
@@ -728,7 +796,7 @@ mod tests {
         io, AccepterExt, TokioExecutor,
     };
 
-    use super::{KcpConnector, KcpListener};
+    use super::{FecConfig, KcpConfig, KcpConnector, KcpListener};
 
     fn init_logger() {
         env_logger::builder()
@@ -809,4 +877,43 @@ mod tests {
                 }
             });
     }
+
+    #[test]
+    pub fn test_kcp_fec() {
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(async move {
+                let udp = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+                let addr = udp.local_addr().unwrap();
+
+                let mut listener = KcpListener::bind(Arc::new(udp), TokioExecutor).unwrap();
+
+                let server = tokio::spawn(async move {
+                    let mut kcp = listener.accept().await.unwrap();
+                    let mut buf = [0u8; 11];
+                    kcp.read_exact(&mut buf).await.unwrap();
+                    kcp.write_all(&buf).await.unwrap();
+                    listener
+                });
+
+                let udp = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+                udp.connect(addr).await.unwrap();
+
+                let config = KcpConfig {
+                    fec: Some(FecConfig::new(4, 2).unwrap()),
+                    ..Default::default()
+                };
+
+                let connector = KcpConnector::with_config(Arc::new(udp), config, TokioExecutor);
+                let mut kcp = connector.connect().await.unwrap();
+
+                kcp.write_all(b"hello world").await.unwrap();
+
+                let mut buf = [0u8; 11];
+                kcp.read_exact(&mut buf).await.unwrap();
+                assert_eq!(&buf, b"hello world");
+
+                drop(server.await.unwrap());
+            });
+    }
 }
@@ -7,10 +7,11 @@ use std::{
 };
 
 use crate::{
-    guard::buffer::Buffer, Address, AsyncRead, AsyncWrite, Kind, NetSocket, Task, UdpSocket,
+    guard::buffer::Buffer, ready, Address, AsyncRead, AsyncWrite, Kind, NetSocket, Task,
+    UdpSocket,
 };
 
-use super::KcpErr;
+use super::{FecDecoder, FecEncoder, KcpErr};
 
 type Callback = Option<Box<dyn FnOnce() + Sync + Send + 'static>>;
 
@@ -19,11 +20,13 @@ pub struct KOutput<C> {
     #[pin]
     pub(crate) output: C,
     pub(crate) target: Option<SocketAddr>,
+    pub(crate) fec: Option<FecEncoder>,
 }
 
 pub struct KcpCore<C> {
     pub(crate) kcp: super::third_party::Kcp<KOutput<C>>,
     pub(crate) kbuf: Buffer<u8>,
+    pub(crate) fec: Option<FecDecoder>,
     pub(crate) kupdate: Option<Task<crate::Result<()>>>,
     pub(crate) read_waker: Option<Waker>,
     pub(crate) write_waker: Option<Waker>,
@@ -52,6 +55,24 @@ impl<C> DerefMut for KcpCore<C> {
     }
 }
 
+impl<C> KOutput<C>
+where
+    C: UdpSocket + Unpin,
+{
+    #[inline]
+    fn poll_send(
+        output: Pin<&C>,
+        target: &Option<SocketAddr>,
+        cx: &mut std::task::Context<'_>,
+        data: &[u8],
+    ) -> Poll<crate::Result<usize>> {
+        match target.as_ref() {
+            None => output.poll_send(cx, data),
+            Some(addr) => output.poll_send_to(cx, addr, data),
+        }
+    }
+}
+
 impl<C> AsyncWrite for KOutput<C>
 where
     C: UdpSocket + Unpin,
@@ -63,10 +84,32 @@ where
     ) -> Poll<crate::Result<usize>> {
         let this = self.project();
         let output = Pin::new(&*this.output);
-        match this.target.as_ref() {
-            None => output.poll_send(cx, data),
-            Some(addr) => output.poll_send_to(cx, addr, data),
+
+        let fec = match this.fec.as_mut() {
+            None => return Self::poll_send(output, this.target, cx, data),
+            Some(fec) => fec,
+        };
+
+        // 先发送上次未发送完的包
+        while let Some(packet) = fec.pending.front() {
+            ready!(Self::poll_send(output, this.target, cx, packet))?;
+            fec.pending.pop_front();
         }
+
+        fec.encode(data)?;
+
+        // 数据已被接收, 未发送完的包留到下次写入时发送
+        while let Some(packet) = fec.pending.front() {
+            match Self::poll_send(output, this.target, cx, packet) {
+                Poll::Pending => break,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Ready(Ok(_)) => {
+                    fec.pending.pop_front();
+                }
+            }
+        }
+
+        Poll::Ready(Ok(data.len()))
     }
 
     fn poll_flush(self: Pin<&mut Self>, _: &mut std::task::Context<'_>) -> Poll<crate::Result<()>> {
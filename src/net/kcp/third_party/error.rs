@@ -20,6 +20,7 @@ pub enum KcpErr {
     UserBufTooSmall,
     NoMoreConv,
     ConnectionReset,
+    Fec(reed_solomon_erasure::Error),
 }
 
 impl StdError for KcpErr {
@@ -55,6 +56,7 @@ impl fmt::Display for KcpErr {
             KcpErr::UserBufTooSmall => write!(f, "UserBufTooSmall"),
             KcpErr::NoMoreConv => write!(f, "NoMoreConv"),
             KcpErr::ConnectionReset => write!(f, "ConnectionReset"),
+            KcpErr::Fec(ref e) => write!(f, "fec error {}", e),
         }
     }
 }
@@ -82,6 +84,7 @@ impl From<KcpErr> for io::Error {
             KcpErr::UserBufTooSmall => ErrorKind::Other,
             KcpErr::NoMoreConv => ErrorKind::Other,
             KcpErr::ConnectionReset => ErrorKind::ConnectionReset,
+            KcpErr::Fec(..) => ErrorKind::InvalidData,
         };
 
         make_io_error(kind, err)
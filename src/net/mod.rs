@@ -3,6 +3,9 @@ pub mod udp;
 #[cfg(feature = "fuso-kcp")]
 pub mod kcp;
 
+#[cfg(feature = "fuso-kcp")]
+pub mod p2p;
+
 #[cfg(feature = "fuso-quic")]
 pub mod quic;
 
@@ -11,6 +14,8 @@ pub mod socks;
 
 pub mod tun;
 
+pub mod haproxy;
+
 #[cfg(feature = "fuso-proxy")]
 pub mod proxy;
 
@@ -0,0 +1,673 @@
+mod server;
+pub use server::*;
+
+mod session;
+pub use session::*;
+
+use std::{
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::Poll,
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "fuso-serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    kcp::{KcpConfig, KcpConnector, KcpListener},
+    protocol::{self, Decode, Encode},
+    ready, time, Address, Executor, NetSocket, ReadBuf, Socket, UdpReceiverExt, UdpSocket,
+};
+
+const KIND_CONTROL: u8 = 0x01;
+const KIND_DATA: u8 = 0x02;
+
+/// 等待对端注册时重发注册消息的间隔
+const REGISTER_INTERVAL: Duration = Duration::from_secs(1);
+const PUNCH_INTERVAL: Duration = Duration::from_millis(100);
+/// 超过该时间仍未打通则改由服务端中转
+const PUNCH_TIMEOUT: Duration = Duration::from_secs(2);
+/// 超过该时间对端仍未注册则放弃
+const PEER_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "fuso-serde", derive(Deserialize, Serialize))]
+pub enum Role {
+    /// 提供服务的一端, 在打通后作为kcp的监听端
+    Provider,
+    /// 访问服务的一端, 在打通后作为kcp的连接端
+    Visitor,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "fuso-serde", derive(Deserialize, Serialize))]
+pub enum Message {
+    /// 客户端向服务端注册, 服务端以此得知客户端的公网地址, 携带令牌与该端的注册密钥
+    Register(String, Role, String),
+    /// 服务端告知对端的公网地址
+    Peer(SocketAddr),
+    Punch(String),
+    PunchAck(String),
+    /// 打洞失败, 由服务端中转数据
+    Relay(String),
+}
+
+/// 打洞完成后与对端通信的udp, 打洞失败时发送的数据会经由服务端中转
+pub struct P2pSocket<C> {
+    core: C,
+    token: String,
+    peer: SocketAddr,
+    server: SocketAddr,
+    relay: AtomicBool,
+}
+
+/// 与 `Poto` 相同的编码, 见 [`crate::protocol::Encode`]
+impl Encode for Role {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Role::Provider => 0u32.encode(buf),
+            Role::Visitor => 1u32.encode(buf),
+        }
+    }
+}
+
+impl Decode for Role {
+    fn decode(buf: &mut &[u8]) -> crate::Result<Self> {
+        match u32::decode(buf)? {
+            0 => Ok(Role::Provider),
+            1 => Ok(Role::Visitor),
+            _ => Err(crate::Kind::Deserialize("invalid role".into()).into()),
+        }
+    }
+}
+
+impl Encode for Message {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Message::Register(token, role, secret) => {
+                0u32.encode(buf);
+                token.encode(buf);
+                role.encode(buf);
+                secret.encode(buf);
+            }
+            Message::Peer(addr) => {
+                1u32.encode(buf);
+                addr.encode(buf);
+            }
+            Message::Punch(token) => {
+                2u32.encode(buf);
+                token.encode(buf);
+            }
+            Message::PunchAck(token) => {
+                3u32.encode(buf);
+                token.encode(buf);
+            }
+            Message::Relay(token) => {
+                4u32.encode(buf);
+                token.encode(buf);
+            }
+        }
+    }
+}
+
+impl Decode for Message {
+    fn decode(buf: &mut &[u8]) -> crate::Result<Self> {
+        match u32::decode(buf)? {
+            0 => Ok(Message::Register(
+                String::decode(buf)?,
+                Role::decode(buf)?,
+                String::decode(buf)?,
+            )),
+            1 => Ok(Message::Peer(SocketAddr::decode(buf)?)),
+            2 => Ok(Message::Punch(String::decode(buf)?)),
+            3 => Ok(Message::PunchAck(String::decode(buf)?)),
+            4 => Ok(Message::Relay(String::decode(buf)?)),
+            _ => Err(crate::Kind::Deserialize("invalid p2p message".into()).into()),
+        }
+    }
+}
+
+pub(crate) fn encode(message: &Message) -> Vec<u8> {
+    let mut packet = vec![KIND_CONTROL];
+    packet.extend(protocol::encode(message));
+    packet
+}
+
+pub(crate) fn decode(packet: &[u8]) -> Option<Message> {
+    match packet.split_first() {
+        Some((&KIND_CONTROL, message)) => protocol::decode(message).ok(),
+        _ => None,
+    }
+}
+
+#[inline]
+pub(crate) fn is_data(packet: &[u8]) -> bool {
+    packet.first() == Some(&KIND_DATA)
+}
+
+/// 通过服务端交换双方的公网地址并尝试打洞, 打洞失败时使用服务端中转
+pub async fn punch<C>(
+    core: C,
+    server: SocketAddr,
+    token: String,
+    secret: String,
+    role: Role,
+) -> crate::Result<P2pSocket<C>>
+where
+    C: UdpSocket + Unpin + Send + Sync,
+{
+    let mut buf = [0u8; 1500];
+
+    let register = encode(&Message::Register(token.clone(), role, secret));
+    let deadline = Instant::now() + PEER_TIMEOUT;
+
+    let peer = loop {
+        if Instant::now() >= deadline {
+            log::warn!("p2p peer did not register in {:?}", PEER_TIMEOUT);
+            return Err(crate::Kind::Timeout(time::Elapsed).into());
+        }
+
+        core.send_to(&server, &register).await?;
+
+        let (n, addr) = match time::wait_for(REGISTER_INTERVAL, core.recv_from(&mut buf)).await {
+            Ok(r) => r?,
+            Err(_) => continue,
+        };
+
+        match decode(&buf[..n]) {
+            Some(Message::Peer(peer)) if addr == server => break peer,
+            _ => log::debug!("ignore packet from {} while registering", addr),
+        }
+    };
+
+    log::info!("p2p peer is {}, start punching", peer);
+
+    let punch = encode(&Message::Punch(token.clone()));
+    let punch_ack = encode(&Message::PunchAck(token.clone()));
+
+    let deadline = Instant::now() + PUNCH_TIMEOUT;
+    let mut punched = false;
+
+    while !punched && Instant::now() < deadline {
+        core.send_to(&peer, &punch).await?;
+
+        let (n, addr) = match time::wait_for(PUNCH_INTERVAL, core.recv_from(&mut buf)).await {
+            Ok(r) => r?,
+            Err(_) => continue,
+        };
+
+        if addr != peer {
+            continue;
+        }
+
+        match decode(&buf[..n]) {
+            Some(Message::Punch(t)) if t == token => {
+                core.send_to(&peer, &punch_ack).await?;
+            }
+            Some(Message::PunchAck(t)) if t == token => {
+                punched = true;
+            }
+            _ => {}
+        }
+    }
+
+    let socket = P2pSocket {
+        core,
+        token,
+        peer,
+        server,
+        relay: AtomicBool::new(!punched),
+    };
+
+    if punched {
+        // 对端可能还没收到应答
+        socket.core.send_to(&peer, &punch_ack).await?;
+        log::info!("punch through to {}", peer);
+    } else {
+        log::warn!("failed to punch through to {}, relay by {}", peer, server);
+        socket
+            .core
+            .send_to(&server, &encode(&Message::Relay(socket.token.clone())))
+            .await?;
+    }
+
+    Ok(socket)
+}
+
+impl<C> P2pSocket<C>
+where
+    C: UdpSocket + Unpin + Send + Sync + 'static,
+{
+    pub fn into_listener<E>(
+        self,
+        config: KcpConfig,
+        executor: E,
+    ) -> crate::Result<KcpListener<Arc<Self>, E>>
+    where
+        E: Executor + Clone + Send + Sync + 'static,
+    {
+        KcpListener::bind_with_config(Arc::new(self), config, executor)
+    }
+
+    pub fn into_connector<E>(self, config: KcpConfig, executor: E) -> KcpConnector<Arc<Self>, E>
+    where
+        E: Executor + Clone + Send + Sync + 'static,
+    {
+        KcpConnector::with_config(Arc::new(self), config, executor)
+    }
+}
+
+impl<C> P2pSocket<C>
+where
+    C: UdpSocket + Unpin,
+{
+    /// 数据是否经由服务端中转
+    pub fn is_relay(&self) -> bool {
+        self.relay.load(Ordering::Relaxed)
+    }
+
+    /// 处理打洞完成后仍在传输的控制消息
+    fn handle_message(&self, cx: &mut std::task::Context<'_>, addr: SocketAddr, packet: &[u8]) {
+        match decode(packet) {
+            Some(Message::Punch(token)) if addr == self.peer && token == self.token => {
+                let punch_ack = encode(&Message::PunchAck(token));
+                let _ = Pin::new(&self.core).poll_send_to(cx, &addr, &punch_ack);
+            }
+            Some(Message::Relay(token)) if addr == self.server && token == self.token => {
+                log::warn!("peer {} requires relay by {}", self.peer, self.server);
+                self.relay.store(true, Ordering::Relaxed);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<C> NetSocket for P2pSocket<C>
+where
+    C: NetSocket,
+{
+    fn local_addr(&self) -> crate::Result<Address> {
+        self.core.local_addr()
+    }
+
+    fn peer_addr(&self) -> crate::Result<Address> {
+        Ok(Address::Single(Socket::udp(self.peer)))
+    }
+}
+
+impl<C> UdpSocket for P2pSocket<C>
+where
+    C: UdpSocket + Unpin,
+{
+    fn poll_recv_from(
+        self: Pin<&Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<crate::Result<SocketAddr>> {
+        let mut packet = vec![0u8; buf.initialize_unfilled().len() + 1];
+
+        loop {
+            let mut packet_buf = ReadBuf::new(&mut packet);
+            let addr = ready!(Pin::new(&self.core).poll_recv_from(cx, &mut packet_buf))?;
+            let n = packet_buf.position();
+            let data = &packet[..n];
+
+            if is_data(data) && (addr == self.peer || addr == self.server) {
+                let unfilled = buf.initialize_unfilled();
+                let n = (n - 1).min(unfilled.len());
+                unfilled[..n].copy_from_slice(&data[1..n + 1]);
+                buf.advance(n);
+                return Poll::Ready(Ok(self.peer));
+            }
+
+            self.handle_message(cx, addr, data);
+        }
+    }
+
+    fn poll_recv(
+        self: Pin<&Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<crate::Result<()>> {
+        ready!(self.poll_recv_from(cx, buf))?;
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_send(
+        self: Pin<&Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<crate::Result<usize>> {
+        let target = if self.is_relay() {
+            &self.server
+        } else {
+            &self.peer
+        };
+
+        let mut packet = Vec::with_capacity(buf.len() + 1);
+        packet.push(KIND_DATA);
+        packet.extend_from_slice(buf);
+
+        ready!(Pin::new(&self.core).poll_send_to(cx, target, &packet))?;
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_send_to(
+        self: Pin<&Self>,
+        cx: &mut std::task::Context<'_>,
+        _: &SocketAddr,
+        buf: &[u8],
+    ) -> Poll<crate::Result<usize>> {
+        self.poll_send(cx, buf)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "fuso-rt-tokio")]
+mod tests {
+    use std::{
+        collections::{HashMap, HashSet},
+        net::SocketAddr,
+        pin::Pin,
+        sync::{Arc, Mutex},
+        task::Poll,
+        time::Duration,
+    };
+
+    use crate::{
+        ext::{AsyncReadExt, AsyncWriteExt},
+        kcp::KcpConfig,
+        AccepterExt, Address, NetSocket, ReadBuf, Socket, TokioExecutor, UdpSocket,
+    };
+
+    use super::{decode, encode, punch, Message, RendezvousServer, Role, Tickets};
+
+    /// 模拟的nat, 只接收已经发送过数据的地址发来的数据,
+    /// `symmetric` 为true时每个目标地址都会分配一个新的公网端口
+    struct Nat {
+        symmetric: bool,
+        public: Mutex<HashMap<Option<SocketAddr>, Arc<tokio::net::UdpSocket>>>,
+        permitted: Mutex<HashSet<SocketAddr>>,
+    }
+
+    impl Nat {
+        fn new(symmetric: bool) -> Arc<Self> {
+            Arc::new(Self {
+                symmetric,
+                public: Default::default(),
+                permitted: Default::default(),
+            })
+        }
+
+        fn public_socket(&self, target: &SocketAddr) -> Arc<tokio::net::UdpSocket> {
+            let key = self.symmetric.then_some(*target);
+            self.public
+                .lock()
+                .unwrap()
+                .entry(key)
+                .or_insert_with(|| {
+                    let udp = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+                    udp.set_nonblocking(true).unwrap();
+                    Arc::new(tokio::net::UdpSocket::from_std(udp).unwrap())
+                })
+                .clone()
+        }
+    }
+
+    impl NetSocket for Nat {
+        fn local_addr(&self) -> crate::Result<Address> {
+            Ok(Address::Single(Socket::udp(([127, 0, 0, 1], 0))))
+        }
+
+        fn peer_addr(&self) -> crate::Result<Address> {
+            self.local_addr()
+        }
+    }
+
+    impl UdpSocket for Nat {
+        fn poll_recv_from(
+            self: Pin<&Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<crate::Result<SocketAddr>> {
+            let sockets = self
+                .public
+                .lock()
+                .unwrap()
+                .values()
+                .cloned()
+                .collect::<Vec<_>>();
+
+            for udp in sockets {
+                loop {
+                    let mut packet = [0u8; 1500];
+                    let mut packet_buf = tokio::io::ReadBuf::new(&mut packet);
+
+                    match udp.poll_recv_from(cx, &mut packet_buf) {
+                        Poll::Pending => break,
+                        Poll::Ready(Err(e)) => return Poll::Ready(Err(e.into())),
+                        Poll::Ready(Ok(addr)) => {
+                            if self.permitted.lock().unwrap().contains(&addr) {
+                                let data = packet_buf.filled();
+                                buf.initialize_unfilled()[..data.len()].copy_from_slice(data);
+                                buf.advance(data.len());
+                                return Poll::Ready(Ok(addr));
+                            }
+                        }
+                    }
+                }
+            }
+
+            Poll::Pending
+        }
+
+        fn poll_recv(
+            self: Pin<&Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<crate::Result<()>> {
+            self.poll_recv_from(cx, buf).map_ok(|_| ())
+        }
+
+        fn poll_send(
+            self: Pin<&Self>,
+            _: &mut std::task::Context<'_>,
+            _: &[u8],
+        ) -> Poll<crate::Result<usize>> {
+            // nat没有默认的目标地址
+            Poll::Ready(Err(
+                crate::Kind::Message("nat requires a target".into()).into()
+            ))
+        }
+
+        fn poll_send_to(
+            self: Pin<&Self>,
+            cx: &mut std::task::Context<'_>,
+            addr: &SocketAddr,
+            buf: &[u8],
+        ) -> Poll<crate::Result<usize>> {
+            self.permitted.lock().unwrap().insert(*addr);
+            self.public_socket(addr)
+                .poll_send_to(cx, buf, *addr)
+                .map_err(Into::into)
+        }
+    }
+
+    async fn recv(udp: &tokio::net::UdpSocket) -> Option<Message> {
+        let mut buf = [0u8; 1500];
+        let n = tokio::time::timeout(Duration::from_millis(300), udp.recv(&mut buf))
+            .await
+            .ok()?
+            .ok()?;
+        decode(&buf[..n])
+    }
+
+    async fn run_p2p(symmetric: bool) -> bool {
+        let udp = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server = udp.local_addr().unwrap();
+
+        let tickets = Tickets::default();
+        let ticket = tickets.issue();
+
+        tokio::spawn(RendezvousServer::new(udp, tickets).run());
+
+        let secret = |role| ticket.secret(role).to_owned();
+
+        let (provider, visitor) = tokio::join!(
+            punch(
+                Nat::new(symmetric),
+                server,
+                ticket.token.clone(),
+                secret(Role::Provider),
+                Role::Provider
+            ),
+            punch(
+                Nat::new(symmetric),
+                server,
+                ticket.token.clone(),
+                secret(Role::Visitor),
+                Role::Visitor
+            )
+        );
+
+        let (provider, visitor) = (provider.unwrap(), visitor.unwrap());
+        let relay = provider.is_relay();
+
+        assert_eq!(relay, visitor.is_relay());
+
+        let mut listener = provider
+            .into_listener(KcpConfig::default(), TokioExecutor)
+            .unwrap();
+
+        let connector = visitor.into_connector(KcpConfig::default(), TokioExecutor);
+
+        let server = tokio::spawn(async move {
+            let mut kcp = listener.accept().await.unwrap();
+            let mut buf = [0u8; 5];
+            kcp.read_exact(&mut buf).await.unwrap();
+            kcp.write_all(&buf).await.unwrap();
+            listener
+        });
+
+        let mut kcp = connector.connect().await.unwrap();
+        kcp.write_all(b"hello").await.unwrap();
+
+        let mut buf = [0u8; 5];
+        kcp.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        drop(server.await.unwrap());
+
+        relay
+    }
+
+    #[test]
+    fn test_p2p_punch() {
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(async move {
+                assert!(!run_p2p(false).await);
+            });
+    }
+
+    /// 只有服务端分配的令牌与对应一端的密钥可以配对, 配对后其他地址无法接管任何一端
+    #[test]
+    fn test_rendezvous_tickets() {
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(async move {
+                let udp = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+                let server = udp.local_addr().unwrap();
+
+                let tickets = Tickets::default();
+                let ticket = tickets.issue();
+
+                tokio::spawn(RendezvousServer::new(udp, tickets).run());
+
+                let provider = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+                let visitor = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+                let attacker = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+                let register = |token: &str, role, secret: &str| {
+                    encode(&Message::Register(token.into(), role, secret.into()))
+                };
+
+                let token = ticket.token.as_str();
+                let provider_secret = ticket.secret(Role::Provider);
+                let visitor_secret = ticket.secret(Role::Visitor);
+
+                provider
+                    .send_to(&register("forged", Role::Provider, provider_secret), server)
+                    .await
+                    .unwrap();
+                visitor
+                    .send_to(&register("forged", Role::Visitor, visitor_secret), server)
+                    .await
+                    .unwrap();
+
+                assert_eq!(recv(&provider).await, None);
+                assert_eq!(recv(&visitor).await, None);
+
+                provider
+                    .send_to(&register(token, Role::Provider, provider_secret), server)
+                    .await
+                    .unwrap();
+
+                // 截获了提供端的注册消息也无法以访问者的身份注册
+                attacker
+                    .send_to(&register(token, Role::Visitor, provider_secret), server)
+                    .await
+                    .unwrap();
+
+                assert_eq!(recv(&attacker).await, None);
+
+                visitor
+                    .send_to(&register(token, Role::Visitor, visitor_secret), server)
+                    .await
+                    .unwrap();
+
+                assert_eq!(
+                    recv(&provider).await,
+                    Some(Message::Peer(visitor.local_addr().unwrap()))
+                );
+                assert_eq!(
+                    recv(&visitor).await,
+                    Some(Message::Peer(provider.local_addr().unwrap()))
+                );
+
+                // 重放注册消息不会改变已经配对的地址
+                attacker
+                    .send_to(&register(token, Role::Visitor, visitor_secret), server)
+                    .await
+                    .unwrap();
+
+                assert_eq!(recv(&attacker).await, None);
+                assert_eq!(recv(&provider).await, None);
+
+                visitor
+                    .send_to(&register(token, Role::Visitor, visitor_secret), server)
+                    .await
+                    .unwrap();
+
+                assert_eq!(
+                    recv(&visitor).await,
+                    Some(Message::Peer(provider.local_addr().unwrap()))
+                );
+            });
+    }
+
+    #[test]
+    fn test_p2p_relay() {
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(async move {
+                assert!(run_p2p(true).await);
+            });
+    }
+}
@@ -0,0 +1,288 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{time, UdpReceiverExt, UdpSocket};
+
+use super::{decode, encode, is_data, Message, Role};
+
+/// 令牌分配后需要在该时间内完成配对
+const TICKET_TIMEOUT: Duration = Duration::from_secs(30);
+/// 超过该时间没有收到双方的任何数据则移除配对
+const PAIR_TIMEOUT: Duration = Duration::from_secs(120);
+/// 清理过期配对的间隔
+const PRUNE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// 服务端经由fuso连接分配的打洞令牌, 打洞服务只接受这些令牌
+#[derive(Clone, Default)]
+pub struct Tickets(Arc<Mutex<HashMap<String, (Instant, Ticket)>>>);
+
+/// 一对客户端共用的打洞令牌, 令牌会出现在打洞的数据包中,
+/// 两端各自的注册密钥只经由fuso连接下发, 得知令牌的第三方无法冒充另一端注册
+#[derive(Debug, Clone)]
+pub struct Ticket {
+    pub token: String,
+    provider: String,
+    visitor: String,
+}
+
+/// 打洞服务端, 负责交换双方的公网地址, 打洞失败时中转双方的数据
+pub struct RendezvousServer<C> {
+    core: C,
+    tickets: Tickets,
+    /// 等待对端注册的客户端
+    waiting: HashMap<String, (SocketAddr, Role, Instant)>,
+    /// 已经完成配对的客户端
+    pairs: HashMap<String, Pair>,
+    /// 中转路由, 地址对应的配对
+    routes: HashMap<SocketAddr, String>,
+}
+
+struct Pair {
+    provider: SocketAddr,
+    visitor: SocketAddr,
+    active: Instant,
+}
+
+impl Ticket {
+    /// `role` 一端注册时需要提供的密钥
+    pub fn secret(&self, role: Role) -> &str {
+        match role {
+            Role::Provider => &self.provider,
+            Role::Visitor => &self.visitor,
+        }
+    }
+}
+
+impl Tickets {
+    /// 分配一个新的令牌, 未在 `TICKET_TIMEOUT` 内配对的令牌失效
+    pub fn issue(&self) -> Ticket {
+        let ticket = Ticket {
+            token: random(),
+            provider: random(),
+            visitor: random(),
+        };
+
+        let mut tickets = self.0.lock().unwrap();
+        tickets.retain(|_, (issued, _)| issued.elapsed() < TICKET_TIMEOUT);
+        tickets.insert(ticket.token.clone(), (Instant::now(), ticket.clone()));
+
+        ticket
+    }
+
+    fn is_valid(&self, token: &str, role: Role, secret: &str) -> bool {
+        let mut tickets = self.0.lock().unwrap();
+        match tickets.get(token) {
+            Some((issued, ticket)) if issued.elapsed() < TICKET_TIMEOUT => {
+                ticket.secret(role) == secret
+            }
+            Some(_) => {
+                tickets.remove(token);
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn revoke(&self, ticket: &str) {
+        self.0.lock().unwrap().remove(ticket);
+    }
+}
+
+fn random() -> String {
+    (0..2)
+        .map(|_| format!("{:016x}", RandomState::new().build_hasher().finish()))
+        .collect()
+}
+
+impl Pair {
+    fn peer_of(&self, addr: &SocketAddr) -> Option<SocketAddr> {
+        if *addr == self.provider {
+            Some(self.visitor)
+        } else if *addr == self.visitor {
+            Some(self.provider)
+        } else {
+            None
+        }
+    }
+}
+
+impl<C> RendezvousServer<C>
+where
+    C: UdpSocket + Unpin + Send + Sync,
+{
+    pub fn new(core: C, tickets: Tickets) -> Self {
+        Self {
+            core,
+            tickets,
+            waiting: Default::default(),
+            pairs: Default::default(),
+            routes: Default::default(),
+        }
+    }
+
+    pub async fn run(mut self) -> crate::Result<()> {
+        let mut buf = [0u8; 1500];
+        let mut pruned = Instant::now();
+
+        loop {
+            if pruned.elapsed() >= PRUNE_INTERVAL {
+                self.prune();
+                pruned = Instant::now();
+            }
+
+            let (n, addr) =
+                match time::wait_for(PRUNE_INTERVAL, self.core.recv_from(&mut buf)).await {
+                    Err(_) => continue,
+                    Ok(Err(e)) => {
+                        log::warn!("p2p receive error {}", e);
+                        continue;
+                    }
+                    Ok(Ok(r)) => r,
+                };
+
+            let packet = &buf[..n];
+
+            if is_data(packet) {
+                let target = self
+                    .routes
+                    .get(&addr)
+                    .and_then(|token| self.pairs.get_mut(token))
+                    .and_then(|pair| {
+                        pair.active = Instant::now();
+                        pair.peer_of(&addr)
+                    });
+
+                if let Some(target) = target {
+                    self.send(&target, packet).await;
+                }
+
+                continue;
+            }
+
+            match decode(packet) {
+                Some(Message::Register(token, role, secret)) => {
+                    self.register(addr, token, role, secret).await;
+                }
+                Some(Message::Relay(token)) => {
+                    self.relay(addr, token).await;
+                }
+                _ => {
+                    log::debug!("ignore invalid p2p packet from {}", addr);
+                }
+            }
+        }
+    }
+
+    /// 发送失败不影响其他客户端
+    async fn send(&self, addr: &SocketAddr, packet: &[u8]) {
+        if let Err(e) = self.core.send_to(addr, packet).await {
+            log::warn!("failed to send p2p packet to {} err={}", addr, e);
+        }
+    }
+
+    /// 每一端以第一次注册的地址为准, 之后其他地址的注册都会被忽略,
+    /// 否则截获注册消息的第三方可以重放该消息接管这一端
+    async fn register(&mut self, addr: SocketAddr, token: String, role: Role, secret: String) {
+        // 对端未收到Peer消息时会重复注册
+        if let Some(pair) = self.pairs.get_mut(&token) {
+            let (registered, peer) = match role {
+                Role::Provider => (pair.provider, pair.visitor),
+                Role::Visitor => (pair.visitor, pair.provider),
+            };
+
+            if registered != addr {
+                log::warn!("ignore p2p {:?} register from {}", role, addr);
+                return;
+            }
+
+            pair.active = Instant::now();
+
+            self.send(&addr, &encode(&Message::Peer(peer))).await;
+
+            return;
+        }
+
+        if !self.tickets.is_valid(&token, role, &secret) {
+            log::debug!("ignore unknown p2p ticket from {}", addr);
+            return;
+        }
+
+        match self.waiting.remove(&token) {
+            Some((peer, peer_role, _)) if peer_role != role => {
+                log::info!("p2p pair {} <-> {}", addr, peer);
+
+                self.tickets.revoke(&token);
+
+                self.send(&addr, &encode(&Message::Peer(peer))).await;
+                self.send(&peer, &encode(&Message::Peer(addr))).await;
+
+                let (provider, visitor) = match role {
+                    Role::Provider => (addr, peer),
+                    Role::Visitor => (peer, addr),
+                };
+
+                self.pairs.insert(
+                    token,
+                    Pair {
+                        provider,
+                        visitor,
+                        active: Instant::now(),
+                    },
+                );
+            }
+            Some((registered, peer_role, since)) if registered != addr => {
+                log::warn!("ignore p2p {:?} register from {}", role, addr);
+                self.waiting.insert(token, (registered, peer_role, since));
+            }
+            _ => {
+                self.waiting.insert(token, (addr, role, Instant::now()));
+            }
+        }
+    }
+
+    async fn relay(&mut self, addr: SocketAddr, token: String) {
+        let peer = match self.pairs.get_mut(&token) {
+            Some(pair) => match pair.peer_of(&addr) {
+                Some(peer) => {
+                    pair.active = Instant::now();
+                    peer
+                }
+                None => return,
+            },
+            None => return,
+        };
+
+        if self.routes.insert(addr, token.clone()).is_none() {
+            log::info!("p2p relay {} <-> {}", addr, peer);
+        }
+
+        self.routes.insert(peer, token.clone());
+
+        // 通知对端同样改由服务端中转
+        self.send(&peer, &encode(&Message::Relay(token))).await;
+    }
+
+    fn prune(&mut self) {
+        self.waiting
+            .retain(|_, (_, _, since)| since.elapsed() < TICKET_TIMEOUT);
+
+        let routes = &mut self.routes;
+
+        self.pairs.retain(|_, pair| {
+            let alive = pair.active.elapsed() < PAIR_TIMEOUT;
+
+            if !alive {
+                log::debug!("p2p pair {} <-> {} expired", pair.provider, pair.visitor);
+                routes.remove(&pair.provider);
+                routes.remove(&pair.visitor);
+            }
+
+            alive
+        });
+    }
+}
@@ -0,0 +1,471 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Debug,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    io,
+    kcp::KcpConfig,
+    protocol::{AsyncRecvPacket, AsyncSendPacket, Auth, Connect, Poto, ToPacket, TryToPoto},
+    time, Accepter, AccepterExt, ClientProvider, Executor, InvalidAddr, Kind, Provider,
+    ProviderTransfer, Socket, Stream, UdpSocket,
+};
+
+use crate::select::Select;
+
+use super::{punch, P2pSocket, Role, Ticket, Tickets, PEER_TIMEOUT};
+
+type BoxedFuture<T> = Pin<Box<dyn std::future::Future<Output = crate::Result<T>> + Send + 'static>>;
+
+/// 等待中的提供端定期发送 `Ping`, 发送失败说明提供端已经断开
+const PROVIDER_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// 提供端取走令牌后需要重新连接, 访问者在该时间内等待提供端
+const PROVIDER_WAIT: Duration = Duration::from_secs(3);
+/// 获取令牌失败后重试的间隔
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// 服务端的p2p服务, 为访问者与同名的提供端分配打洞令牌.
+/// 令牌只经由fuso连接下发, 打洞服务不接受其他的令牌
+#[derive(Clone)]
+pub struct P2pService {
+    port: u16,
+    tickets: Tickets,
+    /// 等待访问者的提供端
+    providers: Arc<Mutex<HashMap<String, VecDeque<async_channel::Sender<Ticket>>>>>,
+}
+
+/// p2p客户端, 经由服务端获取打洞令牌, 与映射使用相同的传输方式与握手.
+/// 打洞服务的地址为服务端的地址加上服务端下发的端口
+pub struct P2pClient<E, CF, S, U> {
+    name: String,
+    executor: E,
+    udp_provider: U,
+    kcp_config: KcpConfig,
+    handshake: Option<ProviderTransfer<S>>,
+    client_provider: ClientProvider<CF>,
+}
+
+/// 提供p2p服务的一端, 每个访问连接单独打洞, 打通后由 `connector` 连接到 `target`
+pub struct P2pProvider<E, CF, S, U, C> {
+    client: P2pClient<E, CF, S, U>,
+    target: Socket,
+    connector: C,
+}
+
+/// 访问p2p服务的一端, 每个本地连接单独打洞, 打通后经由kcp转发到提供端
+pub struct P2pVisitor<E, CF, S, U, A> {
+    client: P2pClient<E, CF, S, U>,
+    accepter_provider: A,
+}
+
+impl P2pService {
+    /// `port` 为打洞服务的端口, `tickets` 需要与打洞服务共享
+    pub fn new(port: u16, tickets: Tickets) -> Self {
+        Self {
+            port,
+            tickets,
+            providers: Default::default(),
+        }
+    }
+
+    /// 提供端等待访问者, 收到令牌后连接结束, 提供端需要重新连接等待下一个访问者
+    pub(crate) async fn provide<S>(
+        &self,
+        mut client: S,
+        name: String,
+    ) -> crate::Result<BoxedFuture<()>>
+    where
+        S: Stream + Send + 'static,
+    {
+        let (sender, receiver) = async_channel::bounded(1);
+
+        {
+            let mut providers = self.providers.lock().unwrap();
+
+            providers.retain(|_, waiting| {
+                waiting.retain(|sender| !sender.is_closed());
+                !waiting.is_empty()
+            });
+
+            providers.entry(name.clone()).or_default().push_back(sender);
+        }
+
+        log::debug!("p2p provider {} is waiting for visitors", name);
+
+        let port = self.port;
+
+        Ok(Box::pin(async move {
+            let ticket = loop {
+                match time::wait_for(PROVIDER_CHECK_INTERVAL, receiver.recv()).await {
+                    Ok(ticket) => break ticket?,
+                    Err(_) => client.send_packet(&Poto::Ping.to_packet_vec()).await?,
+                }
+            };
+
+            let secret = ticket.secret(Role::Provider).to_owned();
+            let connect = Connect::P2pTicket(ticket.token, secret, port);
+
+            client
+                .send_packet(&Poto::Connect(connect, Auth::NoAuth).to_packet_vec())
+                .await
+        }))
+    }
+
+    /// 为访问者与一个等待中的提供端分配同一个令牌
+    pub(crate) async fn visit<S>(
+        &self,
+        mut client: S,
+        name: String,
+    ) -> crate::Result<BoxedFuture<()>>
+    where
+        S: Stream + Send + 'static,
+    {
+        let ticket = self.tickets.issue();
+        let deadline = Instant::now() + PROVIDER_WAIT;
+
+        while !self.notify(&name, &ticket) {
+            if Instant::now() >= deadline {
+                self.tickets.revoke(&ticket.token);
+
+                let error = format!("no p2p provider named {}", name);
+                let message = Poto::MapError(0, error.clone()).to_packet_vec();
+                let _ = client.send_packet(&message).await;
+
+                return Err(Kind::Message(error).into());
+            }
+
+            time::sleep(Duration::from_millis(100)).await;
+        }
+
+        log::info!("p2p visitor {} -> {}", client.peer_addr()?, name);
+
+        let secret = ticket.secret(Role::Visitor).to_owned();
+        let connect = Connect::P2pTicket(ticket.token, secret, self.port);
+
+        client
+            .send_packet(&Poto::Connect(connect, Auth::NoAuth).to_packet_vec())
+            .await?;
+
+        Ok(Box::pin(async move { Ok(()) }))
+    }
+
+    fn notify(&self, name: &str, ticket: &Ticket) -> bool {
+        let mut providers = self.providers.lock().unwrap();
+
+        if let Some(waiting) = providers.get_mut(name) {
+            while let Some(sender) = waiting.pop_front() {
+                if sender.try_send(ticket.clone()).is_ok() {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+}
+
+impl Debug for P2pService {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("P2pService")
+            .field("port", &self.port)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<E, CF, S, U> P2pClient<E, CF, S, U> {
+    pub fn new(
+        name: String,
+        executor: E,
+        udp_provider: U,
+        handshake: Option<ProviderTransfer<S>>,
+        client_provider: ClientProvider<CF>,
+    ) -> Self {
+        Self {
+            name,
+            executor,
+            udp_provider,
+            kcp_config: Default::default(),
+            handshake,
+            client_provider,
+        }
+    }
+
+    /// 打通后的kcp连接使用的配置
+    pub fn with_kcp_config(mut self, kcp_config: KcpConfig) -> Self {
+        self.kcp_config = kcp_config;
+        self
+    }
+
+    /// 作为提供端运行
+    pub fn into_provider<C>(self, target: Socket, connector: C) -> P2pProvider<E, CF, S, U, C> {
+        P2pProvider {
+            client: self,
+            target,
+            connector,
+        }
+    }
+
+    /// 作为访问者运行
+    pub fn into_visitor<A>(self, accepter_provider: A) -> P2pVisitor<E, CF, S, U, A> {
+        P2pVisitor {
+            client: self,
+            accepter_provider,
+        }
+    }
+}
+
+impl<E, CF, S, U, D> P2pClient<E, CF, S, U>
+where
+    E: Executor + Clone + Unpin + Send + Sync + 'static,
+    CF: Provider<Socket, Output = BoxedFuture<S>> + Send + Sync + 'static,
+    S: Stream + Send + 'static,
+    U: Provider<Socket, Output = BoxedFuture<D>> + Send + Sync + 'static,
+    D: UdpSocket + Unpin + Send + Sync + 'static,
+{
+    /// 向服务端请求打洞令牌, 返回令牌、注册密钥与打洞服务的地址
+    async fn ticket(&self, role: Role) -> crate::Result<(String, String, SocketAddr)> {
+        let socket = self.client_provider.default_socket().clone();
+        let server = self.client_provider.connect(socket.clone()).await?;
+
+        let mut server = match self.handshake.as_ref() {
+            None => server,
+            Some(handshake) => handshake.call(server).await?,
+        };
+
+        let connect = match role {
+            Role::Provider => Connect::P2pProvide(self.name.clone()),
+            Role::Visitor => Connect::P2pVisit(self.name.clone()),
+        };
+
+        server
+            .send_packet(&Poto::Connect(connect, Auth::NoAuth).to_packet_vec())
+            .await?;
+
+        let (ticket, secret, port) = loop {
+            match server.recv_packet().await?.try_message()? {
+                Poto::Connect(Connect::P2pTicket(ticket, secret, port), _) => {
+                    break (ticket, secret, port)
+                }
+                Poto::Ping => continue,
+                Poto::MapError(_, e) => return Err(Kind::Message(e).into()),
+                message => return Err(Kind::Unexpected(format!("{}", message)).into()),
+            }
+        };
+
+        let mut rendezvous = socket;
+        rendezvous.set_port(port);
+
+        let rendezvous = rendezvous
+            .as_string()
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| InvalidAddr::Domain(rendezvous.as_string()))?;
+
+        Ok((ticket, secret, rendezvous))
+    }
+
+    async fn punch(
+        &self,
+        (ticket, secret, rendezvous): (String, String, SocketAddr),
+        role: Role,
+    ) -> crate::Result<P2pSocket<D>> {
+        let ip: IpAddr = match rendezvous {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+
+        let udp = self.udp_provider.call(Socket::udp((ip, 0))).await?;
+
+        punch(udp, rendezvous, ticket, secret, role).await
+    }
+}
+
+impl<E, CF, S, U, D, C, O> P2pProvider<E, CF, S, U, C>
+where
+    E: Executor + Clone + Unpin + Send + Sync + 'static,
+    CF: Provider<Socket, Output = BoxedFuture<S>> + Send + Sync + 'static,
+    S: Stream + Send + 'static,
+    U: Provider<Socket, Output = BoxedFuture<D>> + Send + Sync + 'static,
+    D: UdpSocket + Unpin + Send + Sync + 'static,
+    C: Provider<Socket, Output = BoxedFuture<O>> + Send + Sync + 'static,
+    O: Stream + Send + 'static,
+{
+    pub async fn run(self) -> crate::Result<()> {
+        let this = Arc::new(self);
+
+        log::info!("p2p provider {} -> {}", this.client.name, this.target);
+
+        loop {
+            let ticket = match this.client.ticket(Role::Provider).await {
+                Ok(ticket) => ticket,
+                Err(e) => {
+                    log::warn!("failed to wait for p2p visitors err={}", e);
+                    time::sleep(RETRY_DELAY).await;
+                    continue;
+                }
+            };
+
+            let provider = this.clone();
+
+            this.client.executor.spawn(async move {
+                if let Err(e) = provider.serve(ticket).await {
+                    log::warn!("p2p provider error {}", e);
+                }
+            });
+        }
+    }
+
+    async fn serve(self: Arc<Self>, ticket: (String, String, SocketAddr)) -> crate::Result<()> {
+        let socket = self.client.punch(ticket, Role::Provider).await?;
+
+        let mut listener =
+            socket.into_listener(self.client.kcp_config.clone(), self.client.executor.clone())?;
+
+        let stream = time::wait_for(PEER_TIMEOUT, listener.accept()).await??;
+        let local = self.connector.call(self.target.clone()).await?;
+
+        // 收到的kcp数据由listener分发, 转发结束前需要继续运行
+        Select::select(io::forward(stream, local), async move {
+            loop {
+                let _ = listener.accept().await?;
+            }
+        })
+        .await
+    }
+}
+
+impl<E, CF, S, U, D, A, L, T> P2pVisitor<E, CF, S, U, A>
+where
+    E: Executor + Clone + Unpin + Send + Sync + 'static,
+    CF: Provider<Socket, Output = BoxedFuture<S>> + Send + Sync + 'static,
+    S: Stream + Send + 'static,
+    U: Provider<Socket, Output = BoxedFuture<D>> + Send + Sync + 'static,
+    D: UdpSocket + Unpin + Send + Sync + 'static,
+    A: Provider<Socket, Output = BoxedFuture<L>> + Send + Sync + 'static,
+    L: Accepter<Stream = T> + Unpin + Send + 'static,
+    T: Stream + Send + 'static,
+{
+    pub async fn run<B: Into<Socket>>(self, bind: B) -> crate::Result<()> {
+        let mut accepter = self.accepter_provider.call(bind.into()).await?;
+
+        log::info!(
+            "p2p visitor of {} listens on {}",
+            self.client.name,
+            accepter.local_addr()?
+        );
+
+        let client = Arc::new(self.client);
+
+        loop {
+            let stream = accepter.accept().await?;
+            let client = client.clone();
+            let executor = client.executor.clone();
+
+            executor.spawn(async move {
+                if let Err(e) = Self::visit(client, stream).await {
+                    log::warn!("p2p visitor error {}", e);
+                }
+            });
+        }
+    }
+
+    async fn visit(client: Arc<P2pClient<E, CF, S, U>>, stream: T) -> crate::Result<()> {
+        let ticket = client.ticket(Role::Visitor).await?;
+        let socket = client.punch(ticket, Role::Visitor).await?;
+
+        log::debug!(
+            "p2p connected to {}, relay={}",
+            client.name,
+            socket.is_relay()
+        );
+
+        let connector = socket.into_connector(client.kcp_config.clone(), client.executor.clone());
+        let kcp = connector.connect().await?;
+        let r = io::forward(stream, kcp).await;
+
+        drop(connector);
+
+        r
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "fuso-rt-tokio")]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use crate::{
+        p2p::{RendezvousServer, Role, Tickets},
+        testing, Socket, TokioAccepter, TokioConnector, TokioUdpServerProvider,
+    };
+
+    use super::P2pService;
+
+    /// 提供端与访问者经由服务端获取令牌并打洞, 访问者的本地连接应到达提供端的服务
+    #[tokio::test]
+    async fn test_p2p_over_server() {
+        let udp = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let p2p_port = udp.local_addr().unwrap().port();
+        let tickets = Tickets::default();
+
+        tokio::spawn(RendezvousServer::new(udp, tickets.clone()).run());
+
+        let (builder, listened) = crate::builder_server_with_tokio().listening();
+
+        let server = testing::serve(listened, move |bind| {
+            builder
+                .with_penetrate()
+                .with_p2p(P2pService::new(p2p_port, tickets))
+                .with_adapter_mode()
+                .with_normal_unpacker()
+                .build()
+                .bind(bind)
+                .run()
+        })
+        .await;
+
+        let service = testing::echo().await;
+
+        let client = |name: &str| {
+            crate::builder_client_with_tokio().build_p2p(
+                server.clone(),
+                name.to_string(),
+                TokioUdpServerProvider,
+            )
+        };
+
+        let unknown = client("unknown");
+        assert!(unknown.ticket(Role::Visitor).await.is_err());
+
+        tokio::spawn(
+            client("ssh")
+                .into_provider(Socket::tcp(service), TokioConnector::default())
+                .run(),
+        );
+
+        let (accepter, listened) = testing::listening(TokioAccepter);
+
+        tokio::spawn(
+            client("ssh")
+                .into_visitor(accepter)
+                .run(Socket::tcp(([127, 0, 0, 1], 0))),
+        );
+
+        let visit = listened.addr().await;
+
+        let mut visitor = TcpStream::connect(visit.as_string()).await.unwrap();
+
+        visitor.write_all(b"hello world!").await.unwrap();
+
+        let mut buf = [0u8; 12];
+        visitor.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello world!");
+    }
+}
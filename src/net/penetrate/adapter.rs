@@ -1,14 +1,8 @@
 use std::{pin::Pin, sync::Arc};
 
-use crate::{
-    guard::Fallback, server::Server, Accepter, Executor, Provider, ProviderWrapper, Fuso, Socket,
-    Stream,
-};
+use crate::{guard::Fallback, Accepter, Executor, Provider, ProviderWrapper, Socket, Stream};
 
-use super::{
-    server::{Peer, PenetrateProvider},
-    PenetrateServerBuilder,
-};
+use super::{server::Peer, PenetrateServer, PenetrateServerBuilder};
 
 type BoxedFuture<T> = Pin<Box<dyn std::future::Future<Output = crate::Result<T>> + Send + 'static>>;
 
@@ -41,7 +35,7 @@ where
     A: Accepter<Stream = S> + Unpin + Send + 'static,
     S: Stream + Send + Sync + 'static,
 {
-    pub fn build(self) -> Fuso<Server<E, PenetrateProvider<S>, SF, CF, S>> {
+    pub fn build(self) -> PenetrateServer<E, SF, CF, A, S> {
         self.penetrate_builder
             .disable_fallback_strict_mode()
             .build(PenetrateAdapter(Arc::new(self.adapters)))
@@ -0,0 +1,453 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fmt::Display,
+    hash::{Hash, Hasher},
+    net::IpAddr,
+    pin::Pin,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, Weak,
+    },
+    task::{Context, Poll, Wake, Waker},
+};
+
+use crate::{guard::Fallback, io::WriteHalf, Accepter, Acl, Address, NetSocket, Socket};
+
+use super::server::WaitFor;
+
+/// 多个客户端绑定同一个端口时分配访问者的方式
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Balance {
+    /// 依次轮流
+    #[default]
+    RoundRobin,
+    /// 正在转发的连接最少的客户端
+    LeastConnections,
+    /// 按访问者ip固定到同一个客户端, 客户端增减时会重新分配
+    Sticky,
+}
+
+/// 接收访问者的客户端
+pub(crate) struct Member<T> {
+    pub(crate) writer: WriteHalf<T>,
+    pub(crate) client_addr: Address,
+    pub(crate) protocol: u32,
+    /// 客户端绑定时请求的访问者黑白名单
+    pub(crate) acl: Option<Arc<Acl>>,
+    pub(crate) rejected: AtomicU64,
+    id: u64,
+    active: Arc<AtomicUsize>,
+}
+
+/// 正在转发的连接, 释放时从所属客户端的连接数中减去
+pub struct Active(Arc<AtomicUsize>);
+
+/// 分组的编号, 只增不减, 不会与已释放的分组重复
+static NEXT_GROUP: AtomicU64 = AtomicU64::new(0);
+
+/// 绑定同一个端口的客户端, 共享监听器与等待中的映射
+pub(crate) struct Group<T, A> {
+    id: u64,
+    balance: Balance,
+    accepter: Mutex<A>,
+    wakers: Arc<Wakers>,
+    members: Mutex<Vec<Arc<Member<T>>>>,
+    identify: AtomicU64,
+    next: AtomicUsize,
+    pub(crate) wait_for: WaitFor<async_channel::Sender<Fallback<T>>>,
+}
+
+/// 按 `group_key` 索引的分组, 最后一个客户端离开后失效
+pub(crate) type Groups<T, A> = Arc<crate::sync::Mutex<HashMap<String, Weak<Group<T, A>>>>>;
+
+/// 监听器就绪时唤醒所有成员, 由先被调度的成员继续accept
+#[derive(Default)]
+struct Wakers(Mutex<HashMap<u64, Waker>>);
+
+impl<T> Member<T> {
+    pub(crate) fn active(&self) -> Active {
+        self.active.fetch_add(1, Ordering::Relaxed);
+        Active(self.active.clone())
+    }
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Wake for Wakers {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let wakers = std::mem::take(&mut *self.0.lock().unwrap());
+        for waker in wakers.into_values() {
+            waker.wake();
+        }
+    }
+}
+
+impl<T, A> Group<T, A> {
+    pub(crate) fn new(accepter: A, balance: Balance) -> Self {
+        Self {
+            id: NEXT_GROUP.fetch_add(1, Ordering::Relaxed),
+            balance,
+            accepter: Mutex::new(accepter),
+            wakers: Default::default(),
+            members: Default::default(),
+            identify: Default::default(),
+            next: Default::default(),
+            wait_for: WaitFor::default(),
+        }
+    }
+
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub(crate) fn join(
+        &self,
+        writer: WriteHalf<T>,
+        client_addr: Address,
+        protocol: u32,
+        acl: Option<Acl>,
+    ) -> Arc<Member<T>> {
+        let member = Arc::new(Member {
+            writer,
+            client_addr,
+            protocol,
+            acl: acl.filter(|acl| !acl.is_empty()).map(Arc::new),
+            rejected: Default::default(),
+            id: self.identify.fetch_add(1, Ordering::Relaxed),
+            active: Default::default(),
+        });
+
+        let mut members = self.members.lock().unwrap();
+        members.push(member.clone());
+
+        log::debug!(
+            "{} joined, {} clients in rotation",
+            member.client_addr,
+            members.len()
+        );
+
+        member
+    }
+
+    /// 控制连接断开后不再分配访问者
+    pub(crate) fn leave(&self, member: &Member<T>) {
+        self.wakers.0.lock().unwrap().remove(&member.id);
+
+        let mut members = self.members.lock().unwrap();
+        members.retain(|m| m.id != member.id);
+
+        log::debug!(
+            "{} left, {} clients in rotation",
+            member.client_addr,
+            members.len()
+        );
+
+        // 由剩下的成员继续accept
+        drop(members);
+        self.wakers.wake_by_ref();
+    }
+
+    /// 只在 `allowed` 的成员之间分配, 没有成员允许时返回拒绝了该访问者的成员
+    pub(crate) fn choose<F>(
+        &self,
+        visitor: Option<IpAddr>,
+        allowed: F,
+    ) -> Result<Arc<Member<T>>, Vec<Arc<Member<T>>>>
+    where
+        F: Fn(&Member<T>) -> bool,
+    {
+        let members = self.members.lock().unwrap();
+
+        let (members, rejected): (Vec<_>, Vec<_>) =
+            members.iter().cloned().partition(|member| allowed(member));
+
+        if members.is_empty() {
+            return Err(rejected);
+        }
+
+        let len = members.len();
+
+        let index = match (self.balance, visitor) {
+            (Balance::Sticky, Some(ip)) => {
+                let mut hasher = DefaultHasher::new();
+                ip.hash(&mut hasher);
+                hasher.finish() as usize % len
+            }
+            (Balance::LeastConnections, _) => {
+                // 从轮询的位置开始查找, 连接数相同时不总是选中同一个
+                let start = self.next.fetch_add(1, Ordering::Relaxed);
+                (start..start + len)
+                    .map(|i| i % len)
+                    .min_by_key(|&i| members[i].active.load(Ordering::Relaxed))
+                    .unwrap_or_default()
+            }
+            _ => self.next.fetch_add(1, Ordering::Relaxed) % len,
+        };
+
+        Ok(members[index].clone())
+    }
+
+    pub(crate) fn poll_accept(
+        &self,
+        member: &Member<T>,
+        cx: &mut Context<'_>,
+    ) -> Poll<crate::Result<T>>
+    where
+        A: Accepter<Stream = T> + Unpin,
+    {
+        self.wakers
+            .0
+            .lock()
+            .unwrap()
+            .insert(member.id, cx.waker().clone());
+
+        let waker = Waker::from(self.wakers.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        Pin::new(&mut *self.accepter.lock().unwrap()).poll_accept(&mut cx)
+    }
+}
+
+impl<T, A> NetSocket for Group<T, A>
+where
+    A: NetSocket,
+{
+    fn peer_addr(&self) -> crate::Result<Address> {
+        self.accepter.lock().unwrap().peer_addr()
+    }
+
+    fn local_addr(&self) -> crate::Result<Address> {
+        self.accepter.lock().unwrap().local_addr()
+    }
+}
+
+/// 指定端口时按地址分组, 随机端口时按映射名称分组, 都没有时不参与分组
+pub(crate) fn group_key(socket: &Socket, name: Option<&str>) -> Option<String> {
+    match (socket.port(), name) {
+        (0, None) => None,
+        (0, Some(name)) => Some(format!("{}@{}", name, socket)),
+        _ => Some(socket.to_string()),
+    }
+}
+
+impl FromStr for Balance {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "round-robin" | "rr" => Ok(Self::RoundRobin),
+            "least-conn" | "least-connections" => Ok(Self::LeastConnections),
+            "sticky" | "ip-hash" => Ok(Self::Sticky),
+            _ => Err(format!(
+                "unknown balance {}, expect round-robin, least-conn or sticky",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for Balance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::RoundRobin => write!(f, "round-robin"),
+            Self::LeastConnections => write!(f, "least-conn"),
+            Self::Sticky => write!(f, "sticky"),
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "fuso-rt-tokio")]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use crate::{io, testing, Address, Shutdown, Socket, TokioPenetrateConnector};
+
+    use super::{group_key, Balance, Group};
+
+    /// 读取访问者发送的数据后回复自己的标记, 访问者需要先发送足够识别的数据
+    async fn service(tag: u8) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            loop {
+                let (mut tcp, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 12];
+                    tcp.read_exact(&mut buf).await.unwrap();
+                    tcp.write_all(&[tag]).await.unwrap();
+                });
+            }
+        });
+
+        port
+    }
+
+    async fn visit(port: u16) -> u8 {
+        let mut tcp = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        tcp.write_all(b"hello world!").await.unwrap();
+        let mut buf = [0u8; 1];
+        tcp.read_exact(&mut buf).await.unwrap();
+        buf[0]
+    }
+
+    async fn new_group(balance: Balance) -> (Group<TcpStream, ()>, Vec<TcpStream>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let group = Group::new((), balance);
+        let mut streams = Vec::new();
+
+        for _ in 0..3 {
+            let (client, server) = tokio::join!(TcpStream::connect(addr), listener.accept());
+            let (_, writer) = io::split(server.unwrap().0);
+            group.join(writer, Address::Single(Socket::tcp(addr)), 3, None);
+            streams.push(client.unwrap());
+        }
+
+        (group, streams)
+    }
+
+    fn ids(group: &Group<TcpStream, ()>, visitor: &str, n: usize) -> Vec<u64> {
+        (0..n)
+            .map(|_| {
+                group
+                    .choose(visitor.parse().ok(), |_| true)
+                    .ok()
+                    .unwrap()
+                    .id
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_balance() {
+        let (group, _streams) = new_group(Balance::RoundRobin).await;
+        assert_eq!(ids(&group, "10.0.0.1", 4), [0, 1, 2, 0]);
+
+        let (group, _streams) = new_group(Balance::Sticky).await;
+        let first = ids(&group, "10.0.0.1", 1)[0];
+        assert!(ids(&group, "10.0.0.1", 4).iter().all(|id| *id == first));
+
+        let (group, _streams) = new_group(Balance::LeastConnections).await;
+        let busy = group.choose(None, |_| true).ok().unwrap();
+        let _active = (busy.active(), busy.active());
+        let chosen = ids(&group, "10.0.0.1", 4);
+        assert!(chosen.iter().all(|id| *id != busy.id));
+
+        // 离开之后不再分配
+        let member = group.choose(None, |_| true).ok().unwrap();
+        group.leave(&member);
+        assert!(ids(&group, "10.0.0.1", 6).iter().all(|id| *id != member.id));
+
+        // 只在允许访问者的成员之间轮询, 都不允许时返回所有成员
+        let (group, _streams) = new_group(Balance::RoundRobin).await;
+        let chosen = (0..4)
+            .map(|_| group.choose(None, |m| m.id != 0).ok().unwrap().id)
+            .collect::<Vec<_>>();
+        assert_eq!(chosen, [1, 2, 1, 2]);
+        assert_eq!(group.choose(None, |_| false).err().unwrap().len(), 3);
+
+        assert_eq!(
+            "least-conn".parse::<Balance>(),
+            Ok(Balance::LeastConnections)
+        );
+        assert_eq!(Balance::Sticky.to_string(), "sticky");
+        assert!("random".parse::<Balance>().is_err());
+
+        let (second, _streams) = new_group(Balance::RoundRobin).await;
+        assert!(second.id() > group.id());
+
+        let random = Socket::tcp(([0, 0, 0, 0], 0));
+        assert_eq!(group_key(&random, None), None);
+        assert_eq!(
+            group_key(&random, Some("web")),
+            group_key(&random, Some("web"))
+        );
+        assert_ne!(
+            group_key(&random, Some("web")),
+            group_key(&random, Some("db"))
+        );
+        assert!(group_key(&Socket::tcp(([0, 0, 0, 0], 8080)), Some("web")).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_shared_mapping() {
+        let (builder, listened) = crate::builder_server_with_tokio().listening();
+        let penetrate = builder.with_penetrate().with_balance(Balance::RoundRobin);
+        let registry = penetrate.registry();
+
+        let server = testing::serve(listened, move |bind| {
+            penetrate
+                .with_adapter_mode()
+                .with_normal_unpacker()
+                .build()
+                .bind(bind)
+                .run()
+        })
+        .await;
+
+        let mut shutdowns = Vec::new();
+
+        for tag in [b'a', b'b'] {
+            let port = service(tag).await;
+            let server = server.clone();
+            let shutdown = Shutdown::default();
+
+            shutdowns.push(shutdown.clone());
+
+            testing::spawn(move || async move {
+                crate::builder_client_with_tokio()
+                    .using_penetrate(
+                        Socket::tcp(([127, 0, 0, 1], 0)),
+                        Socket::tcp(([127, 0, 0, 1], port)),
+                    )
+                    .name(Some(String::from("web")))
+                    .build(server, TokioPenetrateConnector::new().await.unwrap())
+                    .with_shutdown(shutdown)
+                    .run()
+                    .await
+            });
+        }
+
+        let clients = |n| {
+            let registry = registry.clone();
+            move || {
+                registry
+                    .get("web")
+                    .filter(|mapping| mapping.clients.len() == n)
+            }
+        };
+
+        let port = testing::until(clients(2)).await.port;
+
+        let mut tags = Vec::new();
+        for _ in 0..4 {
+            tags.push(visit(port).await);
+        }
+
+        tags.sort_unstable();
+        assert_eq!(tags, b"aabb");
+
+        // 控制连接断开后不再分配
+        shutdowns.remove(0).shutdown();
+        testing::until(clients(1)).await;
+
+        for _ in 0..3 {
+            assert_eq!(visit(port).await, b'b');
+        }
+    }
+}
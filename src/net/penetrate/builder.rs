@@ -1,19 +1,27 @@
 use std::{pin::Pin, sync::Arc, time::Duration};
 
 use crate::{
-    client::{Client, ClientBuilder, Route},
+    client::{Client, ClientBuilder, Reconnect, Route},
     guard::Fallback,
+    haproxy::ProxyProtocol,
     server::{Server, ServerBuilder},
-    Accepter, Executor, Fuso, Provider, ProviderWrapper, Socket, Stream,
+    Accepter, Acl, Executor, Fuso, Provider, ProviderWrapper, Socket, Stream,
 };
 
 use super::{
     client::PenetrateClientProvider,
     server::{Config, Peer, PenetrateProvider},
+    Balance, Heartbeat, NameConflict, Registry,
 };
 
 type BoxedFuture<T> = Pin<Box<dyn std::future::Future<Output = crate::Result<T>> + Send + 'static>>;
 
+/// 穿透模式的服务端, 每个绑定的端口都由一个 `A` 监听
+pub type PenetrateServer<E, SF, CF, A, S> = Fuso<Server<E, PenetrateProvider<S, A>, SF, CF, S>>;
+
+/// 未设置时客户端发送心跳的间隔
+const DEFAULT_HEARTBEAT_DELAY: Duration = Duration::from_secs(10);
+
 pub struct PenetrateServerBuilder<E, SF, CF, S> {
     is_mixed: bool,
     max_wait_time: Duration,
@@ -21,6 +29,16 @@ pub struct PenetrateServerBuilder<E, SF, CF, S> {
     read_timeout: Option<Duration>,
     write_timeout: Option<Duration>,
     fallback_strict_mode: bool,
+    #[cfg(feature = "fuso-proxy")]
+    allowlist: Option<Arc<crate::proxy::Allowlist>>,
+    #[cfg(feature = "fuso-tun")]
+    tun: Option<crate::tun::TunService>,
+    #[cfg(feature = "fuso-kcp")]
+    p2p: Option<crate::p2p::P2pService>,
+    visitor_acl: Option<Arc<Acl>>,
+    balance: Option<Balance>,
+    name_conflict: NameConflict,
+    registry: Registry,
     server_builder: ServerBuilder<E, SF, CF, S>,
 }
 
@@ -37,6 +55,13 @@ pub struct PenetrateClientBuilder<E, CF, S> {
     maximum_retries: Option<usize>,
     /// 心跳延时
     heartbeat_delay: Option<Duration>,
+    heartbeat: Heartbeat,
+    /// 向本地服务发送的PROXY协议头
+    proxy_protocol: Option<ProxyProtocol>,
+    /// 请求服务端使用的访问者黑白名单
+    visitor_acl: Acl,
+    /// 在服务端注册的映射名称
+    name: Option<String>,
     client_builder: ClientBuilder<E, CF, S>,
 }
 
@@ -49,6 +74,16 @@ impl<E, SF, CF, S> ServerBuilder<E, SF, CF, S> {
             max_wait_time: Duration::from_secs(10),
             heartbeat_timeout: Duration::from_secs(60),
             fallback_strict_mode: true,
+            #[cfg(feature = "fuso-proxy")]
+            allowlist: None,
+            #[cfg(feature = "fuso-tun")]
+            tun: None,
+            #[cfg(feature = "fuso-kcp")]
+            p2p: None,
+            visitor_acl: None,
+            balance: None,
+            name_conflict: Default::default(),
+            registry: Default::default(),
             server_builder: self,
         }
     }
@@ -92,7 +127,53 @@ where
         self
     }
 
-    pub fn build<F>(self, unpacker: F) -> Fuso<Server<E, PenetrateProvider<S>, SF, CF, S>>
+    /// 启用直连模式, 客户端可以请求服务端代为连接 `allowlist` 中的目标
+    #[cfg(feature = "fuso-proxy")]
+    pub fn with_forward_proxy(mut self, allowlist: crate::proxy::Allowlist) -> Self {
+        self.allowlist = Some(Arc::new(allowlist));
+        self
+    }
+
+    /// 允许客户端凭令牌在fuso连接上转发 `tun` 设备的ip包
+    #[cfg(feature = "fuso-tun")]
+    pub fn with_tun(mut self, tun: crate::tun::TunService) -> Self {
+        self.tun = Some(tun);
+        self
+    }
+
+    /// 为同名的p2p客户端分配打洞令牌, 打洞服务需要另外运行
+    #[cfg(feature = "fuso-kcp")]
+    pub fn with_p2p(mut self, p2p: crate::p2p::P2pService) -> Self {
+        self.p2p = Some(p2p);
+        self
+    }
+
+    /// 所有映射的访问者黑白名单, 命中的规则优先于客户端请求的规则,
+    /// 设置了 `allow` 时未命中的访问者一律拒绝
+    pub fn visitor_acl(mut self, acl: Acl) -> Self {
+        self.visitor_acl = Some(acl).filter(|acl| !acl.is_empty()).map(Arc::new);
+        self
+    }
+
+    /// 允许多个客户端绑定同一个端口, 按 `balance` 将访问者分配给它们,
+    /// 客户端的控制连接断开后不再参与分配
+    pub fn with_balance(mut self, balance: Balance) -> Self {
+        self.balance = Some(balance);
+        self
+    }
+
+    /// 客户端请求的映射名称已被使用时的处理方式, 默认拒绝
+    pub fn name_conflict(mut self, conflict: NameConflict) -> Self {
+        self.name_conflict = conflict;
+        self
+    }
+
+    /// 服务端的映射名称表, 可以在运行时查询已注册的映射
+    pub fn registry(&self) -> Registry {
+        self.registry.clone()
+    }
+
+    pub fn build<F>(self, unpacker: F) -> PenetrateServer<E, SF, CF, A, S>
     where
         F: Provider<Fallback<S>, Output = BoxedFuture<Peer<Fallback<S>>>> + Send + Sync + 'static,
     {
@@ -104,8 +185,19 @@ where
                 read_timeout: self.read_timeout,
                 write_timeout: self.write_timeout,
                 fallback_strict_mode: self.fallback_strict_mode,
+                #[cfg(feature = "fuso-proxy")]
+                allowlist: self.allowlist,
+                #[cfg(feature = "fuso-tun")]
+                tun: self.tun,
+                #[cfg(feature = "fuso-kcp")]
+                p2p: self.p2p,
+                visitor_acl: self.visitor_acl,
+                balance: self.balance,
+                name_conflict: self.name_conflict,
             },
             unpacker: Arc::new(ProviderWrapper::wrap(unpacker)),
+            groups: Default::default(),
+            registry: self.registry,
         })
     }
 }
@@ -124,6 +216,10 @@ impl<E, CF, S> ClientBuilder<E, CF, S> {
             maximum_retries: None,
             reconnect_delay: None,
             heartbeat_delay: None,
+            heartbeat: Default::default(),
+            proxy_protocol: None,
+            visitor_acl: Default::default(),
+            name: None,
         }
     }
 }
@@ -154,6 +250,29 @@ where
         self
     }
 
+    /// 连接本地服务后先发送PROXY协议头, 让本地服务得到访问者的真实地址
+    pub fn proxy_protocol(mut self, proxy_protocol: Option<ProxyProtocol>) -> Self {
+        self.proxy_protocol = proxy_protocol;
+        self
+    }
+
+    /// 请求服务端只允许符合规则的访问者, 服务端不支持时绑定失败
+    pub fn visitor_acl(mut self, acl: Acl) -> Self {
+        self.visitor_acl = acl;
+        self
+    }
+
+    /// 以名称在服务端注册映射, 名称冲突时由服务端决定拒绝或重命名
+    pub fn name(mut self, name: Option<String>) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// 客户端的心跳状态, 可以在运行时获取与服务端之间的往返时间
+    pub fn heartbeat(&self) -> Heartbeat {
+        self.heartbeat.clone()
+    }
+
     pub fn build<A: Into<Socket>, C>(
         self,
         server_socket: A,
@@ -162,12 +281,28 @@ where
     where
         C: Provider<Socket, Output = BoxedFuture<Route<S>>> + Unpin + Send + Sync + 'static,
     {
-        self.client_builder.build(
-            server_socket,
-            PenetrateClientProvider {
-                transform: (self.upstream, self.downstream),
-                connector_provider: Arc::new(connector),
-            },
-        )
+        let default = Reconnect::default();
+
+        let reconnect = Reconnect {
+            delay: self.reconnect_delay.unwrap_or(default.delay),
+            max_retries: self.maximum_retries,
+            connect_timeout: self.maximum_wait.unwrap_or(default.connect_timeout),
+            ..default
+        };
+
+        self.client_builder
+            .build(
+                server_socket,
+                PenetrateClientProvider {
+                    transform: (self.upstream, self.downstream),
+                    connector_provider: Arc::new(connector),
+                    heartbeat_delay: self.heartbeat_delay.unwrap_or(DEFAULT_HEARTBEAT_DELAY),
+                    heartbeat: self.heartbeat,
+                    proxy_protocol: self.proxy_protocol,
+                    visitor_acl: self.visitor_acl,
+                    name: self.name,
+                },
+            )
+            .with_reconnect(reconnect)
     }
 }
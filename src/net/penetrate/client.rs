@@ -6,11 +6,17 @@ use std::{future::Future, task::Poll};
 use crate::io::{ReadHalf, WriteHalf};
 use crate::{
     client::Route,
+    ext::AsyncWriteExt,
     generator::Generator,
-    protocol::{AsyncRecvPacket, AsyncSendPacket, Bind, Poto, ToPacket, TryToPoto},
-    Kind, Socket, Stream, {ClientProvider, Provider},
+    haproxy::ProxyProtocol,
+    protocol::{
+        self, AsyncRecvPacket, AsyncSendPacket, Bind, Origin, Poto, ToPacket, TryToPoto, Version,
+    },
+    Acl, Kind, Socket, SocketKind, Stream, {ClientProvider, Provider},
 };
 
+use super::Heartbeat;
+
 use crate::{io, join, time};
 
 type BoxedFuture<T> = Pin<Box<dyn std::future::Future<Output = crate::Result<T>> + Send + 'static>>;
@@ -38,11 +44,21 @@ macro_rules! async_connect {
 pub struct PenetrateClientProvider<C> {
     pub transform: (Socket, Socket),
     pub connector_provider: Arc<C>,
+    /// 向服务端发送心跳的间隔
+    pub heartbeat_delay: Duration,
+    /// 重连后沿用, 用于获取与服务端之间的往返时间
+    pub heartbeat: Heartbeat,
+    /// 连接本地服务后先发送PROXY协议头, 携带访问者的真实地址
+    pub proxy_protocol: Option<ProxyProtocol>,
+    /// 绑定时请求服务端使用的访问者黑白名单
+    pub visitor_acl: Acl,
+    /// 在服务端注册的映射名称
+    pub name: Option<String>,
 }
 
 enum State {
     Ready(BoxedFuture<()>),
-    Map(u32, Socket),
+    Map(u32, Socket, Option<Origin>),
     Error(crate::Error),
 }
 
@@ -50,9 +66,13 @@ pub struct PenetrateClient<CF, C, S> {
     socket: (Socket, Socket),
     reader: ReadHalf<S>,
     writer: WriteHalf<S>,
+    heartbeat: Heartbeat,
+    version: Version,
+    proxy_protocol: Option<ProxyProtocol>,
     futures: Vec<BoxedFuture<State>>,
     client_provider: ClientProvider<CF>,
     connector_provider: Arc<C>,
+    closing: Option<BoxedFuture<()>>,
 }
 
 impl<CF, C, S> Provider<(ClientProvider<CF>, S)> for PenetrateClientProvider<C>
@@ -67,11 +87,55 @@ where
         let socket = self.transform.clone();
 
         let connector_provider = self.connector_provider.clone();
+        let heartbeat_delay = self.heartbeat_delay;
+        let heartbeat = self.heartbeat.clone();
+        let proxy_protocol = self.proxy_protocol;
+        let visitor_acl = self.visitor_acl.clone();
+        let name = self.name.clone();
 
         Box::pin(async move {
             let mut stream = stream;
             let (remote, local) = socket;
-            let message = Poto::Bind(Bind::Bind(remote.clone())).to_packet_vec();
+
+            let version = match protocol::hello(&mut stream).await {
+                Ok(version) => version,
+                Err(e) => {
+                    log::error!("failed to negotiate version with server err={}", e);
+                    return Err(e);
+                }
+            };
+
+            log::debug!("server handshake, {}", version);
+
+            let name = match name {
+                Some(name) if version.protocol >= 4 => Some(name),
+                Some(name) => {
+                    log::warn!(
+                        "the server does not support named mappings, protocol {}, ignore name {}",
+                        version.protocol,
+                        name
+                    );
+                    None
+                }
+                None => None,
+            };
+
+            let bind = if let Some(name) = name {
+                Bind::Named(name, remote.clone(), visitor_acl)
+            } else if visitor_acl.is_empty() {
+                Bind::Bind(remote.clone())
+            } else if version.protocol >= 3 {
+                Bind::Filter(remote.clone(), visitor_acl)
+            } else {
+                // 不能在服务端不支持的情况下把端口暴露给所有人
+                log::error!(
+                    "the server does not support visitor acl, protocol {}",
+                    version.protocol
+                );
+                return Err(Kind::Message(String::from("the server does not support visitor acl")).into());
+            };
+
+            let message = Poto::Bind(bind).to_packet_vec();
 
             if let Err(e) = stream.send_packet(&message).await {
                 log::error!("failed to send listen message to server err={}", e);
@@ -93,24 +157,18 @@ where
 
             let message = unsafe { message.unwrap_unchecked() };
 
-            match message {
-                Poto::Bind(Bind::Bind(mut remote_bind)) => {
+            let mut remote_bind = match message {
+                Poto::Bind(Bind::Bind(remote_bind)) => {
                     log::info!("the server is bound to {}", remote_bind);
-
-                    if remote_bind.is_ip_unspecified() {
-                        remote_bind.from_set_host(client_provider.default_socket());
-                    }
-
-                    if remote_bind.is_ip_unspecified() {
-                        remote_bind.set_ip([127, 0, 0, 1]);
-                    }
-
-                    Ok(PenetrateClient::new(
-                        (remote_bind, local),
-                        stream,
-                        client_provider,
-                        connector_provider,
-                    ))
+                    remote_bind
+                }
+                Poto::Bind(Bind::Named(name, remote_bind, _)) => {
+                    log::info!(
+                        "the server is bound to {}, registered as {}",
+                        remote_bind,
+                        name
+                    );
+                    remote_bind
                 }
                 Poto::Bind(Bind::Failed(socket, e)) => {
                     log::error!(
@@ -118,16 +176,36 @@ where
                         socket,
                         e
                     );
-                    Err(Kind::Message(e).into())
+                    return Err(Kind::Message(e).into());
                 }
                 message => {
                     log::error!(
                         "The message returned by the server cannot be accepted msg={}",
                         message
                     );
-                    Err(Kind::Unexpected(format!("{}", message)).into())
+                    return Err(Kind::Unexpected(format!("{}", message)).into());
                 }
+            };
+
+
+            if remote_bind.is_ip_unspecified() {
+                remote_bind.from_set_host(client_provider.default_socket());
             }
+
+            if remote_bind.is_ip_unspecified() {
+                remote_bind.set_ip([127, 0, 0, 1]);
+            }
+
+            Ok(PenetrateClient::new(
+                (remote_bind, local),
+                stream,
+                client_provider,
+                connector_provider,
+                heartbeat_delay,
+                heartbeat,
+                version,
+            )
+            .with_proxy_protocol(proxy_protocol))
         })
     }
 }
@@ -143,11 +221,26 @@ where
         conn: S,
         client_provider: ClientProvider<CF>,
         connector_provider: Arc<C>,
+        heartbeat_delay: Duration,
+        heartbeat: Heartbeat,
+        version: Version,
     ) -> Self {
         let (reader, writer) = io::split(conn);
 
-        let fut1 = Box::pin(Self::register_server_handle(reader.clone()));
-        let fut2 = Box::pin(Self::guard_server_heartbeat(writer.clone()));
+        heartbeat.reset();
+
+        let fut1 = Box::pin(Self::register_server_handle(
+            reader.clone(),
+            writer.clone(),
+            heartbeat.clone(),
+        ));
+
+        let fut2 = Box::pin(Self::guard_server_heartbeat(
+            writer.clone(),
+            heartbeat_delay,
+            heartbeat.clone(),
+            version.protocol,
+        ));
 
         Self {
             socket,
@@ -155,24 +248,52 @@ where
             connector_provider,
             reader: reader.clone(),
             writer: writer.clone(),
+            heartbeat,
+            version,
+            proxy_protocol: None,
             futures: vec![fut1, fut2],
+            closing: None,
         }
     }
 
-    async fn guard_server_heartbeat(mut writer: WriteHalf<S>) -> crate::Result<State> {
-        let ping = Poto::Ping.to_packet_vec();
+    pub fn with_proxy_protocol(mut self, proxy_protocol: Option<ProxyProtocol>) -> Self {
+        self.proxy_protocol = proxy_protocol;
+        self
+    }
+
+    /// 与服务端协商后的协议版本
+    pub fn version(&self) -> &Version {
+        &self.version
+    }
 
+    async fn guard_server_heartbeat(
+        mut writer: WriteHalf<S>,
+        delay: Duration,
+        heartbeat: Heartbeat,
+        protocol: u32,
+    ) -> crate::Result<State> {
         loop {
+            if heartbeat.is_expired(delay) {
+                log::warn!("no heartbeat from server, rtt was {:?}", heartbeat.rtt());
+                return Ok(State::Error(Kind::Timeout(time::Elapsed).into()));
+            }
+
+            let ping = Heartbeat::ping(protocol).to_packet_vec();
+
             if let Err(e) = writer.send_packet(&ping).await {
                 log::error!("failed to send heartbeat to server err={}", e);
                 return Ok(State::Error(e));
             }
 
-            time::sleep(Duration::from_secs(10)).await;
+            time::sleep(delay).await;
         }
     }
 
-    async fn register_server_handle(mut reader: ReadHalf<S>) -> crate::Result<State> {
+    async fn register_server_handle(
+        mut reader: ReadHalf<S>,
+        mut writer: WriteHalf<S>,
+        heartbeat: Heartbeat,
+    ) -> crate::Result<State> {
         loop {
             let message = match reader.recv_packet().await {
                 Ok(packet) => packet.try_message(),
@@ -186,9 +307,31 @@ where
 
             let message = unsafe { message.unwrap_unchecked() };
 
+            heartbeat.touch();
+
             match message {
+                Poto::Ping => {
+                    log::trace!("server ping received");
+                }
+                Poto::PingAt(timestamp) => {
+                    let pong = Poto::Pong(timestamp).to_packet_vec();
+                    if let Err(e) = writer.send_packet(&pong).await {
+                        return Ok(State::Error(e));
+                    }
+                }
+                Poto::Pong(timestamp) => {
+                    let rtt = heartbeat.pong(timestamp);
+                    log::debug!("server rtt {:?}", rtt);
+                }
                 Poto::Map(id, socket) => {
-                    break Ok(State::Map(id, socket));
+                    break Ok(State::Map(id, socket, None));
+                }
+                Poto::MapFrom(id, socket, origin) => {
+                    break Ok(State::Map(id, socket, Some(origin)));
+                }
+                Poto::Close => {
+                    log::info!("the server is closing");
+                    break Ok(State::Error(Kind::Message("server closed".into()).into()));
                 }
                 message => {
                     log::trace!("received server message {:?}", message);
@@ -218,19 +361,27 @@ where
                     log::warn!("server stops talking");
                     return Poll::Ready(Err(e));
                 }
-                Poll::Ready(Ok(State::Map(id, socket))) => {
+                Poll::Ready(Ok(State::Map(id, socket, origin))) => {
                     log::debug!("{}", socket);
 
                     let (remote, local) = self.socket.clone();
 
+                    // 使用quic连接服务端时, 映射连接同样以quic流的方式建立
+                    let s1_kind = if self.client_provider.default_socket().is_quic() {
+                        SocketKind::Quic
+                    } else {
+                        socket.kind()
+                    };
+
                     let s1_socket = remote
                         .if_stream_mixed(socket.is_mixed())
-                        .with_kind(socket.kind());
+                        .with_kind(s1_kind);
 
                     let s2_socket = socket.default_or(local);
                     let s1_connector = self.client_provider.clone();
                     let s2_connector = self.connector_provider.clone();
                     let writer = self.writer.clone();
+                    let proxy_protocol = self.proxy_protocol;
 
                     let server_fut = async_connect!(writer, s1_connector, id, s1_socket);
                     let client_fut = async_connect!(writer, s2_connector, id, s2_socket);
@@ -264,14 +415,26 @@ where
 
                         Ok(State::Ready({
                             match s2 {
-                                Route::Forward(s2) => Box::pin(io::forward(s1, s2)),
+                                Route::Forward(s2) => match proxy_protocol {
+                                    None => Box::pin(io::forward(s1, s2)),
+                                    Some(proxy_protocol) => Box::pin(async move {
+                                        let mut s2 = s2;
+                                        let header = proxy_protocol.header(origin.as_ref());
+                                        s2.write_all(&header).await?;
+                                        io::forward(s1, s2).await
+                                    }),
+                                },
                                 Route::Provider(s2) => s2.call(s1),
                             }
                         }))
                     };
 
                     let fut1 = Box::pin(future);
-                    let fut2 = Box::pin(Self::register_server_handle(self.reader.clone()));
+                    let fut2 = Box::pin(Self::register_server_handle(
+                        self.reader.clone(),
+                        self.writer.clone(),
+                        self.heartbeat.clone(),
+                    ));
 
                     futures.push(fut1);
                     futures.push(fut2);
@@ -290,4 +453,18 @@ where
 
         Poll::Pending
     }
+
+    fn poll_close(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context,
+    ) -> std::task::Poll<crate::Result<()>> {
+        if self.closing.is_none() {
+            let mut writer = self.writer.clone();
+            self.closing = Some(Box::pin(async move {
+                writer.send_packet(&Poto::Close.to_packet_vec()).await
+            }));
+        }
+
+        Pin::new(unsafe { self.closing.as_mut().unwrap_unchecked() }).poll(cx)
+    }
 }
@@ -0,0 +1,179 @@
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::protocol::Poto;
+
+/// 开始使用 `PingAt` 的协议版本
+const PING_AT_PROTOCOL: u32 = 5;
+
+/// 连续多少个心跳周期没有收到对端的消息时认为对端已经断开
+pub const MAX_MISSED_HEARTBEATS: u32 = 3;
+
+/// 控制连接的心跳状态, 记录最后一次收到对端消息的时间以及最近测得的往返时间
+#[derive(Clone)]
+pub struct Heartbeat {
+    inner: Arc<Mutex<Liveness>>,
+}
+
+struct Liveness {
+    last_seen: Instant,
+    rtt: Option<Duration>,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Liveness {
+                last_seen: Instant::now(),
+                rtt: None,
+            })),
+        }
+    }
+}
+
+impl Heartbeat {
+    /// 按协商的协议版本生成心跳消息, 旧版本的对端无法测量往返时间
+    pub fn ping(protocol: u32) -> Poto {
+        if protocol >= PING_AT_PROTOCOL {
+            Poto::PingAt(Self::timestamp())
+        } else {
+            Poto::Ping
+        }
+    }
+
+    /// 发送 `PingAt` 时携带的时间戳, 对端在 `Pong` 中原样返回
+    pub fn timestamp() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_micros() as u64)
+            .unwrap_or_default()
+    }
+
+    /// 新的控制连接建立后重新计时
+    pub fn reset(&self) {
+        let mut liveness = self.liveness();
+        liveness.last_seen = Instant::now();
+        liveness.rtt = None;
+    }
+
+    /// 收到对端的任意消息
+    pub fn touch(&self) {
+        self.liveness().last_seen = Instant::now();
+    }
+
+    /// 收到 `Pong`, 返回本次测得的往返时间
+    pub fn pong(&self, timestamp: u64) -> Duration {
+        let rtt = Duration::from_micros(Self::timestamp().saturating_sub(timestamp));
+        let mut liveness = self.liveness();
+        liveness.last_seen = Instant::now();
+        liveness.rtt = Some(rtt);
+        rtt
+    }
+
+    /// 最近一次测得的往返时间
+    pub fn rtt(&self) -> Option<Duration> {
+        self.liveness().rtt
+    }
+
+    /// 超过 `interval` 的 [`MAX_MISSED_HEARTBEATS`] 倍没有收到对端的消息
+    pub fn is_expired(&self, interval: Duration) -> bool {
+        self.liveness().last_seen.elapsed() > interval * MAX_MISSED_HEARTBEATS
+    }
+
+    fn liveness(&self) -> MutexGuard<'_, Liveness> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Heartbeat;
+
+    #[test]
+    fn test_heartbeat() {
+        let heartbeat = Heartbeat::default();
+
+        assert!(heartbeat.rtt().is_none());
+
+        let rtt = heartbeat.pong(Heartbeat::timestamp() - 1000);
+
+        assert!(rtt >= Duration::from_millis(1));
+        assert_eq!(heartbeat.rtt(), Some(rtt));
+        assert!(!heartbeat.is_expired(Duration::from_secs(1)));
+
+        std::thread::sleep(Duration::from_millis(40));
+
+        assert!(heartbeat.is_expired(Duration::from_millis(10)));
+
+        heartbeat.reset();
+
+        assert!(heartbeat.rtt().is_none());
+        assert!(!heartbeat.is_expired(Duration::from_millis(10)));
+    }
+
+    /// 客户端与服务端互相发送心跳后可以得到往返时间
+    #[cfg(feature = "fuso-rt-tokio")]
+    #[test]
+    fn test_rtt() {
+        use crate::{Socket, TokioPenetrateConnector};
+
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        std::thread::spawn(move || {
+            tokio::runtime::Runtime::new().unwrap().block_on(
+                crate::builder_server_with_tokio()
+                    .with_penetrate()
+                    .heartbeat_timeout(Duration::from_millis(50))
+                    .with_adapter_mode()
+                    .with_normal_unpacker()
+                    .build()
+                    .bind(Socket::tcp(([127, 0, 0, 1], port)))
+                    .run(),
+            )
+        });
+
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(async move {
+                let builder = crate::builder_client_with_tokio()
+                    .using_penetrate(
+                        Socket::tcp(([127, 0, 0, 1], 0)),
+                        Socket::tcp(([127, 0, 0, 1], 0)),
+                    )
+                    .reconnect_delay(Duration::from_millis(50))
+                    .heartbeat_delay(Duration::from_millis(50));
+
+                let heartbeat = builder.heartbeat();
+
+                let client = builder
+                    .build(
+                        Socket::tcp(([127, 0, 0, 1], port)),
+                        TokioPenetrateConnector::new().await.unwrap(),
+                    )
+                    .run();
+
+                let measured = async {
+                    while heartbeat.rtt().is_none() {
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                    }
+                };
+
+                tokio::select! {
+                    _ = client => {}
+                    _ = tokio::time::timeout(Duration::from_secs(5), measured) => {}
+                }
+
+                assert!(heartbeat.rtt().is_some());
+            });
+    }
+}
@@ -1,12 +1,18 @@
 mod adapter;
+mod balance;
 mod builder;
 
 mod converter;
+mod heartbeat;
+mod registry;
 
 pub use converter::*;
+pub use heartbeat::*;
+pub use registry::{Mapping, MappingClient, NameConflict, Registry};
 
 pub mod client;
 pub mod server;
 
 pub use adapter::*;
+pub use balance::{Active, Balance};
 pub use builder::*;
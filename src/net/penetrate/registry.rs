@@ -0,0 +1,346 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+#[cfg(feature = "fuso-serde")]
+use serde::Serialize;
+
+use crate::SocketKind;
+
+use super::Heartbeat;
+
+/// 名称的最大长度
+const MAX_NAME_LENGTH: usize = 64;
+
+/// 名称已被其他映射使用时的处理方式
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum NameConflict {
+    /// 拒绝后来的客户端
+    #[default]
+    Reject,
+    /// 在名称后追加序号, 如 `web-2`
+    Rename,
+}
+
+/// 已注册的映射, 同一个名称下可能有多个共享端口的客户端
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "fuso-serde", derive(Serialize))]
+pub struct Mapping {
+    pub name: String,
+    pub port: u16,
+    pub kind: SocketKind,
+    pub clients: Vec<MappingClient>,
+}
+
+/// 映射中的一个客户端
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "fuso-serde", derive(Serialize))]
+pub struct MappingClient {
+    pub addr: String,
+    /// 服务端最近一次测得的往返时间, 旧版本的客户端无法测量
+    pub rtt: Option<Duration>,
+}
+
+/// 服务端的映射名称表, 客户端断开后自动移除
+#[derive(Clone, Default)]
+pub struct Registry {
+    mappings: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+struct Entry {
+    port: u16,
+    kind: SocketKind,
+    clients: Vec<(String, Heartbeat)>,
+    /// 共享端口的客户端属于同一个分组, 不视为冲突
+    group: u64,
+}
+
+/// 客户端持有的注册信息, 释放时从名称表中移除
+pub(crate) struct Registration {
+    name: String,
+    client: String,
+    mappings: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+impl Registry {
+    pub fn get(&self, name: &str) -> Option<Mapping> {
+        let mappings = self.mappings.lock().unwrap();
+        mappings.get(name).map(|entry| entry.mapping(name))
+    }
+
+    /// 按名称排序的所有映射
+    pub fn mappings(&self) -> Vec<Mapping> {
+        let mappings = self.mappings.lock().unwrap();
+        let mut mappings = mappings
+            .iter()
+            .map(|(name, entry)| entry.mapping(name))
+            .collect::<Vec<_>>();
+
+        mappings.sort_by(|a, b| a.name.cmp(&b.name));
+        mappings
+    }
+
+    pub(crate) fn register(
+        &self,
+        name: &str,
+        conflict: NameConflict,
+        group: u64,
+        port: u16,
+        kind: SocketKind,
+        client: String,
+    ) -> crate::Result<Registration> {
+        if !is_valid_name(name) {
+            return Err(crate::Kind::Message(format!("invalid mapping name {:?}", name)).into());
+        }
+
+        let mut mappings = self.mappings.lock().unwrap();
+
+        let name = match mappings.get_mut(name) {
+            None => name.to_string(),
+            Some(entry) if entry.group == group => {
+                entry.clients.push((client.clone(), Heartbeat::default()));
+
+                return Ok(Registration {
+                    name: name.to_string(),
+                    client,
+                    mappings: self.mappings.clone(),
+                });
+            }
+            Some(entry) => match conflict {
+                NameConflict::Reject => {
+                    return Err(crate::Kind::Message(format!(
+                        "the name {} is already used by {}",
+                        name,
+                        entry
+                            .clients
+                            .iter()
+                            .map(|(client, _)| client.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ))
+                    .into());
+                }
+                NameConflict::Rename => (2..)
+                    .map(|n| format!("{}-{}", name, n))
+                    .find(|name| !mappings.contains_key(name))
+                    .unwrap_or_default(),
+            },
+        };
+
+        mappings.insert(
+            name.clone(),
+            Entry {
+                port,
+                kind,
+                clients: vec![(client.clone(), Heartbeat::default())],
+                group,
+            },
+        );
+
+        Ok(Registration {
+            name,
+            client,
+            mappings: self.mappings.clone(),
+        })
+    }
+}
+
+impl Entry {
+    fn mapping(&self, name: &str) -> Mapping {
+        Mapping {
+            name: name.to_string(),
+            port: self.port,
+            kind: self.kind,
+            clients: self
+                .clients
+                .iter()
+                .map(|(addr, heartbeat)| MappingClient {
+                    addr: addr.clone(),
+                    rtt: heartbeat.rtt(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Registration {
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// 使用客户端连接的心跳, 以便通过名称表查询往返时间
+    pub(crate) fn watch(&self, heartbeat: Heartbeat) {
+        let mut mappings = self.mappings.lock().unwrap();
+
+        let client = mappings
+            .get_mut(&self.name)
+            .and_then(|entry| entry.clients.iter_mut().find(|(c, _)| c == &self.client));
+
+        if let Some((_, current)) = client {
+            *current = heartbeat;
+        }
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut mappings = self.mappings.lock().unwrap();
+
+        if let Some(entry) = mappings.get_mut(&self.name) {
+            if let Some(index) = entry.clients.iter().position(|(c, _)| c == &self.client) {
+                entry.clients.remove(index);
+            }
+
+            if entry.clients.is_empty() {
+                mappings.remove(&self.name);
+                log::debug!("mapping {} unregistered", self.name);
+            }
+        }
+    }
+}
+
+/// 只允许字母、数字以及 `-` `_` `.`
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+impl FromStr for NameConflict {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "reject" => Ok(Self::Reject),
+            "rename" => Ok(Self::Rename),
+            _ => Err(format!("unknown policy {}, expect reject or rename", s)),
+        }
+    }
+}
+
+impl Display for NameConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Reject => write!(f, "reject"),
+            Self::Rename => write!(f, "rename"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{penetrate::Heartbeat, SocketKind};
+
+    use super::{MappingClient, NameConflict, Registry};
+
+    #[test]
+    fn test_registry() {
+        let registry = Registry::default();
+        let reject = NameConflict::Reject;
+
+        let web = registry
+            .register(
+                "web",
+                reject,
+                1,
+                8080,
+                SocketKind::Tcp,
+                "10.0.0.1:1000".into(),
+            )
+            .unwrap();
+        assert!(registry
+            .register(
+                "web",
+                reject,
+                2,
+                8081,
+                SocketKind::Tcp,
+                "10.0.0.2:1000".into()
+            )
+            .is_err());
+        assert!(registry
+            .register(
+                "bad name",
+                reject,
+                3,
+                8082,
+                SocketKind::Tcp,
+                "10.0.0.3:1000".into()
+            )
+            .is_err());
+
+        // 同一个分组中的客户端共享名称
+        let heartbeat = Heartbeat::default();
+        let shared = registry
+            .register(
+                "web",
+                reject,
+                1,
+                8080,
+                SocketKind::Tcp,
+                "10.0.0.4:1000".into(),
+            )
+            .unwrap();
+        shared.watch(heartbeat.clone());
+        assert_eq!(registry.get("web").unwrap().clients.len(), 2);
+
+        drop(web);
+        assert_eq!(
+            registry.get("web").unwrap().clients,
+            vec![MappingClient {
+                addr: String::from("10.0.0.4:1000"),
+                rtt: None,
+            }]
+        );
+
+        // 名称表中可以查到服务端测得的往返时间
+        let rtt = heartbeat.pong(Heartbeat::timestamp() - 1000);
+        assert!(rtt >= Duration::from_millis(1));
+        assert_eq!(registry.get("web").unwrap().clients[0].rtt, Some(rtt));
+
+        drop(shared);
+        assert!(registry.get("web").is_none());
+
+        let registry = Registry::default();
+        let rename = NameConflict::Rename;
+        let first = registry
+            .register(
+                "db",
+                rename,
+                1,
+                5432,
+                SocketKind::Tcp,
+                "10.0.0.1:1000".into(),
+            )
+            .unwrap();
+        let second = registry
+            .register(
+                "db",
+                rename,
+                2,
+                5433,
+                SocketKind::Tcp,
+                "10.0.0.2:1000".into(),
+            )
+            .unwrap();
+
+        assert_eq!(first.name(), "db");
+        assert_eq!(second.name(), "db-2");
+        assert_eq!(
+            registry
+                .mappings()
+                .iter()
+                .map(|m| (m.name.as_str(), m.port))
+                .collect::<Vec<_>>(),
+            [("db", 5432), ("db-2", 5433)]
+        );
+    }
+}
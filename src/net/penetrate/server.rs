@@ -1,4 +1,12 @@
-use std::{collections::HashMap, fmt::Display, pin::Pin, sync::Arc, task::Poll, time::Duration};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    net::IpAddr,
+    pin::Pin,
+    sync::{atomic::Ordering, Arc, Weak},
+    task::Poll,
+    time::Duration,
+};
 
 use crate::sync::Mutex;
 use std::future::Future;
@@ -8,19 +16,27 @@ use crate::io::{ReadHalf, WriteHalf};
 use crate::{
     ext::AsyncWriteExt,
     generator::Generator,
-    guard::Fallback,
+    guard::{Fallback, Timer},
     io,
-    protocol::{AsyncRecvPacket, AsyncSendPacket, Bind, Poto, ToPacket, TryToPoto},
-    ready, Accepter, ProviderWrapper, Socket, Stream, {Provider, ServerProvider},
+    protocol::{
+        self, AsyncRecvPacket, AsyncSendPacket, Bind, Hello, Origin, Poto, ToPacket, TryToPoto,
+        Version,
+    },
+    ready, Accepter, Acl, ProviderWrapper, Socket, Stream, {Provider, ServerProvider},
 };
 
-use super::converter::Unpacker;
-use crate::{time, Address, Kind, NetSocket, ResultDisplay};
+use super::{
+    balance::{self, Group, Groups, Member},
+    converter::Unpacker,
+    registry::Registration,
+    Active, Balance, Heartbeat, NameConflict, Registry,
+};
+use crate::{error::IdleErr, time, Address, InnerAddr, Kind, NetSocket, ResultDisplay};
 
 type BoxedFuture<T> = Pin<Box<dyn std::future::Future<Output = crate::Result<T>> + Send + 'static>>;
 
 pub enum PenetrateOutcome<T> {
-    Map(T, T),
+    Map(T, T, Active),
     Customize(BoxedFuture<()>),
 }
 
@@ -28,7 +44,7 @@ pub enum State<T> {
     Stop,
     Close(T),
     Finish,
-    Forward(T, T),
+    Forward(T, T, Active),
     Consume(BoxedFuture<()>),
     Error(crate::Error),
 }
@@ -38,7 +54,11 @@ pub enum Visitor<T> {
     Consume(ProviderWrapper<T, ()>),
 }
 
-pub struct PenetrateGenerator<T, A>(Penetrate<T, A>);
+pub enum PenetrateGenerator<T, A> {
+    Penetrate(Box<Penetrate<T, A>>),
+    /// 直连模式, 只产生一个转发任务
+    Forward(Option<BoxedFuture<()>>),
+}
 
 pub enum Peer<T> {
     Mapper(u32, T),
@@ -47,7 +67,7 @@ pub enum Peer<T> {
     Unknown(T),
 }
 
-#[derive(Default, Clone)]
+#[derive(Clone)]
 pub struct WaitFor<T> {
     identify: Arc<Mutex<u32>>,
     wait_list: Arc<async_mutex::Mutex<HashMap<u32, T>>>,
@@ -61,11 +81,28 @@ pub struct Config {
     pub read_timeout: Option<Duration>,
     pub write_timeout: Option<Duration>,
     pub fallback_strict_mode: bool,
+    /// 直连模式允许访问的目标, 为None时不启用直连模式
+    #[cfg(feature = "fuso-proxy")]
+    pub allowlist: Option<Arc<crate::proxy::Allowlist>>,
+    /// 客户端可以请求转发ip包的tun设备, 为None时不启用tun
+    #[cfg(feature = "fuso-tun")]
+    pub tun: Option<crate::tun::TunService>,
+    /// 为p2p客户端分配打洞令牌, 为None时不启用p2p
+    #[cfg(feature = "fuso-kcp")]
+    pub p2p: Option<crate::p2p::P2pService>,
+    /// 服务端的访问者黑白名单, 优先于客户端请求的规则
+    pub visitor_acl: Option<Arc<Acl>>,
+    /// 允许多个客户端绑定同一个端口, 为None时后绑定的客户端失败
+    pub balance: Option<Balance>,
+    /// 映射名称已被其他客户端使用时的处理方式
+    pub name_conflict: NameConflict,
 }
 
-pub struct PenetrateProvider<T> {
+pub struct PenetrateProvider<T, A> {
     pub(crate) config: Config,
     pub(crate) unpacker: Arc<Unpacker<T>>,
+    pub(crate) groups: Groups<T, A>,
+    pub(crate) registry: Registry,
 }
 
 pub struct Penetrate<T, A> {
@@ -73,9 +110,22 @@ pub struct Penetrate<T, A> {
     config: Config,
     client_addr: Address,
     unpacker: Arc<Unpacker<T>>,
-    wait_for: WaitFor<async_channel::Sender<Fallback<T>>>,
     futures: Vec<BoxedFuture<State<T>>>,
-    accepter: A,
+    group: Arc<Group<T, A>>,
+    member: Arc<Member<T>>,
+    heartbeat: Heartbeat,
+    version: Box<Version>,
+    closing: Option<BoxedFuture<()>>,
+    registration: Option<Registration>,
+}
+
+impl<T> Default for WaitFor<T> {
+    fn default() -> Self {
+        Self {
+            identify: Default::default(),
+            wait_list: Default::default(),
+        }
+    }
 }
 
 impl<T> WaitFor<T> {
@@ -108,32 +158,109 @@ where
     T: Stream + Sync + Send + 'static,
     A: Accepter<Stream = T> + Unpin + Send + 'static,
 {
-    pub fn new(config: Config, unpacker: Arc<Unpacker<T>>, client: T, accepter: A) -> Self {
+    pub fn new(
+        config: Config,
+        unpacker: Arc<Unpacker<T>>,
+        client: T,
+        accepter: A,
+        version: Version,
+    ) -> Self {
+        let group = Group::new(accepter, Balance::default());
+        Self::join(config, unpacker, client, Arc::new(group), version, None)
+    }
+
+    /// 加入绑定同一个端口的分组, 与其他客户端一起接收访问者
+    pub(crate) fn join(
+        config: Config,
+        unpacker: Arc<Unpacker<T>>,
+        client: T,
+        group: Arc<Group<T, A>>,
+        version: Version,
+        acl: Option<Acl>,
+    ) -> Self {
         let client_addr = unsafe { client.peer_addr().unwrap_unchecked() };
         let (reader, writer) = crate::io::split(client);
 
-        let wait_for = WaitFor {
-            identify: Default::default(),
-            wait_list: Default::default(),
-        };
+        let wait_for = group.wait_for.clone();
+        let member = group.join(writer.clone(), client_addr.clone(), version.protocol, acl);
+
+        let heartbeat = Heartbeat::default();
 
-        let recv_fut = Self::poll_handle_recv(wait_for.clone(), reader.clone());
-        let write_fut = Self::poll_heartbeat_future(writer.clone(), config.heartbeat_timeout);
+        let recv_fut = Self::poll_handle_recv(
+            wait_for.clone(),
+            reader.clone(),
+            writer.clone(),
+            heartbeat.clone(),
+        );
+
+        let write_fut = Self::poll_heartbeat_future(
+            writer.clone(),
+            config.heartbeat_timeout,
+            heartbeat.clone(),
+            version.protocol,
+        );
 
         Self {
             writer,
             config,
             unpacker,
-            accepter,
-            wait_for,
+            group,
+            member,
             client_addr,
+            heartbeat,
+            version: Box::new(version),
             futures: vec![Box::pin(recv_fut), Box::pin(write_fut)],
+            closing: None,
+            registration: None,
         }
     }
 
+    pub(crate) fn with_registration(mut self, registration: Option<Registration>) -> Self {
+        if let Some(registration) = registration.as_ref() {
+            registration.watch(self.heartbeat.clone());
+        }
+
+        self.registration = registration;
+        self
+    }
+
+    /// 客户端注册的映射名称
+    pub fn name(&self) -> Option<&str> {
+        self.registration.as_ref().map(Registration::name)
+    }
+
+    /// 被该客户端的黑白名单拒绝的访问次数
+    pub fn rejected(&self) -> u64 {
+        self.member.rejected.load(Ordering::Relaxed)
+    }
+
+    /// 最近一次测得的与客户端之间的往返时间
+    pub fn rtt(&self) -> Option<Duration> {
+        self.heartbeat.rtt()
+    }
+
+    /// 与客户端协商后的协议版本
+    pub fn version(&self) -> &Version {
+        &self.version
+    }
+
+    /// 通知客户端服务端即将关闭
+    fn poll_close(&mut self, cx: &mut std::task::Context<'_>) -> Poll<crate::Result<()>> {
+        if self.closing.is_none() {
+            let mut writer = self.writer.clone();
+            self.closing = Some(Box::pin(async move {
+                writer.send_packet(&Poto::Close.to_packet_vec()).await
+            }));
+        }
+
+        Pin::new(unsafe { self.closing.as_mut().unwrap_unchecked() }).poll(cx)
+    }
+
     async fn poll_handle_recv(
         wait_for: WaitFor<async_channel::Sender<Fallback<T>>>,
         mut stream: ReadHalf<T>,
+        mut writer: WriteHalf<T>,
+        heartbeat: Heartbeat,
     ) -> crate::Result<State<T>> {
         loop {
             let packet = stream.recv_packet().await;
@@ -153,10 +280,30 @@ where
 
             let message = unsafe { packet.unwrap_unchecked() };
 
+            heartbeat.touch();
+
             match message {
                 Poto::Ping => {
                     log::trace!("client ping received");
                 }
+                Poto::PingAt(timestamp) => {
+                    log::trace!("client ping received");
+
+                    let pong = Poto::Pong(timestamp).to_packet_vec();
+
+                    if let Err(e) = writer.send_packet(&pong).await {
+                        log::warn!("failed to reply pong to client");
+                        return Ok(State::Error(e));
+                    }
+                }
+                Poto::Pong(timestamp) => {
+                    let rtt = heartbeat.pong(timestamp);
+                    log::debug!("client rtt {:?}", rtt);
+                }
+                Poto::Close => {
+                    log::info!("the client is closing");
+                    return Ok(State::Stop);
+                }
                 Poto::MapError(id, err) => {
                     log::warn!("client mapping failed, msg = {}", err);
                     wait_for.remove(id).await.map(|r| r.close());
@@ -171,12 +318,19 @@ where
     async fn poll_heartbeat_future(
         mut stream: WriteHalf<T>,
         timeout: Duration,
+        heartbeat: Heartbeat,
+        protocol: u32,
     ) -> crate::Result<State<T>> {
-        let ping = Poto::Ping.to_packet_vec();
-
         loop {
+            if heartbeat.is_expired(timeout) {
+                log::warn!("no heartbeat from client, rtt was {:?}", heartbeat.rtt());
+                return Ok(State::Error(Kind::Timeout(time::Elapsed).into()));
+            }
+
             log::trace!("send heartbeat packet to client");
 
+            let ping = Heartbeat::ping(protocol).to_packet_vec();
+
             if let Err(e) = stream.send_packet(&ping).await {
                 log::warn!("failed to send heartbeat packet to client");
                 break Ok(State::Error(e));
@@ -187,13 +341,23 @@ where
     }
 
     fn async_handle(self: &mut Pin<&mut Self>, stream: T) -> BoxedFuture<State<T>> {
-        let mut writer = self.writer.clone();
         let provider = self.unpacker.clone();
         let timeout = self.config.max_wait_time;
-        let wait_for = self.wait_for.clone();
+        let group = self.group.clone();
+        let wait_for = self.group.wait_for.clone();
         let fallback_strict_mode = self.config.fallback_strict_mode;
         let is_mixed = self.config.is_mixed;
-        let client_addr = self.client_addr.clone();
+        let server_acl = self.config.visitor_acl.clone();
+        let tag = match self.name() {
+            Some(name) => format!("[{}] ", name),
+            None => String::new(),
+        };
+
+        // 客户端的映射连接与访问者来自同一个监听器, 识别之后才能检查
+        let visitor_ip = match stream.peer_addr() {
+            Ok(Address::Single(socket)) => socket.ip(),
+            _ => None,
+        };
 
         let fut = async move {
             let mut fallback = Fallback::new(stream, fallback_strict_mode);
@@ -203,9 +367,46 @@ where
 
             match peer {
                 Peer::Visitor(visit, socket) => {
+                    // 可能由同一分组中的其他客户端处理, 只分配给允许该访问者的客户端
+                    let allowed = |member: &Member<T>| {
+                        is_visitor_allowed(server_acl.as_deref(), member.acl.as_deref(), visitor_ip)
+                    };
+
+                    let member = match group.choose(visitor_ip, allowed) {
+                        Ok(member) => member,
+                        Err(rejected) => {
+                            let visitor = visitor_ip
+                                .map_or_else(|| String::from("unknown"), |ip| ip.to_string());
+
+                            for member in rejected {
+                                let total = member.rejected.fetch_add(1, Ordering::Relaxed) + 1;
+
+                                log::info!(
+                                    "{}reject visitor {} to {}, client is {}, {} rejected",
+                                    tag,
+                                    visitor,
+                                    socket,
+                                    member.client_addr,
+                                    total
+                                );
+                            }
+
+                            return Ok(reject(visit));
+                        }
+                    };
+
+                    let mut writer = member.writer.clone();
+                    let client_addr = member.client_addr.clone();
+                    let protocol = member.protocol;
+                    let active = member.active();
+
                     let (accept_tx, accept_ax) = async_channel::bounded(1);
                     let id = wait_for.push(accept_tx).await;
                     let target_addr = socket.clone();
+                    let origin = match &visit {
+                        Visitor::Forward(stream) => origin_of(stream),
+                        Visitor::Consume(_) => None,
+                    };
 
                     let future = {
                         let client_addr = client_addr.clone();
@@ -213,9 +414,16 @@ where
                             // 通知客户端建立连接
                             let socket = socket.if_stream_mixed(is_mixed);
 
-                            log::info!("connect from {} to {}", client_addr, socket);
+                            log::info!("{}connect from {} to {}", tag, client_addr, socket);
 
-                            let message = Poto::Map(id, socket.clone()).to_packet_vec();
+                            let message = match origin {
+                                // 旧版本客户端不认识 MapFrom
+                                Some(origin) if protocol >= 2 => {
+                                    Poto::MapFrom(id, socket.clone(), origin)
+                                }
+                                _ => Poto::Map(id, socket.clone()),
+                            }
+                            .to_packet_vec();
 
                             if let Err(e) = writer.send_packet(&message).await {
                                 log::warn!(
@@ -248,10 +456,15 @@ where
                                     Ok::<_, crate::Error>(State::Forward(
                                         s1.into_inner(),
                                         s2.into_inner(),
+                                        active,
                                     ))
                                 }
                                 Visitor::Consume(provider) => {
-                                    Ok(State::Consume(provider.call(accept_ax.recv().await?)))
+                                    let fut = provider.call(accept_ax.recv().await?);
+                                    Ok(State::Consume(Box::pin(async move {
+                                        let _active = active;
+                                        fut.await
+                                    })))
                                 }
                             }
                         }
@@ -305,17 +518,95 @@ where
     }
 }
 
+fn reject<T>(visit: Visitor<Fallback<T>>) -> State<T> {
+    match visit {
+        Visitor::Forward(stream) => State::Close(stream.into_inner()),
+        Visitor::Consume(_) => State::Finish,
+    }
+}
+
+/// 服务端规则命中时以其为准, 服务端设置了 `allow` 但未命中时拒绝,
+/// 否则使用客户端请求的规则, 设置了任意规则时拒绝无法获取地址的访问者
+fn is_visitor_allowed(server: Option<&Acl>, client: Option<&Acl>, ip: Option<IpAddr>) -> bool {
+    if server.is_none() && client.is_none() {
+        return true;
+    }
+
+    let ip = match ip {
+        Some(ip) => ip,
+        None => return false,
+    };
+
+    if let Some(server) = server {
+        if let Some(allowed) = server.matches(&ip) {
+            return allowed;
+        }
+
+        if !server.allow.is_empty() {
+            return false;
+        }
+    }
+
+    match client {
+        Some(client) => client.is_allowed(&ip),
+        None => true,
+    }
+}
+
+/// 以实际监听的端口注册映射名称, 同一分组中的客户端共用一个名称
+fn register<T, A>(
+    registry: &Registry,
+    name: &str,
+    conflict: NameConflict,
+    group: &Arc<Group<T, A>>,
+    socket: &Socket,
+    client_addr: String,
+) -> crate::Result<Registration>
+where
+    T: Stream,
+    A: Accepter<Stream = T>,
+{
+    let port = match group.local_addr() {
+        Ok(Address::Single(addr)) => addr.port(),
+        _ => socket.port(),
+    };
+
+    registry.register(name, conflict, group.id(), port, socket.kind(), client_addr)
+}
+
+/// 访问者的来源地址与其连接的服务端地址
+fn origin_of<S: NetSocket>(stream: &S) -> Option<Origin> {
+    let socket_addr = |address: Address| match address {
+        Address::Single(socket) => match socket.inner() {
+            InnerAddr::Socket(addr) => Some(*addr),
+            InnerAddr::Domain(_, _) => None,
+        },
+        Address::Many(_) => None,
+    };
+
+    Some(Origin {
+        source: socket_addr(stream.peer_addr().ok()?)?,
+        destination: socket_addr(stream.local_addr().ok()?)?,
+    })
+}
+
+impl<T, A> Drop for Penetrate<T, A> {
+    fn drop(&mut self) {
+        self.group.leave(&self.member);
+    }
+}
+
 impl<T, A> NetSocket for Penetrate<T, A>
 where
     T: Stream,
     A: Accepter<Stream = T>,
 {
     fn peer_addr(&self) -> crate::Result<Address> {
-        self.accepter.peer_addr()
+        self.group.peer_addr()
     }
 
     fn local_addr(&self) -> crate::Result<Address> {
-        self.accepter.local_addr()
+        self.group.local_addr()
     }
 }
 
@@ -337,7 +628,7 @@ where
         let mut poll_accepter = true;
 
         while poll_accepter {
-            poll_accepter = match Pin::new(&mut self.accepter).poll_accept(cx)? {
+            poll_accepter = match self.group.poll_accept(&self.member, cx)? {
                 Poll::Pending => false,
                 Poll::Ready(stream) => {
                     futures.push(self.async_handle(stream));
@@ -350,9 +641,11 @@ where
                     Poll::Pending => {
                         self.futures.push(future);
                     }
-                    Poll::Ready(Ok(State::Forward(s1, s2))) => {
+                    Poll::Ready(Ok(State::Forward(s1, s2, active))) => {
                         self.futures.extend(futures);
-                        return Poll::Ready(Ok::<_, crate::Error>(PenetrateOutcome::Map(s1, s2)));
+                        return Poll::Ready(Ok::<_, crate::Error>(PenetrateOutcome::Map(
+                            s1, s2, active,
+                        )));
                     }
                     Poll::Ready(Ok(State::Consume(fut))) => {
                         self.futures.extend(futures);
@@ -385,7 +678,7 @@ where
     }
 }
 
-impl<SF, CF, A, S> Provider<(ServerProvider<SF, CF>, S)> for PenetrateProvider<S>
+impl<SF, CF, A, S> Provider<(ServerProvider<SF, CF>, S)> for PenetrateProvider<S, A>
 where
     SF: Provider<Socket, Output = BoxedFuture<A>> + Send + Sync + 'static,
     CF: Provider<Socket, Output = BoxedFuture<S>> + Send + Sync + 'static,
@@ -397,14 +690,80 @@ where
     fn call(&self, (provider, mut client): (ServerProvider<SF, CF>, S)) -> Self::Output {
         let peer_provider = self.unpacker.clone();
         let config = self.config.clone();
+        let groups = self.groups.clone();
+        let registry = self.registry.clone();
+        let conflict = self.config.name_conflict;
 
         Box::pin(async move {
-            let message = client.recv_packet().await?.try_message()?;
+            // 不能让迟迟不发送消息的连接一直占用
+            let mut message = time::wait_for(config.max_wait_time, client.recv_packet())
+                .await??
+                .try_message()?;
+
+            let version = match message {
+                Poto::Hello(Hello::Hello(remote)) => {
+                    let version = match protocol::accept_hello(&mut client, remote).await {
+                        Ok(version) => version,
+                        Err(e) => {
+                            log::warn!("client {} rejected, {}", client.peer_addr()?, e);
+                            return Err(e);
+                        }
+                    };
+
+                    message = time::wait_for(config.max_wait_time, client.recv_packet())
+                        .await??
+                        .try_message()?;
+
+                    version
+                }
+                _ => Version::legacy(),
+            };
+
+            log::debug!("client {} handshake, {}", client.peer_addr()?, version);
 
-            let (socket, accepter) = match message {
+            let (socket, acl, name) = match message {
                 Poto::Bind(Bind::Bind(addr)) => {
                     log::debug!("try to bind the server to {}", addr);
-                    (addr.clone(), provider.bind(addr).await)
+                    (addr, None, None)
+                }
+                Poto::Bind(Bind::Filter(addr, acl)) => {
+                    log::debug!("try to bind the server to {}, visitor acl {}", addr, acl);
+                    (addr, Some(acl), None)
+                }
+                Poto::Bind(Bind::Named(name, addr, acl)) => {
+                    log::debug!("try to bind the server to {} as {}", addr, name);
+                    let acl = if acl.is_empty() { None } else { Some(acl) };
+                    (addr, acl, Some(name))
+                }
+                #[cfg(feature = "fuso-proxy")]
+                Poto::Connect(crate::protocol::Connect::TCP(Some(addr)), _)
+                    if config.allowlist.is_some() =>
+                {
+                    let allowlist = unsafe { config.allowlist.as_ref().unwrap_unchecked() };
+                    let fut = crate::proxy::egress(allowlist, provider, client, addr).await?;
+                    return Ok(PenetrateGenerator::Forward(Some(fut)));
+                }
+                #[cfg(feature = "fuso-tun")]
+                Poto::Connect(crate::protocol::Connect::Tun, auth) if config.tun.is_some() => {
+                    let tun = unsafe { config.tun.as_ref().unwrap_unchecked() };
+                    let fut = tun.accept(client, auth).await?;
+                    return Ok(PenetrateGenerator::Forward(Some(fut)));
+                }
+                #[cfg(feature = "fuso-kcp")]
+                Poto::Connect(crate::protocol::Connect::P2pProvide(name), _)
+                    if config.p2p.is_some() =>
+                {
+                    let p2p = unsafe { config.p2p.as_ref().unwrap_unchecked() };
+                    let fut = p2p.provide(client, name).await?;
+                    return Ok(PenetrateGenerator::Forward(Some(fut)));
+                }
+                #[cfg(feature = "fuso-kcp")]
+                Poto::Connect(crate::protocol::Connect::P2pVisit(name), _)
+                    if config.p2p.is_some() =>
+                {
+                    let p2p = unsafe { config.p2p.as_ref().unwrap_unchecked() };
+                    let fut = p2p.visit(client, name).await?;
+                    return Ok(PenetrateGenerator::Forward(Some(fut)));
                 }
                 message => {
                     log::debug!("received an invalid message {}", message);
@@ -412,7 +771,43 @@ where
                 }
             };
 
-            match accepter {
+            let group = match (config.balance, balance::group_key(&socket, name.as_deref())) {
+                (Some(balance), Some(key)) => {
+                    let mut groups = groups.lock().await;
+                    groups.retain(|_, group| group.strong_count() > 0);
+
+                    match groups.get(&key).and_then(Weak::upgrade) {
+                        Some(group) => {
+                            log::info!(
+                                "client {} joins the mapping of {}",
+                                client.peer_addr()?,
+                                key
+                            );
+                            Ok(group)
+                        }
+                        None => provider.bind(socket.clone()).await.map(|accepter| {
+                            let group = Arc::new(Group::new(accepter, balance));
+                            groups.insert(key, Arc::downgrade(&group));
+                            group
+                        }),
+                    }
+                }
+                _ => provider
+                    .bind(socket.clone())
+                    .await
+                    .map(|accepter| Arc::new(Group::new(accepter, Balance::default()))),
+            };
+
+            let group = match (group, name) {
+                (Ok(group), Some(name)) => {
+                    let client_addr = client.peer_addr()?.to_string();
+                    register(&registry, &name, conflict, &group, &socket, client_addr)
+                        .map(|registration| (group, Some(registration)))
+                }
+                (group, _) => group.map(|group| (group, None)),
+            };
+
+            match group {
                 Err(e) => {
                     let message =
                         Poto::Bind(Bind::Failed(socket, e.to_string())).to_packet_vec();
@@ -425,26 +820,38 @@ where
 
                     return Err(e);
                 }
-                Ok(accepter) => {
-                    let message = Poto::Bind(Bind::Bind(socket.clone())).to_packet_vec();
+                Ok((group, registration)) => {
+                    // 随机端口时告知客户端实际监听的端口, 客户端通过该端口建立映射连接
+                    let mut bound = socket.clone();
+                    if let (0, Ok(Address::Single(addr))) = (socket.port(), group.local_addr()) {
+                        bound.set_port(addr.port());
+                    }
+
+                    let message = match &registration {
+                        Some(registration) => {
+                            Bind::Named(registration.name().to_string(), bound, Acl::default())
+                        }
+                        None => Bind::Bind(bound),
+                    };
+
+                    let message = Poto::Bind(message).to_packet_vec();
                     if let Err(e) = client.send_packet(&message).await {
-                        drop(accepter);
+                        drop(group);
                         log::warn!("failed to send message to client err={}", e);
                         Err(e)
                     } else {
                         log::info!(
-                            "start port mapping ! client is {} and the server is {}",
+                            "start port mapping {}! client is {} and the server is {}",
+                            registration.as_ref().map_or("", Registration::name),
                             client.peer_addr()?,
-                            accepter.local_addr()?
+                            group.local_addr()?
                         );
 
-                        log::info!("please visit {} for port mapping", accepter.local_addr()?);
+                        log::info!("please visit {} for port mapping", group.local_addr()?);
 
-                        Ok(PenetrateGenerator(Penetrate::new(
-                            config,
-                            peer_provider,
-                            client,
-                            accepter,
+                        Ok(PenetrateGenerator::Penetrate(Box::new(
+                            Penetrate::join(config, peer_provider, client, group, version, acl)
+                                .with_registration(registration),
                         )))
                     }
                 }
@@ -464,20 +871,52 @@ where
         mut self: Pin<&mut Self>,
         cx: &mut std::task::Context,
     ) -> Poll<crate::Result<Self::Output>> {
-        match ready!(Pin::new(&mut self.0).poll_accept(cx)?) {
+        let penetrate = match &mut *self {
+            PenetrateGenerator::Penetrate(penetrate) => penetrate,
+            PenetrateGenerator::Forward(fut) => return Poll::Ready(Ok(fut.take())),
+        };
+
+        let read_timeout = penetrate.config.read_timeout;
+        let write_timeout = penetrate.config.write_timeout;
+
+        match ready!(Pin::new(&mut **penetrate).poll_accept(cx)?) {
             PenetrateOutcome::Customize(fut) => {
                 log::debug!("custom mode");
                 Poll::Ready(Ok(Some(fut)))
             }
-            PenetrateOutcome::Map(s1, s2) => Poll::Ready(Ok(Some(Box::pin(async move {
+            PenetrateOutcome::Map(s1, s2, active) => Poll::Ready(Ok(Some(Box::pin(async move {
+                let _active = active;
                 log::debug!("start forwarding");
-                if let Err(e) = io::forward(s1, s2).await {
-                    log::warn!("forward error {}", e);
+                let r = if read_timeout.is_none() && write_timeout.is_none() {
+                    // 不包装原始连接, 以便转发时可以使用splice
+                    io::forward(s1, s2).await
+                } else {
+                    let s1 = Timer::new(s1, read_timeout, write_timeout);
+                    let s2 = Timer::new(s2, read_timeout, write_timeout);
+                    io::forward(s1, s2).await
                 };
+
+                match r {
+                    Ok(()) => {}
+                    Err(e) if matches!(e.kind(), Kind::Idle(IdleErr::Read(_))) => {
+                        log::debug!("close idle connection, {}", e);
+                    }
+                    Err(e) => log::warn!("forward error {}", e),
+                }
                 Ok(())
             })))),
         }
     }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context,
+    ) -> Poll<crate::Result<()>> {
+        match &mut *self {
+            PenetrateGenerator::Penetrate(penetrate) => penetrate.poll_close(cx),
+            PenetrateGenerator::Forward(_) => Poll::Ready(Ok(())),
+        }
+    }
 }
 
 impl Display for Config {
@@ -489,3 +928,45 @@ impl Display for Config {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use crate::Acl;
+
+    use super::is_visitor_allowed;
+
+    fn acl(allow: &[&str], deny: &[&str]) -> Acl {
+        Acl {
+            allow: allow.iter().map(|cidr| cidr.parse().unwrap()).collect(),
+            deny: deny.iter().map(|cidr| cidr.parse().unwrap()).collect(),
+        }
+    }
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn test_visitor_acl() {
+        let client = acl(&["10.0.0.0/8"], &["10.0.0.1"]);
+        assert!(is_visitor_allowed(None, None, None));
+        assert!(is_visitor_allowed(None, Some(&client), ip("10.0.0.2")));
+        assert!(!is_visitor_allowed(None, Some(&client), ip("10.0.0.1")));
+        assert!(!is_visitor_allowed(None, Some(&client), ip("192.168.1.1")));
+        assert!(!is_visitor_allowed(None, Some(&client), None));
+
+        // 服务端命中的规则优先
+        let server = acl(&["10.0.0.1"], &["10.0.0.3"]);
+        assert!(is_visitor_allowed(Some(&server), Some(&client), ip("10.0.0.1")));
+        assert!(!is_visitor_allowed(Some(&server), Some(&client), ip("10.0.0.3")));
+        assert!(!is_visitor_allowed(Some(&server), None, ip("10.0.0.2")));
+
+        let server = acl(&[], &["192.168.0.0/16"]);
+        assert!(is_visitor_allowed(Some(&server), Some(&client), ip("10.0.0.2")));
+        assert!(!is_visitor_allowed(Some(&server), Some(&client), ip("10.0.0.1")));
+        assert!(!is_visitor_allowed(Some(&server), None, ip("192.168.1.1")));
+        assert!(is_visitor_allowed(Some(&server), None, ip("172.16.0.1")));
+    }
+}
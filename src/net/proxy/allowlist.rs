@@ -0,0 +1,195 @@
+use std::{fmt::Display, net::IpAddr, str::FromStr};
+
+use crate::{Addr, Cidr, InvalidAddr};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Host {
+    Any,
+    Cidr(Cidr),
+    /// `*.example.com`, 同时匹配 `example.com`
+    Suffix(String),
+    Domain(String),
+}
+
+/// 直连模式下允许客户端访问的目标, 格式为 `host[:port]`:
+/// host 可以是 `*`, ip, cidr(如 `10.0.0.0/8`), 域名或 `*.example.com`,
+/// port 可以是 `*`, 单个端口或 `1000-2000`, ipv6 需要使用 `[fe80::/10]:22` 的形式
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    host: Host,
+    ports: (u16, u16),
+}
+
+/// 目标地址允许列表, 为空时拒绝所有目标
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Allowlist {
+    rules: Vec<Rule>,
+}
+
+impl Allowlist {
+    pub fn new(rules: Vec<Rule>) -> Self {
+        Self { rules }
+    }
+
+    pub fn allow_all() -> Self {
+        Self::new(vec![Rule {
+            host: Host::Any,
+            ports: (0, u16::MAX),
+        }])
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn is_allowed(&self, target: &Addr) -> bool {
+        self.rules.iter().any(|rule| rule.is_match(target))
+    }
+}
+
+impl FromIterator<Rule> for Allowlist {
+    fn from_iter<T: IntoIterator<Item = Rule>>(iter: T) -> Self {
+        Self::new(iter.into_iter().collect())
+    }
+}
+
+impl Rule {
+    pub fn is_match(&self, target: &Addr) -> bool {
+        let port = target.port();
+
+        if port < self.ports.0 || port > self.ports.1 {
+            return false;
+        }
+
+        match (&self.host, target.ip(), target.domain()) {
+            (Host::Any, _, _) => true,
+            (Host::Cidr(cidr), Some(ip), _) => cidr.contains(&ip),
+            (Host::Domain(domain), _, Some(target)) => domain.eq_ignore_ascii_case(target),
+            (Host::Suffix(suffix), _, Some(target)) => {
+                let target = target.to_ascii_lowercase();
+                target == *suffix || target.ends_with(&format!(".{}", suffix))
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Rule {
+    type Err = crate::Error;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidAddr::Domain(rule.to_string());
+
+        let (host, port) = match rule.strip_prefix('[') {
+            Some(rest) => {
+                let (host, rest) = rest.split_once(']').ok_or_else(invalid)?;
+                (host, rest.strip_prefix(':'))
+            }
+            None => match rule.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (rule, None),
+            },
+        };
+
+        let ports = match port {
+            None | Some("*") => (0, u16::MAX),
+            Some(port) => match port.split_once('-') {
+                None => {
+                    let port = port.parse().map_err(|_| invalid())?;
+                    (port, port)
+                }
+                Some((start, end)) => (
+                    start.parse().map_err(|_| invalid())?,
+                    end.parse().map_err(|_| invalid())?,
+                ),
+            },
+        };
+
+        let host = if host == "*" {
+            Host::Any
+        } else if let Some(suffix) = host.strip_prefix("*.") {
+            Host::Suffix(suffix.to_ascii_lowercase())
+        } else if host.contains('/') {
+            Host::Cidr(host.parse().map_err(|_| invalid())?)
+        } else if let Ok(ip) = host.parse::<IpAddr>() {
+            Host::Cidr(Cidr::host(ip))
+        } else if !host.is_empty() {
+            Host::Domain(host.to_ascii_lowercase())
+        } else {
+            return Err(invalid().into());
+        };
+
+        Ok(Self { host, ports })
+    }
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.host {
+            Host::Any => write!(f, "*")?,
+            Host::Cidr(cidr) if cidr.is_ipv4() => write!(f, "{}", cidr)?,
+            Host::Cidr(cidr) => write!(f, "[{}]", cidr)?,
+            Host::Suffix(suffix) => write!(f, "*.{}", suffix)?,
+            Host::Domain(domain) => write!(f, "{}", domain)?,
+        }
+
+        match self.ports {
+            (0, u16::MAX) => Ok(()),
+            (start, end) if start == end => write!(f, ":{}", start),
+            (start, end) => write!(f, ":{}-{}", start, end),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Addr;
+
+    use super::{Allowlist, Rule};
+
+    fn allowlist(rules: &[&str]) -> Allowlist {
+        rules
+            .iter()
+            .map(|rule| rule.parse::<Rule>().unwrap())
+            .collect()
+    }
+
+    fn addr(addr: &str) -> Addr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let allowlist = allowlist(&[
+            "10.0.0.0/8:22",
+            "192.168.1.10",
+            "*.internal.example.com:8000-9000",
+            "db.local:5432",
+            "[fd00::/8]:*",
+        ]);
+
+        assert!(allowlist.is_allowed(&addr("10.1.2.3:22")));
+        assert!(!allowlist.is_allowed(&addr("10.1.2.3:23")));
+        assert!(!allowlist.is_allowed(&addr("11.1.2.3:22")));
+        assert!(allowlist.is_allowed(&addr("192.168.1.10:3306")));
+        assert!(!allowlist.is_allowed(&addr("192.168.1.11:3306")));
+        assert!(allowlist.is_allowed(&addr("api.internal.example.com:8080")));
+        assert!(allowlist.is_allowed(&addr("internal.example.com:8000")));
+        assert!(!allowlist.is_allowed(&addr("evilinternal.example.com:8000")));
+        assert!(!allowlist.is_allowed(&addr("api.internal.example.com:80")));
+        assert!(allowlist.is_allowed(&addr("DB.local:5432")));
+        assert!(!allowlist.is_allowed(&addr("db.local:5433")));
+        assert!(allowlist.is_allowed(&addr("[fd12::1]:443")));
+        assert!(!allowlist.is_allowed(&addr("[fe80::1]:443")));
+
+        assert!(!Allowlist::default().is_allowed(&addr("127.0.0.1:22")));
+        assert!(Allowlist::allow_all().is_allowed(&addr("127.0.0.1:22")));
+
+        assert!("10.0.0.0/33".parse::<Rule>().is_err());
+        assert!("db.local:x".parse::<Rule>().is_err());
+        assert_eq!(
+            "10.0.0.0/8:22".parse::<Rule>().unwrap().to_string(),
+            "10.0.0.0/8:22"
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use std::{pin::Pin, sync::Arc};
+
+use crate::{
+    io, Accepter, AccepterExt, Addr, ClientProvider, Executor, Provider, ProviderTransfer, Socket,
+    Stream,
+};
+
+type BoxedFuture<T> = Pin<Box<dyn std::future::Future<Output = crate::Result<T>> + Send + 'static>>;
+
+/// 直连模式, 本地监听的连接均由服务端代为连接到 `target`
+pub struct ForwardProxy<E, A, CF, S> {
+    target: Addr,
+    executor: Arc<E>,
+    accepter_provider: Arc<A>,
+    handshake: Option<ProviderTransfer<S>>,
+    client_provider: ClientProvider<CF>,
+}
+
+impl<E, A, CF, S> ForwardProxy<E, A, CF, S> {
+    pub fn new(
+        target: Addr,
+        executor: E,
+        accepter_provider: A,
+        handshake: Option<ProviderTransfer<S>>,
+        client_provider: ClientProvider<CF>,
+    ) -> Self {
+        Self {
+            target,
+            handshake,
+            client_provider,
+            executor: Arc::new(executor),
+            accepter_provider: Arc::new(accepter_provider),
+        }
+    }
+}
+
+impl<E, A, CF, L, S> ForwardProxy<E, A, CF, S>
+where
+    E: Executor + Send + Sync + 'static,
+    A: Provider<Socket, Output = BoxedFuture<L>> + Send + Sync + 'static,
+    L: Accepter<Stream = S> + Unpin + Send + 'static,
+    CF: Provider<Socket, Output = BoxedFuture<S>> + Send + Sync + 'static,
+    S: Stream + Send + 'static,
+{
+    pub async fn run<T: Into<Socket>>(self, bind: T) -> crate::Result<()> {
+        let mut accepter = self.accepter_provider.call(bind.into()).await?;
+
+        log::info!(
+            "forward {} to {} through {}",
+            accepter.local_addr()?,
+            self.target,
+            self.client_provider.default_socket()
+        );
+
+        loop {
+            let stream = accepter.accept().await?;
+            let this = self.clone();
+
+            self.executor.spawn(async move {
+                if let Err(e) = this.forward(stream).await {
+                    log::warn!("failed to forward to {} err={}", this.target, e);
+                }
+            });
+        }
+    }
+
+    async fn forward(&self, stream: S) -> crate::Result<()> {
+        let server = self
+            .client_provider
+            .connect(self.client_provider.default_socket().clone())
+            .await?;
+
+        let mut server = match self.handshake.as_ref() {
+            None => server,
+            Some(handshake) => handshake.call(server).await?,
+        };
+
+        super::request(&mut server, self.target.clone()).await?;
+
+        io::forward(stream, server).await
+    }
+}
+
+impl<E, A, CF, S> Clone for ForwardProxy<E, A, CF, S> {
+    fn clone(&self) -> Self {
+        Self {
+            target: self.target.clone(),
+            executor: self.executor.clone(),
+            handshake: self.handshake.clone(),
+            accepter_provider: self.accepter_provider.clone(),
+            client_provider: self.client_provider.clone(),
+        }
+    }
+}
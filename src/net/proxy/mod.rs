@@ -0,0 +1,131 @@
+mod allowlist;
+pub use allowlist::*;
+
+mod client;
+pub use client::*;
+
+use std::pin::Pin;
+
+use crate::{
+    io,
+    protocol::{AsyncRecvPacket, AsyncSendPacket, Auth, Connect, Poto, ToPacket, TryToPoto},
+    Addr, Kind, Provider, ServerProvider, Socket, Stream,
+};
+
+type BoxedFuture<T> = Pin<Box<dyn std::future::Future<Output = crate::Result<T>> + Send + 'static>>;
+
+/// 客户端请求服务端代为连接 `target`, 成功后 `stream` 即为到 `target` 的连接
+pub async fn request<S>(stream: &mut S, target: Addr) -> crate::Result<()>
+where
+    S: Stream + Unpin,
+{
+    let message = Poto::Connect(Connect::TCP(Some(target)), Auth::NoAuth).to_packet_vec();
+
+    stream.send_packet(&message).await?;
+
+    match stream.recv_packet().await?.try_message()? {
+        Poto::Forward(_) => Ok(()),
+        Poto::MapError(_, e) => Err(Kind::Message(e).into()),
+        message => Err(Kind::Unexpected(format!("{}", message)).into()),
+    }
+}
+
+/// 服务端代替客户端连接目标地址, 目标不在允许列表中时拒绝连接
+pub(crate) async fn egress<SF, CF, S>(
+    allowlist: &Allowlist,
+    provider: ServerProvider<SF, CF>,
+    mut client: S,
+    target: Addr,
+) -> crate::Result<BoxedFuture<()>>
+where
+    CF: Provider<Socket, Output = BoxedFuture<S>> + Send + Sync + 'static,
+    S: Stream + Send + 'static,
+{
+    let client_addr = client.peer_addr()?;
+
+    let result = match allowlist.is_allowed(&target) {
+        false => Err(Kind::Message(format!("{} is not allowed", target)).into()),
+        true => {
+            provider
+                .connector_provider
+                .call(Socket::tcp(target.clone()))
+                .await
+        }
+    };
+
+    let server = match result {
+        Ok(server) => server,
+        Err(e) => {
+            log::warn!("refused to proxy {} to {}, err={}", client_addr, target, e);
+            let message = Poto::MapError(0, e.to_string()).to_packet_vec();
+            let _ = client.send_packet(&message).await;
+            return Err(e);
+        }
+    };
+
+    client
+        .send_packet(&Poto::Forward(target.clone()).to_packet_vec())
+        .await?;
+
+    log::info!("proxy {} to {}", client_addr, target);
+
+    Ok(Box::pin(io::forward(client, server)))
+}
+
+#[cfg(test)]
+#[cfg(feature = "fuso-rt-tokio")]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use crate::{testing, Socket, TokioAccepter};
+
+    use super::{request, Allowlist};
+
+    #[tokio::test]
+    async fn test_forward_proxy() {
+        let echo = testing::echo().await;
+        let allowlist: Allowlist = [echo.to_string().parse().unwrap()].into_iter().collect();
+
+        let (builder, listened) = crate::builder_server_with_tokio().listening();
+
+        let server = testing::serve(listened, move |bind| {
+            builder
+                .with_penetrate()
+                .with_forward_proxy(allowlist)
+                .with_adapter_mode()
+                .with_normal_unpacker()
+                .build()
+                .bind(bind)
+                .run()
+        })
+        .await;
+
+        let (accepter, listened) = testing::listening(TokioAccepter);
+
+        tokio::spawn(
+            crate::builder_client_with_tokio()
+                .build_forward_proxy(server.clone(), echo.into(), accepter)
+                .run(Socket::tcp(([127, 0, 0, 1], 0))),
+        );
+
+        let proxy = listened.addr().await;
+
+        let mut tcp = tokio::net::TcpStream::connect(proxy.as_string())
+            .await
+            .unwrap();
+
+        tcp.write_all(b"hello").await.unwrap();
+
+        let mut buf = [0u8; 5];
+        tcp.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        // 不在允许列表中的目标
+        let mut tcp = tokio::net::TcpStream::connect(server.as_string())
+            .await
+            .unwrap();
+
+        let denied = ([127, 0, 0, 1], echo.port() + 1).into();
+        assert!(request(&mut tcp, denied).await.is_err());
+    }
+}
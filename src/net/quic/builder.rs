@@ -0,0 +1,113 @@
+use std::{future::Future, pin::Pin, sync::Arc, task::Poll};
+
+use rustls::pki_types::{
+    pem::{self, PemObject},
+    CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer,
+};
+
+use crate::{
+    mixing::MixListener, server::ServerBuilder, Accepter, Address, FusoStream, NetSocket, Provider,
+    QuicErr, Socket, SocketErr, ToBoxStream,
+};
+
+use super::{QuicListener, QuicVerify};
+
+type BoxedFuture<T> = Pin<Box<dyn Future<Output = crate::Result<T>> + Send + 'static>>;
+
+/// 服务端需要使用固定的证书, 客户端才能固定或使用CA校验该证书
+#[derive(Clone)]
+pub struct QuicAccepterProvider {
+    certificate: Arc<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+}
+
+pub struct QuicAccepter(QuicListener);
+
+impl QuicAccepterProvider {
+    /// `cert_chain` 与 `key` 均为DER编码, `key` 为 PKCS#8 格式
+    pub fn with_certificate(cert_chain: Vec<Vec<u8>>, key: Vec<u8>) -> Self {
+        Self {
+            certificate: Arc::new((
+                cert_chain.into_iter().map(CertificateDer::from).collect(),
+                PrivateKeyDer::Pkcs8(key.into()),
+            )),
+        }
+    }
+
+    /// 读取PEM或DER编码的证书链与 PKCS#8 私钥
+    pub fn load(cert_path: &str, key_path: &str) -> crate::Result<Self> {
+        let cert_chain = QuicVerify::load_certificates(cert_path)?;
+
+        let data = std::fs::read(key_path)?;
+
+        let key = match PrivatePkcs8KeyDer::from_pem_slice(&data) {
+            Ok(key) => key,
+            Err(pem::Error::NoItemsFound) => PrivatePkcs8KeyDer::from(data),
+            Err(e) => return Err(QuicErr::Config(format!("{} {}", key_path, e)).into()),
+        };
+
+        Ok(Self {
+            certificate: Arc::new((cert_chain, key.into())),
+        })
+    }
+}
+
+impl NetSocket for QuicAccepter {
+    fn peer_addr(&self) -> crate::Result<Address> {
+        self.0.peer_addr()
+    }
+
+    fn local_addr(&self) -> crate::Result<Address> {
+        self.0.local_addr()
+    }
+}
+
+impl Accepter for QuicAccepter {
+    type Stream = FusoStream;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<crate::Result<Self::Stream>> {
+        Pin::new(&mut self.0)
+            .poll_accept(cx)?
+            .map(|quic| Ok(quic.into_boxed_stream()))
+    }
+}
+
+impl Provider<Socket> for QuicAccepterProvider {
+    type Output = BoxedFuture<QuicAccepter>;
+
+    fn call(&self, socket: Socket) -> Self::Output {
+        let certificate = self.certificate.clone();
+
+        Box::pin(async move {
+            if !(socket.is_mixed() || socket.is_quic()) {
+                return Err(SocketErr::NotSupport(socket).into());
+            }
+
+            let (cert_chain, key) = &*certificate;
+            let config = super::server_config(cert_chain.clone(), key.clone_key())?;
+
+            let addr = super::resolve_addr(&socket).await.map_err(|e| {
+                log::warn!("quic bind failed addr={}, err={}", socket.addr(), e);
+                e
+            })?;
+
+            Ok(QuicAccepter(QuicListener::bind(config, addr)?))
+        })
+    }
+}
+
+impl<E, SF, CF, A1> ServerBuilder<E, SF, CF, FusoStream>
+where
+    SF: Provider<Socket, Output = BoxedFuture<A1>> + Send + Sync + 'static,
+    A1: Accepter<Stream = FusoStream> + Unpin + Send + 'static,
+{
+    /// quic与kcp都需要占用同一端口的udp, 所以两者不能同时使用
+    pub fn with_quic_accepter(
+        self,
+        provider: QuicAccepterProvider,
+    ) -> ServerBuilder<E, MixListener<SF, QuicAccepterProvider, FusoStream>, CF, FusoStream> {
+        self.add_accepter(provider)
+    }
+}
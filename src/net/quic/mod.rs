@@ -0,0 +1,504 @@
+mod stream;
+pub use stream::*;
+
+mod builder;
+pub use builder::*;
+
+use std::{collections::HashMap, future::Future, net::SocketAddr, pin::Pin, sync::Arc, task::Poll};
+
+use async_mutex::Mutex;
+use rustls::pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+
+use crate::{Accepter, Address, InvalidAddr, NetSocket, QuicErr, Socket};
+
+type BoxedFuture<T> = Pin<Box<dyn Future<Output = T> + Send + 'static>>;
+
+/// 未使用CA校验时使用的sni
+const SERVER_NAME: &str = "fuso";
+
+enum State {
+    Incoming(Box<quinn::Incoming>),
+    Connect(quinn::Connection),
+    Open(quinn::Connection, quinn::SendStream, quinn::RecvStream),
+    Close(SocketAddr),
+}
+
+pub struct QuicListener {
+    endpoint: quinn::Endpoint,
+    local_addr: Address,
+    futures: Vec<BoxedFuture<crate::Result<State>>>,
+}
+
+#[derive(Clone)]
+pub struct QuicConnector {
+    endpoint: quinn::Endpoint,
+    server_name: String,
+    connections: Arc<Mutex<HashMap<SocketAddr, quinn::Connection>>>,
+}
+
+/// 客户端校验服务端证书的方式
+#[derive(Debug, Clone)]
+pub enum QuicVerify {
+    /// 只接受这些服务端证书, 服务端需要使用固定的证书
+    Pinned(Vec<CertificateDer<'static>>),
+    /// 使用CA证书校验, 服务端证书需要包含指定的域名
+    Ca(Vec<CertificateDer<'static>>, String),
+    /// 不校验服务端证书, 传输的安全性交由上层的加密来保证
+    Insecure,
+}
+
+/// 固定证书校验, `pinned` 为None时信任所有证书
+#[derive(Debug)]
+struct PinnedServerVerification {
+    pinned: Option<Vec<CertificateDer<'static>>>,
+    provider: Arc<rustls::crypto::CryptoProvider>,
+}
+
+impl QuicVerify {
+    /// 读取PEM或DER编码的证书文件
+    pub fn load_certificates(path: &str) -> crate::Result<Vec<CertificateDer<'static>>> {
+        let data = std::fs::read(path)?;
+
+        let certs = CertificateDer::pem_slice_iter(&data)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| QuicErr::Config(format!("{} {}", path, e)))?;
+
+        Ok(match certs.is_empty() {
+            true => vec![CertificateDer::from(data)],
+            false => certs,
+        })
+    }
+}
+
+pub(crate) async fn resolve_addr(socket: &Socket) -> crate::Result<SocketAddr> {
+    tokio::net::lookup_host(socket.as_string())
+        .await?
+        .next()
+        .ok_or_else(|| InvalidAddr::Domain(socket.as_string()).into())
+}
+
+pub(crate) fn server_config(
+    cert_chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+) -> crate::Result<quinn::ServerConfig> {
+    quinn::ServerConfig::with_single_cert(cert_chain, key).map_err(|e| QuicErr::Tls(e).into())
+}
+
+fn client_config(verify: QuicVerify) -> crate::Result<quinn::ClientConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+    let verifier: Arc<dyn rustls::client::danger::ServerCertVerifier> = match verify {
+        QuicVerify::Insecure => Arc::new(PinnedServerVerification {
+            pinned: None,
+            provider: provider.clone(),
+        }),
+        QuicVerify::Pinned(certs) => Arc::new(PinnedServerVerification {
+            pinned: Some(certs),
+            provider: provider.clone(),
+        }),
+        QuicVerify::Ca(certs, _) => {
+            let mut roots = rustls::RootCertStore::empty();
+
+            for cert in certs {
+                roots.add(cert).map_err(QuicErr::Tls)?;
+            }
+
+            rustls::client::WebPkiServerVerifier::builder_with_provider(
+                Arc::new(roots),
+                provider.clone(),
+            )
+            .build()
+            .map_err(|e| QuicErr::Config(e.to_string()))?
+        }
+    };
+
+    let crypto = rustls::ClientConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(QuicErr::Tls)?
+        .dangerous()
+        .with_custom_certificate_verifier(verifier)
+        .with_no_client_auth();
+
+    let crypto = quinn::crypto::rustls::QuicClientConfig::try_from(crypto)
+        .map_err(|e| QuicErr::Config(e.to_string()))?;
+
+    Ok(quinn::ClientConfig::new(Arc::new(crypto)))
+}
+
+impl QuicListener {
+    pub fn bind(config: quinn::ServerConfig, addr: SocketAddr) -> crate::Result<Self> {
+        let endpoint = quinn::Endpoint::server(config, addr)?;
+        let local_addr = Address::Single(Socket::quic(endpoint.local_addr()?));
+
+        let accept_fut = Self::run_accept(endpoint.clone());
+
+        Ok(Self {
+            endpoint,
+            local_addr,
+            futures: vec![Box::pin(accept_fut)],
+        })
+    }
+
+    async fn run_accept(endpoint: quinn::Endpoint) -> crate::Result<State> {
+        match endpoint.accept().await {
+            Some(incoming) => Ok(State::Incoming(Box::new(incoming))),
+            None => Err(QuicErr::EndpointClosed.into()),
+        }
+    }
+
+    /// 握手单独进行, 未完成握手的客户端不会阻塞其他客户端
+    async fn run_handshake(incoming: Box<quinn::Incoming>) -> crate::Result<State> {
+        let remote_addr = incoming.remote_address();
+
+        match (*incoming).await {
+            Ok(connection) => {
+                log::debug!("quic connection from {}", remote_addr);
+                Ok(State::Connect(connection))
+            }
+            Err(e) => {
+                log::warn!("quic handshake with {} failed err={}", remote_addr, e);
+                Ok(State::Close(remote_addr))
+            }
+        }
+    }
+
+    async fn run_accept_stream(connection: quinn::Connection) -> crate::Result<State> {
+        match connection.accept_bi().await {
+            Ok((send, recv)) => Ok(State::Open(connection, send, recv)),
+            Err(e) => {
+                log::debug!(
+                    "quic connection {} closed err={}",
+                    connection.remote_address(),
+                    e
+                );
+                Ok(State::Close(connection.remote_address()))
+            }
+        }
+    }
+}
+
+impl NetSocket for QuicListener {
+    fn peer_addr(&self) -> crate::Result<Address> {
+        Ok(self.local_addr.clone())
+    }
+
+    fn local_addr(&self) -> crate::Result<Address> {
+        Ok(self.local_addr.clone())
+    }
+}
+
+impl Accepter for QuicListener {
+    type Stream = QuicStream;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<crate::Result<Self::Stream>> {
+        let mut futures = std::mem::take(&mut self.futures);
+
+        while let Some(mut future) = futures.pop() {
+            match Pin::new(&mut future).poll(cx) {
+                Poll::Pending => {
+                    self.futures.push(future);
+                }
+                Poll::Ready(Err(e)) => {
+                    self.futures.extend(futures);
+                    return Poll::Ready(Err(e));
+                }
+                Poll::Ready(Ok(State::Close(addr))) => {
+                    log::debug!("stop accepting quic streams from {}", addr);
+                }
+                Poll::Ready(Ok(State::Incoming(incoming))) => {
+                    futures.push(Box::pin(Self::run_accept(self.endpoint.clone())));
+                    futures.push(Box::pin(Self::run_handshake(incoming)));
+                }
+                Poll::Ready(Ok(State::Connect(connection))) => {
+                    futures.push(Box::pin(Self::run_accept_stream(connection)));
+                }
+                Poll::Ready(Ok(State::Open(connection, send, recv))) => {
+                    let peer_addr = Address::Single(Socket::quic(connection.remote_address()));
+
+                    self.futures
+                        .push(Box::pin(Self::run_accept_stream(connection)));
+
+                    self.futures.extend(futures);
+
+                    return Poll::Ready(Ok(QuicStream::new(
+                        send,
+                        recv,
+                        self.local_addr.clone(),
+                        peer_addr,
+                    )));
+                }
+            }
+        }
+
+        Poll::Pending
+    }
+}
+
+impl QuicConnector {
+    /// 只能连接与 `addr` 地址族相同的服务端
+    pub fn bind(addr: SocketAddr, verify: QuicVerify) -> crate::Result<Self> {
+        let server_name = match &verify {
+            QuicVerify::Ca(_, server_name) => server_name.clone(),
+            _ => SERVER_NAME.to_owned(),
+        };
+
+        let mut endpoint = quinn::Endpoint::client(addr)?;
+
+        endpoint.set_default_client_config(client_config(verify)?);
+
+        Ok(Self {
+            endpoint,
+            server_name,
+            connections: Default::default(),
+        })
+    }
+
+    /// 同一个服务端地址复用一个quic连接, 每次调用都会在该连接上打开一个新的双向流
+    pub async fn connect(&self, socket: Socket) -> crate::Result<QuicStream> {
+        let addr = resolve_addr(&socket).await?;
+
+        let connection = {
+            let mut connections = self.connections.lock().await;
+
+            match connections.get(&addr) {
+                Some(connection) if connection.close_reason().is_none() => connection.clone(),
+                _ => {
+                    log::debug!("try to establish a quic connection to {}", addr);
+
+                    let connection = self
+                        .endpoint
+                        .connect(addr, &self.server_name)
+                        .map_err(QuicErr::Connect)?
+                        .await
+                        .map_err(QuicErr::Connection)?;
+
+                    connections.insert(addr, connection.clone());
+
+                    connection
+                }
+            }
+        };
+
+        let (send, recv) = connection.open_bi().await.map_err(QuicErr::Connection)?;
+
+        Ok(QuicStream::new(
+            send,
+            recv,
+            Address::Single(Socket::quic(self.endpoint.local_addr()?)),
+            Address::Single(Socket::quic(addr)),
+        ))
+    }
+}
+
+impl rustls::client::danger::ServerCertVerifier for PinnedServerVerification {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _: &[CertificateDer<'_>],
+        _: &ServerName<'_>,
+        _: &[u8],
+        _: UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        match &self.pinned {
+            Some(pinned) if !pinned.iter().any(|cert| cert == end_entity) => {
+                Err(rustls::Error::InvalidCertificate(
+                    rustls::CertificateError::ApplicationVerificationFailure,
+                ))
+            }
+            _ => Ok(rustls::client::danger::ServerCertVerified::assertion()),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer};
+
+    use crate::{
+        ext::{AsyncReadExt, AsyncWriteExt},
+        AccepterExt, Socket,
+    };
+
+    use super::{QuicConnector, QuicListener, QuicVerify};
+
+    fn certificate() -> (CertificateDer<'static>, quinn::ServerConfig) {
+        let cert = rcgen::generate_simple_self_signed(vec![super::SERVER_NAME.to_owned()]).unwrap();
+        let cert_der = CertificateDer::from(cert.cert);
+        let key_der = PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der());
+        let config = super::server_config(vec![cert_der.clone()], key_der.into()).unwrap();
+        (cert_der, config)
+    }
+
+    #[test]
+    fn test_quic_stream() {
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(async move {
+                let (cert, config) = certificate();
+                let mut listener =
+                    QuicListener::bind(config, SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+
+                let addr = match listener.endpoint.local_addr() {
+                    Ok(addr) => addr,
+                    Err(e) => panic!("{}", e),
+                };
+
+                let server = tokio::spawn(async move {
+                    for _ in 0..2 {
+                        let mut stream = listener.accept().await.unwrap();
+                        let mut buf = [0u8; 5];
+                        stream.read_exact(&mut buf).await.unwrap();
+                        stream.write_all(&buf).await.unwrap();
+                        stream.close().await.unwrap();
+                    }
+                    // 监听器释放时会关闭所有连接, 需等客户端读取完成后再释放
+                    listener
+                });
+
+                let connector = QuicConnector::bind(
+                    SocketAddr::from(([127, 0, 0, 1], 0)),
+                    QuicVerify::Pinned(vec![cert]),
+                )
+                .unwrap();
+
+                for _ in 0..2 {
+                    let mut stream = connector.connect(Socket::quic(addr)).await.unwrap();
+                    stream.write_all(b"hello").await.unwrap();
+                    let mut buf = [0u8; 5];
+                    stream.read_exact(&mut buf).await.unwrap();
+                    assert_eq!(&buf, b"hello");
+                }
+
+                assert_eq!(connector.connections.lock().await.len(), 1);
+
+                drop(server.await.unwrap());
+            });
+    }
+
+    #[test]
+    fn test_quic_verify() {
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(async move {
+                let (cert, config) = certificate();
+                let (other, _) = certificate();
+
+                let mut listener =
+                    QuicListener::bind(config, SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+
+                let addr = listener.endpoint.local_addr().unwrap();
+
+                tokio::spawn(async move { while listener.accept().await.is_ok() {} });
+
+                let bind = SocketAddr::from(([127, 0, 0, 1], 0));
+
+                let pinned = QuicConnector::bind(bind, QuicVerify::Pinned(vec![other])).unwrap();
+                assert!(pinned.connect(Socket::quic(addr)).await.is_err());
+
+                let ca = QuicConnector::bind(bind, QuicVerify::Ca(vec![], "fuso".into()));
+                assert!(ca.is_err());
+
+                let ca = QuicConnector::bind(bind, QuicVerify::Ca(vec![cert], "other".into()));
+                assert!(ca.unwrap().connect(Socket::quic(addr)).await.is_err());
+
+                let insecure = QuicConnector::bind(bind, QuicVerify::Insecure).unwrap();
+                assert!(insecure.connect(Socket::quic(addr)).await.is_ok());
+            });
+    }
+
+    /// 只转发客户端的第一个数据包, 服务端的握手将一直无法完成
+    #[test]
+    fn test_quic_stalled_handshake() {
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(async move {
+                let (cert, config) = certificate();
+                let mut listener =
+                    QuicListener::bind(config, SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+
+                let addr = listener.endpoint.local_addr().unwrap();
+
+                let server = tokio::spawn(async move {
+                    let mut stream = listener.accept().await.unwrap();
+                    let mut buf = [0u8; 5];
+                    stream.read_exact(&mut buf).await.unwrap();
+                    stream.write_all(&buf).await.unwrap();
+                    listener
+                });
+
+                let relay = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+                let relay_addr = relay.local_addr().unwrap();
+
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 1500];
+                    let (n, _) = relay.recv_from(&mut buf).await.unwrap();
+                    relay.send_to(&buf[..n], addr).await.unwrap();
+                    loop {
+                        relay.recv_from(&mut buf).await.unwrap();
+                    }
+                });
+
+                let bind = SocketAddr::from(([127, 0, 0, 1], 0));
+
+                let stalled = QuicConnector::bind(bind, QuicVerify::Pinned(vec![cert.clone()]));
+                let stalled = stalled.unwrap();
+
+                tokio::spawn(async move { stalled.connect(Socket::quic(relay_addr)).await });
+
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+                let connector = QuicConnector::bind(bind, QuicVerify::Pinned(vec![cert])).unwrap();
+
+                let fut = async move {
+                    let mut stream = connector.connect(Socket::quic(addr)).await.unwrap();
+                    stream.write_all(b"hello").await.unwrap();
+                    let mut buf = [0u8; 5];
+                    stream.read_exact(&mut buf).await.unwrap();
+                    drop(server.await.unwrap());
+                };
+
+                tokio::time::timeout(std::time::Duration::from_secs(3), fut)
+                    .await
+                    .unwrap();
+            });
+    }
+}
@@ -0,0 +1,77 @@
+use std::{pin::Pin, task::Poll};
+
+use crate::{Address, NetSocket};
+
+#[pin_project::pin_project]
+pub struct QuicStream {
+    #[pin]
+    pub(crate) send: quinn::SendStream,
+    #[pin]
+    pub(crate) recv: quinn::RecvStream,
+    pub(crate) local_addr: Address,
+    pub(crate) peer_addr: Address,
+}
+
+impl QuicStream {
+    pub fn new(
+        send: quinn::SendStream,
+        recv: quinn::RecvStream,
+        local_addr: Address,
+        peer_addr: Address,
+    ) -> Self {
+        Self {
+            send,
+            recv,
+            local_addr,
+            peer_addr,
+        }
+    }
+}
+
+impl NetSocket for QuicStream {
+    fn peer_addr(&self) -> crate::Result<Address> {
+        Ok(self.peer_addr.clone())
+    }
+
+    fn local_addr(&self) -> crate::Result<Address> {
+        Ok(self.local_addr.clone())
+    }
+}
+
+impl tokio::io::AsyncRead for QuicStream {
+    #[inline]
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        tokio::io::AsyncRead::poll_read(self.project().recv, cx, buf)
+    }
+}
+
+impl tokio::io::AsyncWrite for QuicStream {
+    #[inline]
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        tokio::io::AsyncWrite::poll_write(self.project().send, cx, buf)
+    }
+
+    #[inline]
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        tokio::io::AsyncWrite::poll_flush(self.project().send, cx)
+    }
+
+    #[inline]
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        tokio::io::AsyncWrite::poll_shutdown(self.project().send, cx)
+    }
+}
@@ -47,7 +47,6 @@ enum State {
     Success(Option<Socket>),
 }
 
-#[repr(C)]
 #[derive(Clone, Copy)]
 struct Head {
     ver: u8,
@@ -139,7 +138,10 @@ impl<'a> TryFrom<&'a [u8]> for Head {
             });
         }
 
-        let head = unsafe { (value.as_ptr() as *mut Self).as_ref().unwrap_unchecked() };
+        let head = Head {
+            ver: value[0],
+            nmethod: value[1],
+        };
 
         if head.ver != 0x05 {
             return Err(SocksErr::Head {
@@ -149,7 +151,7 @@ impl<'a> TryFrom<&'a [u8]> for Head {
             .into());
         }
 
-        return Ok(head.clone());
+        Ok(head)
     }
 }
 
@@ -160,36 +162,37 @@ fn parse_address(cmd: u8, _: u8, atype: u8, data: &[u8]) -> crate::Result<Socket
         return Err(SocksErr::BindNotSupport.into());
     }
 
-    let addr = match atype {
-        0x01 => {
-            #[repr(C)]
-            struct V4 {
-                ip: [u8; 4],
-                port: [u8; 2],
-            }
-
-            let v4 = unsafe { (data.as_ptr() as *mut V4).as_ref().unwrap_unchecked() };
+    // 地址 + 2字节端口, 域名不能为空
+    let expect = match atype {
+        0x01 => 6,
+        0x04 => 18,
+        0x03 => data.len().max(3),
+        _ => return Err(SocksErr::InvalidAddress.into()),
+    };
 
-            Addr::from((v4.ip, u16::from_be_bytes(v4.port)))
+    if data.len() != expect {
+        return Err(SocksErr::BadLength {
+            expect,
+            current: data.len(),
         }
-        0x04 => {
-            #[repr(C)]
-            struct V6 {
-                ip: [u8; 16],
-                port: [u8; 2],
-            }
+        .into());
+    }
 
-            let v6 = unsafe { (data.as_ptr() as *mut V6).as_ref().unwrap_unchecked() };
+    let (host, port) = data.split_at(data.len() - 2);
+    let port = u16::from_be_bytes([port[0], port[1]]);
 
-            Addr::from((v6.ip, u16::from_be_bytes(v6.port)))
+    let addr = match atype {
+        0x01 => {
+            let mut ip = [0u8; 4];
+            ip.copy_from_slice(host);
+            Addr::from((ip, port))
         }
-        0x03 => {
-            let len = data.len();
-            let port = u16::from_be_bytes([data[len - 2], data[len - 1]]);
-            let domain = String::from_utf8_lossy(&data[..len - 2]);
-            Addr::from((format!("{}", domain), port))
+        0x04 => {
+            let mut ip = [0u8; 16];
+            ip.copy_from_slice(host);
+            Addr::from((ip, port))
         }
-        _ => return Err(SocksErr::InvalidAddress.into()),
+        _ => Addr::from((String::from_utf8_lossy(host).into_owned(), port)),
     };
 
     Ok({
@@ -271,7 +274,7 @@ where
                 }
                 State::Request(0x05, cmd, rsv, 0x03, 1) if 1 == *read_offset => {
                     *read_offset = 0;
-                    let len = (read_buf[0] + 2) as usize;
+                    let len = read_buf[0] as usize + 2;
                     read_buf.clear();
                     read_buf.resize(len, 0);
                     let new_state = State::Request(0x05, *cmd, *rsv, 0x03, len);
@@ -373,6 +376,32 @@ where
     stream.write_all(&buf).await
 }
 
+/// 拆分udp转发包中的目标地址与数据, `data` 从 DST.ADDR 开始
+fn parse_forward_data(atype: u8, data: &[u8]) -> crate::Result<(Addr, &[u8])> {
+    let (offset, size) = match atype {
+        0x03 => match data.first() {
+            Some(len) => (1, *len as usize + 2),
+            None => return Err(Kind::BadForward.into()),
+        },
+        0x01 => (0, 6),
+        0x04 => (0, 18),
+        _ => return Err(SocksErr::InvalidAddress.into()),
+    };
+
+    if data.len() < offset + size {
+        return Err(SocksErr::BadLength {
+            expect: offset + size,
+            current: data.len(),
+        }
+        .into());
+    }
+
+    let (addr, data) = data[offset..].split_at(size);
+    let addr = parse_address(0x03, 0, atype, addr)?.into_addr();
+
+    Ok((addr, data))
+}
+
 //  +----+------+------+----------+----------+----------+
 //  |RSV | FRAG | ATYP | DST.ADDR | DST.PORT |   DATA   |
 //  +----+------+------+----------+----------+----------+
@@ -398,20 +427,13 @@ where
         data.len()
     );
 
-    let (size, data) = match atype {
-        0x03 => (data[4] as usize, &data[5..]),
-        0x01 => (6, &data[4..]),
-        0x04 => (18, &data[4..]),
-        _ => return Err(SocksErr::InvalidAddress.into()),
-    };
-
-    let addr = parse_address(0x03, 0, atype, data)?.into_addr();
+    let (addr, data) = parse_forward_data(atype, &data[4..])?;
 
     let message = Poto::Forward(addr.clone()).to_packet_vec();
 
     s1.write_all(&message).await?;
 
-    let data = make_packet(data[size..].to_vec()).encode();
+    let data = make_packet(data.to_vec()).encode();
 
     s1.write_all(&data).await?;
 
@@ -464,3 +486,90 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use crate::{Addr, InnerAddr};
+
+    use super::{parse_address, parse_forward_data, Head};
+
+    /// xorshift, 保证每次运行的输入相同
+    fn random(seed: &mut u64) -> u64 {
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 7;
+        *seed ^= *seed << 17;
+        *seed
+    }
+
+    fn random_bytes(seed: &mut u64, max: usize) -> Vec<u8> {
+        let len = random(seed) as usize % max;
+        (0..len).map(|_| random(seed) as u8).collect()
+    }
+
+    #[test]
+    fn test_forward_data_round_trip() {
+        let mut seed = 0x5eed;
+
+        for _ in 0..1000 {
+            let payload = random_bytes(&mut seed, 64);
+            let port = random(&mut seed) as u16;
+            let mut packet = Vec::new();
+
+            let expect = match random(&mut seed) % 3 {
+                0 => {
+                    let ip = (random(&mut seed) as u32).to_be_bytes();
+                    packet.extend(ip);
+                    Addr::from((ip, port))
+                }
+                1 => {
+                    let ip = (random(&mut seed) as u128).to_be_bytes();
+                    packet.extend(ip);
+                    Addr::from((ip, port))
+                }
+                _ => {
+                    let len = random(&mut seed) % 32 + 1;
+                    let domain = (0..len)
+                        .map(|_| (b'a' + (random(&mut seed) % 26) as u8) as char)
+                        .collect::<String>();
+                    packet.push(domain.len() as u8);
+                    packet.extend(domain.as_bytes());
+                    Addr::from((domain, port))
+                }
+            };
+
+            let atype = match expect.inner() {
+                InnerAddr::Socket(SocketAddr::V4(_)) => 0x01,
+                InnerAddr::Socket(SocketAddr::V6(_)) => 0x04,
+                InnerAddr::Domain(_, _) => 0x03,
+            };
+
+            packet.extend(port.to_be_bytes());
+            packet.extend(&payload);
+
+            let (addr, data) = parse_forward_data(atype, &packet).unwrap();
+            assert!(addr == expect);
+            assert_eq!(data, &payload[..]);
+
+            for len in 0..packet.len() - payload.len() {
+                assert!(parse_forward_data(atype, &packet[..len]).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn test_fuzz_parser() {
+        let mut seed = 0xf0550;
+
+        for _ in 0..10000 {
+            let data = random_bytes(&mut seed, 64);
+            let cmd = random(&mut seed) as u8 % 4;
+            let atype = random(&mut seed) as u8 % 5;
+
+            let _ = Head::try_from(&data[..]);
+            let _ = parse_address(cmd, 0, atype, &data);
+            let _ = parse_forward_data(atype, &data);
+        }
+    }
+}
@@ -0,0 +1,370 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::{ready, Kind, Stream};
+
+type BoxedFuture<T> = Pin<Box<dyn Future<Output = T> + Send + 'static>>;
+
+/// ip包的最大长度
+pub const DEFAULT_MTU: usize = 1500;
+
+/// 收发ip包的设备, 每次读写都是一个完整的ip包
+pub trait Device {
+    fn poll_recv(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<crate::Result<Vec<u8>>>;
+
+    fn poll_send(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        packet: &[u8],
+    ) -> Poll<crate::Result<()>>;
+
+    fn mtu(&self) -> usize {
+        DEFAULT_MTU
+    }
+}
+
+pub struct RecvPacket<'a, D> {
+    device: &'a mut D,
+}
+
+pub struct SendPacket<'a, D> {
+    device: &'a mut D,
+    packet: &'a [u8],
+}
+
+pub trait DeviceExt: Device {
+    fn recv(&mut self) -> RecvPacket<'_, Self>
+    where
+        Self: Sized + Unpin,
+    {
+        RecvPacket { device: self }
+    }
+
+    fn send<'a>(&'a mut self, packet: &'a [u8]) -> SendPacket<'a, Self>
+    where
+        Self: Sized + Unpin,
+    {
+        SendPacket {
+            device: self,
+            packet,
+        }
+    }
+}
+
+impl<D> DeviceExt for D where D: Device {}
+
+impl<D> Device for &mut D
+where
+    D: Device + Unpin + ?Sized,
+{
+    fn poll_recv(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<crate::Result<Vec<u8>>> {
+        Pin::new(&mut **self).poll_recv(cx)
+    }
+
+    fn poll_send(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        packet: &[u8],
+    ) -> Poll<crate::Result<()>> {
+        Pin::new(&mut **self).poll_send(cx, packet)
+    }
+
+    fn mtu(&self) -> usize {
+        (**self).mtu()
+    }
+}
+
+impl<'a, D> Future for RecvPacket<'a, D>
+where
+    D: Device + Unpin,
+{
+    type Output = crate::Result<Vec<u8>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut *self.device).poll_recv(cx)
+    }
+}
+
+impl<'a, D> Future for SendPacket<'a, D>
+where
+    D: Device + Unpin,
+{
+    type Output = crate::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let packet = self.packet;
+        Pin::new(&mut *self.device).poll_send(cx, packet)
+    }
+}
+
+/// 内存中的设备, 成对创建, 一端发送的包由另一端收到
+pub struct MemoryDevice {
+    mtu: usize,
+    sender: async_channel::Sender<Vec<u8>>,
+    receiver: async_channel::Receiver<Vec<u8>>,
+    recv_fut: Option<BoxedFuture<Result<Vec<u8>, async_channel::RecvError>>>,
+    send_fut: Option<BoxedFuture<Result<(), async_channel::SendError<Vec<u8>>>>>,
+}
+
+impl MemoryDevice {
+    pub fn pair(mtu: usize) -> (Self, Self) {
+        let (tx1, rx1) = async_channel::bounded(1024);
+        let (tx2, rx2) = async_channel::bounded(1024);
+
+        let make = |sender, receiver| Self {
+            mtu,
+            sender,
+            receiver,
+            recv_fut: None,
+            send_fut: None,
+        };
+
+        (make(tx1, rx2), make(tx2, rx1))
+    }
+}
+
+impl Device for MemoryDevice {
+    fn poll_recv(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<crate::Result<Vec<u8>>> {
+        if self.recv_fut.is_none() {
+            let receiver = self.receiver.clone();
+            self.recv_fut = Some(Box::pin(async move { receiver.recv().await }));
+        }
+
+        let r = ready!(Pin::new(unsafe { self.recv_fut.as_mut().unwrap_unchecked() }).poll(cx));
+
+        self.recv_fut = None;
+
+        Poll::Ready(r.map_err(|_| Kind::Channel.into()))
+    }
+
+    fn poll_send(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        packet: &[u8],
+    ) -> Poll<crate::Result<()>> {
+        if self.send_fut.is_none() {
+            let sender = self.sender.clone();
+            let packet = packet.to_vec();
+            self.send_fut = Some(Box::pin(async move { sender.send(packet).await }));
+        }
+
+        let r = ready!(Pin::new(unsafe { self.send_fut.as_mut().unwrap_unchecked() }).poll(cx));
+
+        self.send_fut = None;
+
+        Poll::Ready(r.map_err(|_| Kind::Channel.into()))
+    }
+
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+}
+
+/// 通过fuso连接收发ip包, 每个包前加上2字节的长度
+pub struct PacketStream<S> {
+    stream: S,
+    mtu: usize,
+    read_buf: Vec<u8>,
+    read_offset: usize,
+    write_buf: Vec<u8>,
+    write_offset: usize,
+}
+
+impl<S> PacketStream<S> {
+    pub fn new(stream: S) -> Self {
+        Self::with_mtu(stream, DEFAULT_MTU)
+    }
+
+    pub fn with_mtu(stream: S, mtu: usize) -> Self {
+        Self {
+            stream,
+            mtu,
+            read_buf: vec![0; 2],
+            read_offset: 0,
+            write_buf: Vec::new(),
+            write_offset: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S> Device for PacketStream<S>
+where
+    S: Stream + Unpin,
+{
+    fn poll_recv(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<crate::Result<Vec<u8>>> {
+        let this = &mut *self;
+
+        loop {
+            while this.read_offset < this.read_buf.len() {
+                let mut buf = crate::ReadBuf::new(&mut this.read_buf[this.read_offset..]);
+                let n = ready!(Pin::new(&mut this.stream).poll_read(cx, &mut buf))?;
+
+                if n == 0 {
+                    return Poll::Ready(Err(
+                        Kind::IO(std::io::ErrorKind::UnexpectedEof.into()).into()
+                    ));
+                }
+
+                this.read_offset += n;
+            }
+
+            // 读取完长度后再读取包
+            if this.read_buf.len() == 2 && this.read_offset == 2 {
+                let len = u16::from_be_bytes([this.read_buf[0], this.read_buf[1]]) as usize;
+
+                if len > this.mtu || len == 0 {
+                    return Poll::Ready(Err(
+                        Kind::Unexpected(format!("bad packet size {}", len)).into()
+                    ));
+                }
+
+                this.read_buf = vec![0; len + 2];
+                continue;
+            }
+
+            let packet = std::mem::replace(&mut this.read_buf, vec![0; 2]);
+            this.read_offset = 0;
+
+            return Poll::Ready(Ok(packet[2..].to_vec()));
+        }
+    }
+
+    fn poll_send(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        packet: &[u8],
+    ) -> Poll<crate::Result<()>> {
+        let this = &mut *self;
+
+        if this.write_buf.is_empty() {
+            if packet.len() > this.mtu || packet.len() > u16::MAX as usize {
+                return Poll::Ready(Err(Kind::Unexpected(format!(
+                    "bad packet size {}",
+                    packet.len()
+                ))
+                .into()));
+            }
+
+            this.write_buf
+                .extend_from_slice(&(packet.len() as u16).to_be_bytes());
+            this.write_buf.extend_from_slice(packet);
+        }
+
+        while this.write_offset < this.write_buf.len() {
+            let n = ready!(
+                Pin::new(&mut this.stream).poll_write(cx, &this.write_buf[this.write_offset..])
+            )?;
+
+            if n == 0 {
+                return Poll::Ready(Err(Kind::IO(std::io::ErrorKind::WriteZero.into()).into()));
+            }
+
+            this.write_offset += n;
+        }
+
+        this.write_buf.clear();
+        this.write_offset = 0;
+
+        Poll::Ready(Ok(()))
+    }
+
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+}
+
+/// 在两个设备之间双向转发ip包, 任意一端出错时返回
+pub async fn transfer<D1, D2>(mut d1: D1, mut d2: D2) -> crate::Result<()>
+where
+    D1: Device + Unpin,
+    D2: Device + Unpin,
+{
+    let mut d1_pending: Option<Vec<u8>> = None;
+    let mut d2_pending: Option<Vec<u8>> = None;
+
+    std::future::poll_fn(|cx| loop {
+        let mut progress = false;
+
+        progress |= poll_direction(cx, &mut d1, &mut d2, &mut d1_pending)?;
+        progress |= poll_direction(cx, &mut d2, &mut d1, &mut d2_pending)?;
+
+        if !progress {
+            return Poll::Pending;
+        }
+    })
+    .await
+}
+
+/// 从 `from` 读取一个包写入 `to`, 返回是否有进展
+fn poll_direction<D1, D2>(
+    cx: &mut Context<'_>,
+    from: &mut D1,
+    to: &mut D2,
+    pending: &mut Option<Vec<u8>>,
+) -> crate::Result<bool>
+where
+    D1: Device + Unpin,
+    D2: Device + Unpin,
+{
+    if pending.is_none() {
+        match Pin::new(&mut *from).poll_recv(cx)? {
+            Poll::Pending => return Ok(false),
+            Poll::Ready(packet) => *pending = Some(packet),
+        }
+    }
+
+    let packet = unsafe { pending.as_ref().unwrap_unchecked() };
+
+    match Pin::new(&mut *to).poll_send(cx, packet)? {
+        Poll::Pending => Ok(false),
+        Poll::Ready(()) => {
+            *pending = None;
+            Ok(true)
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "fuso-rt-tokio")]
+mod tests {
+    use super::{transfer, DeviceExt, MemoryDevice, PacketStream};
+
+    #[test]
+    fn test_transfer_over_stream() {
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(async move {
+                let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let addr = listener.local_addr().unwrap();
+
+                let (mut local, device) = MemoryDevice::pair(1500);
+
+                tokio::spawn(async move {
+                    let (tcp, _) = listener.accept().await.unwrap();
+                    transfer(device, PacketStream::new(tcp)).await
+                });
+
+                let tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+                let mut remote = PacketStream::new(tcp);
+
+                for size in [1, 20, 1500] {
+                    let packet = vec![size as u8; size];
+
+                    local.send(&packet).await.unwrap();
+                    assert_eq!(remote.recv().await.unwrap(), packet);
+
+                    remote.send(&packet).await.unwrap();
+                    assert_eq!(local.recv().await.unwrap(), packet);
+                }
+
+                assert!(remote.send(&[0u8; 1501]).await.is_err());
+            });
+    }
+}
@@ -0,0 +1,148 @@
+use std::{
+    ffi::CStr,
+    os::unix::io::{AsRawFd, FromRawFd, OwnedFd},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tokio::io::unix::AsyncFd;
+
+use crate::{ready, Kind};
+
+use super::{Device, DEFAULT_MTU};
+
+const TUNSETIFF: libc::c_ulong = 0x400454ca;
+const IFF_TUN: libc::c_short = 0x0001;
+const IFF_NO_PI: libc::c_short = 0x1000;
+
+#[repr(C)]
+struct IfReq {
+    name: [libc::c_char; libc::IFNAMSIZ],
+    flags: libc::c_short,
+    _pad: [u8; 22],
+}
+
+/// linux内核的tun设备, 需要root权限, 地址与路由需要自行配置, 如:
+/// `ip addr add 10.0.0.1/24 dev fuso0 && ip link set fuso0 up`
+pub struct TunDevice {
+    fd: AsyncFd<OwnedFd>,
+    name: String,
+    mtu: usize,
+}
+
+impl TunDevice {
+    pub fn open(name: &str) -> crate::Result<Self> {
+        if name.len() >= libc::IFNAMSIZ {
+            return Err(Kind::Unexpected(format!("bad tun name {}", name)).into());
+        }
+
+        let fd = unsafe {
+            libc::open(
+                c"/dev/net/tun".as_ptr(),
+                libc::O_RDWR | libc::O_NONBLOCK | libc::O_CLOEXEC,
+            )
+        };
+
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut req = IfReq {
+            name: [0; libc::IFNAMSIZ],
+            flags: IFF_TUN | IFF_NO_PI,
+            _pad: [0; 22],
+        };
+
+        for (dst, src) in req.name.iter_mut().zip(name.bytes()) {
+            *dst = src as libc::c_char;
+        }
+
+        if unsafe { libc::ioctl(fd.as_raw_fd(), TUNSETIFF as _, &mut req) } < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        let name = unsafe { CStr::from_ptr(req.name.as_ptr()) }
+            .to_string_lossy()
+            .into_owned();
+
+        Ok(Self {
+            name,
+            fd: AsyncFd::new(fd)?,
+            mtu: DEFAULT_MTU,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Device for TunDevice {
+    fn poll_recv(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<crate::Result<Vec<u8>>> {
+        let mut packet = vec![0u8; self.mtu];
+
+        loop {
+            let mut guard = ready!(self.fd.poll_read_ready(cx))?;
+
+            let r = guard.try_io(|fd| {
+                let n = unsafe {
+                    libc::read(
+                        fd.as_raw_fd(),
+                        packet.as_mut_ptr() as *mut libc::c_void,
+                        packet.len(),
+                    )
+                };
+
+                match n {
+                    n if n < 0 => Err(std::io::Error::last_os_error()),
+                    n => Ok(n as usize),
+                }
+            });
+
+            match r {
+                Err(_) => continue,
+                Ok(Err(e)) => return Poll::Ready(Err(e.into())),
+                Ok(Ok(n)) => {
+                    packet.truncate(n);
+                    return Poll::Ready(Ok(packet));
+                }
+            }
+        }
+    }
+
+    fn poll_send(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        packet: &[u8],
+    ) -> Poll<crate::Result<()>> {
+        loop {
+            let mut guard = ready!(self.fd.poll_write_ready(cx))?;
+
+            let r = guard.try_io(|fd| {
+                let n = unsafe {
+                    libc::write(
+                        fd.as_raw_fd(),
+                        packet.as_ptr() as *const libc::c_void,
+                        packet.len(),
+                    )
+                };
+
+                match n {
+                    n if n < 0 => Err(std::io::Error::last_os_error()),
+                    _ => Ok(()),
+                }
+            });
+
+            match r {
+                Err(_) => continue,
+                Ok(r) => return Poll::Ready(r.map_err(Into::into)),
+            }
+        }
+    }
+
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+}
@@ -0,0 +1,17 @@
+mod device;
+pub use device::*;
+
+#[cfg(feature = "fuso-tun")]
+mod stack;
+#[cfg(feature = "fuso-tun")]
+pub use stack::*;
+
+#[cfg(all(target_os = "linux", feature = "fuso-tun", feature = "fuso-rt-tokio"))]
+mod linux;
+#[cfg(all(target_os = "linux", feature = "fuso-tun", feature = "fuso-rt-tokio"))]
+pub use linux::*;
+
+#[cfg(feature = "fuso-tun")]
+mod session;
+#[cfg(feature = "fuso-tun")]
+pub use session::*;
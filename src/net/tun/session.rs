@@ -0,0 +1,315 @@
+use std::{
+    fmt::Debug,
+    net::Ipv4Addr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    io,
+    protocol::{AsyncRecvPacket, AsyncSendPacket, Auth, Connect, Poto, ToPacket, TryToPoto},
+    AccepterExt, Address, ClientProvider, Executor, FusoStream, Kind, NetSocket, Provider,
+    ProviderTransfer, Shutdown, Socket, Stream, ToBoxStream,
+};
+
+use super::{transfer, Device, PacketStream, Stack};
+
+type BoxedFuture<T> = Pin<Box<dyn std::future::Future<Output = crate::Result<T>> + Send + 'static>>;
+
+type Serve = dyn Fn(FusoStream, Shutdown) -> BoxedFuture<()> + Send + Sync;
+
+/// 服务端的tun设备, 客户端在fuso连接上请求 `Connect::Tun` 并提供令牌.
+/// 同一时间只转发给一个客户端, 后通过校验的客户端接管设备
+#[derive(Clone)]
+pub struct TunService {
+    token: Arc<[u8]>,
+    serve: Arc<Serve>,
+    /// 当前客户端的关闭信号
+    current: Arc<Mutex<Shutdown>>,
+}
+
+/// 客户端, 终结从服务端tun设备发来的tcp连接, 并从客户端所在的网络连接原始的目标地址,
+/// 与映射使用相同的传输方式与握手. 只转发tcp, 来自tun的udp与icmp会被丢弃
+pub struct TunClient<E, CF, S, C> {
+    token: String,
+    addr: Ipv4Addr,
+    executor: E,
+    connector: C,
+    handshake: Option<ProviderTransfer<S>>,
+    client_provider: ClientProvider<CF>,
+}
+
+impl TunService {
+    pub fn new<D>(device: D, token: &str) -> Self
+    where
+        D: Device + Unpin + Send + 'static,
+    {
+        let device = Arc::new(async_mutex::Mutex::new(device));
+
+        let serve = move |stream: FusoStream, shutdown: Shutdown| -> BoxedFuture<()> {
+            let device = device.clone();
+
+            Box::pin(async move {
+                let fut = async move {
+                    // 等待被接管的客户端释放设备
+                    let mut device = device.lock().await;
+                    let packets = PacketStream::with_mtu(stream, device.mtu());
+                    transfer(&mut *device, packets).await
+                };
+
+                shutdown.run_until(fut).await.unwrap_or(Ok(()))
+            })
+        };
+
+        Self {
+            token: token.as_bytes().into(),
+            serve: Arc::new(serve),
+            current: Default::default(),
+        }
+    }
+
+    /// 校验令牌后返回转发任务, 之前的客户端将被断开
+    pub(crate) async fn accept<S>(
+        &self,
+        mut client: S,
+        auth: Auth,
+    ) -> crate::Result<BoxedFuture<()>>
+    where
+        S: Stream + Send + 'static,
+    {
+        let client_addr = client.peer_addr()?;
+
+        let authorized = match &auth {
+            Auth::Auth(token) => constant_time_eq(token, &self.token),
+            Auth::NoAuth => false,
+        };
+
+        if !authorized {
+            log::warn!("tun client {} rejected, bad token", client_addr);
+            let message = Poto::MapError(0, String::from("bad tun token")).to_packet_vec();
+            let _ = client.send_packet(&message).await;
+            return Err(Kind::Message("bad tun token".into()).into());
+        }
+
+        let message = Poto::Connect(Connect::Tun, Auth::NoAuth).to_packet_vec();
+        client.send_packet(&message).await?;
+
+        let shutdown = Shutdown::default();
+
+        let previous = std::mem::replace(
+            &mut *self
+                .current
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()),
+            shutdown.clone(),
+        );
+
+        previous.shutdown();
+
+        log::info!("tun client {} connected", client_addr);
+
+        let fut = (self.serve)(client.into_boxed_stream(), shutdown);
+
+        Ok(Box::pin(async move {
+            let r = fut.await;
+            log::info!("tun client {} disconnected", client_addr);
+            r
+        }))
+    }
+}
+
+impl Debug for TunService {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TunService").finish_non_exhaustive()
+    }
+}
+
+impl<E, CF, S, C> TunClient<E, CF, S, C> {
+    pub fn new(
+        token: String,
+        addr: Ipv4Addr,
+        executor: E,
+        connector: C,
+        handshake: Option<ProviderTransfer<S>>,
+        client_provider: ClientProvider<CF>,
+    ) -> Self {
+        Self {
+            token,
+            addr,
+            executor,
+            connector,
+            handshake,
+            client_provider,
+        }
+    }
+}
+
+impl<E, CF, S, C, O> TunClient<E, CF, S, C>
+where
+    E: Executor + Send + Sync + 'static,
+    CF: Provider<Socket, Output = BoxedFuture<S>> + Send + Sync + 'static,
+    S: Stream + Send + 'static,
+    C: Provider<Socket, Output = BoxedFuture<O>> + Send + Sync + 'static,
+    O: Stream + Send + 'static,
+{
+    pub async fn run(self) -> crate::Result<()> {
+        let server = self
+            .client_provider
+            .connect(self.client_provider.default_socket().clone())
+            .await?;
+
+        let server = match self.handshake.as_ref() {
+            None => server,
+            Some(handshake) => handshake.call(server).await?,
+        };
+
+        relay(
+            server,
+            &self.token,
+            self.addr,
+            self.executor,
+            self.connector,
+        )
+        .await
+    }
+}
+
+/// 客户端请求服务端转发tun, 成功后在 `stream` 上收发ip包
+pub async fn request<S>(stream: &mut S, token: &str) -> crate::Result<()>
+where
+    S: Stream + Unpin,
+{
+    let message =
+        Poto::Connect(Connect::Tun, Auth::Auth(token.as_bytes().to_vec())).to_packet_vec();
+
+    stream.send_packet(&message).await?;
+
+    match stream.recv_packet().await?.try_message()? {
+        Poto::Connect(Connect::Tun, _) => Ok(()),
+        Poto::MapError(_, e) => Err(Kind::Message(e).into()),
+        message => Err(Kind::Unexpected(format!("{}", message)).into()),
+    }
+}
+
+/// 客户端, 在服务端连接上运行协议栈, 经由tun到达的连接由客户端代为连接原始的目标地址.
+/// `addr` 为协议栈自身的地址
+pub async fn relay<S, E, CF, O>(
+    mut stream: S,
+    token: &str,
+    addr: Ipv4Addr,
+    executor: E,
+    connector: CF,
+) -> crate::Result<()>
+where
+    S: Stream + Send + 'static,
+    E: Executor + Send + Sync + 'static,
+    CF: Provider<Socket, Output = BoxedFuture<O>> + Send + Sync + 'static,
+    O: Stream + Send + 'static,
+{
+    request(&mut stream, token).await?;
+
+    let packets = PacketStream::new(stream);
+
+    let (stack, mut listener) = Stack::new(packets, addr);
+
+    let executor = Arc::new(executor);
+    let connector = Arc::new(connector);
+
+    let mut task = executor.spawn(stack);
+
+    // 协议栈退出后listener会返回错误
+    let r = loop {
+        let stream = match listener.accept().await {
+            Ok(stream) => stream,
+            Err(e) => break Err(e),
+        };
+
+        let target = match stream.local_addr()? {
+            Address::Single(target) => target,
+            Address::Many(_) => continue,
+        };
+
+        let connector = connector.clone();
+
+        executor.spawn(async move {
+            log::debug!("tun relay {}", target);
+
+            match connector.call(target.clone()).await {
+                Ok(local) => {
+                    let _ = io::forward(stream, local).await;
+                }
+                Err(e) => {
+                    log::warn!("failed to connect to {} err={}", target, e);
+                }
+            }
+        });
+    };
+
+    task.abort();
+
+    r
+}
+
+/// 比较耗时与相同前缀的长度无关
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+#[cfg(feature = "fuso-rt-tokio")]
+mod tests {
+    use super::{request, TunService};
+    use crate::{
+        net::tun::{DeviceExt, MemoryDevice, PacketStream},
+        protocol::{AsyncRecvPacket, Connect, Poto, TryToPoto},
+    };
+
+    #[test]
+    fn test_tun_service() {
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(async move {
+                let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let addr = listener.local_addr().unwrap();
+
+                let (mut peer, device) = MemoryDevice::pair(1500);
+                let service = TunService::new(device, "token");
+
+                tokio::spawn(async move {
+                    loop {
+                        let (mut tcp, _) = listener.accept().await.unwrap();
+                        let service = service.clone();
+                        tokio::spawn(async move {
+                            let auth = match tcp.recv_packet().await?.try_message()? {
+                                Poto::Connect(Connect::Tun, auth) => auth,
+                                _ => unreachable!(),
+                            };
+                            service.accept(tcp, auth).await?.await
+                        });
+                    }
+                });
+
+                let mut tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+                assert!(request(&mut tcp, "bad").await.is_err());
+
+                let mut first = tokio::net::TcpStream::connect(addr).await.unwrap();
+                request(&mut first, "token").await.unwrap();
+                let mut first = PacketStream::new(first);
+
+                peer.send(b"hello").await.unwrap();
+                assert_eq!(first.recv().await.unwrap(), b"hello");
+                first.send(b"world").await.unwrap();
+                assert_eq!(peer.recv().await.unwrap(), b"world");
+
+                // 后来的客户端接管设备
+                let mut second = tokio::net::TcpStream::connect(addr).await.unwrap();
+                request(&mut second, "token").await.unwrap();
+                let mut second = PacketStream::new(second);
+
+                assert!(first.recv().await.is_err());
+
+                peer.send(b"hello").await.unwrap();
+                assert_eq!(second.recv().await.unwrap(), b"hello");
+            });
+    }
+}
@@ -0,0 +1,711 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use smoltcp::{
+    iface::{Config, Interface, SocketHandle, SocketSet},
+    phy::{self, DeviceCapabilities, Medium},
+    socket::tcp,
+    time::Instant,
+    wire::{HardwareAddress, IpCidr, IpProtocol, Ipv4Address, Ipv4Packet, TcpPacket},
+};
+
+use crate::{ready, Accepter, Address, AsyncRead, AsyncWrite, Kind, NetSocket, ReadBuf, Socket};
+
+use super::Device;
+
+type BoxedFuture<T> = Pin<Box<dyn Future<Output = T> + Send + 'static>>;
+
+/// 每个连接在协议栈与 `TunStream` 之间缓存的最大字节数
+const FLOW_BUFFER_SIZE: usize = 64 * 1024;
+
+/// 每次最多从设备读取的包数, 避免饿死其他连接
+const MAX_BATCH: usize = 64;
+
+/// 连接在协议栈与 `TunStream` 之间共享的状态
+#[derive(Default)]
+struct Flow {
+    recv: VecDeque<u8>,
+    send: VecDeque<u8>,
+    read_waker: Option<Waker>,
+    write_waker: Option<Waker>,
+    /// 对端已关闭写入
+    eof: bool,
+    /// 连接已断开, 不能再写入
+    broken: bool,
+    /// 本地已关闭写入
+    shutdown: bool,
+    /// `TunStream` 已释放
+    released: bool,
+    /// 已向对端发送fin
+    fin_sent: bool,
+}
+
+type SharedFlow = Arc<Mutex<Flow>>;
+
+type SharedWaker = Arc<Mutex<Option<Waker>>>;
+
+/// 用户态tcp/ip协议栈, 终结设备上收到的所有tcp连接,
+/// 每个连接以 `TunStream` 的形式由 `TunListener` 接收, `local_addr` 为原始的目标地址.
+/// 目前只处理ipv4的tcp, 其他包会被丢弃
+pub struct Stack<D> {
+    device: D,
+    iface: Interface,
+    sockets: SocketSet<'static>,
+    queue: Queue,
+    /// 等待握手完成的socket及其监听的地址
+    listening: HashMap<SocketHandle, SocketAddrV4>,
+    flows: HashMap<SocketHandle, SharedFlow>,
+    waker: SharedWaker,
+    accept: async_channel::Sender<TunStream>,
+    timer: Option<BoxedFuture<()>>,
+    pending: Option<Vec<u8>>,
+}
+
+pub struct TunListener {
+    addr: Ipv4Addr,
+    receiver: async_channel::Receiver<TunStream>,
+    accept_fut: Option<BoxedFuture<Result<TunStream, async_channel::RecvError>>>,
+}
+
+pub struct TunStream {
+    flow: SharedFlow,
+    waker: SharedWaker,
+    local_addr: SocketAddr,
+    peer_addr: SocketAddr,
+}
+
+/// smoltcp使用的设备, 只是两个包队列
+struct Queue {
+    mtu: usize,
+    rx: VecDeque<Vec<u8>>,
+    tx: VecDeque<Vec<u8>>,
+}
+
+struct RxToken(Vec<u8>);
+
+struct TxToken<'a>(&'a mut VecDeque<Vec<u8>>);
+
+impl<D> Stack<D>
+where
+    D: Device + Unpin,
+{
+    /// `addr` 为协议栈自身的地址, 不能与需要访问的目标地址冲突
+    pub fn new(device: D, addr: Ipv4Addr) -> (Self, TunListener) {
+        let mut queue = Queue {
+            mtu: device.mtu(),
+            rx: VecDeque::new(),
+            tx: VecDeque::new(),
+        };
+
+        let config = Config::new(HardwareAddress::Ip);
+        let mut iface = Interface::new(config, &mut queue, Instant::now());
+        let ip = Ipv4Address::from_bytes(&addr.octets());
+
+        iface.update_ip_addrs(|addrs| {
+            let _ = addrs.push(IpCidr::new(ip.into(), 32));
+        });
+
+        // 所有地址都经由自身路由, 配合any_ip接收发往任意地址的包
+        let _ = iface.routes_mut().add_default_ipv4_route(ip);
+        iface.set_any_ip(true);
+
+        let (accept, receiver) = async_channel::unbounded();
+
+        let stack = Self {
+            device,
+            iface,
+            queue,
+            accept,
+            sockets: SocketSet::new(Vec::new()),
+            listening: HashMap::new(),
+            flows: HashMap::new(),
+            waker: Default::default(),
+            timer: None,
+            pending: None,
+        };
+
+        let listener = TunListener {
+            addr,
+            receiver,
+            accept_fut: None,
+        };
+
+        (stack, listener)
+    }
+
+    /// 收到新连接的syn包时, 为目标地址创建一个监听的socket
+    fn prepare(&mut self, packet: &[u8]) {
+        let packet = match Ipv4Packet::new_checked(packet) {
+            Ok(packet) if packet.next_header() == IpProtocol::Tcp => packet,
+            _ => return,
+        };
+
+        let tcp = match TcpPacket::new_checked(packet.payload()) {
+            Ok(tcp) if tcp.syn() && !tcp.ack() => tcp,
+            _ => return,
+        };
+
+        let src = SocketAddrV4::new(Ipv4Addr::from(packet.src_addr().0), tcp.src_port());
+        let dst = SocketAddrV4::new(Ipv4Addr::from(packet.dst_addr().0), tcp.dst_port());
+
+        let exists = self.listening.iter().any(|(handle, listen)| {
+            let socket = self.sockets.get::<tcp::Socket>(*handle);
+            *listen == dst
+                && match socket.remote_endpoint() {
+                    None => socket.state() == tcp::State::Listen,
+                    Some(remote) => remote == src.into(),
+                }
+        });
+
+        if exists {
+            return;
+        }
+
+        let mut socket = tcp::Socket::new(
+            tcp::SocketBuffer::new(vec![0; FLOW_BUFFER_SIZE]),
+            tcp::SocketBuffer::new(vec![0; FLOW_BUFFER_SIZE]),
+        );
+
+        socket.set_timeout(Some(smoltcp::time::Duration::from_secs(120)));
+
+        if let Err(e) = socket.listen(dst) {
+            log::warn!("failed to listen on {} err={}", dst, e);
+            return;
+        }
+
+        log::debug!("tun accept {} -> {}", src, dst);
+
+        let handle = self.sockets.add(socket);
+        self.listening.insert(handle, dst);
+    }
+
+    /// 同步socket与 `TunStream` 之间的数据, 返回是否有进展
+    fn sync(&mut self) -> crate::Result<bool> {
+        let mut progress = false;
+
+        let established = self
+            .listening
+            .iter()
+            .filter_map(|(handle, listen)| {
+                let socket = self.sockets.get::<tcp::Socket>(*handle);
+                match socket.state() {
+                    tcp::State::Listen | tcp::State::SynReceived => None,
+                    _ => Some((*handle, *listen, socket.remote_endpoint())),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        for (handle, listen, remote) in established {
+            self.listening.remove(&handle);
+
+            let remote = match remote {
+                Some(remote) if self.sockets.get::<tcp::Socket>(handle).is_active() => remote,
+                _ => {
+                    self.sockets.remove(handle);
+                    continue;
+                }
+            };
+
+            let flow = SharedFlow::default();
+
+            let stream = TunStream {
+                flow: flow.clone(),
+                waker: self.waker.clone(),
+                local_addr: listen.into(),
+                peer_addr: SocketAddr::new(remote.addr.into(), remote.port),
+            };
+
+            if self.accept.try_send(stream).is_err() {
+                return Err(Kind::Channel.into());
+            }
+
+            self.flows.insert(handle, flow);
+            progress = true;
+        }
+
+        let mut closed = Vec::new();
+
+        for (handle, flow) in self.flows.iter() {
+            let socket = self.sockets.get_mut::<tcp::Socket>(*handle);
+            let mut flow = flow.lock()?;
+            let flow = &mut *flow;
+
+            while socket.can_recv() && (flow.released || flow.recv.len() < FLOW_BUFFER_SIZE) {
+                let room = FLOW_BUFFER_SIZE - flow.recv.len().min(FLOW_BUFFER_SIZE);
+                let released = flow.released;
+                let recv = &mut flow.recv;
+
+                let n = socket
+                    .recv(|data| {
+                        // 已释放的连接直接丢弃收到的数据
+                        let n = if released {
+                            data.len()
+                        } else {
+                            data.len().min(room)
+                        };
+                        if !released {
+                            recv.extend(&data[..n]);
+                        }
+                        (n, n)
+                    })
+                    .unwrap_or(0);
+
+                if n == 0 {
+                    break;
+                }
+
+                progress = true;
+                if let Some(waker) = flow.read_waker.take() {
+                    waker.wake();
+                }
+            }
+
+            while socket.can_send() && !flow.send.is_empty() {
+                let n = socket.send_slice(flow.send.as_slices().0).unwrap_or(0);
+
+                if n == 0 {
+                    break;
+                }
+
+                flow.send.drain(..n);
+                progress = true;
+                if let Some(waker) = flow.write_waker.take() {
+                    waker.wake();
+                }
+            }
+
+            if !flow.fin_sent && (flow.shutdown || flow.released) && flow.send.is_empty() {
+                flow.fin_sent = true;
+                socket.close();
+                progress = true;
+            }
+
+            if !flow.eof && !socket.may_recv() && !socket.can_recv() {
+                flow.eof = true;
+                if let Some(waker) = flow.read_waker.take() {
+                    waker.wake();
+                }
+            }
+
+            if !flow.broken && socket.state() == tcp::State::Closed {
+                flow.broken = true;
+                flow.eof = true;
+                if let Some(waker) = flow.read_waker.take() {
+                    waker.wake();
+                }
+                if let Some(waker) = flow.write_waker.take() {
+                    waker.wake();
+                }
+            }
+
+            if flow.released && socket.state() == tcp::State::Closed {
+                closed.push(*handle);
+            }
+        }
+
+        for handle in closed {
+            self.flows.remove(&handle);
+            self.sockets.remove(handle);
+        }
+
+        Ok(progress)
+    }
+}
+
+impl<D> Future for Stack<D>
+where
+    D: Device + Unpin,
+{
+    type Output = crate::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+
+        *this.waker.lock()? = Some(cx.waker().clone());
+
+        loop {
+            let mut progress = false;
+
+            while this.queue.rx.len() < MAX_BATCH {
+                match Pin::new(&mut this.device).poll_recv(cx)? {
+                    Poll::Pending => break,
+                    Poll::Ready(packet) if !is_ipv4_tcp(&packet) => {}
+                    Poll::Ready(packet) => {
+                        this.prepare(&packet);
+                        this.queue.rx.push_back(packet);
+                    }
+                }
+            }
+
+            progress |= this.sync()?;
+
+            let now = Instant::now();
+
+            progress |= this.iface.poll(now, &mut this.queue, &mut this.sockets);
+            progress |= this.sync()?;
+
+            loop {
+                if this.pending.is_none() {
+                    this.pending = this.queue.tx.pop_front();
+                }
+
+                let packet = match this.pending.as_ref() {
+                    None => break,
+                    Some(packet) => packet,
+                };
+
+                match Pin::new(&mut this.device).poll_send(cx, packet)? {
+                    Poll::Pending => break,
+                    Poll::Ready(()) => {
+                        this.pending = None;
+                    }
+                }
+            }
+
+            if progress || !this.queue.rx.is_empty() {
+                continue;
+            }
+
+            this.timer = match this.iface.poll_delay(now, &this.sockets) {
+                None => None,
+                Some(delay) => {
+                    let delay: Duration = delay.into();
+                    Some(Box::pin(crate::time::sleep(delay)))
+                }
+            };
+
+            if let Some(timer) = this.timer.as_mut() {
+                if timer.as_mut().poll(cx).is_ready() {
+                    continue;
+                }
+            }
+
+            return Poll::Pending;
+        }
+    }
+}
+
+/// 只有ipv4的tcp包交给协议栈, 其他包直接丢弃, 避免协议栈自行响应icmp
+fn is_ipv4_tcp(packet: &[u8]) -> bool {
+    match Ipv4Packet::new_checked(packet) {
+        Ok(packet) if packet.next_header() == IpProtocol::Tcp => true,
+        Ok(packet) => {
+            log::debug!(
+                "drop {} packet to {}, only tcp is forwarded",
+                packet.next_header(),
+                packet.dst_addr()
+            );
+            false
+        }
+        Err(_) => false,
+    }
+}
+
+impl phy::Device for Queue {
+    type RxToken<'a> = RxToken;
+    type TxToken<'a> = TxToken<'a>;
+
+    fn receive(&mut self, _: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let packet = self.rx.pop_front()?;
+        Some((RxToken(packet), TxToken(&mut self.tx)))
+    }
+
+    fn transmit(&mut self, _: Instant) -> Option<Self::TxToken<'_>> {
+        Some(TxToken(&mut self.tx))
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut capabilities = DeviceCapabilities::default();
+        capabilities.medium = Medium::Ip;
+        capabilities.max_transmission_unit = self.mtu;
+        capabilities
+    }
+}
+
+impl phy::RxToken for RxToken {
+    fn consume<R, F>(mut self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        f(&mut self.0)
+    }
+}
+
+impl<'a> phy::TxToken for TxToken<'a> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let mut packet = vec![0; len];
+        let r = f(&mut packet);
+        self.0.push_back(packet);
+        r
+    }
+}
+
+impl NetSocket for TunListener {
+    fn peer_addr(&self) -> crate::Result<Address> {
+        self.local_addr()
+    }
+
+    fn local_addr(&self) -> crate::Result<Address> {
+        Ok(Address::Single(Socket::tcp(SocketAddr::from((
+            self.addr, 0,
+        )))))
+    }
+}
+
+impl Accepter for TunListener {
+    type Stream = TunStream;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<crate::Result<Self::Stream>> {
+        if self.accept_fut.is_none() {
+            let receiver = self.receiver.clone();
+            self.accept_fut = Some(Box::pin(async move { receiver.recv().await }));
+        }
+
+        let r = ready!(Pin::new(unsafe { self.accept_fut.as_mut().unwrap_unchecked() }).poll(cx));
+
+        self.accept_fut = None;
+
+        Poll::Ready(r.map_err(|_| Kind::Channel.into()))
+    }
+}
+
+impl TunStream {
+    fn wake_stack(&self) -> crate::Result<()> {
+        if let Some(waker) = self.waker.lock()?.as_ref() {
+            waker.wake_by_ref();
+        }
+
+        Ok(())
+    }
+}
+
+impl NetSocket for TunStream {
+    fn peer_addr(&self) -> crate::Result<Address> {
+        Ok(Address::Single(Socket::tcp(self.peer_addr)))
+    }
+
+    fn local_addr(&self) -> crate::Result<Address> {
+        Ok(Address::Single(Socket::tcp(self.local_addr)))
+    }
+}
+
+impl AsyncRead for TunStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<crate::Result<usize>> {
+        let mut flow = self.flow.lock()?;
+
+        if flow.recv.is_empty() {
+            if flow.eof {
+                return Poll::Ready(Ok(0));
+            }
+
+            flow.read_waker = Some(cx.waker().clone());
+
+            return Poll::Pending;
+        }
+
+        let unfilled = buf.initialize_unfilled();
+        let n = unfilled.len().min(flow.recv.len());
+
+        for (dst, src) in unfilled.iter_mut().zip(flow.recv.drain(..n)) {
+            *dst = src;
+        }
+
+        buf.advance(n);
+
+        drop(flow);
+
+        self.wake_stack()?;
+
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl AsyncWrite for TunStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<crate::Result<usize>> {
+        let mut flow = self.flow.lock()?;
+
+        if flow.broken || flow.shutdown {
+            return Poll::Ready(Err(Kind::IO(std::io::ErrorKind::BrokenPipe.into()).into()));
+        }
+
+        let n = (FLOW_BUFFER_SIZE - flow.send.len().min(FLOW_BUFFER_SIZE)).min(buf.len());
+
+        if n == 0 {
+            flow.write_waker = Some(cx.waker().clone());
+
+            return Poll::Pending;
+        }
+
+        flow.send.extend(&buf[..n]);
+
+        drop(flow);
+
+        self.wake_stack()?;
+
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<crate::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<crate::Result<()>> {
+        self.flow.lock()?.shutdown = true;
+        self.wake_stack()?;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for TunStream {
+    fn drop(&mut self) {
+        if let Ok(mut flow) = self.flow.lock() {
+            flow.released = true;
+            flow.recv.clear();
+        }
+
+        let _ = self.wake_stack();
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "fuso-rt-tokio")]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use smoltcp::{
+        iface::{Config, Interface, SocketSet},
+        socket::tcp,
+        time::Instant,
+        wire::{HardwareAddress, IpAddress, IpCidr, Ipv4Address},
+    };
+
+    use crate::{
+        ext::{AsyncReadExt, AsyncWriteExt},
+        net::tun::{DeviceExt, MemoryDevice},
+        AccepterExt, Address, NetSocket,
+    };
+
+    use super::{Queue, Stack};
+
+    /// 另一端使用smoltcp作为客户端, 访问任意地址的连接都应由协议栈接收
+    #[test]
+    fn test_stack_accept() {
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(async move {
+                let (mut peer, device) = MemoryDevice::pair(1500);
+                let (stack, mut listener) = Stack::new(device, Ipv4Addr::new(10, 255, 255, 1));
+
+                tokio::spawn(stack);
+
+                tokio::spawn(async move {
+                    let mut stream = listener.accept().await.unwrap();
+
+                    match stream.local_addr().unwrap() {
+                        Address::Single(socket) => {
+                            assert_eq!(socket.as_string(), "192.168.7.7:8080")
+                        }
+                        Address::Many(_) => unreachable!(),
+                    }
+
+                    let mut buf = [0u8; 5];
+                    stream.read_exact(&mut buf).await.unwrap();
+                    stream.write_all(&buf).await.unwrap();
+                });
+
+                let mut queue = Queue {
+                    mtu: 1500,
+                    rx: Default::default(),
+                    tx: Default::default(),
+                };
+
+                let mut iface =
+                    Interface::new(Config::new(HardwareAddress::Ip), &mut queue, Instant::now());
+
+                iface.update_ip_addrs(|addrs| {
+                    let _ = addrs.push(IpCidr::new(IpAddress::v4(10, 0, 0, 2), 24));
+                });
+
+                let _ = iface
+                    .routes_mut()
+                    .add_default_ipv4_route(Ipv4Address::new(10, 0, 0, 1));
+
+                let mut sockets = SocketSet::new(Vec::new());
+
+                let handle = sockets.add(tcp::Socket::new(
+                    tcp::SocketBuffer::new(vec![0; 4096]),
+                    tcp::SocketBuffer::new(vec![0; 4096]),
+                ));
+
+                sockets
+                    .get_mut::<tcp::Socket>(handle)
+                    .connect(
+                        iface.context(),
+                        (IpAddress::v4(192, 168, 7, 7), 8080),
+                        40000,
+                    )
+                    .unwrap();
+
+                let mut sent = false;
+                let mut echo = Vec::new();
+
+                let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+
+                while echo.len() < 5 {
+                    assert!(std::time::Instant::now() < deadline);
+
+                    iface.poll(Instant::now(), &mut queue, &mut sockets);
+
+                    while let Some(packet) = queue.tx.pop_front() {
+                        peer.send(&packet).await.unwrap();
+                    }
+
+                    let socket = sockets.get_mut::<tcp::Socket>(handle);
+
+                    if !sent && socket.can_send() {
+                        socket.send_slice(b"hello").unwrap();
+                        sent = true;
+                    }
+
+                    while socket.can_recv() {
+                        socket
+                            .recv(|data| {
+                                echo.extend_from_slice(data);
+                                (data.len(), ())
+                            })
+                            .unwrap();
+                    }
+
+                    if let Ok(Ok(packet)) =
+                        tokio::time::timeout(std::time::Duration::from_millis(10), peer.recv())
+                            .await
+                    {
+                        queue.rx.push_back(packet);
+                    }
+                }
+
+                assert_eq!(echo, b"hello");
+            });
+    }
+}
@@ -0,0 +1,441 @@
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
+};
+
+use async_mutex::Mutex;
+
+use crate::{
+    client::{self, Transport, Upstream},
+    kcp, time, Executor, FusoStream, Provider, Runtime, Socket, Task, ToBoxStream,
+    UdpSocketWrapper,
+};
+
+type BoxedFuture<O> = Pin<Box<dyn Future<Output = crate::Result<O>> + Send + 'static>>;
+
+type KcpConnector<R> = kcp::KcpConnector<UdpSocketWrapper, RuntimeExecutor<R>>;
+
+/// 未设置时建立tcp连接的超时时间
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 使用运行时 `R` 执行任务
+#[derive(Default, Clone, Copy)]
+pub struct RuntimeExecutor<R>(R);
+
+/// 连接服务端, tcp与udp都由运行时 `R` 建立
+#[derive(Default, Clone)]
+pub struct RuntimeConnector<R> {
+    runtime: R,
+    kcp: Arc<Mutex<HashMap<String, Arc<KcpConnector<R>>>>>,
+    kcp_config: kcp::KcpConfig,
+    transport: Transport,
+    /// 优先使用tcp时, tcp连接失败并改用kcp的服务端地址
+    tcp_failed: Arc<std::sync::Mutex<HashSet<String>>>,
+    /// 连接服务端时依次经过的上游代理, 只对tcp生效
+    upstream: Arc<Vec<Upstream>>,
+    /// 建立tcp连接以及与上游代理握手的超时时间
+    connect_timeout: Option<Duration>,
+    /// 每个地址族使用一个quic连接器
+    #[cfg(feature = "fuso-quic")]
+    quic: Arc<Mutex<HashMap<bool, crate::quic::QuicConnector>>>,
+    /// 未设置时不允许使用quic
+    #[cfg(feature = "fuso-quic")]
+    quic_verify: Option<crate::quic::QuicVerify>,
+}
+
+impl<R> Executor for RuntimeExecutor<R>
+where
+    R: Runtime,
+{
+    fn spawn<F, O>(&self, fut: F) -> Task<O>
+    where
+        F: Future<Output = O> + Send + 'static,
+        O: Send + 'static,
+    {
+        let mut task = self.0.spawn(Box::pin(async move {
+            let _ = fut.await;
+        }));
+
+        Task {
+            detach_task_fn: task.detach_task_fn.take(),
+            abort_task_fn: task.abort_task_fn.take(),
+            _marked: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<R> Provider<Socket> for RuntimeConnector<R>
+where
+    R: Runtime + Clone + Unpin,
+{
+    type Output = BoxedFuture<FusoStream>;
+
+    fn call(&self, socket: Socket) -> Self::Output {
+        let connector = self.clone();
+
+        Box::pin(async move {
+            if socket.is_quic() {
+                return connector.connect_quic(socket).await;
+            }
+
+            if socket.is_mixed() && socket.is_ufd() {
+                return connector.connect_kcp(&socket).await;
+            }
+
+            match connector.transport {
+                Transport::Tcp => connector.connect_tcp(&socket).await,
+                Transport::Kcp => connector.connect_kcp(&socket).await,
+                Transport::PreferTcp => {
+                    let addr = socket.as_string();
+
+                    // tcp不可用时每次重试都需要等待超时, 改用kcp之后只要kcp有响应就继续使用kcp
+                    if connector.tcp_failed.lock().unwrap().contains(&addr) {
+                        let (kcp, _) = connector.kcp_connector(&socket).await?;
+
+                        if kcp.is_responded() {
+                            return Ok(kcp.connect().await?.into_boxed_stream());
+                        }
+
+                        log::info!("kcp seems unreachable, retry tcp {}", socket);
+                    }
+
+                    match connector.connect_tcp(&socket).await {
+                        Ok(stream) => {
+                            connector.tcp_failed.lock().unwrap().remove(&addr);
+                            Ok(stream)
+                        }
+                        Err(_) => {
+                            log::info!("fallback to kcp {}", socket);
+                            connector.tcp_failed.lock().unwrap().insert(addr);
+                            connector.connect_kcp(&socket).await
+                        }
+                    }
+                }
+                Transport::PreferKcp => {
+                    let (kcp, created) = connector.kcp_connector(&socket).await?;
+
+                    // kcp是无连接的, 之前建立的kcp连接一直没有收到响应时才认为kcp不可用
+                    if created || kcp.is_responded() {
+                        return Ok(kcp.connect().await?.into_boxed_stream());
+                    }
+
+                    log::info!("kcp seems unreachable, fallback to tcp {}", socket);
+
+                    match connector.connect_tcp(&socket).await {
+                        Ok(stream) => Ok(stream),
+                        Err(_) => Ok(kcp.connect().await?.into_boxed_stream()),
+                    }
+                }
+            }
+        })
+    }
+}
+
+impl<R> RuntimeConnector<R>
+where
+    R: Runtime + Clone + Unpin,
+{
+    async fn connect_tcp(&self, socket: &Socket) -> crate::Result<FusoStream> {
+        let server = match self.upstream.first() {
+            None => socket.clone(),
+            Some(upstream) => Socket::tcp(upstream.addr.clone()),
+        };
+
+        let runtime = self.runtime.clone();
+        let upstream = self.upstream.clone();
+        let target = socket.addr().clone();
+
+        let timeout = self.connect_timeout.unwrap_or(CONNECT_TIMEOUT);
+
+        time::wait_for(timeout, async move {
+            let mut tcp = runtime.connect_tcp(server).await?;
+            client::handshake_chain(&mut tcp, &upstream, &target).await?;
+            Ok(tcp)
+        })
+        .await
+        .and_then(|r| r)
+        .map_err(|e| {
+            log::warn!("connect to {} failed err={}", socket, e);
+            e
+        })
+    }
+
+    async fn connect_kcp(&self, socket: &Socket) -> crate::Result<FusoStream> {
+        let (kcp, _) = self.kcp_connector(socket).await?;
+        Ok(kcp.connect().await?.into_boxed_stream())
+    }
+
+    #[cfg(feature = "fuso-quic")]
+    async fn connect_quic(&self, socket: Socket) -> crate::Result<FusoStream> {
+        let addr = crate::quic::resolve_addr(&socket).await?;
+
+        let quic = {
+            let mut quic = self.quic.lock().await;
+
+            match quic.get(&addr.is_ipv6()) {
+                Some(quic) => quic.clone(),
+                None => {
+                    let verify = self.quic_verify.clone().ok_or_else(|| {
+                        crate::QuicErr::Config("server certificate verification not set".into())
+                    })?;
+
+                    let bind = match addr {
+                        std::net::SocketAddr::V4(_) => std::net::SocketAddr::from(([0; 4], 0)),
+                        std::net::SocketAddr::V6(_) => std::net::SocketAddr::from(([0u16; 8], 0)),
+                    };
+
+                    let connector = crate::quic::QuicConnector::bind(bind, verify)?;
+                    quic.insert(addr.is_ipv6(), connector.clone());
+                    connector
+                }
+            }
+        };
+
+        Ok(quic.connect(Socket::quic(addr)).await?.into_boxed_stream())
+    }
+
+    #[cfg(not(feature = "fuso-quic"))]
+    async fn connect_quic(&self, socket: Socket) -> crate::Result<FusoStream> {
+        Err(crate::SocketErr::NotSupport(socket).into())
+    }
+
+    /// 每个服务端地址使用一个独立的udp socket, 返回值表示是否为新建的
+    async fn kcp_connector(&self, socket: &Socket) -> crate::Result<(Arc<KcpConnector<R>>, bool)> {
+        let mut kcp = self.kcp.lock().await;
+        let addr = socket.as_string();
+
+        if let Some(connector) = kcp.get(&addr) {
+            return Ok((connector.clone(), false));
+        }
+
+        let udp = self
+            .runtime
+            .connect_udp(Socket::udp(socket.addr().clone()))
+            .await?;
+
+        let connector = Arc::new(kcp::KcpConnector::with_config(
+            udp,
+            self.kcp_config.clone(),
+            RuntimeExecutor(self.runtime.clone()),
+        ));
+
+        kcp.insert(addr, connector.clone());
+
+        Ok((connector, true))
+    }
+}
+
+impl<R> RuntimeConnector<R>
+where
+    R: Default,
+{
+    pub fn with_kcp_config(kcp_config: kcp::KcpConfig) -> Self {
+        Self {
+            kcp_config,
+            ..Default::default()
+        }
+    }
+}
+
+impl<E, R> client::ClientBuilder<E, RuntimeConnector<R>, FusoStream>
+where
+    R: Clone,
+{
+    /// 设置客户端建立kcp连接时使用的参数, 需要与服务端保持一致
+    pub fn with_kcp_config(mut self, kcp_config: kcp::KcpConfig) -> Self {
+        let mut connector = (*self.client_provider.connect_provider).clone();
+        connector.kcp_config = kcp_config;
+        self.client_provider.connect_provider = Arc::new(connector);
+        self
+    }
+
+    /// 设置连接服务端时经过的上游代理, 多个代理时按顺序串联
+    pub fn with_upstream(mut self, upstream: Vec<Upstream>) -> Self {
+        let mut connector = (*self.client_provider.connect_provider).clone();
+        connector.upstream = Arc::new(upstream);
+        self.client_provider.connect_provider = Arc::new(connector);
+        self
+    }
+
+    /// 设置建立tcp连接的超时时间, 包括与上游代理的握手, 默认为10秒
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        let mut connector = (*self.client_provider.connect_provider).clone();
+        connector.connect_timeout = Some(timeout);
+        self.client_provider.connect_provider = Arc::new(connector);
+        self
+    }
+
+    /// 设置连接quic服务端时校验证书的方式, 未设置时无法使用quic
+    #[cfg(feature = "fuso-quic")]
+    pub fn with_quic_verify(mut self, verify: crate::quic::QuicVerify) -> Self {
+        let mut connector = (*self.client_provider.connect_provider).clone();
+        connector.quic_verify = Some(verify);
+        self.client_provider.connect_provider = Arc::new(connector);
+        self
+    }
+
+    /// 设置控制连接与映射连接使用的传输方式, 使用kcp时服务端需要开启kcp
+    pub fn with_transport(mut self, transport: Transport) -> Self {
+        let mut connector = (*self.client_provider.connect_provider).clone();
+        connector.transport = transport;
+        self.client_provider.connect_provider = Arc::new(connector);
+        self
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "fuso-rt-tokio")]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use tokio::net::{TcpListener, UdpSocket};
+
+    use crate::{
+        client::Transport,
+        ext::{AsyncReadExt, AsyncWriteExt},
+        kcp::KcpListener,
+        AccepterExt, Address, Kind, NetSocket, Provider, Socket, TokioConnector, TokioExecutor,
+    };
+
+    /// 在同一端口上启动tcp与kcp回显服务, 不需要的一方只占用端口
+    async fn serve(tcp: bool, kcp: bool) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let udp = UdpSocket::bind(("127.0.0.1", port)).await.unwrap();
+
+        if tcp {
+            echo_tcp(listener);
+        }
+
+        if kcp {
+            let mut listener = KcpListener::bind(Arc::new(udp), TokioExecutor).unwrap();
+            tokio::spawn(async move {
+                while let Ok(mut kcp) = listener.accept().await {
+                    tokio::spawn(async move {
+                        let mut buf = [0u8; 5];
+                        kcp.read_exact(&mut buf).await?;
+                        kcp.write_all(&buf).await
+                    });
+                }
+            });
+        }
+
+        port
+    }
+
+    fn echo_tcp(listener: TcpListener) {
+        tokio::spawn(async move {
+            while let Ok((mut tcp, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 5];
+                    tcp.read_exact(&mut buf).await?;
+                    tcp.write_all(&buf).await
+                });
+            }
+        });
+    }
+
+    /// 建立连接并完成一次回显, 返回是否为tcp
+    async fn connect(connector: &TokioConnector, port: u16) -> crate::Result<bool> {
+        let mut stream = connector.call(Socket::tcp(([127, 0, 0, 1], port))).await?;
+
+        let is_tcp = match stream.peer_addr()? {
+            Address::Single(socket) => socket.is_tcp(),
+            Address::Many(_) => unreachable!(),
+        };
+
+        // 只有tcp的服务端不会响应kcp, 此时只检查连接类型
+        if is_tcp || connector.transport != Transport::PreferKcp {
+            stream.write_all(b"hello").await?;
+            let mut buf = [0u8; 5];
+            stream.read_exact(&mut buf).await?;
+            assert_eq!(&buf, b"hello");
+        }
+
+        Ok(is_tcp)
+    }
+
+    fn connector(transport: Transport) -> TokioConnector {
+        TokioConnector {
+            transport,
+            connect_timeout: Some(Duration::from_secs(1)),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transport() {
+        let both = serve(true, true).await;
+        let tcp_only = serve(true, false).await;
+        let kcp_only = serve(false, true).await;
+
+        assert!(connect(&connector(Transport::Tcp), both).await.unwrap());
+        assert!(!connect(&connector(Transport::Kcp), both).await.unwrap());
+        assert!(connect(&connector(Transport::Tcp), kcp_only).await.is_err());
+
+        // tcp连接失败后改用kcp, 之后kcp有响应时不再尝试tcp
+        let prefer_tcp = connector(Transport::PreferTcp);
+        assert!(connect(&prefer_tcp, both).await.unwrap());
+        assert!(!connect(&prefer_tcp, kcp_only).await.unwrap());
+
+        echo_tcp(TcpListener::bind(("127.0.0.1", kcp_only)).await.unwrap());
+        assert!(!connect(&prefer_tcp, kcp_only).await.unwrap());
+
+        // 第一次总是使用kcp, 之后只有kcp始终收不到响应时才改用tcp
+        let prefer_kcp = connector(Transport::PreferKcp);
+        assert!(!connect(&prefer_kcp, tcp_only).await.unwrap());
+        assert!(connect(&prefer_kcp, tcp_only).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_prefer_kcp_responded() {
+        let port = serve(true, true).await;
+        let connector = connector(Transport::PreferKcp);
+
+        let mut stream = connector
+            .call(Socket::tcp(([127, 0, 0, 1], port)))
+            .await
+            .unwrap();
+
+        stream.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+
+        // kcp收到过响应, 不再改用tcp
+        assert!(!connect(&connector, port).await.unwrap());
+    }
+
+    /// 上游代理不响应时在设置的时间内返回超时
+    #[tokio::test]
+    async fn test_connect_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let mut streams = Vec::new();
+            while let Ok((tcp, _)) = listener.accept().await {
+                streams.push(tcp);
+            }
+        });
+
+        let connector = TokioConnector {
+            upstream: Arc::new(vec![format!("socks5://{}", addr).parse().unwrap()]),
+            connect_timeout: Some(Duration::from_millis(200)),
+            ..Default::default()
+        };
+
+        let err = tokio::time::timeout(
+            Duration::from_secs(5),
+            connector.call(Socket::tcp(([127, 0, 0, 1], 80))),
+        )
+        .await
+        .unwrap()
+        .err()
+        .unwrap();
+
+        assert!(matches!(err.kind(), Kind::Timeout(_)));
+    }
+}
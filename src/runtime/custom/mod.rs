@@ -0,0 +1,165 @@
+mod penetrate;
+pub use penetrate::connector::*;
+
+use std::{future::Future, net::SocketAddr, pin::Pin, sync::Arc, time::Duration};
+
+use crate::{
+    client, current_runtime, server, AccepterWrapper, Address, ClientProvider, Executor,
+    FusoStream, InnerAddr, InvalidAddr, NetSocket, Provider, Runtime, RuntimeFuture,
+    ServerProvider, Socket, SocketErr, Task, UdpSocketWrapper,
+};
+
+type BoxedFuture<O> = Pin<Box<dyn Future<Output = crate::Result<O>> + Send + 'static>>;
+
+/// 使用 `install_runtime` 注册的运行时执行任务
+#[derive(Default, Clone, Copy)]
+pub struct CustomExecutor;
+/// 转发到 `install_runtime` 注册的运行时
+#[derive(Default, Clone, Copy)]
+pub struct CustomRuntime;
+pub struct CustomAccepter;
+
+/// 连接服务端
+pub type CustomConnector = crate::RuntimeConnector<CustomRuntime>;
+
+pub struct CustomUdpServerProvider;
+pub struct CustomUdpForwardProvider;
+
+impl Executor for CustomExecutor {
+    fn spawn<F, O>(&self, fut: F) -> Task<O>
+    where
+        F: Future<Output = O> + Send + 'static,
+        O: Send + 'static,
+    {
+        let mut task = current_runtime().spawn(Box::pin(async move {
+            let _ = fut.await;
+        }));
+
+        Task {
+            detach_task_fn: task.detach_task_fn.take(),
+            abort_task_fn: task.abort_task_fn.take(),
+            _marked: std::marker::PhantomData,
+        }
+    }
+}
+
+impl Runtime for CustomRuntime {
+    fn spawn(&self, fut: Pin<Box<dyn Future<Output = ()> + Send + 'static>>) -> Task<()> {
+        current_runtime().spawn(fut)
+    }
+
+    fn sleep(&self, timeout: Duration) -> Pin<Box<dyn Future<Output = ()> + Send + 'static>> {
+        current_runtime().sleep(timeout)
+    }
+
+    fn bind_tcp(&self, socket: Socket) -> RuntimeFuture<AccepterWrapper<FusoStream>> {
+        current_runtime().bind_tcp(socket)
+    }
+
+    fn connect_tcp(&self, socket: Socket) -> RuntimeFuture<FusoStream> {
+        current_runtime().connect_tcp(socket)
+    }
+
+    fn bind_udp(&self, socket: Socket) -> RuntimeFuture<UdpSocketWrapper> {
+        current_runtime().bind_udp(socket)
+    }
+
+    fn connect_udp(&self, socket: Socket) -> RuntimeFuture<UdpSocketWrapper> {
+        current_runtime().connect_udp(socket)
+    }
+}
+
+impl Provider<Socket> for CustomAccepter {
+    type Output = BoxedFuture<AccepterWrapper<FusoStream>>;
+
+    fn call(&self, socket: Socket) -> Self::Output {
+        Box::pin(async move {
+            if socket.is_tcp() {
+                current_runtime().bind_tcp(socket).await
+            } else {
+                Err(SocketErr::NotSupport(socket).into())
+            }
+        })
+    }
+}
+
+impl ServerProvider<CustomAccepter, CustomConnector> {
+    pub fn with_custom() -> Self {
+        ServerProvider {
+            accepter_provider: Arc::new(CustomAccepter),
+            connector_provider: Arc::new(CustomConnector::default()),
+        }
+    }
+}
+
+impl ClientProvider<CustomConnector> {
+    pub fn with_custom() -> Self {
+        ClientProvider {
+            server_socket: Default::default(),
+            connect_provider: Arc::new(CustomConnector::default()),
+        }
+    }
+}
+
+pub fn builder_server_with_custom(
+) -> server::ServerBuilder<CustomExecutor, CustomAccepter, CustomConnector, FusoStream> {
+    server::ServerBuilder {
+        is_mixed: false,
+        executor: CustomExecutor,
+        handshake: None,
+        server_provider: ServerProvider::with_custom(),
+    }
+}
+
+pub fn builder_client_with_custom(
+) -> client::ClientBuilder<CustomExecutor, CustomConnector, FusoStream> {
+    client::ClientBuilder {
+        executor: CustomExecutor,
+        handshake: None,
+        client_provider: ClientProvider::with_custom(),
+    }
+}
+
+impl Provider<()> for CustomUdpForwardProvider {
+    type Output = BoxedFuture<(SocketAddr, UdpSocketWrapper)>;
+
+    fn call(&self, _: ()) -> Self::Output {
+        Box::pin(async move {
+            let udp = current_runtime()
+                .bind_udp(Socket::udp(([0, 0, 0, 0], 0)))
+                .await?;
+
+            let addr = match udp.local_addr()? {
+                Address::Single(socket) => match socket.into_addr().into_inner() {
+                    InnerAddr::Socket(addr) => addr,
+                    InnerAddr::Domain(domain, _) => return Err(InvalidAddr::Domain(domain).into()),
+                },
+                address => return Err(InvalidAddr::Domain(address.to_string()).into()),
+            };
+
+            log::debug!("udp listening on {}", addr);
+
+            Ok((addr, udp))
+        })
+    }
+}
+
+impl Provider<Socket> for CustomUdpServerProvider {
+    type Output = BoxedFuture<UdpSocketWrapper>;
+
+    fn call(&self, socket: Socket) -> Self::Output {
+        Box::pin(async move {
+            if socket.is_mixed() || socket.is_kcp() || socket.is_udp() {
+                current_runtime()
+                    .bind_udp(socket.clone())
+                    .await
+                    .map_err(|e| {
+                        log::warn!("udp bind failed addr={}, err={}", socket.addr(), e);
+                        e
+                    })
+            } else {
+                Err(SocketErr::NotSupport(socket).into())
+            }
+        })
+    }
+}
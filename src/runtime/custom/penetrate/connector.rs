@@ -0,0 +1,83 @@
+use std::{
+    net::{SocketAddr, ToSocketAddrs},
+    pin::Pin,
+    sync::Arc,
+};
+
+use crate::{
+    client::Route,
+    current_runtime,
+    penetrate::SocksUdpForwardConverter,
+    udp::{Datagram, VirtualUdpSocket},
+    Addr, Address, CustomExecutor, FusoStream, InnerAddr, InvalidAddr, NetSocket, Provider,
+    ProviderWrapper, Socket, SocketErr, SocketKind, UdpSocketWrapper,
+};
+
+type BoxedFuture<O> = Pin<Box<dyn std::future::Future<Output = crate::Result<O>> + Send + 'static>>;
+
+pub struct CustomPenetrateConnector {
+    udp: Arc<Datagram<UdpSocketWrapper, CustomExecutor>>,
+}
+
+pub struct CustomUdpForwardClientProvider(Arc<Datagram<UdpSocketWrapper, CustomExecutor>>);
+
+impl CustomPenetrateConnector {
+    pub async fn new() -> crate::Result<Self> {
+        let udp = current_runtime()
+            .bind_udp(Socket::udp(([0, 0, 0, 0], 0)))
+            .await?;
+
+        Ok(Self {
+            udp: Arc::new(Datagram::new(udp, CustomExecutor)?),
+        })
+    }
+}
+
+impl Provider<Socket> for CustomPenetrateConnector {
+    type Output = BoxedFuture<Route<FusoStream>>;
+
+    fn call(&self, socket: Socket) -> Self::Output {
+        let udp = self.udp.clone();
+        Box::pin(async move {
+            match socket.kind() {
+                SocketKind::Tcp => Ok(Route::Forward(current_runtime().connect_tcp(socket).await?)),
+                SocketKind::Ufd => {
+                    let provider = ProviderWrapper::wrap(CustomUdpForwardClientProvider(udp));
+
+                    Ok(Route::Provider(ProviderWrapper::wrap(
+                        SocksUdpForwardConverter(provider),
+                    )))
+                }
+                _ => Err(SocketErr::NotSupport(socket).into()),
+            }
+        })
+    }
+}
+
+impl Provider<Addr> for CustomUdpForwardClientProvider {
+    type Output = BoxedFuture<(SocketAddr, VirtualUdpSocket<UdpSocketWrapper>)>;
+
+    fn call(&self, addr: Addr) -> Self::Output {
+        let udp = self.0.clone();
+
+        Box::pin(async move {
+            log::debug!("try connect to udp {}", addr);
+
+            let addr = addr
+                .as_string()
+                .to_socket_addrs()?
+                .next()
+                .ok_or(InvalidAddr::Domain(addr.as_string()))?;
+
+            let udp = udp.connect(addr).await?;
+
+            match udp.local_addr()? {
+                Address::Single(socket) => match socket.into_addr().into_inner() {
+                    InnerAddr::Socket(addr) => Ok((addr, udp)),
+                    _ => Err(InvalidAddr::Domain(addr.to_string()).into()),
+                },
+                Address::Many(_) => Err(InvalidAddr::Domain(addr.to_string()).into()),
+            }
+        })
+    }
+}
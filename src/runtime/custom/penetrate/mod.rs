@@ -0,0 +1 @@
+pub(super) mod connector;
@@ -1,3 +1,9 @@
+mod rt;
+pub use self::rt::*;
+
+mod connector;
+pub use self::connector::*;
+
 #[cfg(feature = "fuso-rt-smol")]
 mod smol;
 #[cfg(feature = "fuso-rt-smol")]
@@ -7,3 +13,8 @@ pub use self::smol::*;
 mod tokio;
 #[cfg(feature = "fuso-rt-tokio")]
 pub use self::tokio::*;
+
+#[cfg(feature = "fuso-rt-custom")]
+mod custom;
+#[cfg(feature = "fuso-rt-custom")]
+pub use self::custom::*;
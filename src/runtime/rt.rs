@@ -0,0 +1,110 @@
+use std::{future::Future, pin::Pin, sync::OnceLock, time::Duration};
+
+use crate::{AccepterWrapper, FusoStream, Kind, Socket, Task, UdpSocketWrapper};
+
+pub type RuntimeFuture<O> = Pin<Box<dyn Future<Output = crate::Result<O>> + Send + 'static>>;
+
+static RUNTIME: OnceLock<Box<dyn Runtime>> = OnceLock::new();
+
+/// 异步运行时, fuso内部的任务、定时器以及tcp与udp都经由它创建,
+/// 使用 `fuso-rt-custom` 时通过 `install_runtime` 接入其他运行时
+pub trait Runtime: Send + Sync + 'static {
+    /// 运行后台任务
+    fn spawn(&self, fut: Pin<Box<dyn Future<Output = ()> + Send + 'static>>) -> Task<()>;
+
+    /// 等待一段时间
+    fn sleep(&self, timeout: Duration) -> Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+    /// 监听tcp地址
+    fn bind_tcp(&self, socket: Socket) -> RuntimeFuture<AccepterWrapper<FusoStream>>;
+
+    /// 建立tcp连接
+    fn connect_tcp(&self, socket: Socket) -> RuntimeFuture<FusoStream>;
+
+    /// 绑定udp地址
+    fn bind_udp(&self, socket: Socket) -> RuntimeFuture<UdpSocketWrapper>;
+
+    /// 绑定随机端口并连接到udp地址
+    fn connect_udp(&self, socket: Socket) -> RuntimeFuture<UdpSocketWrapper>;
+}
+
+/// 注册全局运行时, 只能注册一次, 需要在使用fuso之前完成
+pub fn install_runtime<R>(runtime: R) -> crate::Result<()>
+where
+    R: Runtime,
+{
+    RUNTIME
+        .set(Box::new(runtime))
+        .map_err(|_| Kind::AlreadyUsed.into())
+}
+
+/// 当前使用的运行时, 没有注册时使用内置的运行时
+pub fn current_runtime() -> &'static dyn Runtime {
+    RUNTIME.get_or_init(default_runtime).as_ref()
+}
+
+#[cfg(feature = "fuso-rt-tokio")]
+fn default_runtime() -> Box<dyn Runtime> {
+    Box::new(crate::TokioRuntime)
+}
+
+#[cfg(feature = "fuso-rt-smol")]
+fn default_runtime() -> Box<dyn Runtime> {
+    Box::new(crate::SmolRuntime)
+}
+
+#[cfg(feature = "fuso-rt-custom")]
+fn default_runtime() -> Box<dyn Runtime> {
+    panic!("no runtime installed, call fuso::install_runtime first")
+}
+
+#[cfg(test)]
+#[cfg(feature = "fuso-rt-tokio")]
+mod tests {
+    use crate::{
+        ext::{AsyncReadExt, AsyncWriteExt},
+        AccepterExt, Address, NetSocket, Runtime, Socket, TokioRuntime, UdpReceiverExt,
+    };
+
+    /// 只通过运行时提供的tcp与udp完成一次收发
+    #[tokio::test]
+    async fn test_runtime_socket() {
+        let runtime: &dyn Runtime = &TokioRuntime;
+
+        let mut listener = runtime
+            .bind_tcp(Socket::tcp(([127, 0, 0, 1], 0)))
+            .await
+            .unwrap();
+
+        let server = match listener.local_addr().unwrap() {
+            Address::Single(socket) => socket,
+            Address::Many(_) => unreachable!(),
+        };
+
+        let mut client = runtime.connect_tcp(server).await.unwrap();
+        let mut stream = listener.accept().await.unwrap();
+
+        client.write_all(b"hello").await.unwrap();
+
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        let udp = runtime
+            .bind_udp(Socket::udp(([127, 0, 0, 1], 0)))
+            .await
+            .unwrap();
+
+        let server = match udp.local_addr().unwrap() {
+            Address::Single(socket) => Socket::udp(socket.into_addr()),
+            Address::Many(_) => unreachable!(),
+        };
+
+        let peer = runtime.connect_udp(server).await.unwrap();
+        peer.send(b"world").await.unwrap();
+
+        let mut buf = [0u8; 64];
+        let (n, _) = udp.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"world");
+    }
+}
@@ -1,34 +1,43 @@
-use std::{net::SocketAddr, pin::Pin, sync::Arc, task::Poll};
+mod penetrate;
+pub use penetrate::connector::*;
 
-use futures::Future;
-use smol::net::{AsyncToSocketAddrs, TcpStream};
+use std::{future::Future, net::SocketAddr, pin::Pin, sync::Arc, task::Poll, time::Duration};
+
+use smol::{net::TcpStream, Async};
 
 use crate::{
-    client::{ClientBuilder, Mapper},
-    server::ServerBuilder,
-    Accepter, ClientFactory, Executor, Factory, FusoStream, ServerFactory, Socket, Transfer,
+    client, ready, server, Accepter, AccepterWrapper, Address, ClientProvider, Executor,
+    FusoStream, NetSocket, Provider, Runtime, RuntimeFuture, ServerProvider, Socket, SocketErr,
+    Task, ToBoxStream, UdpSocket, UdpSocketWrapper,
 };
 
-type BoxedFuture<T> = Pin<Box<dyn Future<Output = crate::Result<T>> + Send + 'static>>;
+type BoxedFuture<O> = Pin<Box<dyn std::future::Future<Output = crate::Result<O>> + Send + 'static>>;
+
+/// smol的udp socket
+pub type SmolUdpSocket = Async<std::net::UdpSocket>;
 
-#[derive(Default, Clone)]
+#[derive(Default, Clone, Copy)]
 pub struct SmolExecutor;
-pub struct SmolListener;
+/// smol运行时, 启用 `fuso-rt-smol` 时默认使用
+#[derive(Default, Clone, Copy)]
+pub struct SmolRuntime;
 pub struct SmolAccepter;
 
-pub struct SmolPenetrateConnector;
-
 pub struct SmolTcpListener {
     tcp: smol::net::TcpListener,
     accept_fut: Option<BoxedFuture<(TcpStream, SocketAddr)>>,
 }
 
-pub struct SmolConnector;
+/// 连接服务端
+pub type SmolConnector = crate::RuntimeConnector<SmolRuntime>;
+
+pub struct SmolUdpServerProvider;
+pub struct SmolUdpForwardProvider;
 
 impl SmolTcpListener {
     pub async fn bind<A>(addr: A) -> std::io::Result<Self>
     where
-        A: AsyncToSocketAddrs,
+        A: smol::net::AsyncToSocketAddrs,
     {
         let tcp = smol::net::TcpListener::bind(addr).await?;
 
@@ -40,117 +49,393 @@ impl SmolTcpListener {
 }
 
 impl Executor for SmolExecutor {
-    fn spawn<F, O>(&self, fut: F)
+    fn spawn<F, O>(&self, fut: F) -> Task<O>
     where
-        F: futures::Future<Output = O> + Send + 'static,
+        F: std::future::Future<Output = O> + Send + 'static,
         O: Send + 'static,
     {
-        smol::spawn(fut).detach();
+        // smol的task释放时会取消, 与tokio保持一致, 未主动取消时分离
+        let task = Arc::new(std::sync::Mutex::new(Some(smol::spawn(fut))));
+        let abort_task = task.clone();
+
+        Task {
+            detach_task_fn: Some(Box::new(move || {
+                if let Some(task) = task.lock().ok().and_then(|mut task| task.take()) {
+                    task.detach();
+                }
+            })),
+            abort_task_fn: Some(Box::new(move || {
+                drop(abort_task.lock().ok().and_then(|mut task| task.take()));
+                log::debug!("abort task");
+            })),
+            _marked: std::marker::PhantomData,
+        }
     }
 }
 
-impl Factory<Socket> for SmolAccepter {
-    type Output = BoxedFuture<SmolTcpListener>;
+/// 同时启用tokio时使用tokio的实现
+#[cfg(not(feature = "fuso-rt-tokio"))]
+impl crate::Shutdown {
+    /// 收到SIGINT或SIGTERM时发出关闭信号
+    pub fn listen_signal(&self) {
+        use signal_hook::consts::{SIGINT, SIGTERM};
 
-    fn call(&self, socket: Socket) -> Self::Output {
-        Box::pin(async move {
-            match socket {
-                Socket::Tcp(addr) => SmolTcpListener::bind(format!("{}", addr))
-                    .await
-                    .map_err(Into::into),
-                _ => todo!(),
+        let shutdown = self.clone();
+
+        // smol没有信号支持, 在单独的线程中等待
+        std::thread::spawn(move || {
+            #[cfg(unix)]
+            match signal_hook::iterator::Signals::new([SIGINT, SIGTERM]) {
+                Ok(mut signals) => {
+                    signals.forever().next();
+                }
+                Err(e) => {
+                    log::warn!("failed to listen signal err={}", e);
+                    return;
+                }
             }
-        })
-    }
-}
 
-impl Accepter for SmolTcpListener {
-    type Stream = FusoStream;
+            #[cfg(not(unix))]
+            {
+                let received = Arc::new(std::sync::atomic::AtomicBool::new(false));
 
-    fn poll_accept(
-        mut self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> std::task::Poll<crate::Result<Self::Stream>> {
-        let mut fut = match self.accept_fut.take() {
-            Some(fut) => fut,
-            None => {
-                let tcp = self.tcp.clone();
-                Box::pin(async move { tcp.accept().await.map_err(Into::into) })
-            }
-        };
+                for signal in [SIGINT, SIGTERM] {
+                    if let Err(e) = signal_hook::flag::register(signal, received.clone()) {
+                        log::warn!("failed to listen signal {} err={}", signal, e);
+                    }
+                }
 
-        match Pin::new(&mut fut).poll(cx) {
-            Poll::Ready(stream) => Poll::Ready(stream.map(|(stream, addr)| {
-                log::debug!("accept connection from {}", addr);
-                stream.transfer()
-            })),
-            Poll::Pending => {
-                drop(std::mem::replace(&mut self.accept_fut, Some(fut)));
-                Poll::Pending
+                while !received.load(std::sync::atomic::Ordering::Relaxed) {
+                    std::thread::sleep(Duration::from_millis(100));
+                }
             }
-        }
+
+            log::info!("received shutdown signal");
+
+            shutdown.shutdown();
+        });
     }
 }
 
-impl Factory<Socket> for SmolConnector {
-    type Output = BoxedFuture<FusoStream>;
-    fn call(&self, socket: Socket) -> Self::Output {
+impl Runtime for SmolRuntime {
+    fn spawn(&self, fut: Pin<Box<dyn Future<Output = ()> + Send + 'static>>) -> Task<()> {
+        SmolExecutor.spawn(fut)
+    }
+
+    fn sleep(&self, timeout: Duration) -> Pin<Box<dyn Future<Output = ()> + Send + 'static>> {
         Box::pin(async move {
-            match socket {
-                Socket::Udp(_) => todo!(),
-                Socket::Tcp(addr) => Ok(TcpStream::connect(format!("{}", addr)).await?.transfer()),
-                Socket::Kcp(_) => todo!(),
-                Socket::Quic(_) => todo!(),
-            }
+            smol::Timer::after(timeout).await;
+        })
+    }
+
+    fn bind_tcp(&self, socket: Socket) -> RuntimeFuture<AccepterWrapper<FusoStream>> {
+        let listener = SmolAccepter.call(socket);
+        Box::pin(async move { Ok(AccepterWrapper::wrap(listener.await?)) })
+    }
+
+    fn connect_tcp(&self, socket: Socket) -> RuntimeFuture<FusoStream> {
+        Box::pin(async move {
+            Ok({
+                TcpStream::connect(socket.as_string())
+                    .await?
+                    .into_boxed_stream()
+            })
+        })
+    }
+
+    fn bind_udp(&self, socket: Socket) -> RuntimeFuture<UdpSocketWrapper> {
+        Box::pin(async move {
+            let udp = smol::net::UdpSocket::bind(socket.as_string()).await?;
+            Ok(UdpSocketWrapper::wrap(Arc::<SmolUdpSocket>::from(udp)))
+        })
+    }
+
+    fn connect_udp(&self, socket: Socket) -> RuntimeFuture<UdpSocketWrapper> {
+        Box::pin(async move {
+            let udp = smol::net::UdpSocket::bind("0.0.0.0:0").await?;
+            udp.connect(socket.as_string()).await?;
+            Ok(UdpSocketWrapper::wrap(Arc::<SmolUdpSocket>::from(udp)))
         })
     }
 }
 
-impl Factory<Socket> for SmolPenetrateConnector {
-    type Output = BoxedFuture<Mapper<FusoStream>>;
+impl Provider<Socket> for SmolAccepter {
+    type Output = BoxedFuture<SmolTcpListener>;
 
     fn call(&self, socket: Socket) -> Self::Output {
         Box::pin(async move {
-            match socket {
-                Socket::Tcp(addr) => Ok(Mapper::Forward(
-                    TcpStream::connect(format!("{}", addr)).await?.transfer(),
-                )),
-                _ => unimplemented!(),
-            }
+            Ok({
+                if socket.is_tcp() {
+                    SmolTcpListener::bind(socket.as_string()).await?
+                } else {
+                    return Err(SocketErr::NotSupport(socket).into());
+                }
+            })
         })
     }
 }
 
-impl ServerFactory<SmolAccepter, SmolConnector> {
+impl NetSocket for TcpStream {
+    fn peer_addr(&self) -> crate::Result<Address> {
+        Ok(Address::Single(Socket::tcp(TcpStream::peer_addr(self)?)))
+    }
+
+    fn local_addr(&self) -> crate::Result<Address> {
+        Ok(Address::Single(Socket::tcp(TcpStream::local_addr(self)?)))
+    }
+}
+
+impl NetSocket for SmolTcpListener {
+    fn local_addr(&self) -> crate::Result<Address> {
+        Ok(Address::Single(Socket::tcp(self.tcp.local_addr()?)))
+    }
+
+    fn peer_addr(&self) -> crate::Result<Address> {
+        Ok(Address::Single(Socket::tcp(self.tcp.local_addr()?)))
+    }
+}
+
+impl Accepter for SmolTcpListener {
+    type Stream = FusoStream;
+
+    fn poll_accept(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<crate::Result<Self::Stream>> {
+        if self.accept_fut.is_none() {
+            let tcp = self.tcp.clone();
+            self.accept_fut = Some(Box::pin(
+                async move { tcp.accept().await.map_err(Into::into) },
+            ));
+        }
+
+        let r = ready!(Pin::new(unsafe { self.accept_fut.as_mut().unwrap_unchecked() }).poll(cx));
+
+        self.accept_fut = None;
+
+        Poll::Ready(r.map(|(tcp, addr)| {
+            log::debug!("accept connection from {}", addr);
+            tcp.into_boxed_stream()
+        }))
+    }
+}
+
+impl ServerProvider<SmolAccepter, SmolConnector> {
     pub fn with_smol() -> Self {
-        Self {
-            accepter_factory: Arc::new(SmolAccepter),
-            connector_factory: Arc::new(SmolConnector),
+        ServerProvider {
+            accepter_provider: Arc::new(SmolAccepter),
+            connector_provider: Arc::new(SmolConnector::default()),
         }
     }
 }
 
-impl ClientFactory<SmolConnector> {
+impl ClientProvider<SmolConnector> {
     pub fn with_smol() -> Self {
-        Self {
-            connect_factory: Arc::new(SmolConnector),
+        ClientProvider {
+            server_socket: Default::default(),
+            connect_provider: Arc::new(SmolConnector::default()),
         }
     }
 }
 
 pub fn builder_server_with_smol(
-) -> ServerBuilder<SmolExecutor, SmolAccepter, SmolConnector, FusoStream> {
-    ServerBuilder {
+) -> server::ServerBuilder<SmolExecutor, SmolAccepter, SmolConnector, FusoStream> {
+    server::ServerBuilder {
+        is_mixed: false,
         executor: SmolExecutor,
         handshake: None,
-        server_factory: ServerFactory::with_smol(),
+        server_provider: ServerProvider::with_smol(),
     }
 }
 
-pub fn builder_client_with_smol() -> ClientBuilder<SmolExecutor, SmolConnector, FusoStream> {
-    ClientBuilder {
+pub fn builder_client_with_smol() -> client::ClientBuilder<SmolExecutor, SmolConnector, FusoStream>
+{
+    client::ClientBuilder {
         executor: SmolExecutor,
         handshake: None,
-        client_factory: ClientFactory::with_smol(),
+        client_provider: ClientProvider::with_smol(),
+    }
+}
+
+impl NetSocket for SmolUdpSocket {
+    fn peer_addr(&self) -> crate::Result<Address> {
+        Ok(Address::Single(Socket::udp(self.get_ref().peer_addr()?)))
+    }
+
+    fn local_addr(&self) -> crate::Result<Address> {
+        Ok(Address::Single(Socket::udp(self.get_ref().local_addr()?)))
+    }
+}
+
+/// 非阻塞的调用返回 `WouldBlock` 时等待socket就绪后重试
+macro_rules! poll_io {
+    ($cx:expr, $ready:expr, $io:expr) => {
+        loop {
+            match $io {
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    ready!($ready($cx))?;
+                }
+                r => break Poll::Ready(r.map_err(crate::Error::from)),
+            }
+        }
+    };
+}
+
+impl UdpSocket for SmolUdpSocket {
+    fn poll_recv_from(
+        self: Pin<&Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut crate::ReadBuf<'_>,
+    ) -> Poll<crate::Result<SocketAddr>> {
+        let udp = self.get_ref();
+
+        let (n, addr) = ready!(poll_io!(
+            cx,
+            |cx| udp.poll_readable(cx),
+            udp.get_ref().recv_from(buf.initialize_unfilled())
+        ))?;
+
+        buf.advance(n);
+
+        Poll::Ready(Ok(addr))
+    }
+
+    fn poll_recv(
+        self: Pin<&Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut crate::ReadBuf<'_>,
+    ) -> Poll<crate::Result<()>> {
+        let udp = self.get_ref();
+
+        let n = ready!(poll_io!(
+            cx,
+            |cx| udp.poll_readable(cx),
+            udp.get_ref().recv(buf.initialize_unfilled())
+        ))?;
+
+        buf.advance(n);
+
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_send(
+        self: Pin<&Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<crate::Result<usize>> {
+        let udp = self.get_ref();
+        poll_io!(cx, |cx| udp.poll_writable(cx), udp.get_ref().send(buf))
+    }
+
+    fn poll_send_to(
+        self: Pin<&Self>,
+        cx: &mut std::task::Context<'_>,
+        addr: &SocketAddr,
+        buf: &[u8],
+    ) -> Poll<crate::Result<usize>> {
+        let udp = self.get_ref();
+        poll_io!(
+            cx,
+            |cx| udp.poll_writable(cx),
+            udp.get_ref().send_to(buf, addr)
+        )
+    }
+}
+
+impl Provider<()> for SmolUdpForwardProvider {
+    type Output = BoxedFuture<(SocketAddr, SmolUdpSocket)>;
+
+    fn call(&self, _: ()) -> Self::Output {
+        Box::pin(async move {
+            let udp = SmolUdpSocket::bind(([0, 0, 0, 0], 0))?;
+            let addr = udp.get_ref().local_addr()?;
+
+            log::debug!("udp listening on {}", addr);
+
+            Ok((addr, udp))
+        })
+    }
+}
+
+impl Provider<Socket> for SmolUdpServerProvider {
+    type Output = BoxedFuture<Arc<SmolUdpSocket>>;
+
+    fn call(&self, socket: Socket) -> Self::Output {
+        Box::pin(async move {
+            Ok({
+                if socket.is_mixed() || socket.is_kcp() || socket.is_udp() {
+                    Arc::from({
+                        smol::net::UdpSocket::bind(socket.as_string())
+                            .await
+                            .map_err(|e| {
+                                log::warn!("udp bind failed addr={}, err={}", socket.addr(), e);
+                                e
+                            })?
+                    })
+                } else {
+                    return Err(SocketErr::NotSupport(socket).into());
+                }
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smol::io::{AsyncReadExt, AsyncWriteExt};
+
+    use crate::{testing, SmolPenetrateConnector, Socket};
+
+    /// 服务端与客户端均使用smol运行时, 访问端口的连接应被转发到本地服务,
+    /// 访问者发送的数据需要超过包头的长度, 否则服务端会一直等待识别
+    #[test]
+    fn test_smol_penetrate() {
+        smol::block_on(async move {
+            let (builder, listened) = crate::builder_server_with_smol().listening();
+            let penetrate = builder.with_penetrate();
+            let registry = penetrate.registry();
+
+            let server = testing::serve(listened, move |bind| {
+                penetrate
+                    .with_adapter_mode()
+                    .with_normal_unpacker()
+                    .build()
+                    .bind(bind)
+                    .run()
+            })
+            .await;
+
+            let echo = smol::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let echo_addr = echo.local_addr().unwrap();
+
+            smol::spawn(async move {
+                let (mut tcp, _) = echo.accept().await.unwrap();
+                let mut buf = [0u8; 64];
+                tcp.read_exact(&mut buf).await.unwrap();
+                tcp.write_all(&buf).await.unwrap();
+            })
+            .detach();
+
+            testing::spawn(move || async move {
+                crate::builder_client_with_smol()
+                    .using_penetrate(Socket::tcp(([127, 0, 0, 1], 0)), Socket::tcp(echo_addr))
+                    .name(Some(String::from("echo")))
+                    .build(server, SmolPenetrateConnector::new().await.unwrap())
+                    .run()
+                    .await
+            });
+
+            let port = testing::until(|| registry.get("echo")).await.port;
+
+            let mut tcp = smol::net::TcpStream::connect(("127.0.0.1", port))
+                .await
+                .unwrap();
+
+            tcp.write_all(&[b'x'; 64]).await.unwrap();
+
+            let mut buf = [0u8; 64];
+            tcp.read_exact(&mut buf).await.unwrap();
+            assert_eq!(buf, [b'x'; 64]);
+        });
     }
 }
@@ -0,0 +1,105 @@
+use std::{
+    net::{SocketAddr, ToSocketAddrs},
+    pin::Pin,
+    sync::Arc,
+};
+
+use smol::net::TcpStream;
+
+use crate::{
+    client::Route,
+    penetrate::SocksUdpForwardConverter,
+    udp::{Datagram, VirtualUdpSocket},
+    Addr, Address, FusoStream, InnerAddr, InvalidAddr, NetSocket, Provider, ProviderWrapper,
+    SmolExecutor, SmolUdpSocket, Socket, SocketErr, SocketKind, ToBoxStream,
+};
+
+type BoxedFuture<O> = Pin<Box<dyn std::future::Future<Output = crate::Result<O>> + Send + 'static>>;
+
+pub struct SmolTcpConnector;
+
+pub struct SmolPenetrateConnector {
+    udp: Arc<Datagram<Arc<SmolUdpSocket>, SmolExecutor>>,
+}
+
+pub struct SmolUdpForwardClientProvider(Arc<Datagram<Arc<SmolUdpSocket>, SmolExecutor>>);
+
+impl SmolPenetrateConnector {
+    pub async fn new() -> crate::Result<Self> {
+        Ok(Self {
+            udp: Arc::new({
+                Datagram::new(
+                    Arc::new(SmolUdpSocket::bind(([0, 0, 0, 0], 0))?),
+                    SmolExecutor,
+                )?
+            }),
+        })
+    }
+}
+
+impl Provider<Socket> for SmolTcpConnector {
+    type Output = BoxedFuture<FusoStream>;
+
+    fn call(&self, socket: Socket) -> Self::Output {
+        Box::pin(async move {
+            Ok({
+                TcpStream::connect(socket.as_string())
+                    .await?
+                    .into_boxed_stream()
+            })
+        })
+    }
+}
+
+impl Provider<Socket> for SmolPenetrateConnector {
+    type Output = BoxedFuture<Route<FusoStream>>;
+
+    fn call(&self, socket: Socket) -> Self::Output {
+        let udp = self.udp.clone();
+        Box::pin(async move {
+            match socket.kind() {
+                SocketKind::Tcp => Ok(Route::Forward(
+                    TcpStream::connect(socket.as_string())
+                        .await?
+                        .into_boxed_stream(),
+                )),
+                SocketKind::Ufd => {
+                    let provider = ProviderWrapper::wrap(SmolUdpForwardClientProvider(udp));
+
+                    Ok(Route::Provider(ProviderWrapper::wrap(
+                        SocksUdpForwardConverter(provider),
+                    )))
+                }
+                _ => Err(SocketErr::NotSupport(socket).into()),
+            }
+        })
+    }
+}
+
+impl Provider<Addr> for SmolUdpForwardClientProvider {
+    type Output = BoxedFuture<(SocketAddr, VirtualUdpSocket<Arc<SmolUdpSocket>>)>;
+
+    fn call(&self, addr: Addr) -> Self::Output {
+        let udp = self.0.clone();
+
+        Box::pin(async move {
+            log::debug!("try connect to udp {}", addr);
+
+            let addr = addr
+                .as_string()
+                .to_socket_addrs()?
+                .next()
+                .ok_or(InvalidAddr::Domain(addr.as_string()))?;
+
+            let udp = udp.connect(addr).await?;
+
+            match udp.local_addr()? {
+                Address::Single(socket) => match socket.into_addr().into_inner() {
+                    InnerAddr::Socket(addr) => Ok((addr, udp)),
+                    _ => Err(InvalidAddr::Domain(addr.to_string()).into()),
+                },
+                Address::Many(_) => Err(InvalidAddr::Domain(addr.to_string()).into()),
+            }
+        })
+    }
+}
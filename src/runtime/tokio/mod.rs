@@ -1,26 +1,28 @@
 mod penetrate;
 pub use penetrate::connector::*;
 
-use std::{net::SocketAddr, pin::Pin, sync::Arc, task::Poll};
+use std::{net::SocketAddr, pin::Pin, sync::Arc, task::Poll, time::Duration};
 
-use async_mutex::Mutex;
 use tokio::net::TcpListener;
 
 use crate::{
-    client::{self},
-    kcp, ready, server, Accepter, Address, ClientProvider, Executor, FusoStream, NetSocket,
-    Provider, ServerProvider, Socket, SocketErr, Task, ToBoxStream, UdpSocket,
+    client, ready, server, Accepter, AccepterWrapper, Address, ClientProvider, Executor,
+    FusoStream, NetSocket, Provider, Runtime, RuntimeFuture, ServerProvider, Shutdown, Socket,
+    SocketErr, Task, ToBoxStream, UdpSocket, UdpSocketWrapper,
 };
 
 type BoxedFuture<O> = Pin<Box<dyn std::future::Future<Output = crate::Result<O>> + Send + 'static>>;
 
 #[derive(Clone, Copy)]
 pub struct TokioExecutor;
+/// tokio运行时, 启用 `fuso-rt-tokio` 时默认使用
+#[derive(Default, Clone, Copy)]
+pub struct TokioRuntime;
 pub struct TokioTcpListener(tokio::net::TcpListener);
 pub struct TokioAccepter;
-pub struct TokioConnector(
-    Arc<Mutex<Option<kcp::KcpConnector<Arc<tokio::net::UdpSocket>, TokioExecutor>>>>,
-);
+
+/// 连接服务端
+pub type TokioConnector = crate::RuntimeConnector<TokioRuntime>;
 
 pub struct TokioUdpSocket;
 
@@ -46,6 +48,86 @@ impl Executor for TokioExecutor {
     }
 }
 
+impl Runtime for TokioRuntime {
+    fn spawn(
+        &self,
+        fut: Pin<Box<dyn std::future::Future<Output = ()> + Send + 'static>>,
+    ) -> Task<()> {
+        TokioExecutor.spawn(fut)
+    }
+
+    fn sleep(
+        &self,
+        timeout: Duration,
+    ) -> Pin<Box<dyn std::future::Future<Output = ()> + Send + 'static>> {
+        Box::pin(tokio::time::sleep(timeout))
+    }
+
+    fn bind_tcp(&self, socket: Socket) -> RuntimeFuture<AccepterWrapper<FusoStream>> {
+        let listener = TokioAccepter.call(socket);
+        Box::pin(async move { Ok(AccepterWrapper::wrap(listener.await?)) })
+    }
+
+    fn connect_tcp(&self, socket: Socket) -> RuntimeFuture<FusoStream> {
+        Box::pin(async move {
+            Ok({
+                tokio::net::TcpStream::connect(socket.as_string())
+                    .await?
+                    .into_boxed_stream()
+            })
+        })
+    }
+
+    fn bind_udp(&self, socket: Socket) -> RuntimeFuture<UdpSocketWrapper> {
+        Box::pin(async move {
+            let udp = tokio::net::UdpSocket::bind(socket.as_string()).await?;
+            Ok(UdpSocketWrapper::wrap(udp))
+        })
+    }
+
+    fn connect_udp(&self, socket: Socket) -> RuntimeFuture<UdpSocketWrapper> {
+        Box::pin(async move {
+            let udp = tokio::net::UdpSocket::bind("0.0.0.0:0").await?;
+            udp.connect(socket.as_string()).await?;
+            Ok(UdpSocketWrapper::wrap(udp))
+        })
+    }
+}
+
+impl Shutdown {
+    /// 收到SIGINT或SIGTERM时发出关闭信号
+    pub fn listen_signal(&self) {
+        let shutdown = self.clone();
+
+        tokio::spawn(async move {
+            #[cfg(unix)]
+            {
+                use tokio::signal::unix::{signal, SignalKind};
+
+                match signal(SignalKind::terminate()) {
+                    Ok(mut terminate) => {
+                        tokio::select! {
+                            _ = tokio::signal::ctrl_c() => {}
+                            _ = terminate.recv() => {}
+                        }
+                    }
+                    Err(e) => {
+                        log::warn!("failed to listen SIGTERM err={}", e);
+                        let _ = tokio::signal::ctrl_c().await;
+                    }
+                }
+            }
+
+            #[cfg(not(unix))]
+            let _ = tokio::signal::ctrl_c().await;
+
+            log::info!("received shutdown signal");
+
+            shutdown.shutdown();
+        });
+    }
+}
+
 impl Provider<Socket> for TokioAccepter {
     type Output = BoxedFuture<TokioTcpListener>;
 
@@ -55,7 +137,7 @@ impl Provider<Socket> for TokioAccepter {
                 if socket.is_tcp() {
                     TcpListener::bind(socket.as_string())
                         .await
-                        .map(|tcp| TokioTcpListener(tcp))?
+                        .map(TokioTcpListener)?
                 } else {
                     return Err(SocketErr::NotSupport(socket).into());
                 }
@@ -72,6 +154,40 @@ impl NetSocket for tokio::net::TcpStream {
     fn local_addr(&self) -> crate::Result<Address> {
         Ok(Address::Single(Socket::tcp(self.local_addr()?)))
     }
+
+    #[cfg(all(target_os = "linux", feature = "fuso-splice"))]
+    fn as_raw_socket(&self) -> Option<&dyn crate::splice::RawSocket> {
+        Some(self)
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "fuso-splice"))]
+impl crate::splice::RawSocket for tokio::net::TcpStream {
+    fn as_raw_fd(&self) -> std::os::fd::RawFd {
+        std::os::fd::AsRawFd::as_raw_fd(self)
+    }
+
+    fn poll_read_ready(&self, cx: &mut std::task::Context<'_>) -> Poll<std::io::Result<()>> {
+        tokio::net::TcpStream::poll_read_ready(self, cx)
+    }
+
+    fn poll_write_ready(&self, cx: &mut std::task::Context<'_>) -> Poll<std::io::Result<()>> {
+        tokio::net::TcpStream::poll_write_ready(self, cx)
+    }
+
+    fn try_read_io(
+        &self,
+        f: &mut dyn FnMut() -> std::io::Result<usize>,
+    ) -> std::io::Result<usize> {
+        self.try_io(tokio::io::Interest::READABLE, f)
+    }
+
+    fn try_write_io(
+        &self,
+        f: &mut dyn FnMut() -> std::io::Result<usize>,
+    ) -> std::io::Result<usize> {
+        self.try_io(tokio::io::Interest::WRITABLE, f)
+    }
 }
 
 impl NetSocket for TokioTcpListener {
@@ -102,49 +218,11 @@ impl Accepter for TokioTcpListener {
     }
 }
 
-impl Provider<Socket> for TokioConnector {
-    type Output = BoxedFuture<FusoStream>;
-
-    fn call(&self, socket: Socket) -> Self::Output {
-        let kcp = self.0.clone();
-
-        Box::pin(async move {
-            Ok({
-                if !socket.is_mixed() {
-                    tokio::net::TcpStream::connect(socket.as_string())
-                        .await?
-                        .into_boxed_stream()
-                } else {
-                    let mut kcp = kcp.lock().await;
-
-                    if kcp.is_none() {
-                        let udp = tokio::net::UdpSocket::bind("0.0.0.0:0").await?;
-                        udp.connect(socket.as_string()).await?;
-                        *kcp = Some(kcp::KcpConnector::new(Arc::new(udp), TokioExecutor));
-                    }
-
-                    if kcp.is_some() && socket.is_ufd() {
-                        kcp.as_ref().unwrap().connect().await?.into_boxed_stream()
-                    } else {
-                        tokio::net::TcpStream::connect(socket.as_string())
-                            .await
-                            .map_err(|e| {
-                                log::warn!("connect to {} failed err={}", socket, e);
-                                e
-                            })?
-                            .into_boxed_stream()
-                    }
-                }
-            })
-        })
-    }
-}
-
 impl ServerProvider<TokioAccepter, TokioConnector> {
     pub fn with_tokio() -> Self {
         ServerProvider {
             accepter_provider: Arc::new(TokioAccepter),
-            connector_provider: Arc::new(TokioConnector(Default::default())),
+            connector_provider: Arc::new(TokioConnector::default()),
         }
     }
 }
@@ -153,7 +231,7 @@ impl ClientProvider<TokioConnector> {
     pub fn with_tokio() -> Self {
         ClientProvider {
             server_socket: Default::default(),
-            connect_provider: Arc::new(TokioConnector(Default::default())),
+            connect_provider: Arc::new(TokioConnector::default()),
         }
     }
 }
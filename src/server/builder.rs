@@ -2,7 +2,7 @@ use std::{pin::Pin, sync::Arc};
 
 use crate::{
     generator::Generator, Provider, ProviderChain, ProviderTransfer, Fuso, ServerProvider, Socket,
-    Stream,
+    Stream, DEFAULT_GRACE_PERIOD,
 };
 
 use super::Server;
@@ -44,6 +44,8 @@ where
             executor: self.executor,
             provider: self.server_provider,
             handshake: self.handshake.map(Arc::new),
+            shutdown: Default::default(),
+            grace_period: DEFAULT_GRACE_PERIOD,
         })
     }
 }
@@ -1,8 +1,8 @@
 mod builder;
 pub use builder::*;
 
-use crate::{generator::GeneratorEx, Serve, Socket};
-use std::{pin::Pin, sync::Arc};
+use crate::{generator::GeneratorEx, Serve, Shutdown, Socket, TaskTracker};
+use std::{pin::Pin, sync::Arc, time::Duration};
 
 use crate::{
     generator::Generator, Accepter, AccepterExt, Executor, Provider, ProviderTransfer, Fuso,
@@ -17,6 +17,8 @@ pub struct Server<E, H, SF, CF, SI> {
     pub(crate) handler: Arc<H>,
     pub(crate) provider: ServerProvider<SF, CF>,
     pub(crate) handshake: Option<Arc<ProviderTransfer<SI>>>,
+    pub(crate) shutdown: Shutdown,
+    pub(crate) grace_period: Duration,
 }
 
 impl<E, H, A, G, SF, CF, SI> Server<E, H, SF, CF, SI>
@@ -34,8 +36,13 @@ where
 
         log::info!("the server listens on {}", accepter.local_addr()?);
 
+        let tasks = TaskTracker::default();
+
         loop {
-            let stream = accepter.accept().await?;
+            let stream = match self.shutdown.run_until(accepter.accept()).await {
+                Some(stream) => stream?,
+                None => break,
+            };
 
             if let Ok(addr) = stream.peer_addr() {
                 log::debug!("connection from {}", addr);
@@ -45,8 +52,10 @@ where
             let handshake = self.handshake.clone();
             let provider = self.provider.clone();
             let handler = self.handler.clone();
+            let shutdown = self.shutdown.clone();
+            let forwards = tasks.clone();
 
-            self.executor.spawn(async move {
+            tasks.spawn(&self.executor, async move {
                 let stream = match handshake.as_ref() {
                     None => Ok(stream),
                     Some(provider) => {
@@ -76,14 +85,19 @@ where
                 let mut generator = unsafe { generator.unwrap_unchecked() };
 
                 loop {
-                    match generator.next().await {
-                        Ok(None) => break,
-                        Err(e) => {
+                    match shutdown.run_until(generator.next()).await {
+                        None => {
+                            log::debug!("notify the peer to close");
+                            let _ = generator.close().await;
+                            break;
+                        }
+                        Some(Ok(None)) => break,
+                        Some(Err(e)) => {
                             log::warn!("An error occurred {}", e);
                             break;
                         }
-                        Ok(Some(fut)) => {
-                            executor.spawn(fut);
+                        Some(Ok(Some(fut))) => {
+                            forwards.spawn(&executor, fut);
                         }
                     }
                 }
@@ -91,6 +105,12 @@ where
                 log::warn!("stop processing");
             });
         }
+
+        log::info!("the server is shutting down, {} tasks are running", tasks.len());
+
+        tasks.drain(self.grace_period).await;
+
+        Ok(())
     }
 }
 
@@ -111,12 +131,67 @@ where
             executor: self.0.executor,
             handshake: self.0.handshake,
             handler: self.0.handler,
+            shutdown: self.0.shutdown,
+            grace_period: self.0.grace_period,
         })
     }
 
+    /// 使用外部的关闭信号, 多个服务可以共享同一个信号
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.0.shutdown = shutdown;
+        self
+    }
+
+    /// 关闭时等待转发结束的时间, 超时后取消剩余的转发
+    pub fn with_grace_period(mut self, grace_period: Duration) -> Self {
+        self.0.grace_period = grace_period;
+        self
+    }
+
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.0.shutdown.clone()
+    }
+
     pub fn run(self) -> Fuso<Serve> {
         Fuso(Serve {
             fut: Box::pin(self.0.run()),
         })
     }
 }
+
+#[cfg(test)]
+#[cfg(feature = "fuso-rt-tokio")]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{Shutdown, Socket};
+
+    /// 收到关闭信号后服务端停止监听并正常退出
+    #[test]
+    fn test_server_shutdown() {
+        let shutdown = Shutdown::default();
+
+        let handle = {
+            let shutdown = shutdown.clone();
+            std::thread::spawn(move || {
+                tokio::runtime::Runtime::new().unwrap().block_on(
+                    crate::builder_server_with_tokio()
+                        .with_penetrate()
+                        .with_adapter_mode()
+                        .with_normal_unpacker()
+                        .build()
+                        .bind(Socket::tcp(([127, 0, 0, 1], 0)))
+                        .with_shutdown(shutdown)
+                        .with_grace_period(Duration::from_millis(100))
+                        .run(),
+                )
+            })
+        };
+
+        std::thread::sleep(Duration::from_millis(200));
+
+        shutdown.shutdown();
+
+        assert!(handle.join().unwrap().is_ok());
+    }
+}
@@ -0,0 +1,145 @@
+//! 测试共用的辅助函数, 服务端与客户端都监听随机端口, 通过实际监听的地址等待就绪
+
+#[cfg(feature = "fuso-rt-tokio")]
+pub(crate) mod tcp;
+
+use std::{future::Future, pin::Pin, sync::Arc, time::Duration};
+
+use crate::{server::ServerBuilder, time, Address, NetSocket, Provider, Socket};
+
+type BoxedFuture<O> = Pin<Box<dyn Future<Output = crate::Result<O>> + Send + 'static>>;
+
+/// 等待就绪的最长时间
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 记录第一次监听的实际地址
+pub(crate) struct Listening<P> {
+    provider: Arc<P>,
+    sender: async_channel::Sender<Socket>,
+}
+
+/// 等待 `Listening` 开始监听
+pub(crate) struct Listened(async_channel::Receiver<Socket>);
+
+pub(crate) fn listening<P>(provider: P) -> (Listening<P>, Listened) {
+    wrap(Arc::new(provider))
+}
+
+fn wrap<P>(provider: Arc<P>) -> (Listening<P>, Listened) {
+    let (sender, receiver) = async_channel::bounded(1);
+    (Listening { provider, sender }, Listened(receiver))
+}
+
+impl<P, A> Provider<Socket> for Listening<P>
+where
+    P: Provider<Socket, Output = BoxedFuture<A>>,
+    A: NetSocket + Send + 'static,
+{
+    type Output = BoxedFuture<A>;
+
+    fn call(&self, socket: Socket) -> Self::Output {
+        let fut = self.provider.call(socket);
+        let sender = self.sender.clone();
+
+        Box::pin(async move {
+            let accepter = fut.await?;
+
+            if let Address::Single(socket) = accepter.local_addr()? {
+                if sender.try_send(socket).is_ok() {
+                    sender.close();
+                }
+            }
+
+            Ok(accepter)
+        })
+    }
+}
+
+impl Listened {
+    /// 实际监听的地址
+    pub(crate) async fn addr(self) -> Socket {
+        time::wait_for(TIMEOUT, self.0.recv())
+            .await
+            .expect("not listening in time")
+            .expect("listener has been dropped")
+    }
+}
+
+impl<E, SF, CF, S> ServerBuilder<E, SF, CF, S> {
+    /// 服务端开始监听后可以由 `Listened` 得到监听的地址
+    pub(crate) fn listening(self) -> (ServerBuilder<E, Listening<SF>, CF, S>, Listened) {
+        let (provider, listened) = wrap(self.server_provider.accepter_provider);
+
+        let builder = ServerBuilder {
+            executor: self.executor,
+            is_mixed: self.is_mixed,
+            handshake: self.handshake,
+            server_provider: crate::ServerProvider {
+                accepter_provider: Arc::new(provider),
+                connector_provider: self.server_provider.connector_provider,
+            },
+        };
+
+        (builder, listened)
+    }
+}
+
+/// 在单独的线程中运行, 服务端与客户端的future没有实现Send
+#[cfg(any(feature = "fuso-rt-tokio", feature = "fuso-rt-smol"))]
+pub(crate) fn spawn<F, Fut>(f: F)
+where
+    F: FnOnce() -> Fut + Send + 'static,
+    Fut: Future + 'static,
+{
+    std::thread::spawn(move || {
+        #[cfg(feature = "fuso-rt-tokio")]
+        tokio::runtime::Runtime::new().unwrap().block_on(f());
+        #[cfg(feature = "fuso-rt-smol")]
+        smol::block_on(f());
+    });
+}
+
+/// 在单独的线程中以随机端口运行服务端, 返回实际监听的地址
+#[cfg(any(feature = "fuso-rt-tokio", feature = "fuso-rt-smol"))]
+pub(crate) async fn serve<F, Fut>(listened: Listened, run: F) -> Socket
+where
+    F: FnOnce(Socket) -> Fut + Send + 'static,
+    Fut: Future + 'static,
+{
+    spawn(move || run(Socket::tcp(([127, 0, 0, 1], 0))));
+    listened.addr().await
+}
+
+/// 等待 `f` 返回结果
+pub(crate) async fn until<T, F>(mut f: F) -> T
+where
+    F: FnMut() -> Option<T>,
+{
+    for _ in 0..TIMEOUT.as_millis() / 10 {
+        if let Some(value) = f() {
+            return value;
+        }
+
+        time::sleep(Duration::from_millis(10)).await;
+    }
+
+    panic!("not ready in time")
+}
+
+/// 回显收到的数据, 返回监听的地址
+#[cfg(feature = "fuso-rt-tokio")]
+pub(crate) async fn echo() -> std::net::SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        while let Ok((tcp, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = tokio::io::split(tcp);
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+
+    addr
+}
@@ -0,0 +1,9 @@
+use tokio::net::{TcpListener, TcpStream};
+
+/// 一对互相连接的tcp连接
+pub async fn pair() -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (s1, s2) = tokio::join!(TcpStream::connect(addr), listener.accept());
+    (s1.unwrap(), s2.unwrap().0)
+}